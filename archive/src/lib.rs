@@ -0,0 +1,244 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::*;
+
+mod storage;
+
+/// Blocks returned per requested range
+const MAX_BLOCKS_PER_RESPONSE: u128 = 100;
+
+/// ICRC-3 generic value, the representation blocks are stored and served in
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum Value {
+    Blob(Vec<u8>),
+    Text(String),
+    Nat(u128),
+    Int(i128),
+    Array(Vec<Value>),
+    Map(Vec<(String, Value)>),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct GetBlocksArgs {
+    start: u128,
+    length: u128,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct BlockWithId {
+    id: u128,
+    block: Value,
+}
+
+/// Archive query callback, `func (vec GetBlocksArgs) -> (GetBlocksResult) query`
+#[derive(Deserialize, Clone, Debug)]
+#[serde(transparent)]
+pub struct QueryBlockArchiveFn(candid::Func);
+
+impl CandidType for QueryBlockArchiveFn {
+    fn _ty() -> candid::types::Type {
+        candid::types::Type::Func(candid::types::Function {
+            modes: vec![candid::parser::types::FuncMode::Query],
+            args: vec![Vec::<GetBlocksArgs>::ty()],
+            rets: vec![GetBlocksResult::ty()],
+        })
+    }
+    
+    fn idl_serialize<S: candid::types::Serializer>(&self, serializer: S) -> Result<(), S::Error> {
+        self.0.idl_serialize(serializer)
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ArchivedBlocks {
+    args: Vec<GetBlocksArgs>,
+    callback: QueryBlockArchiveFn,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct GetBlocksResult {
+    log_length: u128,
+    blocks: Vec<BlockWithId>,
+    /// Always empty: an archive holds its blocks itself
+    archived_blocks: Vec<ArchivedBlocks>,
+}
+
+/// Installation arguments, passed by the ledger that spawns the archive
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ArchiveInit {
+    ledger_id: Principal,
+    /// Ledger index of the first block this archive receives
+    block_offset: u64,
+    /// Blocks the archive accepts before the ledger must spawn another
+    max_blocks: u64,
+}
+
+#[derive(CandidType, Deserialize, Default)]
+struct ArchiveState {
+    /// Only canister allowed to append blocks
+    ledger_id: Option<Principal>,
+    block_offset: u64,
+    max_blocks: u64,
+    blocks: Vec<Value>,
+}
+
+impl ArchiveState {
+    /// Appends blocks sent by `caller`, which must be the ledger
+    fn append(&mut self, caller: Principal, blocks: Vec<Value>) -> Result<(), String> {
+        if self.ledger_id != Some(caller) {
+            return Err("Unauthorized".to_string());
+        }
+        if self.blocks.len() as u64 + blocks.len() as u64 > self.max_blocks {
+            return Err("Archive is full".to_string());
+        }
+        self.blocks.extend(blocks);
+        Ok(())
+    }
+    
+    /// Serves `icrc3_get_blocks`, at most `MAX_BLOCKS_PER_RESPONSE` blocks per range
+    fn get_blocks(&self, args: Vec<GetBlocksArgs>) -> GetBlocksResult {
+        let first = self.block_offset as u128;
+        let end = first + self.blocks.len() as u128;
+        
+        let mut blocks = Vec::new();
+        for range in args {
+            let range_end = range.start
+                .saturating_add(range.length.min(MAX_BLOCKS_PER_RESPONSE))
+                .min(end);
+            for id in range.start.max(first)..range_end {
+                blocks.push(BlockWithId {
+                    id,
+                    block: self.blocks[(id - first) as usize].clone(),
+                });
+            }
+        }
+        
+        GetBlocksResult {
+            log_length: end,
+            blocks,
+            archived_blocks: Vec::new(),
+        }
+    }
+}
+
+// Canister endpoints
+#[init]
+fn init(args: ArchiveInit) {
+    let state = storage::get_mut::<ArchiveState>();
+    state.ledger_id = Some(args.ledger_id);
+    state.block_offset = args.block_offset;
+    state.max_blocks = args.max_blocks;
+}
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    let state = storage::get::<ArchiveState>();
+    ic_cdk::storage::stable_save((state,)).expect("Failed to save archive");
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    let (state,): (ArchiveState,) = ic_cdk::storage::stable_restore().expect("Failed to restore archive");
+    *storage::get_mut::<ArchiveState>() = state;
+}
+
+/// Appends the ledger's next blocks, in order
+#[update]
+fn append_blocks(blocks: Vec<Value>) {
+    let state = storage::get_mut::<ArchiveState>();
+    if let Err(e) = state.append(ic_cdk::caller(), blocks) {
+        ic_cdk::trap(&e);
+    }
+}
+
+/// Blocks the archive can still accept
+#[query]
+fn remaining_capacity() -> u64 {
+    let state = storage::get::<ArchiveState>();
+    state.max_blocks.saturating_sub(state.blocks.len() as u64)
+}
+
+/// Cycles the archive holds, read by the ledger to decide on top-ups
+#[query]
+fn cycles_balance() -> u128 {
+    ic_cdk::api::canister_balance128()
+}
+
+/// Blocks by ledger index; indices outside the archive are left out
+#[query]
+fn icrc3_get_blocks(args: Vec<GetBlocksArgs>) -> GetBlocksResult {
+    storage::get::<ArchiveState>().get_blocks(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn ledger() -> Principal {
+        Principal::from_slice(&[1; 29])
+    }
+    
+    /// An archive taking blocks from index 1000, holding at most `max_blocks`
+    fn archive(max_blocks: u64) -> ArchiveState {
+        ArchiveState {
+            ledger_id: Some(ledger()),
+            block_offset: 1000,
+            max_blocks,
+            blocks: Vec::new(),
+        }
+    }
+    
+    fn blocks(count: u128) -> Vec<Value> {
+        (0..count).map(Value::Nat).collect()
+    }
+    
+    fn range(start: u128, length: u128) -> GetBlocksArgs {
+        GetBlocksArgs { start, length }
+    }
+    
+    fn ids(result: &GetBlocksResult) -> Vec<u128> {
+        result.blocks.iter().map(|block| block.id).collect()
+    }
+    
+    #[test]
+    fn serves_appended_blocks_by_ledger_index() {
+        let mut archive = archive(1_000);
+        archive.append(ledger(), blocks(3)).unwrap();
+        archive.append(ledger(), blocks(2)).unwrap();
+        
+        let result = archive.get_blocks(vec![range(1001, 3)]);
+        assert_eq!(result.log_length, 1005);
+        assert_eq!(ids(&result), vec![1001, 1002, 1003]);
+        assert!(matches!(result.blocks[2].block, Value::Nat(0)));
+        assert!(result.archived_blocks.is_empty());
+    }
+    
+    #[test]
+    fn pages_ranges_and_leaves_out_indices_it_does_not_hold() {
+        let mut archive = archive(1_000);
+        archive.append(ledger(), blocks(250)).unwrap();
+        
+        // Ranges are capped at 100 blocks each
+        let result = archive.get_blocks(vec![range(1000, 250)]);
+        assert_eq!(ids(&result), (1000..1100).collect::<Vec<_>>());
+        
+        let result = archive.get_blocks(vec![range(990, 15), range(1245, 10), range(2000, 5)]);
+        assert_eq!(ids(&result), vec![1000, 1001, 1002, 1003, 1004, 1245, 1246, 1247, 1248, 1249]);
+    }
+    
+    #[test]
+    fn rejects_blocks_from_other_callers() {
+        let mut archive = archive(1_000);
+        let other = Principal::from_slice(&[2; 29]);
+        assert_eq!(archive.append(other, blocks(1)), Err("Unauthorized".to_string()));
+        assert_eq!(archive.append(Principal::anonymous(), blocks(1)), Err("Unauthorized".to_string()));
+        assert!(archive.blocks.is_empty());
+    }
+    
+    #[test]
+    fn rejects_blocks_past_its_capacity() {
+        let mut archive = archive(3);
+        archive.append(ledger(), blocks(2)).unwrap();
+        assert_eq!(archive.append(ledger(), blocks(2)), Err("Archive is full".to_string()));
+        assert_eq!(archive.blocks.len(), 2);
+    }
+}
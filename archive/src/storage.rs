@@ -0,0 +1,26 @@
+//! Heap singletons by type, as `ic_cdk::storage::get` and `get_mut` provided before ic-cdk 0.5
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+thread_local! {
+    static SINGLETONS: RefCell<HashMap<TypeId, Box<dyn Any>>> = RefCell::new(HashMap::new());
+}
+
+/// The canister's instance of `T`, created from its default on first use
+pub fn get_mut<T: Default + 'static>() -> &'static mut T {
+    SINGLETONS.with(|singletons| {
+        let mut singletons = singletons.borrow_mut();
+        let value = singletons
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(T::default()));
+        let value: *mut T = value.downcast_mut::<T>().expect("Singleton stored under another type");
+        // Values are boxed and never dropped, so they stay put as the map grows, and canisters run
+        // one message at a time
+        unsafe { &mut *value }
+    })
+}
+
+pub fn get<T: Default + 'static>() -> &'static T {
+    get_mut::<T>()
+}
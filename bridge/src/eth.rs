@@ -0,0 +1,322 @@
+use sha3::{Digest, Keccak256};
+
+/// Selector of `mint(address,uint256)` on the wrapped iUSD contract
+const MINT_SELECTOR: [u8; 4] = [0x40, 0xc1, 0x0f, 0x19];
+/// EIP-2718 type of EIP-1559 transactions
+const EIP1559_TX_TYPE: u8 = 0x02;
+/// Event the contract emits when wrapped iUSD is burned to be released on the IC; `from` is
+/// indexed and the data holds the recipient principal (length-prefixed), subaccount and amount
+const BRIDGE_TO_IC_EVENT: &str = "BridgeToIc(address,bytes32,bytes32,uint256)";
+
+pub fn keccak256(data: &[u8]) -> [u8; 32] {
+    Keccak256::digest(data).into()
+}
+
+/// Parses a `0x`-prefixed 20-byte address
+pub fn parse_address(address: &str) -> Result<[u8; 20], String> {
+    let bytes = hex::decode(address.strip_prefix("0x").ok_or("Address must start with 0x")?)
+        .map_err(|_| "Address is not hex".to_string())?;
+    bytes.try_into().map_err(|_| "Address must be 20 bytes".to_string())
+}
+
+pub fn format_address(address: &[u8; 20]) -> String {
+    format!("0x{}", hex::encode(address))
+}
+
+/// Ethereum address of a SEC1-encoded secp256k1 public key
+pub fn address_of(public_key: &[u8]) -> Result<[u8; 20], String> {
+    let key = k256::ecdsa::VerifyingKey::from_sec1_bytes(public_key)
+        .map_err(|_| "Malformed public key".to_string())?;
+    let point = key.to_encoded_point(false);
+    Ok(keccak256(&point.as_bytes()[1..])[12..].try_into().unwrap())
+}
+
+/// Calldata of `mint(recipient, amount)`
+pub fn mint_calldata(recipient: &[u8; 20], amount: u128) -> Vec<u8> {
+    let mut data = MINT_SELECTOR.to_vec();
+    data.extend_from_slice(&[0u8; 12]);
+    data.extend_from_slice(recipient);
+    data.extend_from_slice(&[0u8; 16]);
+    data.extend_from_slice(&amount.to_be_bytes());
+    data
+}
+
+fn rlp_length_prefix(len: usize, short_offset: u8) -> Vec<u8> {
+    if len <= 55 {
+        return vec![short_offset + len as u8];
+    }
+    let len_bytes: Vec<u8> = len.to_be_bytes().into_iter().skip_while(|byte| *byte == 0).collect();
+    let mut prefix = vec![short_offset + 55 + len_bytes.len() as u8];
+    prefix.extend(len_bytes);
+    prefix
+}
+
+fn rlp_bytes(bytes: &[u8]) -> Vec<u8> {
+    if bytes.len() == 1 && bytes[0] < 0x80 {
+        return bytes.to_vec();
+    }
+    let mut encoded = rlp_length_prefix(bytes.len(), 0x80);
+    encoded.extend_from_slice(bytes);
+    encoded
+}
+
+/// Integers are encoded big-endian without leading zeros, so zero is the empty string
+fn rlp_uint(n: u128) -> Vec<u8> {
+    let bytes: Vec<u8> = n.to_be_bytes().into_iter().skip_while(|byte| *byte == 0).collect();
+    rlp_bytes(&bytes)
+}
+
+fn rlp_list(items: &[Vec<u8>]) -> Vec<u8> {
+    let payload = items.concat();
+    let mut encoded = rlp_length_prefix(payload.len(), 0xc0);
+    encoded.extend(payload);
+    encoded
+}
+
+/// EIP-1559 transaction without a value or access list
+pub struct Eip1559Transaction {
+    pub chain_id: u64,
+    pub nonce: u64,
+    pub max_priority_fee_per_gas: u128,
+    pub max_fee_per_gas: u128,
+    pub gas_limit: u128,
+    pub to: [u8; 20],
+    pub data: Vec<u8>,
+}
+
+impl Eip1559Transaction {
+    fn fields(&self) -> Vec<Vec<u8>> {
+        vec![
+            rlp_uint(self.chain_id as u128),
+            rlp_uint(self.nonce as u128),
+            rlp_uint(self.max_priority_fee_per_gas),
+            rlp_uint(self.max_fee_per_gas),
+            rlp_uint(self.gas_limit),
+            rlp_bytes(&self.to),
+            rlp_uint(0),
+            rlp_bytes(&self.data),
+            rlp_list(&[]),
+        ]
+    }
+    
+    /// Hash the sender signs
+    pub fn signing_hash(&self) -> [u8; 32] {
+        let mut payload = vec![EIP1559_TX_TYPE];
+        payload.extend(rlp_list(&self.fields()));
+        keccak256(&payload)
+    }
+    
+    /// Raw transaction for `eth_sendRawTransaction` from a 64-byte `r || s` signature over
+    /// `signing_hash` by `public_key`
+    pub fn encode_signed(&self, signature: &[u8], public_key: &[u8]) -> Result<Vec<u8>, String> {
+        let signature = k256::ecdsa::Signature::from_slice(signature)
+            .map_err(|_| "Malformed signature".to_string())?;
+        // Ethereum only accepts the low-s form of a signature
+        let signature = signature.normalize_s().unwrap_or(signature);
+        let y_parity = recovery_id(&self.signing_hash(), &signature, public_key)?;
+        
+        let bytes = signature.to_bytes();
+        let mut fields = self.fields();
+        fields.push(rlp_uint(y_parity as u128));
+        fields.push(rlp_bytes(trim_leading_zeros(&bytes[..32])));
+        fields.push(rlp_bytes(trim_leading_zeros(&bytes[32..])));
+        
+        let mut raw = vec![EIP1559_TX_TYPE];
+        raw.extend(rlp_list(&fields));
+        Ok(raw)
+    }
+}
+
+fn trim_leading_zeros(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|byte| *byte != 0).unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+/// Recovery id under which the signature recovers to `public_key`
+fn recovery_id(digest: &[u8; 32], signature: &k256::ecdsa::Signature, public_key: &[u8]) -> Result<u8, String> {
+    let expected = k256::ecdsa::VerifyingKey::from_sec1_bytes(public_key)
+        .map_err(|_| "Malformed public key".to_string())?;
+    for id in 0..2 {
+        let recovery_id = k256::ecdsa::RecoveryId::from_byte(id).unwrap();
+        let recovered = k256::ecdsa::VerifyingKey::recover_from_prehash(digest, signature, recovery_id);
+        if recovered.is_ok_and(|key| key == expected) {
+            return Ok(id);
+        }
+    }
+    Err("Signature does not match the bridge key".to_string())
+}
+
+pub fn bridge_to_ic_topic() -> [u8; 32] {
+    keccak256(BRIDGE_TO_IC_EVENT.as_bytes())
+}
+
+/// Recipient principal bytes, subaccount and amount of a `BridgeToIc` log's data
+pub fn decode_bridge_to_ic(data: &[u8]) -> Result<(Vec<u8>, [u8; 32], u128), String> {
+    if data.len() != 3 * 32 {
+        return Err("BridgeToIc data must be three words".to_string());
+    }
+    
+    let principal_len = data[0] as usize;
+    if principal_len > 29 {
+        return Err("BridgeToIc principal is too long".to_string());
+    }
+    let principal = data[1..1 + principal_len].to_vec();
+    let subaccount: [u8; 32] = data[32..64].try_into().unwrap();
+    if data[64..80].iter().any(|byte| *byte != 0) {
+        return Err("BridgeToIc amount out of range".to_string());
+    }
+    let amount = u128::from_be_bytes(data[80..96].try_into().unwrap());
+    Ok((principal, subaccount, amount))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use k256::ecdsa::SigningKey;
+    
+    /// Private key of the EIP-155 example transaction
+    const EIP155_KEY: [u8; 32] = [0x46; 32];
+    
+    fn mint_transaction() -> Eip1559Transaction {
+        Eip1559Transaction {
+            chain_id: 1,
+            nonce: 7,
+            max_priority_fee_per_gas: 2_000_000_000,
+            max_fee_per_gas: 100_000_000_000,
+            gas_limit: 100_000,
+            to: [0x11; 20],
+            data: mint_calldata(&[0x22; 20], 12_345),
+        }
+    }
+    
+    #[test]
+    fn keccak_and_selectors_match_known_values() {
+        assert_eq!(
+            hex::encode(keccak256(b"")),
+            "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+        );
+        assert_eq!(keccak256(b"mint(address,uint256)")[..4], MINT_SELECTOR);
+    }
+    
+    #[test]
+    fn rlp_encodes_like_the_spec_examples() {
+        assert_eq!(rlp_bytes(b"dog"), hex::decode("83646f67").unwrap());
+        assert_eq!(rlp_list(&[rlp_bytes(b"cat"), rlp_bytes(b"dog")]), hex::decode("c88363617483646f67").unwrap());
+        assert_eq!(rlp_bytes(b""), vec![0x80]);
+        assert_eq!(rlp_list(&[]), vec![0xc0]);
+        assert_eq!(rlp_uint(0), vec![0x80]);
+        assert_eq!(rlp_uint(15), vec![0x0f]);
+        assert_eq!(rlp_uint(1024), vec![0x82, 0x04, 0x00]);
+        
+        let long = b"Lorem ipsum dolor sit amet, consectetur adipisicing elit";
+        let mut expected = vec![0xb8, 0x38];
+        expected.extend_from_slice(long);
+        assert_eq!(rlp_bytes(long), expected);
+    }
+    
+    #[test]
+    fn mint_calldata_is_selector_and_two_words() {
+        let data = mint_calldata(&[0x22; 20], 12_345);
+        assert_eq!(data.len(), 4 + 2 * 32);
+        assert_eq!(data[..4], MINT_SELECTOR);
+        assert_eq!(data[4..16], [0; 12]);
+        assert_eq!(data[16..36], [0x22; 20]);
+        assert_eq!(u128::from_be_bytes(data[52..68].try_into().unwrap()), 12_345);
+    }
+    
+    #[test]
+    fn signing_hash_matches_a_reference_encoding() {
+        // Keccak-256 of 0x02 || rlp([chain_id, nonce, priority fee, max fee, gas, to, 0, data, []]),
+        // computed with an independent RLP and Keccak implementation
+        assert_eq!(
+            hex::encode(mint_transaction().signing_hash()),
+            "ae1430e86e9ac79713cccb41ac4f79b45da76859e56d3b1ddf574dd93e01e5f9"
+        );
+    }
+    
+    #[test]
+    fn address_of_matches_the_eip155_example() {
+        let key = SigningKey::from_bytes(&EIP155_KEY.into()).unwrap();
+        let public_key = key.verifying_key().to_encoded_point(true);
+        assert_eq!(
+            format_address(&address_of(public_key.as_bytes()).unwrap()),
+            "0x9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f"
+        );
+    }
+    
+    #[test]
+    fn signed_transactions_carry_a_recoverable_low_s_signature() {
+        let key = SigningKey::from_bytes(&EIP155_KEY.into()).unwrap();
+        let public_key = key.verifying_key().to_encoded_point(true);
+        let tx = mint_transaction();
+        let hash = tx.signing_hash();
+        let (signature, _) = key.sign_prehash_recoverable(&hash).unwrap();
+        
+        let raw = tx.encode_signed(&signature.to_bytes(), public_key.as_bytes()).unwrap();
+        
+        let signature = signature.normalize_s().unwrap_or(signature);
+        assert!(signature.normalize_s().is_none());
+        let y_parity = recovery_id(&hash, &signature, public_key.as_bytes()).unwrap();
+        let recovered = k256::ecdsa::VerifyingKey::recover_from_prehash(
+            &hash,
+            &signature,
+            k256::ecdsa::RecoveryId::from_byte(y_parity).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(recovered, *key.verifying_key());
+        
+        // The raw transaction is the unsigned fields followed by y parity, r and s
+        let bytes = signature.to_bytes();
+        let mut fields = tx.fields();
+        fields.push(rlp_uint(y_parity as u128));
+        fields.push(rlp_bytes(trim_leading_zeros(&bytes[..32])));
+        fields.push(rlp_bytes(trim_leading_zeros(&bytes[32..])));
+        assert_eq!(raw[0], EIP1559_TX_TYPE);
+        assert_eq!(raw[1..], rlp_list(&fields)[..]);
+    }
+    
+    #[test]
+    fn signatures_by_another_key_are_rejected() {
+        let key = SigningKey::from_bytes(&EIP155_KEY.into()).unwrap();
+        let other = SigningKey::from_bytes(&[0x47; 32].into()).unwrap();
+        let tx = mint_transaction();
+        let (signature, _) = other.sign_prehash_recoverable(&tx.signing_hash()).unwrap();
+        let public_key = key.verifying_key().to_encoded_point(true);
+        
+        assert_eq!(
+            tx.encode_signed(&signature.to_bytes(), public_key.as_bytes()),
+            Err("Signature does not match the bridge key".to_string())
+        );
+    }
+    
+    /// `BridgeToIc` data: length-prefixed principal, subaccount and amount words
+    pub(crate) fn bridge_to_ic_data(principal: &[u8], subaccount: [u8; 32], amount: u128) -> Vec<u8> {
+        let mut data = vec![principal.len() as u8];
+        data.extend_from_slice(principal);
+        data.resize(32, 0);
+        data.extend_from_slice(&subaccount);
+        data.extend_from_slice(&[0; 16]);
+        data.extend_from_slice(&amount.to_be_bytes());
+        data
+    }
+    
+    #[test]
+    fn decodes_bridge_to_ic_data() {
+        let data = bridge_to_ic_data(&[7; 10], [3; 32], 5_000);
+        assert_eq!(decode_bridge_to_ic(&data), Ok((vec![7; 10], [3; 32], 5_000)));
+    }
+    
+    #[test]
+    fn rejects_malformed_bridge_to_ic_data() {
+        let data = bridge_to_ic_data(&[7; 10], [3; 32], 5_000);
+        assert!(decode_bridge_to_ic(&data[..64]).is_err());
+        
+        let mut too_long = data.clone();
+        too_long[0] = 30;
+        assert_eq!(decode_bridge_to_ic(&too_long), Err("BridgeToIc principal is too long".to_string()));
+        
+        let mut too_large = data;
+        too_large[64] = 1;
+        assert_eq!(decode_bridge_to_ic(&too_large), Err("BridgeToIc amount out of range".to_string()));
+    }
+}
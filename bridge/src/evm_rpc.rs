@@ -0,0 +1,206 @@
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_cdk::api::call::CallResult;
+
+/// Cycles attached to each EVM RPC call, which queries every provider; the unused portion is
+/// refunded
+const EVM_RPC_CYCLES_FEE: u128 = 50_000_000_000;
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum EthMainnetService {
+    Alchemy,
+    Ankr,
+    BlockPi,
+    Cloudflare,
+    PublicNode,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum EthSepoliaService {
+    Alchemy,
+    Ankr,
+    BlockPi,
+    PublicNode,
+    Sepolia,
+}
+
+/// Providers every call goes to; None leaves the choice to the EVM RPC canister's defaults
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum RpcServices {
+    EthMainnet(Option<Vec<EthMainnetService>>),
+    EthSepolia(Option<Vec<EthSepoliaService>>),
+}
+
+/// Providers reached through the EVM RPC canister
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct RpcConfig {
+    pub evm_rpc_canister: Principal,
+    pub services: RpcServices,
+    pub max_response_bytes: u64,
+}
+
+/// Per-call settings of the EVM RPC canister; responses must agree across all providers unless a
+/// consensus strategy is passed, so none is
+#[derive(CandidType, Deserialize)]
+struct CallConfig {
+    #[serde(rename = "responseSizeEstimate")]
+    response_size_estimate: Option<u64>,
+}
+
+#[derive(CandidType)]
+enum BlockTag {
+    Latest,
+    Pending,
+}
+
+#[derive(CandidType)]
+struct GetTransactionCountArgs {
+    address: String,
+    block: BlockTag,
+}
+
+/// Result of a call as every provider returned it. Provider errors are reported as a variant this
+/// canister does not model, so only their presence is kept
+#[derive(CandidType, Deserialize)]
+enum MultiResult<T> {
+    Consistent(Result<T, candid::Reserved>),
+    Inconsistent(Vec<(candid::Reserved, Result<T, candid::Reserved>)>),
+}
+
+impl<T> MultiResult<T> {
+    /// The result, if every provider returned it
+    fn consistent(self, method: &str) -> Result<T, String> {
+        match self {
+            MultiResult::Consistent(Ok(value)) => Ok(value),
+            MultiResult::Consistent(Err(_)) => Err(format!("{} failed at the EVM RPC providers", method)),
+            MultiResult::Inconsistent(_) => Err(format!("EVM RPC providers disagree on {}", method)),
+        }
+    }
+    
+    /// Every provider's result, failing if any of them failed
+    fn all(self, method: &str) -> Result<Vec<T>, String> {
+        let results = match self {
+            MultiResult::Consistent(result) => vec![result],
+            MultiResult::Inconsistent(results) => results.into_iter().map(|(_, result)| result).collect(),
+        };
+        results
+            .into_iter()
+            .map(|result| result.map_err(|_| format!("{} failed at an EVM RPC provider", method)))
+            .collect()
+    }
+}
+
+/// An `eth_sendRawTransaction` outcome; a provider that already has the transaction reports `Ok`
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum SendRawTransactionStatus {
+    /// Accepted, with the transaction hash if the provider returned one
+    Ok(Option<String>),
+    /// A transaction with this nonce is already mined, which may be this one
+    NonceTooLow,
+    NonceTooHigh,
+    InsufficientFunds,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    pub address: String,
+    pub topics: Vec<String>,
+    pub data: String,
+    #[serde(rename = "logIndex")]
+    pub log_index: Option<Nat>,
+    pub removed: bool,
+}
+
+/// The receipt fields the bridge reads; providers must agree on the whole receipt
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TransactionReceipt {
+    #[serde(rename = "blockNumber")]
+    pub block_number: Nat,
+    /// 1 if the transaction succeeded, 0 if it reverted
+    pub status: Option<Nat>,
+    pub logs: Vec<LogEntry>,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+struct Block {
+    number: Nat,
+}
+
+impl RpcConfig {
+    /// Calls a typed method of the EVM RPC canister, which sends it to every provider
+    async fn call<A: CandidType, T: CandidType + for<'de> Deserialize<'de>>(
+        &self,
+        method: &str,
+        arg: A,
+    ) -> Result<MultiResult<T>, String> {
+        let config = CallConfig {
+            response_size_estimate: Some(self.max_response_bytes),
+        };
+        let call_result: CallResult<(MultiResult<T>,)> = ic_cdk::api::call::call_with_payment128(
+            self.evm_rpc_canister,
+            method,
+            (self.services.clone(), Some(config), arg),
+            EVM_RPC_CYCLES_FEE,
+        )
+        .await;
+        let (result,) = call_result
+            .map_err(|(code, msg)| format!("EVM RPC call failed: {:?} - {}", code, msg))?;
+        Ok(result)
+    }
+    
+    /// Latest block number; providers a block or two apart are common, so this is the lowest one
+    /// reported, and every provider must answer
+    pub async fn block_number(&self) -> Result<u64, String> {
+        let result: MultiResult<Block> = self.call("eth_getBlockByNumber", BlockTag::Latest).await?;
+        let blocks = result.all("eth_getBlockByNumber")?;
+        let lowest = blocks.iter().map(|block| &block.number).min().ok_or("No EVM RPC provider answered")?;
+        nat_to_u64(lowest)
+    }
+    
+    /// Transactions sent from `address`, including pending ones
+    pub async fn transaction_count(&self, address: &str) -> Result<u64, String> {
+        let args = GetTransactionCountArgs {
+            address: address.to_string(),
+            block: BlockTag::Pending,
+        };
+        let result: MultiResult<Nat> = self.call("eth_getTransactionCount", args).await?;
+        nat_to_u64(&result.consistent("eth_getTransactionCount")?)
+    }
+    
+    /// Broadcasts a signed transaction. Providers relay transactions to each other, so it counts
+    /// as accepted once any of them accepts it; otherwise the most telling of their answers is
+    /// returned
+    pub async fn send_raw_transaction(&self, raw: &[u8]) -> Result<SendRawTransactionStatus, String> {
+        let raw = format!("0x{}", hex::encode(raw));
+        let result: MultiResult<SendRawTransactionStatus> = self.call("eth_sendRawTransaction", raw).await?;
+        let statuses = match result {
+            MultiResult::Consistent(result) => vec![result],
+            MultiResult::Inconsistent(results) => results.into_iter().map(|(_, result)| result).collect(),
+        };
+        statuses
+            .into_iter()
+            .flatten()
+            .min_by_key(|status| match status {
+                SendRawTransactionStatus::Ok(_) => 0,
+                SendRawTransactionStatus::NonceTooLow => 1,
+                _ => 2,
+            })
+            .ok_or_else(|| "eth_sendRawTransaction failed at every EVM RPC provider".to_string())
+    }
+    
+    /// Receipt of a mined transaction, as every provider returned it; None while it is pending or
+    /// unknown
+    pub async fn transaction_receipt(&self, tx_hash: &str) -> Result<Option<TransactionReceipt>, String> {
+        let result: MultiResult<Option<TransactionReceipt>> =
+            self.call("eth_getTransactionReceipt", tx_hash.to_string()).await?;
+        result.consistent("eth_getTransactionReceipt")
+    }
+}
+
+pub fn nat_to_u64(value: &Nat) -> Result<u64, String> {
+    u64::try_from(&value.0).map_err(|_| format!("{} does not fit in 64 bits", value))
+}
+
+/// Decodes hex data such as `"0xdeadbeef"`
+pub fn parse_data(text: &str) -> Result<Vec<u8>, String> {
+    hex::decode(text.trim_start_matches("0x")).map_err(|e| format!("Invalid hex data: {}", e))
+}
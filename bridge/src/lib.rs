@@ -0,0 +1,936 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::call::CallResult;
+use ic_cdk::api::management_canister::ecdsa::{
+    ecdsa_public_key, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgument, SignWithEcdsaArgument,
+    SignWithEcdsaResponse,
+};
+use ic_cdk_macros::*;
+use std::collections::BTreeSet;
+mod eth;
+mod evm_rpc;
+mod storage;
+
+use eth::Eip1559Transaction;
+use evm_rpc::{nat_to_u64, parse_data, RpcConfig, RpcServices, SendRawTransactionStatus, TransactionReceipt};
+
+/// Cycles attached to each signing request; the unused portion is refunded
+const SIGN_WITH_ECDSA_CYCLES: u128 = 30_000_000_000;
+/// Time a broadcast mint is given to be mined before a retry replaces it at higher fees
+const REPLACEMENT_DELAY_NANOS: u64 = 5 * 60 * 1_000_000_000;
+/// Raise of both gas fees a replacement makes; nodes only take replacements raising them by 10%
+const FEE_BUMP_PERCENT: u128 = 20;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Account {
+    owner: Principal,
+    subaccount: Option<[u8; 32]>,
+}
+
+/// Bridge settings
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct BridgeConfig {
+    /// iUSD ledger tokens are locked on
+    iusd_ledger: Principal,
+    /// Wrapped iUSD ERC-20, deployed with 8 decimals like iUSD and mintable only by the
+    /// bridge's address
+    erc20_contract: String,
+    chain_id: u64,
+    rpc: RpcConfig,
+    /// Threshold ECDSA key, e.g. "key_1" on mainnet or "dfx_test_key" locally
+    key_name: String,
+    gas_limit: u128,
+    max_fee_per_gas: u128,
+    max_priority_fee_per_gas: u128,
+    /// Highest max fee per gas a replacement of a stuck mint may bid; mints at it are resent as
+    /// they are until gas prices fall
+    fee_cap_per_gas: u128,
+    /// Blocks a burn must be buried under before its iUSD is released, against reorgs
+    min_confirmations: u64,
+    /// Smallest amount bridged to Ethereum, so a mint is worth its gas
+    min_amount: u128,
+}
+
+/// Installation arguments
+#[derive(CandidType, Deserialize)]
+pub struct InitArgs {
+    config: BridgeConfig,
+    /// Principals allowed to change the config and the admin set
+    admins: Vec<Principal>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum DepositStatus {
+    /// iUSD is locked; the mint transaction is not signed yet
+    Locked,
+    /// Mint transaction signed but not yet accepted by the provider
+    Signed,
+    /// Mint transaction broadcast
+    Sent,
+    /// Mint transaction mined and succeeded
+    Minted,
+}
+
+/// Gas fees of a signed mint transaction
+#[derive(CandidType, Deserialize, Clone, Copy, Debug)]
+pub struct GasFees {
+    max_fee_per_gas: u128,
+    max_priority_fee_per_gas: u128,
+}
+
+impl GasFees {
+    /// Fees of a replacement transaction, or None if they would pass `cap`
+    fn bumped(&self, cap: u128) -> Option<GasFees> {
+        let raise = |fee: u128| fee.saturating_add(fee.saturating_mul(FEE_BUMP_PERCENT) / 100).saturating_add(1);
+        let max_fee_per_gas = raise(self.max_fee_per_gas);
+        if max_fee_per_gas > cap {
+            return None;
+        }
+        Some(GasFees {
+            max_fee_per_gas,
+            max_priority_fee_per_gas: raise(self.max_priority_fee_per_gas).min(max_fee_per_gas),
+        })
+    }
+}
+
+/// iUSD locked on the IC to be minted as wrapped iUSD on Ethereum
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Deposit {
+    id: u64,
+    from: Principal,
+    /// Ethereum address the wrapped iUSD is minted to
+    recipient: String,
+    amount: u128,
+    /// Ledger block of the lock
+    ledger_block: u128,
+    /// Nonce of the mint transaction, fixed once assigned so a retry cannot leave a gap
+    nonce: Option<u64>,
+    tx_hash: Option<String>,
+    /// Signed mint transaction, resent as is on retry until it is replaced
+    raw_tx: Option<Vec<u8>>,
+    fees: Option<GasFees>,
+    /// Earlier mint transactions with the same nonce, replaced at higher fees; any of them may be
+    /// the one mined
+    replaced_tx_hashes: Vec<String>,
+    /// When the mint transaction was last broadcast (nanoseconds)
+    sent_at: Option<u64>,
+    status: DepositStatus,
+    last_error: Option<String>,
+    created_at: u64,
+}
+
+/// Wrapped iUSD burned on Ethereum and released on the IC
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Withdrawal {
+    /// Ethereum transaction and log index of the burn
+    tx_hash: String,
+    log_index: u64,
+    /// Ethereum address that burned
+    from: String,
+    to: Account,
+    amount: u128,
+    /// Ledger block of the release
+    ledger_block: u128,
+    timestamp: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct BridgeStatus {
+    config: Option<BridgeConfig>,
+    /// Ethereum address of the bridge key, once it has been fetched
+    address: Option<String>,
+    next_nonce: Option<u64>,
+    /// iUSD locked against wrapped iUSD in circulation
+    total_locked: u128,
+    deposits: u64,
+    withdrawals: u64,
+}
+
+#[derive(CandidType)]
+struct TransferArg {
+    from_subaccount: Option<[u8; 32]>,
+    to: Account,
+    amount: u128,
+    fee: Option<u128>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum TransferError {
+    BadFee { expected_fee: u128 },
+    BadBurn { min_burn_amount: u128 },
+    InsufficientFunds { balance: u128 },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: u128 },
+    TemporarilyUnavailable,
+    GenericError { error_code: u128, message: String },
+}
+
+#[derive(CandidType)]
+struct TransferFromArgs {
+    spender_subaccount: Option<[u8; 32]>,
+    from: Account,
+    to: Account,
+    amount: u128,
+    fee: Option<u128>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum TransferFromError {
+    BadFee { expected_fee: u128 },
+    BadBurn { min_burn_amount: u128 },
+    InsufficientFunds { balance: u128 },
+    InsufficientAllowance { allowance: u128 },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: u128 },
+    TemporarilyUnavailable,
+    GenericError { error_code: u128, message: String },
+}
+
+#[derive(CandidType, Deserialize, Default)]
+struct BridgeState {
+    config: Option<BridgeConfig>,
+    /// SEC1-compressed public key of the bridge key, fetched on first use
+    public_key: Option<Vec<u8>>,
+    /// Nonce of the next mint transaction, read from the chain on first use
+    next_nonce: Option<u64>,
+    deposits: Vec<Deposit>,
+    withdrawals: Vec<Withdrawal>,
+    /// Burns released or being released, by lowercase transaction hash and log index
+    claimed: BTreeSet<(String, u64)>,
+    /// Deposits whose mint is being signed or sent, so concurrent retries cannot take two nonces
+    in_flight: BTreeSet<u64>,
+    total_locked: u128,
+    /// Principals allowed to administer the bridge; None in state saved before admins existed,
+    /// until an upgrade passes them
+    admins: Option<BTreeSet<Principal>>,
+}
+
+impl BridgeState {
+    fn config(&self) -> Result<&BridgeConfig, String> {
+        self.config.as_ref().ok_or_else(|| "Bridge not configured".to_string())
+    }
+    
+    fn is_admin(&self, principal: &Principal) -> bool {
+        self.admins.as_ref().is_some_and(|admins| admins.contains(principal))
+    }
+    
+    fn apply(&mut self, args: InitArgs) -> Result<(), String> {
+        validate_config(&args.config)?;
+        if args.admins.is_empty() {
+            return Err("At least one admin is required".to_string());
+        }
+        self.set_config(args.config);
+        self.admins = Some(args.admins.into_iter().collect());
+        Ok(())
+    }
+    
+    fn set_config(&mut self, config: BridgeConfig) {
+        // A different key signs from a different address, whose nonces start over
+        let key_changed = self.config.as_ref().is_none_or(|current| current.key_name != config.key_name);
+        if key_changed {
+            self.public_key = None;
+            self.next_nonce = None;
+        }
+        self.config = Some(config);
+    }
+}
+
+/// Fails unless the caller is a bridge admin
+fn require_admin() -> Result<(), String> {
+    if !storage::get::<BridgeState>().is_admin(&ic_cdk::caller()) {
+        return Err("Unauthorized: only bridge admins can call this".to_string());
+    }
+    Ok(())
+}
+
+fn key_id(config: &BridgeConfig) -> EcdsaKeyId {
+    EcdsaKeyId {
+        curve: EcdsaCurve::Secp256k1,
+        name: config.key_name.clone(),
+    }
+}
+
+fn derivation_path() -> Vec<Vec<u8>> {
+    vec![b"erc20-bridge".to_vec()]
+}
+
+fn validate_config(config: &BridgeConfig) -> Result<(), String> {
+    eth::parse_address(&config.erc20_contract)?;
+    if config.key_name.is_empty() {
+        return Err("Key name must not be empty".to_string());
+    }
+    if config.min_confirmations == 0 {
+        return Err("At least one confirmation is required".to_string());
+    }
+    if config.fee_cap_per_gas < config.max_fee_per_gas {
+        return Err("Fee cap must be at least the max fee per gas".to_string());
+    }
+    let providers = match &config.rpc.services {
+        RpcServices::EthMainnet(services) => services.as_ref().map(Vec::len),
+        RpcServices::EthSepolia(services) => services.as_ref().map(Vec::len),
+    };
+    if providers.is_some_and(|providers| providers < 2) {
+        return Err("At least two EVM RPC providers are required, so their answers can be compared".to_string());
+    }
+    Ok(())
+}
+
+async fn public_key(config: &BridgeConfig) -> Result<Vec<u8>, String> {
+    if let Some(public_key) = &storage::get::<BridgeState>().public_key {
+        return Ok(public_key.clone());
+    }
+    
+    let (response,) = ecdsa_public_key(EcdsaPublicKeyArgument {
+        canister_id: None,
+        derivation_path: derivation_path(),
+        key_id: key_id(config),
+    })
+    .await
+    .map_err(|(code, msg)| format!("Failed to fetch bridge key: {:?} - {}", code, msg))?;
+    
+    let state = storage::get_mut::<BridgeState>();
+    state.public_key = Some(response.public_key.clone());
+    Ok(response.public_key)
+}
+
+async fn sign(config: &BridgeConfig, message_hash: [u8; 32]) -> Result<Vec<u8>, String> {
+    let call_result: CallResult<(SignWithEcdsaResponse,)> = ic_cdk::api::call::call_with_payment128(
+        Principal::management_canister(),
+        "sign_with_ecdsa",
+        (SignWithEcdsaArgument {
+            message_hash: message_hash.to_vec(),
+            derivation_path: derivation_path(),
+            key_id: key_id(config),
+        },),
+        SIGN_WITH_ECDSA_CYCLES,
+    )
+    .await;
+    let (response,) = call_result
+        .map_err(|(code, msg)| format!("Failed to sign transaction: {:?} - {}", code, msg))?;
+    Ok(response.signature)
+}
+
+/// Takes the next nonce of the bridge address
+async fn reserve_nonce(config: &BridgeConfig, public_key: &[u8]) -> Result<u64, String> {
+    if storage::get::<BridgeState>().next_nonce.is_none() {
+        let address = eth::format_address(&eth::address_of(public_key)?);
+        let count = config.rpc.transaction_count(&address).await?;
+        // Another call may have read the count meanwhile and already used it
+        storage::get_mut::<BridgeState>().next_nonce.get_or_insert(count);
+    }
+    
+    let state = storage::get_mut::<BridgeState>();
+    let nonce = state.next_nonce.unwrap();
+    state.next_nonce = Some(nonce + 1);
+    Ok(nonce)
+}
+
+/// Pulls iUSD the caller approved the bridge to spend (ICRC-2), returning the ledger block
+async fn lock(config: &BridgeConfig, from: Principal, amount: u128) -> Result<u128, String> {
+    let args = TransferFromArgs {
+        spender_subaccount: None,
+        from: Account {
+            owner: from,
+            subaccount: None,
+        },
+        to: Account {
+            owner: ic_cdk::id(),
+            subaccount: None,
+        },
+        amount,
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    };
+    
+    let result: CallResult<(Result<u128, TransferFromError>,)> =
+        ic_cdk::call(config.iusd_ledger, "icrc2_transfer_from", (args,)).await;
+    match result {
+        Ok((Ok(block),)) => Ok(block),
+        Ok((Err(e),)) => Err(format!("iUSD transfer_from rejected: {:?}", e)),
+        Err((code, msg)) => Err(format!("Failed to lock iUSD: {:?} - {}", code, msg)),
+    }
+}
+
+/// Sends locked iUSD to `to`, which receives `amount` less the ledger fee
+async fn release(config: &BridgeConfig, to: Account, amount: u128) -> Result<u128, String> {
+    let fee_result: CallResult<(u128,)> = ic_cdk::call(config.iusd_ledger, "icrc1_fee", ()).await;
+    let (fee,) = fee_result
+        .map_err(|(code, msg)| format!("Failed to read iUSD fee: {:?} - {}", code, msg))?;
+    if amount <= fee {
+        return Err("Burn does not cover the iUSD transfer fee".to_string());
+    }
+    
+    let args = TransferArg {
+        from_subaccount: None,
+        to,
+        amount: amount - fee,
+        fee: Some(fee),
+        memo: None,
+        created_at_time: None,
+    };
+    let result: CallResult<(Result<u128, TransferError>,)> =
+        ic_cdk::call(config.iusd_ledger, "icrc1_transfer", (args,)).await;
+    match result {
+        Ok((Ok(block),)) => Ok(block),
+        Ok((Err(e),)) => Err(format!("iUSD transfer rejected: {:?}", e)),
+        Err((code, msg)) => Err(format!("Failed to release iUSD: {:?} - {}", code, msg)),
+    }
+}
+
+/// Signs the deposit's mint transaction at `fees`, taking a nonce first if it has none. A
+/// transaction signed before is kept in `replaced_tx_hashes`, as it may still be mined instead
+async fn sign_mint(config: &BridgeConfig, id: u64, fees: GasFees) -> Result<(), String> {
+    let deposit = storage::get::<BridgeState>().deposits[id as usize].clone();
+    let public_key = public_key(config).await?;
+    let nonce = match deposit.nonce {
+        Some(nonce) => nonce,
+        None => {
+            let nonce = reserve_nonce(config, &public_key).await?;
+            storage::get_mut::<BridgeState>().deposits[id as usize].nonce = Some(nonce);
+            nonce
+        }
+    };
+    
+    let tx = Eip1559Transaction {
+        chain_id: config.chain_id,
+        nonce,
+        max_priority_fee_per_gas: fees.max_priority_fee_per_gas,
+        max_fee_per_gas: fees.max_fee_per_gas,
+        gas_limit: config.gas_limit,
+        to: eth::parse_address(&config.erc20_contract)?,
+        data: eth::mint_calldata(&eth::parse_address(&deposit.recipient)?, deposit.amount),
+    };
+    let signature = sign(config, tx.signing_hash()).await?;
+    let raw_tx = tx.encode_signed(&signature, &public_key)?;
+    
+    let deposit = &mut storage::get_mut::<BridgeState>().deposits[id as usize];
+    let tx_hash = format!("0x{}", hex::encode(eth::keccak256(&raw_tx)));
+    if let Some(replaced) = deposit.tx_hash.replace(tx_hash) {
+        deposit.replaced_tx_hashes.push(replaced);
+    }
+    deposit.raw_tx = Some(raw_tx);
+    deposit.fees = Some(fees);
+    deposit.status = DepositStatus::Signed;
+    Ok(())
+}
+
+/// Looks for a mined transaction among the deposit's current and replaced ones, and marks the
+/// deposit minted if one succeeded. A reverted mint used its nonce without minting, so the
+/// deposit goes back to `Locked` to be signed again with a new nonce
+async fn check_mined(config: &BridgeConfig, id: u64) -> Result<bool, String> {
+    let deposit = storage::get::<BridgeState>().deposits[id as usize].clone();
+    for tx_hash in deposit.tx_hash.iter().chain(deposit.replaced_tx_hashes.iter()) {
+        let receipt = match config.rpc.transaction_receipt(tx_hash).await? {
+            Some(receipt) => receipt,
+            None => continue,
+        };
+        
+        let deposit = &mut storage::get_mut::<BridgeState>().deposits[id as usize];
+        if succeeded(&receipt)? {
+            if deposit.tx_hash.as_ref() != Some(tx_hash) {
+                deposit.replaced_tx_hashes.retain(|replaced| replaced != tx_hash);
+                deposit.replaced_tx_hashes.extend(deposit.tx_hash.replace(tx_hash.clone()));
+            }
+            deposit.status = DepositStatus::Minted;
+            return Ok(true);
+        }
+        
+        deposit.nonce = None;
+        deposit.tx_hash = None;
+        deposit.raw_tx = None;
+        deposit.fees = None;
+        deposit.replaced_tx_hashes.clear();
+        deposit.sent_at = None;
+        deposit.status = DepositStatus::Locked;
+        return Err(format!("Mint transaction {} reverted; a retry signs a new one", tx_hash));
+    }
+    Ok(false)
+}
+
+/// Broadcasts the deposit's signed mint transaction. A nonce reported as used counts only once
+/// one of the deposit's own transactions is found mined with it
+async fn broadcast_mint(config: &BridgeConfig, id: u64) -> Result<(), String> {
+    let deposit = storage::get::<BridgeState>().deposits[id as usize].clone();
+    let raw_tx = deposit.raw_tx.ok_or("Mint transaction not signed")?;
+    
+    match config.rpc.send_raw_transaction(&raw_tx).await? {
+        SendRawTransactionStatus::Ok(_) => {
+            let deposit = &mut storage::get_mut::<BridgeState>().deposits[id as usize];
+            deposit.status = DepositStatus::Sent;
+            deposit.sent_at = Some(ic_cdk::api::time());
+            Ok(())
+        }
+        SendRawTransactionStatus::NonceTooLow => {
+            if check_mined(config, id).await? {
+                return Ok(());
+            }
+            Err(format!(
+                "Nonce {} is already used, but none of the deposit's transactions is mined yet",
+                deposit.nonce.unwrap_or_default()
+            ))
+        }
+        SendRawTransactionStatus::NonceTooHigh => Err("Nonce is ahead of the bridge address's transactions".to_string()),
+        SendRawTransactionStatus::InsufficientFunds => Err("Bridge address cannot pay for the mint's gas".to_string()),
+    }
+}
+
+/// Moves a deposit's mint along: signs it, resends it, checks whether it was mined, and replaces
+/// it at higher fees once it has been pending for `REPLACEMENT_DELAY_NANOS`
+async fn submit_mint(id: u64) -> Result<(), String> {
+    let state = storage::get::<BridgeState>();
+    let config = state.config()?.clone();
+    let deposit = state.deposits.get(id as usize).ok_or("Deposit not found")?.clone();
+    
+    match deposit.status {
+        DepositStatus::Minted => return Ok(()),
+        DepositStatus::Locked => {
+            let fees = GasFees {
+                max_fee_per_gas: config.max_fee_per_gas,
+                max_priority_fee_per_gas: config.max_priority_fee_per_gas,
+            };
+            sign_mint(&config, id, fees).await?;
+        }
+        DepositStatus::Signed => {}
+        DepositStatus::Sent => {
+            if check_mined(&config, id).await? {
+                return Ok(());
+            }
+            let pending_for = ic_cdk::api::time().saturating_sub(deposit.sent_at.unwrap_or_default());
+            if pending_for < REPLACEMENT_DELAY_NANOS {
+                return Ok(());
+            }
+            // At the fee cap the same transaction is resent, in case it was dropped
+            if let Some(fees) = deposit.fees.and_then(|fees| fees.bumped(config.fee_cap_per_gas)) {
+                sign_mint(&config, id, fees).await?;
+            }
+        }
+    }
+    
+    broadcast_mint(&config, id).await
+}
+
+/// Runs `submit_mint`, keeping its error on the deposit for a later retry
+async fn process_deposit(id: u64) -> Result<Deposit, String> {
+    if !storage::get_mut::<BridgeState>().in_flight.insert(id) {
+        return Err("Deposit is already being processed".to_string());
+    }
+    let result = submit_mint(id).await;
+    
+    let state = storage::get_mut::<BridgeState>();
+    state.in_flight.remove(&id);
+    let deposit = &mut state.deposits[id as usize];
+    deposit.last_error = result.err();
+    Ok(deposit.clone())
+}
+
+/// Whether a mined transaction succeeded
+fn succeeded(receipt: &TransactionReceipt) -> Result<bool, String> {
+    let status = receipt.status.as_ref().ok_or("Receipt has no status")?;
+    Ok(nat_to_u64(status)? == 1)
+}
+
+/// `BridgeToIc` logs of the wrapped iUSD contract in a receipt, as (log index, burner,
+/// recipient, amount)
+fn bridge_logs(config: &BridgeConfig, receipt: &TransactionReceipt) -> Result<Vec<(u64, String, Account, u128)>, String> {
+    let contract = config.erc20_contract.to_lowercase();
+    let topic = format!("0x{}", hex::encode(eth::bridge_to_ic_topic()));
+    
+    let mut burns = Vec::new();
+    for log in &receipt.logs {
+        let from_contract = log.address.to_lowercase() == contract;
+        let is_burn = log.topics.first().map(|first| first.to_lowercase()) == Some(topic.clone());
+        if !from_contract || !is_burn || log.removed {
+            continue;
+        }
+        
+        let burner = parse_data(log.topics.get(1).ok_or("BridgeToIc log has no sender")?)?;
+        if burner.len() != 32 {
+            return Err("BridgeToIc sender must be one word".to_string());
+        }
+        let (principal, subaccount, amount) = eth::decode_bridge_to_ic(&parse_data(&log.data)?)?;
+        let owner = Principal::try_from_slice(&principal)
+            .map_err(|_| "BridgeToIc recipient is not a principal".to_string())?;
+        let log_index = log.log_index.as_ref().ok_or("BridgeToIc log has no index")?;
+        burns.push((
+            nat_to_u64(log_index)?,
+            eth::format_address(&burner[12..].try_into().unwrap()),
+            Account {
+                owner,
+                subaccount: Some(subaccount).filter(|subaccount| *subaccount != [0; 32]),
+            },
+            amount,
+        ));
+    }
+    Ok(burns)
+}
+
+// Canister endpoints
+#[init]
+fn init(args: InitArgs) {
+    if let Err(e) = storage::get_mut::<BridgeState>().apply(args) {
+        ic_cdk::trap(&format!("Invalid init args: {}", e));
+    }
+}
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    let state = storage::get::<BridgeState>();
+    ic_cdk::storage::stable_save((state,)).expect("Failed to save bridge state");
+}
+
+/// Upgrades may pass new install arguments, replacing the config and the admins; the argument is
+/// decoded by hand so upgrades that send none keep both
+#[post_upgrade]
+fn post_upgrade() {
+    let (state,): (BridgeState,) = ic_cdk::storage::stable_restore().expect("Failed to restore bridge state");
+    let bridge_state = storage::get_mut::<BridgeState>();
+    *bridge_state = state;
+    
+    let raw_args = ic_cdk::api::call::arg_data_raw();
+    if raw_args.is_empty() {
+        return;
+    }
+    let args = candid::decode_one::<Option<InitArgs>>(&raw_args)
+        .unwrap_or_else(|e| ic_cdk::trap(&format!("Invalid upgrade args: {}", e)));
+    if let Some(args) = args {
+        if let Err(e) = bridge_state.apply(args) {
+            ic_cdk::trap(&format!("Invalid upgrade args: {}", e));
+        }
+    }
+}
+
+/// Locks iUSD the caller approved the bridge to spend and mints it as wrapped iUSD to an
+/// Ethereum address. The deposit is kept even if the mint cannot be sent yet; `retry_deposit`
+/// sends it later
+#[update]
+async fn bridge_to_ethereum(recipient: String, amount: u128) -> Result<Deposit, String> {
+    let caller = ic_cdk::caller();
+    if caller == Principal::anonymous() {
+        return Err("Anonymous callers cannot bridge".to_string());
+    }
+    let state = storage::get::<BridgeState>();
+    let config = state.config()?.clone();
+    eth::parse_address(&recipient)?;
+    if amount < config.min_amount {
+        return Err(format!("At least {} iUSD units can be bridged", config.min_amount));
+    }
+    
+    let ledger_block = lock(&config, caller, amount).await?;
+    
+    let state = storage::get_mut::<BridgeState>();
+    let id = state.deposits.len() as u64;
+    state.deposits.push(Deposit {
+        id,
+        from: caller,
+        recipient,
+        amount,
+        ledger_block,
+        nonce: None,
+        tx_hash: None,
+        raw_tx: None,
+        fees: None,
+        replaced_tx_hashes: Vec::new(),
+        sent_at: None,
+        status: DepositStatus::Locked,
+        last_error: None,
+        created_at: ic_cdk::api::time(),
+    });
+    state.total_locked += amount;
+    
+    process_deposit(id).await
+}
+
+/// Signs or resends a deposit's mint transaction, marks it minted once it is mined, and replaces
+/// it at higher fees if it has been pending too long; callable by anyone
+#[update]
+async fn retry_deposit(id: u64) -> Result<Deposit, String> {
+    let state = storage::get::<BridgeState>();
+    if state.deposits.get(id as usize).is_none() {
+        return Err("Deposit not found".to_string());
+    }
+    process_deposit(id).await
+}
+
+/// Releases the iUSD of every `BridgeToIc` burn in an Ethereum transaction to the recipients
+/// it names, once the transaction has `min_confirmations`; callable by anyone
+#[update]
+async fn claim_from_ethereum(tx_hash: String) -> Result<Vec<Withdrawal>, String> {
+    let state = storage::get::<BridgeState>();
+    let config = state.config()?.clone();
+    let tx_hash = tx_hash.to_lowercase();
+    
+    // Every provider must return the same receipt, so a single faulty or compromised one cannot
+    // make up a burn
+    let receipt = config.rpc.transaction_receipt(&tx_hash).await?
+        .ok_or("Transaction is pending or unknown")?;
+    if !succeeded(&receipt)? {
+        return Err("Transaction reverted".to_string());
+    }
+    let block = nat_to_u64(&receipt.block_number)?;
+    let head = config.rpc.block_number().await?;
+    let confirmations = (head + 1).saturating_sub(block);
+    if confirmations < config.min_confirmations {
+        return Err(format!("{} of {} confirmations", confirmations, config.min_confirmations));
+    }
+    
+    let mut withdrawals = Vec::new();
+    for (log_index, from, to, amount) in bridge_logs(&config, &receipt)? {
+        // Claimed before the release call, so a concurrent claim of the same burn is skipped
+        let key = (tx_hash.clone(), log_index);
+        if !storage::get_mut::<BridgeState>().claimed.insert(key.clone()) {
+            continue;
+        }
+        
+        let ledger_block = match release(&config, to.clone(), amount).await {
+            Ok(block) => block,
+            Err(e) => {
+                storage::get_mut::<BridgeState>().claimed.remove(&key);
+                return Err(e);
+            }
+        };
+        
+        let state = storage::get_mut::<BridgeState>();
+        let withdrawal = Withdrawal {
+            tx_hash: tx_hash.clone(),
+            log_index,
+            from,
+            to,
+            amount,
+            ledger_block,
+            timestamp: ic_cdk::api::time(),
+        };
+        state.total_locked = state.total_locked.saturating_sub(amount);
+        state.withdrawals.push(withdrawal.clone());
+        withdrawals.push(withdrawal);
+    }
+    Ok(withdrawals)
+}
+
+/// Ethereum address wrapped iUSD is minted from; the contract must grant it the minter role
+#[update]
+async fn get_bridge_address() -> Result<String, String> {
+    let state = storage::get::<BridgeState>();
+    let config = state.config()?.clone();
+    let public_key = public_key(&config).await?;
+    Ok(eth::format_address(&eth::address_of(&public_key)?))
+}
+
+#[query]
+fn get_deposit(id: u64) -> Option<Deposit> {
+    let state = storage::get::<BridgeState>();
+    state.deposits.get(id as usize).cloned()
+}
+
+#[query]
+fn get_deposits_of(from: Principal) -> Vec<Deposit> {
+    let state = storage::get::<BridgeState>();
+    state.deposits.iter().filter(|deposit| deposit.from == from).cloned().collect()
+}
+
+#[query]
+fn get_withdrawals_of(owner: Principal) -> Vec<Withdrawal> {
+    let state = storage::get::<BridgeState>();
+    state.withdrawals.iter().filter(|withdrawal| withdrawal.to.owner == owner).cloned().collect()
+}
+
+#[query]
+fn get_bridge_status() -> BridgeStatus {
+    let state = storage::get::<BridgeState>();
+    BridgeStatus {
+        config: state.config.clone(),
+        address: state.public_key
+            .as_ref()
+            .and_then(|public_key| eth::address_of(public_key).ok())
+            .map(|address| eth::format_address(&address)),
+        next_nonce: state.next_nonce,
+        total_locked: state.total_locked,
+        deposits: state.deposits.len() as u64,
+        withdrawals: state.withdrawals.len() as u64,
+    }
+}
+
+#[update]
+fn set_bridge_config(config: BridgeConfig) -> Result<(), String> {
+    require_admin()?;
+    validate_config(&config)?;
+    
+    storage::get_mut::<BridgeState>().set_config(config);
+    Ok(())
+}
+
+#[update]
+fn add_bridge_admin(admin: Principal) -> Result<(), String> {
+    require_admin()?;
+    
+    let state = storage::get_mut::<BridgeState>();
+    state.admins.get_or_insert_with(BTreeSet::new).insert(admin);
+    Ok(())
+}
+
+/// Removes an admin; the last one cannot be removed, so the bridge always stays administrable
+#[update]
+fn remove_bridge_admin(admin: Principal) -> Result<(), String> {
+    require_admin()?;
+    
+    let admins = storage::get_mut::<BridgeState>().admins.get_or_insert_with(BTreeSet::new);
+    if admins.len() == 1 && admins.contains(&admin) {
+        return Err("Cannot remove the last admin".to_string());
+    }
+    admins.remove(&admin);
+    Ok(())
+}
+
+#[query]
+fn get_bridge_admins() -> Vec<Principal> {
+    let state = storage::get::<BridgeState>();
+    state.admins.iter().flatten().copied().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm_rpc::{LogEntry, RpcServices};
+    use candid::Nat;
+    
+    const CONTRACT: &str = "0xabababababababababababababababababababab";
+    
+    fn config() -> BridgeConfig {
+        BridgeConfig {
+            iusd_ledger: Principal::anonymous(),
+            erc20_contract: CONTRACT.to_string(),
+            chain_id: 1,
+            rpc: RpcConfig {
+                evm_rpc_canister: Principal::anonymous(),
+                services: RpcServices::EthMainnet(None),
+                max_response_bytes: 10_000,
+            },
+            key_name: "key_1".to_string(),
+            gas_limit: 100_000,
+            max_fee_per_gas: 100_000_000_000,
+            max_priority_fee_per_gas: 2_000_000_000,
+            fee_cap_per_gas: 150_000_000_000,
+            min_confirmations: 12,
+            min_amount: 1,
+        }
+    }
+    
+    /// A `BridgeToIc` burn of `amount` by 0x3333…33 to a principal of ten 7s
+    fn burn_log(log_index: u64, amount: u128) -> LogEntry {
+        let burner = format!("0x{}{}", "00".repeat(12), "33".repeat(20));
+        LogEntry {
+            address: CONTRACT.to_uppercase().replacen("0X", "0x", 1),
+            topics: vec![format!("0x{}", hex::encode(eth::bridge_to_ic_topic())), burner],
+            data: format!("0x{}", hex::encode(eth::tests::bridge_to_ic_data(&[7; 10], [0; 32], amount))),
+            log_index: Some(Nat::from(log_index)),
+            removed: false,
+        }
+    }
+    
+    fn receipt(logs: Vec<LogEntry>) -> TransactionReceipt {
+        TransactionReceipt {
+            block_number: Nat::from(100u64),
+            status: Some(Nat::from(1u64)),
+            logs,
+        }
+    }
+    
+    #[test]
+    fn reads_burns_of_the_contract() {
+        let burns = bridge_logs(&config(), &receipt(vec![burn_log(3, 5_000), burn_log(4, 6_000)])).unwrap();
+        assert_eq!(burns.len(), 2);
+        
+        let (log_index, from, to, amount) = &burns[0];
+        assert_eq!(*log_index, 3);
+        assert_eq!(from, &format!("0x{}", "33".repeat(20)));
+        assert_eq!(to.owner, Principal::from_slice(&[7; 10]));
+        assert_eq!(to.subaccount, None);
+        assert_eq!(*amount, 5_000);
+        assert_eq!(burns[1].3, 6_000);
+    }
+    
+    #[test]
+    fn skips_logs_of_other_contracts() {
+        let mut foreign = burn_log(1, 5_000);
+        foreign.address = format!("0x{}", "cd".repeat(20));
+        let burns = bridge_logs(&config(), &receipt(vec![foreign, burn_log(2, 6_000)])).unwrap();
+        assert_eq!(burns.iter().map(|burn| burn.0).collect::<Vec<_>>(), vec![2]);
+    }
+    
+    #[test]
+    fn skips_removed_logs() {
+        let mut removed = burn_log(1, 5_000);
+        removed.removed = true;
+        assert!(bridge_logs(&config(), &receipt(vec![removed])).unwrap().is_empty());
+    }
+    
+    #[test]
+    fn skips_logs_of_other_events() {
+        let mut transfer = burn_log(1, 5_000);
+        transfer.topics[0] = format!("0x{}", hex::encode(eth::keccak256(b"Transfer(address,address,uint256)")));
+        let mut untopiced = burn_log(2, 5_000);
+        untopiced.topics.clear();
+        assert!(bridge_logs(&config(), &receipt(vec![transfer, untopiced])).unwrap().is_empty());
+    }
+    
+    #[test]
+    fn rejects_burns_with_a_malformed_sender() {
+        let mut short_sender = burn_log(1, 5_000);
+        short_sender.topics[1] = format!("0x{}", "33".repeat(20));
+        assert_eq!(
+            bridge_logs(&config(), &receipt(vec![short_sender])).unwrap_err(),
+            "BridgeToIc sender must be one word"
+        );
+        
+        let mut no_sender = burn_log(1, 5_000);
+        no_sender.topics.truncate(1);
+        assert_eq!(
+            bridge_logs(&config(), &receipt(vec![no_sender])).unwrap_err(),
+            "BridgeToIc log has no sender"
+        );
+    }
+    
+    #[test]
+    fn receipts_succeed_only_with_status_one() {
+        let mut mined = receipt(Vec::new());
+        assert_eq!(succeeded(&mined), Ok(true));
+        mined.status = Some(Nat::from(0u64));
+        assert_eq!(succeeded(&mined), Ok(false));
+        mined.status = None;
+        assert!(succeeded(&mined).is_err());
+    }
+    
+    #[test]
+    fn bumped_fees_rise_by_the_bump_until_the_cap() {
+        let config = config();
+        let fees = GasFees {
+            max_fee_per_gas: config.max_fee_per_gas,
+            max_priority_fee_per_gas: config.max_priority_fee_per_gas,
+        };
+        
+        let bumped = fees.bumped(config.fee_cap_per_gas).unwrap();
+        assert_eq!(bumped.max_fee_per_gas, 120_000_000_001);
+        assert_eq!(bumped.max_priority_fee_per_gas, 2_400_000_001);
+        
+        // 144 gwei is under the 150 gwei cap; the next raise would pass it
+        let bumped = bumped.bumped(config.fee_cap_per_gas).unwrap();
+        assert!(bumped.max_fee_per_gas <= config.fee_cap_per_gas);
+        assert!(bumped.bumped(config.fee_cap_per_gas).is_none());
+    }
+    
+    #[test]
+    fn bumped_priority_fees_stay_under_the_max_fee() {
+        let fees = GasFees {
+            max_fee_per_gas: 10,
+            max_priority_fee_per_gas: 10,
+        };
+        let bumped = fees.bumped(100).unwrap();
+        assert_eq!(bumped.max_fee_per_gas, 13);
+        assert_eq!(bumped.max_priority_fee_per_gas, 13);
+    }
+}
@@ -0,0 +1,184 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::*;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Roles admin endpoints require; Admin also passes every other role's checks
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Protocol parameters and role assignment
+    Admin,
+    /// Pausing asset prices in an emergency
+    Guardian,
+    /// Price sources, aggregation and circuit breaker settings
+    OracleManager,
+    /// Protocol funds and fees (no oracle endpoint requires it yet)
+    Treasurer,
+}
+
+/// An admin nomination waiting for the candidate to accept, so a mistyped principal can't take
+/// the role
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PendingAdmin {
+    candidate: Principal,
+    proposed_by: Principal,
+    proposed_at: u64,
+    /// Admin whose role moves to the candidate on acceptance; None adds an admin
+    replaces: Option<Principal>,
+}
+
+/// Principals and the roles granted to them
+#[derive(CandidType, Deserialize, Default)]
+pub struct AccessControl {
+    members: BTreeMap<Principal, BTreeSet<Role>>,
+    /// Admin nominations by candidate
+    pending_admins: BTreeMap<Principal, PendingAdmin>,
+}
+
+impl AccessControl {
+    pub fn has_role(&self, principal: &Principal, role: Role) -> bool {
+        // The canister holds every role, so calls it makes to itself keep passing
+        if *principal == ic_cdk::id() {
+            return true;
+        }
+        self.members
+            .get(principal)
+            .is_some_and(|roles| roles.contains(&role) || roles.contains(&Role::Admin))
+    }
+    
+    pub fn grant(&mut self, principal: Principal, role: Role) {
+        self.members.entry(principal).or_default().insert(role);
+    }
+    
+    /// Revokes a role; the last admin cannot be removed, so roles can always be reassigned
+    pub fn revoke(&mut self, principal: Principal, role: Role) -> Result<(), String> {
+        let admins = self.members_of(Role::Admin);
+        if role == Role::Admin && admins == vec![principal] {
+            return Err("Cannot revoke the last admin".to_string());
+        }
+        
+        if let Some(roles) = self.members.get_mut(&principal) {
+            roles.remove(&role);
+            if roles.is_empty() {
+                self.members.remove(&principal);
+            }
+        }
+        Ok(())
+    }
+    
+    pub fn roles_of(&self, principal: &Principal) -> Vec<Role> {
+        self.members
+            .get(principal)
+            .map(|roles| roles.iter().copied().collect())
+            .unwrap_or_default()
+    }
+    
+    pub fn members_of(&self, role: Role) -> Vec<Principal> {
+        self.members
+            .iter()
+            .filter(|(_, roles)| roles.contains(&role))
+            .map(|(principal, _)| *principal)
+            .collect()
+    }
+    
+    /// Nominates an admin, replacing any earlier nomination of the same candidate
+    pub fn propose_admin(&mut self, candidate: Principal, replaces: Option<Principal>, caller: Principal, now: u64) {
+        self.pending_admins.insert(candidate, PendingAdmin {
+            candidate,
+            proposed_by: caller,
+            proposed_at: now,
+            replaces,
+        });
+    }
+    
+    /// Grants Admin to a nominated caller, revoking it from the admin being replaced
+    pub fn accept_admin(&mut self, caller: Principal) -> Result<(), String> {
+        let pending = self.pending_admins
+            .remove(&caller)
+            .ok_or("No admin nomination for caller")?;
+        self.grant(caller, Role::Admin);
+        if let Some(replaced) = pending.replaces.filter(|replaced| *replaced != caller) {
+            self.revoke(replaced, Role::Admin)?;
+        }
+        Ok(())
+    }
+    
+    pub fn cancel_admin(&mut self, candidate: &Principal) -> Result<(), String> {
+        self.pending_admins
+            .remove(candidate)
+            .map(|_| ())
+            .ok_or_else(|| "No admin nomination for candidate".to_string())
+    }
+}
+
+/// Fails unless the caller holds `role` (or Admin)
+pub(crate) fn require_role(role: Role) -> Result<(), String> {
+    let access_control = crate::storage::get::<AccessControl>();
+    if !access_control.has_role(&ic_cdk::caller(), role) {
+        return Err(format!("Unauthorized: requires the {:?} role", role));
+    }
+    Ok(())
+}
+
+// Canister endpoints
+#[update]
+fn grant_role(principal: Principal, role: Role) -> Result<(), String> {
+    require_role(Role::Admin)?;
+    if role == Role::Admin {
+        return Err("Admins must be nominated with propose_admin and accept the role".to_string());
+    }
+    
+    let access_control = crate::storage::get_mut::<AccessControl>();
+    access_control.grant(principal, role);
+    Ok(())
+}
+
+#[update]
+fn revoke_role(principal: Principal, role: Role) -> Result<(), String> {
+    require_role(Role::Admin)?;
+    
+    let access_control = crate::storage::get_mut::<AccessControl>();
+    access_control.revoke(principal, role)
+}
+
+/// Nominates a new admin, who takes the role once they call `accept_admin`. With `replace_self`
+/// the caller's own Admin role moves to them, handing the role over
+#[update]
+fn propose_admin(candidate: Principal, replace_self: bool) -> Result<(), String> {
+    require_role(Role::Admin)?;
+    
+    let caller = ic_cdk::caller();
+    let access_control = crate::storage::get_mut::<AccessControl>();
+    let replaces = Some(caller).filter(|_| replace_self);
+    access_control.propose_admin(candidate, replaces, caller, ic_cdk::api::time() / 1_000_000_000);
+    Ok(())
+}
+
+/// Accepts the caller's admin nomination
+#[update]
+fn accept_admin() -> Result<(), String> {
+    let access_control = crate::storage::get_mut::<AccessControl>();
+    access_control.accept_admin(ic_cdk::caller())
+}
+
+#[update]
+fn cancel_admin_proposal(candidate: Principal) -> Result<(), String> {
+    require_role(Role::Admin)?;
+    
+    let access_control = crate::storage::get_mut::<AccessControl>();
+    access_control.cancel_admin(&candidate)
+}
+
+#[query]
+fn get_pending_admins() -> Vec<PendingAdmin> {
+    crate::storage::get::<AccessControl>().pending_admins.values().cloned().collect()
+}
+
+#[query]
+fn get_roles(principal: Principal) -> Vec<Role> {
+    crate::storage::get::<AccessControl>().roles_of(&principal)
+}
+
+#[query]
+fn get_role_members(role: Role) -> Vec<Principal> {
+    crate::storage::get::<AccessControl>().members_of(role)
+}
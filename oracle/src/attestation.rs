@@ -0,0 +1,183 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::call::CallResult;
+use ic_cdk::api::management_canister::ecdsa::{
+    ecdsa_public_key, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgument, SignWithEcdsaArgument,
+    SignWithEcdsaResponse,
+};
+use ic_cdk_macros::*;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use crate::access_control::{require_role, Role};
+use crate::price_feed::AggregatedPrice;
+
+/// Domain separator prefixed to every attested message
+const ATTESTATION_DOMAIN: &[u8] = b"iUSD price attestation v1";
+/// Cycles attached to each signing request; the unused portion is refunded
+const SIGN_WITH_ECDSA_CYCLES: u128 = 30_000_000_000;
+
+/// Settings for signing aggregated prices
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AttestationConfig {
+    /// Whether accepted prices are signed
+    enabled: bool,
+    /// Threshold ECDSA key, e.g. "key_1" on mainnet or "dfx_test_key" locally
+    key_name: String,
+    /// Minimum seconds between signatures for one asset, since each costs cycles
+    min_interval_seconds: u64,
+}
+
+impl Default for AttestationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            key_name: "key_1".to_string(),
+            min_interval_seconds: 300,
+        }
+    }
+}
+
+/// A signed aggregated price
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PriceAttestation {
+    asset: String,
+    /// Price mantissa
+    price: u128,
+    decimals: u32,
+    /// Timestamp of the aggregation (seconds)
+    timestamp: u64,
+    /// SHA-256 of the domain separator, then the length-prefixed asset and the big-endian
+    /// price, decimals and timestamp
+    message_hash: Vec<u8>,
+    /// secp256k1 signature over `message_hash`, 64 bytes `r || s`
+    signature: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Default)]
+pub struct PriceAttestor {
+    config: AttestationConfig,
+    /// SEC1-compressed public key of the signing key, fetched on first use
+    public_key: Option<Vec<u8>>,
+    latest: HashMap<String, PriceAttestation>,
+    last_error: Option<String>,
+}
+
+impl PriceAttestor {
+    fn key_id(&self) -> EcdsaKeyId {
+        EcdsaKeyId {
+            curve: EcdsaCurve::Secp256k1,
+            name: self.config.key_name.clone(),
+        }
+    }
+    
+    /// Signs an accepted price unless attestations are off or the asset was signed recently
+    pub async fn attest(&mut self, asset: &str, price: &AggregatedPrice) {
+        if !self.config.enabled {
+            return;
+        }
+        let recent = self.latest.get(asset).is_some_and(|latest| {
+            price.timestamp < latest.timestamp + self.config.min_interval_seconds
+        });
+        if recent {
+            return;
+        }
+        
+        match self.sign(asset, price).await {
+            Ok(attestation) => {
+                self.latest.insert(asset.to_string(), attestation);
+                self.last_error = None;
+            }
+            Err(e) => self.last_error = Some(format!("{}: {}", asset, e)),
+        }
+    }
+    
+    async fn sign(&mut self, asset: &str, price: &AggregatedPrice) -> Result<PriceAttestation, String> {
+        if self.public_key.is_none() {
+            let (response,) = ecdsa_public_key(EcdsaPublicKeyArgument {
+                canister_id: None,
+                derivation_path: derivation_path(),
+                key_id: self.key_id(),
+            })
+            .await
+            .map_err(|(code, msg)| format!("Failed to fetch attestation key: {:?} - {}", code, msg))?;
+            self.public_key = Some(response.public_key);
+        }
+        
+        let message_hash = attestation_hash(asset, price.price, price.decimals, price.timestamp);
+        let call_result: CallResult<(SignWithEcdsaResponse,)> = ic_cdk::api::call::call_with_payment128(
+            Principal::management_canister(),
+            "sign_with_ecdsa",
+            (SignWithEcdsaArgument {
+                message_hash: message_hash.clone(),
+                derivation_path: derivation_path(),
+                key_id: self.key_id(),
+            },),
+            SIGN_WITH_ECDSA_CYCLES,
+        )
+        .await;
+        let (response,) = call_result
+            .map_err(|(code, msg)| format!("Failed to sign price: {:?} - {}", code, msg))?;
+            
+        Ok(PriceAttestation {
+            asset: asset.to_string(),
+            price: price.price,
+            decimals: price.decimals,
+            timestamp: price.timestamp,
+            message_hash,
+            signature: response.signature,
+        })
+    }
+}
+
+fn derivation_path() -> Vec<Vec<u8>> {
+    vec![b"price-attestation".to_vec()]
+}
+
+/// Hash signed for a price, reproducible by any verifier from the attested fields
+fn attestation_hash(asset: &str, price: u128, decimals: u32, timestamp: u64) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(ATTESTATION_DOMAIN);
+    hasher.update([asset.len() as u8]);
+    hasher.update(asset.as_bytes());
+    hasher.update(price.to_be_bytes());
+    hasher.update(decimals.to_be_bytes());
+    hasher.update(timestamp.to_be_bytes());
+    hasher.finalize().to_vec()
+}
+
+// Canister endpoints
+#[query]
+fn get_price_attestation(asset: String) -> Option<PriceAttestation> {
+    let attestor = crate::storage::get::<PriceAttestor>();
+    attestor.latest.get(&asset).cloned()
+}
+
+/// Public key to verify attestations with, once the first one has been signed
+#[query]
+fn get_attestation_public_key() -> Option<Vec<u8>> {
+    let attestor = crate::storage::get::<PriceAttestor>();
+    attestor.public_key.clone()
+}
+
+#[query]
+fn get_attestation_status() -> (AttestationConfig, Option<String>) {
+    let attestor = crate::storage::get::<PriceAttestor>();
+    (attestor.config.clone(), attestor.last_error.clone())
+}
+
+#[update]
+fn set_attestation_config(config: AttestationConfig) -> Result<(), String> {
+    require_role(Role::OracleManager)?;
+    
+    if config.key_name.is_empty() {
+        return Err("Key name must not be empty".to_string());
+    }
+    
+    let attestor = crate::storage::get_mut::<PriceAttestor>();
+    // A different key signs with a different public key
+    if config.key_name != attestor.config.key_name {
+        attestor.public_key = None;
+        attestor.latest.clear();
+    }
+    attestor.config = config;
+    Ok(())
+}
@@ -0,0 +1,44 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::*;
+mod access_control;
+mod attestation;
+mod price_alerts;
+mod price_feed;
+mod sources;
+mod stable_history;
+mod stable_state;
+mod storage;
+mod subscriptions;
+
+/// Installation arguments
+#[derive(CandidType, Deserialize)]
+pub struct InitArgs {
+    /// Principals granted the Admin role, which can then grant the other roles
+    admins: Vec<Principal>,
+}
+
+#[init]
+fn init(args: InitArgs) {
+    let access_control = storage::get_mut::<access_control::AccessControl>();
+    for admin in args.admins {
+        access_control.grant(admin, access_control::Role::Admin);
+    }
+    
+    // Keep the price cache warm so consumers read prices instead of fetching them
+    let price_cache = storage::get_mut::<price_feed::PriceCache>();
+    price_cache.schedule();
+}
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    stable_state::save();
+}
+
+/// Roles, sources, subscribers and cached prices carry over; the refresh timer does not survive
+/// the upgrade, so it is started again
+#[post_upgrade]
+fn post_upgrade() {
+    stable_state::restore();
+    let price_cache = storage::get_mut::<price_feed::PriceCache>();
+    price_cache.schedule();
+}
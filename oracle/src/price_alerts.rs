@@ -0,0 +1,210 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::*;
+use std::collections::{BTreeMap, VecDeque};
+use crate::price_feed::SUPPORTED_ASSETS;
+
+/// Alerts a single principal may hold at once
+const MAX_ALERTS_PER_OWNER: usize = 20;
+/// Triggered events kept for cursor reads; older ones are dropped
+const MAX_ALERT_EVENTS: usize = 1000;
+/// Largest page returned by `get_price_alert_events`
+const MAX_EVENT_PAGE: usize = 100;
+
+/// Side of the threshold that triggers an alert
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum AlertDirection {
+    /// Triggers when the price falls below the threshold
+    Below,
+    /// Triggers when the price rises above the threshold
+    Above,
+}
+
+/// Canister method called with an `AlertEvent` when an alert triggers
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AlertCallback {
+    canister: Principal,
+    method: String,
+}
+
+/// A registered price threshold
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PriceAlert {
+    id: u64,
+    owner: Principal,
+    asset: String,
+    /// Threshold price, fixed-point with `PRICE_DECIMALS` decimals
+    threshold: u128,
+    direction: AlertDirection,
+    /// Optional one-way notification; events are queued either way
+    callback: Option<AlertCallback>,
+    /// Cleared when the alert triggers and set again once the price is back on the other side,
+    /// so an alert fires once per crossing
+    armed: bool,
+}
+
+/// A threshold crossing, readable by cursor
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AlertEvent {
+    /// Position in the event log, usable as a cursor
+    index: u64,
+    alert_id: u64,
+    owner: Principal,
+    asset: String,
+    threshold: u128,
+    direction: AlertDirection,
+    /// Price that crossed the threshold
+    price: u128,
+    timestamp: u64,
+}
+
+/// A page of events and the cursor to pass for the next one
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AlertEventPage {
+    events: Vec<AlertEvent>,
+    next_cursor: u64,
+}
+
+#[derive(CandidType, Deserialize, Default)]
+pub struct PriceAlerts {
+    alerts: BTreeMap<u64, PriceAlert>,
+    next_alert_id: u64,
+    events: VecDeque<AlertEvent>,
+    next_event_index: u64,
+}
+
+impl PriceAlerts {
+    pub fn register(
+        &mut self,
+        owner: Principal,
+        asset: String,
+        threshold: u128,
+        direction: AlertDirection,
+        callback: Option<AlertCallback>,
+    ) -> Result<u64, String> {
+        if !SUPPORTED_ASSETS.contains(&asset.as_str()) {
+            return Err(format!("Unsupported asset: {}", asset));
+        }
+        if threshold == 0 {
+            return Err("Alert threshold must be positive".to_string());
+        }
+        if self.alerts.values().filter(|alert| alert.owner == owner).count() >= MAX_ALERTS_PER_OWNER {
+            return Err(format!("At most {} alerts per principal", MAX_ALERTS_PER_OWNER));
+        }
+        
+        let id = self.next_alert_id;
+        self.next_alert_id += 1;
+        self.alerts.insert(id, PriceAlert {
+            id,
+            owner,
+            asset,
+            threshold,
+            direction,
+            callback,
+            armed: true,
+        });
+        Ok(id)
+    }
+    
+    pub fn cancel(&mut self, owner: Principal, alert_id: u64) -> Result<(), String> {
+        match self.alerts.get(&alert_id) {
+            Some(alert) if alert.owner == owner => {
+                self.alerts.remove(&alert_id);
+                Ok(())
+            }
+            _ => Err("Alert not found".to_string()),
+        }
+    }
+    
+    /// Checks an asset's alerts against a newly accepted price, queueing and notifying crossings
+    pub fn on_price_update(&mut self, asset: &str, price: u128, timestamp: u64) {
+        let mut triggered = Vec::new();
+        for alert in self.alerts.values_mut().filter(|alert| alert.asset == asset) {
+            let crossed = match alert.direction {
+                AlertDirection::Below => price < alert.threshold,
+                AlertDirection::Above => price > alert.threshold,
+            };
+            if crossed && alert.armed {
+                triggered.push(alert.clone());
+            }
+            alert.armed = !crossed;
+        }
+        
+        for alert in triggered {
+            let event = AlertEvent {
+                index: self.next_event_index,
+                alert_id: alert.id,
+                owner: alert.owner,
+                asset: alert.asset,
+                threshold: alert.threshold,
+                direction: alert.direction,
+                price,
+                timestamp,
+            };
+            self.next_event_index += 1;
+            
+            // One-way call, so a slow or failing subscriber cannot hold up the refresh
+            if let Some(callback) = alert.callback {
+                let _ = ic_cdk::api::call::notify(callback.canister, &callback.method, (event.clone(),));
+            }
+            
+            self.events.push_back(event);
+            if self.events.len() > MAX_ALERT_EVENTS {
+                self.events.pop_front();
+            }
+        }
+    }
+    
+    /// An owner's events at or after `cursor`
+    pub fn events_since(&self, owner: Principal, cursor: u64, limit: usize) -> AlertEventPage {
+        let limit = limit.min(MAX_EVENT_PAGE);
+        let events: Vec<AlertEvent> = self.events
+            .iter()
+            .filter(|event| event.index >= cursor && event.owner == owner)
+            .take(limit)
+            .cloned()
+            .collect();
+            
+        // A short page means the whole log was scanned, so the cursor can skip to its end
+        let next_cursor = match events.last() {
+            Some(last) if events.len() == limit => last.index + 1,
+            None if limit == 0 => cursor,
+            _ => cursor.max(self.next_event_index),
+        };
+        AlertEventPage { events, next_cursor }
+    }
+}
+
+// Canister endpoints
+#[update]
+fn register_price_alert(
+    asset: String,
+    threshold: u128,
+    direction: AlertDirection,
+    callback: Option<AlertCallback>,
+) -> Result<u64, String> {
+    let alerts = crate::storage::get_mut::<PriceAlerts>();
+    alerts.register(ic_cdk::caller(), asset, threshold, direction, callback)
+}
+
+#[update]
+fn cancel_price_alert(alert_id: u64) -> Result<(), String> {
+    let alerts = crate::storage::get_mut::<PriceAlerts>();
+    alerts.cancel(ic_cdk::caller(), alert_id)
+}
+
+#[query]
+fn list_price_alerts() -> Vec<PriceAlert> {
+    let caller = ic_cdk::caller();
+    let alerts = crate::storage::get::<PriceAlerts>();
+    alerts.alerts
+        .values()
+        .filter(|alert| alert.owner == caller)
+        .cloned()
+        .collect()
+}
+
+#[query]
+fn get_price_alert_events(cursor: u64, limit: u64) -> AlertEventPage {
+    let alerts = crate::storage::get::<PriceAlerts>();
+    alerts.events_since(ic_cdk::caller(), cursor, limit as usize)
+}
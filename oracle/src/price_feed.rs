@@ -0,0 +1,2188 @@
+use candid::{CandidType, Deserialize, Principal};
+use futures::future::join_all;
+use ic_cdk::api::management_canister::http_request::{HttpResponse, TransformArgs};
+use ic_cdk_macros::*;
+use ic_cdk_timers::TimerId;
+use k256::ecdsa::signature::Verifier;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use crate::access_control::{require_role, Role};
+use crate::attestation::PriceAttestor;
+use crate::price_alerts::PriceAlerts;
+use crate::sources::{self, IcTransport, OutcallRequest, PriceSource, Transport, WormholeGuardianSet};
+use crate::stable_history::{self, SampleRing};
+use crate::subscriptions::Subscribers;
+use std::time::Duration;
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct PriceData {
+    /// Price in USD, fixed-point with `PRICE_DECIMALS` decimals
+    pub(crate) price: u128,
+    /// Timestamp of the price
+    pub(crate) timestamp: u64,
+    /// Source of the price
+    source: String,
+    /// Weight of the source in the median
+    weight: u32,
+}
+
+/// How much a source counted towards an aggregated price
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct SourceContribution {
+    /// Source name
+    source: String,
+    /// Price reported by the source
+    price: u128,
+    /// Weight of the source
+    weight: u32,
+    /// Share of the total weight (basis points)
+    weight_share: u32,
+    /// Deviation from the aggregated price (percentage)
+    deviation: f64,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct AggregatedPrice {
+    /// Final aggregated price, as an integer mantissa
+    pub(crate) price: u128,
+    /// Decimals of the mantissa (a price of 1 USD is `10^decimals`)
+    pub(crate) decimals: u32,
+    /// Timestamp of the aggregation
+    pub(crate) timestamp: u64,
+    /// Number of fresh sources aggregated, never fewer than `quorum`
+    sources_used: u8,
+    /// Sources the tier required for this asset
+    quorum: u8,
+    /// Maximum deviation between sources (percentage)
+    max_deviation: f64,
+    /// Per-source breakdown of the prices that were aggregated
+    contributions: Vec<SourceContribution>,
+    /// Sources dropped as outliers before the median was taken
+    rejected_sources: Vec<String>,
+    /// Oracle tier the price came from
+    tier: OracleTier,
+    /// Lowest source price in the aggregation
+    pub(crate) min_price: u128,
+    /// Highest source price in the aggregation
+    pub(crate) max_price: u128,
+    /// Standard deviation of the source prices
+    pub(crate) std_deviation: u128,
+}
+
+/// Levels of an asset's oracle fallback hierarchy
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+pub enum OracleTier {
+    /// The Exchange Rate Canister
+    Xrc,
+    /// Weighted median of the enabled HTTPS exchange sources
+    ExchangeMedian,
+    /// The last accepted price, served until it goes stale
+    LastGoodPrice,
+}
+
+/// Decimals of fixed-point prices (price in 1e-8 USD)
+pub const PRICE_DECIMALS: u32 = 8;
+
+const DEFAULT_MAX_PRICE_AGE_SECONDS: u64 = 300; // 5 minutes
+const DEFAULT_MAX_FALLBACK_AGE_SECONDS: u64 = 3600; // 1 hour
+const DEFAULT_MAX_DEVIATION_THRESHOLD: f64 = 0.05; // 5% maximum deviation allowed
+const DEFAULT_EMA_SMOOTHING_BPS: u32 = 2000; // each sample moves the EMA 20% of the way
+const DEFAULT_MIN_SOURCES: u8 = 2;
+/// Sources further than this many median absolute deviations from the median are dropped as outliers
+const OUTLIER_MAD_MULTIPLIER: u128 = 5;
+/// Distance from the median never treated as an outlier (basis points), so a tight cluster with a
+/// near-zero MAD doesn't reject honest ticks
+const MIN_OUTLIER_DISTANCE_BPS: u128 = 50;
+
+/// Smoothed price the vault logic uses for LTV and liquidation checks
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+pub enum PriceSmoothing {
+    /// Time-weighted average over the TWAP window
+    Twap,
+    /// Exponentially-weighted moving average over accepted prices
+    Ema,
+}
+
+/// Per-asset oracle tolerances
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct AssetOracleConfig {
+    /// Oldest source or cached price accepted (seconds)
+    pub(crate) max_price_age_seconds: u64,
+    /// Oldest last good price served while every live tier is failing (seconds)
+    max_fallback_age_seconds: u64,
+    /// Largest deviation of any source from the median (e.g. 0.05 = 5%)
+    max_deviation: f64,
+    /// Fresh sources an exchange median needs, e.g. 3 for BTC with five sources configured
+    min_sources: u8,
+    /// Smoothing applied to the price the vaults see
+    smoothing: PriceSmoothing,
+    /// Weight of each new sample in the EMA (basis points)
+    ema_smoothing_bps: u32,
+}
+
+impl Default for AssetOracleConfig {
+    fn default() -> Self {
+        Self {
+            max_price_age_seconds: DEFAULT_MAX_PRICE_AGE_SECONDS,
+            max_fallback_age_seconds: DEFAULT_MAX_FALLBACK_AGE_SECONDS,
+            max_deviation: DEFAULT_MAX_DEVIATION_THRESHOLD,
+            min_sources: DEFAULT_MIN_SOURCES,
+            smoothing: PriceSmoothing::Twap,
+            ema_smoothing_bps: DEFAULT_EMA_SMOOTHING_BPS,
+        }
+    }
+}
+
+/// Assets kept in the price cache
+pub(crate) const SUPPORTED_ASSETS: [&str; 3] = ["ICP", "BTC", "ETH"];
+/// Default seconds between price cache refreshes
+const DEFAULT_REFRESH_INTERVAL_SECONDS: u64 = 60;
+
+/// Currency every source quotes in and the vaults are valued in
+pub(crate) const USD: &str = "USD";
+/// Currencies prices can be quoted in; non-USD quotes are converted through XRC forex rates
+pub(crate) const SUPPORTED_QUOTES: [&str; 3] = [USD, "EUR", "CHF"];
+/// Seconds between forex rate refreshes; XRC's forex rates move far less often than crypto prices
+const QUOTE_RATE_REFRESH_SECONDS: u64 = 3600;
+/// Oldest forex rate used to convert a cached price (seconds)
+const MAX_QUOTE_RATE_AGE_SECONDS: u64 = 24 * 3600;
+
+/// Oracle circuit breaker on abrupt price moves
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct CircuitBreakerConfig {
+    /// Largest move from the last accepted price accepted without confirmation (e.g. 0.1 = 10%)
+    max_price_move: f64,
+    /// Moves are only checked against prices accepted within this window (seconds)
+    window_seconds: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            max_price_move: 0.10,   // 10%
+            window_seconds: 600,    // 10 minutes
+        }
+    }
+}
+
+/// Default window for time-weighted average prices
+const DEFAULT_TWAP_WINDOW_SECONDS: u64 = 1800; // 30 minutes
+/// Upper bound on samples retained per asset
+const MAX_PRICE_SAMPLES: u64 = 1000;
+
+/// An aggregated price observation
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct PriceSample {
+    /// Aggregated price, fixed-point with `PRICE_DECIMALS` decimals
+    pub(crate) price: u128,
+    /// Timestamp of the aggregation (seconds)
+    pub(crate) timestamp: u64,
+}
+
+/// Cached price for an asset with staleness metadata
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct CachedPrice {
+    /// Aggregated price at the last successful refresh
+    price: AggregatedPrice,
+    /// When the entry was last refreshed (seconds)
+    last_updated: u64,
+    /// Whether the entry is older than the maximum price age, or the fallback age when degraded
+    is_stale: bool,
+    /// Whether the live tiers are failing and the last good price is being served
+    is_degraded: bool,
+    /// Error from the most recent refresh, if it failed
+    last_error: Option<String>,
+    /// Whether the guardian has paused the asset's price
+    is_paused: bool,
+    /// Abrupt move awaiting confirmation; liquidations for the asset are paused meanwhile
+    unconfirmed_price: Option<AggregatedPrice>,
+}
+
+/// What consumers of the oracle receive per asset, by subscription or from `get_price_update`
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct OraclePriceUpdate {
+    asset: String,
+    cached: CachedPrice,
+    /// Price under the asset's smoothing mode (TWAP or EMA), used for LTV checks
+    smoothed_price: Option<u128>,
+    /// Whether the circuit breaker is holding an abrupt move
+    liquidations_paused: bool,
+}
+
+/// Per-asset prices refreshed on a timer, so vault operations don't each trigger outcalls
+pub struct PriceCache {
+    /// Last successfully aggregated price per asset, with its refresh time (seconds)
+    entries: HashMap<String, (AggregatedPrice, u64)>,
+    /// Error from the most recent failed refresh per asset
+    last_errors: HashMap<String, String>,
+    /// Prices that tripped the circuit breaker, held until the next round confirms them
+    unconfirmed: HashMap<String, AggregatedPrice>,
+    /// USD value of each non-USD quote currency, with its refresh time (seconds)
+    quote_rates: HashMap<String, (u128, u64)>,
+    /// Circuit breaker thresholds
+    circuit_breaker: CircuitBreakerConfig,
+    /// Seconds between refreshes
+    refresh_interval_seconds: u64,
+    timer_id: Option<TimerId>,
+}
+
+impl Default for PriceCache {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+            last_errors: HashMap::new(),
+            unconfirmed: HashMap::new(),
+            quote_rates: HashMap::new(),
+            circuit_breaker: CircuitBreakerConfig::default(),
+            refresh_interval_seconds: DEFAULT_REFRESH_INTERVAL_SECONDS,
+            timer_id: None,
+        }
+    }
+}
+
+/// The price cache as saved across upgrades, without its timer
+#[derive(CandidType, Deserialize)]
+pub struct PriceCacheState {
+    entries: HashMap<String, (AggregatedPrice, u64)>,
+    last_errors: HashMap<String, String>,
+    unconfirmed: HashMap<String, AggregatedPrice>,
+    quote_rates: HashMap<String, (u128, u64)>,
+    circuit_breaker: CircuitBreakerConfig,
+    refresh_interval_seconds: u64,
+}
+
+impl PriceCache {
+    pub(crate) fn to_state(&self) -> PriceCacheState {
+        PriceCacheState {
+            entries: self.entries.clone(),
+            last_errors: self.last_errors.clone(),
+            unconfirmed: self.unconfirmed.clone(),
+            quote_rates: self.quote_rates.clone(),
+            circuit_breaker: self.circuit_breaker.clone(),
+            refresh_interval_seconds: self.refresh_interval_seconds,
+        }
+    }
+    
+    /// A cache holding saved entries, to be scheduled once installed
+    pub(crate) fn from_state(state: PriceCacheState) -> Self {
+        Self {
+            entries: state.entries,
+            last_errors: state.last_errors,
+            unconfirmed: state.unconfirmed,
+            quote_rates: state.quote_rates,
+            circuit_breaker: state.circuit_breaker,
+            refresh_interval_seconds: state.refresh_interval_seconds,
+            timer_id: None,
+        }
+    }
+    
+    /// Starts the recurring refresh, with an immediate first run
+    pub fn schedule(&mut self) {
+        if let Some(timer_id) = self.timer_id.take() {
+            ic_cdk_timers::clear_timer(timer_id);
+        }
+        
+        ic_cdk_timers::set_timer(Duration::ZERO, || {
+            ic_cdk::spawn(async {
+                let price_cache = crate::storage::get_mut::<PriceCache>();
+                price_cache.refresh().await;
+            })
+        });
+        
+        let interval = Duration::from_secs(self.refresh_interval_seconds);
+        self.timer_id = Some(ic_cdk_timers::set_timer_interval(interval, || {
+            ic_cdk::spawn(async {
+                let price_cache = crate::storage::get_mut::<PriceCache>();
+                price_cache.refresh().await;
+            })
+        }));
+    }
+    
+    /// Fetches every supported asset in one round with all their outcalls in flight together, and
+    /// returns each asset's outcome; a failed or unconfirmed fetch keeps the previous entry
+    pub async fn refresh(&mut self) -> HashMap<String, Result<AggregatedPrice, String>> {
+        let fetched = join_all(SUPPORTED_ASSETS.iter().map(|asset| fetch_prices(asset, USD))).await;
+        let now = ic_cdk::api::time() / 1_000_000_000;
+        let mut results = HashMap::new();
+        for (asset, fetched) in SUPPORTED_ASSETS.into_iter().zip(fetched) {
+            let result = match fetched {
+                Ok(price) => self.check_price_move(asset, price, now),
+                Err(e) => {
+                    self.fall_back(asset, now);
+                    Err(e)
+                }
+            };
+            
+            match &result {
+                Ok(price) => {
+                    self.accept(asset, price.clone(), now);
+                    let attestor = crate::storage::get_mut::<PriceAttestor>();
+                    attestor.attest(asset, price).await;
+                }
+                Err(e) => {
+                    self.last_errors.insert(asset.to_string(), e.clone());
+                }
+            }
+            
+            // Subscribers hear about every round, so they also learn of fallbacks and held prices
+            self.publish(asset, now);
+            results.insert(asset.to_string(), result);
+        }
+        
+        self.refresh_quote_rates().await;
+        results
+    }
+    
+    /// Refreshes forex rates older than the refresh period; a failed fetch keeps the previous rate
+    async fn refresh_quote_rates(&mut self) {
+        for quote in SUPPORTED_QUOTES.into_iter().filter(|quote| *quote != USD) {
+            let now = ic_cdk::api::time() / 1_000_000_000;
+            let due = self.quote_rates
+                .get(quote)
+                .is_none_or(|(_, refreshed)| now.saturating_sub(*refreshed) >= QUOTE_RATE_REFRESH_SECONDS);
+            if !due {
+                continue;
+            }
+            
+            if let Ok((rate, _)) = sources::fetch_fiat_rate(&IcTransport, quote).await {
+                self.quote_rates.insert(quote.to_string(), (rate, now));
+            }
+        }
+    }
+    
+    /// Sends an asset's current state to the subscribers
+    fn publish(&self, asset: &str, now: u64) {
+        if let Some(update) = self.price_update(asset, now) {
+            let subscribers = crate::storage::get::<Subscribers>();
+            subscribers.publish(&update);
+        }
+    }
+    
+    /// An asset's cached price with its smoothed price and liquidation status
+    pub fn price_update(&self, asset: &str, now: u64) -> Option<OraclePriceUpdate> {
+        let cached = self.get(asset, now)?;
+        let source_registry = crate::storage::get::<SourceRegistry>();
+        let price_history = crate::storage::get::<PriceHistory>();
+        Some(OraclePriceUpdate {
+            asset: asset.to_string(),
+            smoothed_price: price_history.smoothed(asset, now, &source_registry.asset_config(asset).smoothing),
+            liquidations_paused: self.is_tripped(asset),
+            cached,
+        })
+    }
+    
+    /// Caches an accepted price and records it in the history
+    fn accept(&mut self, asset: &str, price: AggregatedPrice, now: u64) {
+        let source_registry = crate::storage::get::<SourceRegistry>();
+        let config = source_registry.asset_config(asset);
+        let price_history = crate::storage::get_mut::<PriceHistory>();
+        price_history.record(asset, price.price, price.timestamp, config.ema_smoothing_bps);
+        
+        let price_alerts = crate::storage::get_mut::<PriceAlerts>();
+        price_alerts.on_price_update(asset, price.price, now);
+        
+        self.entries.insert(asset.to_string(), (price, now));
+        self.last_errors.remove(asset);
+    }
+    
+    /// Keeps serving the last accepted price, flagged as degraded, when the hierarchy allows it and
+    /// it is within the asset's fallback age; otherwise drops it
+    fn fall_back(&mut self, asset: &str, now: u64) {
+        let source_registry = crate::storage::get::<SourceRegistry>();
+        let max_age = source_registry.asset_config(asset).max_fallback_age_seconds;
+        let usable = source_registry.hierarchy(asset).contains(&OracleTier::LastGoodPrice)
+            && self.entries
+                .get(asset)
+                .is_some_and(|(_, last_updated)| now.saturating_sub(*last_updated) <= max_age);
+                
+        if usable {
+            if let Some((price, _)) = self.entries.get_mut(asset) {
+                price.tier = OracleTier::LastGoodPrice;
+            }
+        } else {
+            self.entries.remove(asset);
+        }
+    }
+    
+    /// Accepts a price unless it moved abruptly from the last accepted one, in which case it
+    /// is held until a subsequent round confirms it
+    fn check_price_move(&mut self, asset: &str, price: AggregatedPrice, now: u64) -> Result<AggregatedPrice, String> {
+        let max_move = self.circuit_breaker.max_price_move;
+        // After an upgrade the cache is empty, so the move is measured from the persisted history
+        let price_history = crate::storage::get::<PriceHistory>();
+        let last = self.entries
+            .get(asset)
+            .map(|(last, last_updated)| (last.price, *last_updated))
+            .or_else(|| price_history.latest(asset).map(|sample| (sample.price, sample.timestamp)));
+        let abrupt = match last {
+            Some((last_price, last_updated)) => {
+                now.saturating_sub(last_updated) <= self.circuit_breaker.window_seconds
+                    && price.price.abs_diff(last_price) as f64 / last_price as f64 > max_move
+            }
+            None => false,
+        };
+        if !abrupt {
+            self.unconfirmed.remove(asset);
+            return Ok(price);
+        }
+        
+        // A second consecutive round near the suspicious price confirms the move
+        match self.unconfirmed.remove(asset) {
+            Some(pending) if price.price.abs_diff(pending.price) as f64 / pending.price as f64 <= max_move => Ok(price),
+            _ => {
+                self.unconfirmed.insert(asset.to_string(), price);
+                Err("Abrupt price move awaiting confirmation".to_string())
+            }
+        }
+    }
+    
+    /// Whether the circuit breaker is holding an unconfirmed price for the asset
+    pub fn is_tripped(&self, asset: &str) -> bool {
+        self.unconfirmed.contains_key(asset)
+    }
+    
+    /// Returns the cached price for an asset with its staleness at `now`
+    pub fn get(&self, asset: &str, now: u64) -> Option<CachedPrice> {
+        let (price, last_updated) = self.entries.get(asset)?;
+        let source_registry = crate::storage::get::<SourceRegistry>();
+        let config = source_registry.asset_config(asset);
+        let is_degraded = price.tier == OracleTier::LastGoodPrice;
+        let max_age = if is_degraded {
+            config.max_fallback_age_seconds
+        } else {
+            config.max_price_age_seconds
+        };
+        
+        Some(CachedPrice {
+            price: price.clone(),
+            last_updated: *last_updated,
+            is_stale: now.saturating_sub(*last_updated) > max_age,
+            is_degraded,
+            last_error: self.last_errors.get(asset).cloned(),
+            is_paused: source_registry.paused_assets.contains(asset),
+            unconfirmed_price: self.unconfirmed.get(asset).cloned(),
+        })
+    }
+    
+    /// Returns the cached price for an asset converted to `quote` with the cached forex rate
+    pub fn get_in(&self, asset: &str, quote: &str, now: u64) -> Result<CachedPrice, String> {
+        let cached = self.get(asset, now).ok_or("No cached price for asset")?;
+        if quote == USD {
+            return Ok(cached);
+        }
+        
+        let (rate, refreshed) = self.quote_rates
+            .get(quote)
+            .ok_or_else(|| format!("No forex rate for {}", quote))?;
+        if now.saturating_sub(*refreshed) > MAX_QUOTE_RATE_AGE_SECONDS {
+            return Err(format!("Forex rate for {} is stale", quote));
+        }
+        Ok(CachedPrice {
+            price: convert_quote(&cached.price, *rate),
+            unconfirmed_price: cached.unconfirmed_price.as_ref().map(|price| convert_quote(price, *rate)),
+            ..cached
+        })
+    }
+}
+
+/// Rolling window of recent aggregated prices per asset
+pub struct PriceHistory {
+    /// Samples and EMA per asset, in stable memory so smoothing and the circuit breaker survive upgrades
+    rings: HashMap<String, SampleRing>,
+    /// Length of the TWAP window (seconds)
+    pub(crate) twap_window_seconds: u64,
+}
+
+impl Default for PriceHistory {
+    fn default() -> Self {
+        Self {
+            rings: SUPPORTED_ASSETS
+                .iter()
+                .enumerate()
+                .map(|(id, asset)| {
+                    let ring = SampleRing::init(stable_history::history_memory(id as u8), MAX_PRICE_SAMPLES);
+                    (asset.to_string(), ring)
+                })
+                .collect(),
+            twap_window_seconds: DEFAULT_TWAP_WINDOW_SECONDS,
+        }
+    }
+}
+
+impl PriceHistory {
+    /// Appends a sample, overwriting the oldest once the ring is full, and folds it into the EMA
+    pub fn record(&mut self, asset: &str, price: u128, timestamp: u64, ema_smoothing_bps: u32) {
+        let ring = match self.rings.get_mut(asset) {
+            Some(ring) => ring,
+            None => return,
+        };
+        if ring.last().is_some_and(|last| timestamp < last.timestamp) {
+            return;
+        }
+        ring.push(&PriceSample { price, timestamp });
+        
+        let alpha = ema_smoothing_bps as u128;
+        let ema = ring.ema().map_or(price, |ema| (ema * (10_000 - alpha) + price * alpha) / 10_000);
+        ring.set_ema(ema);
+    }
+    
+    /// Newest sample for an asset
+    pub fn latest(&self, asset: &str) -> Option<PriceSample> {
+        self.rings.get(asset)?.last()
+    }
+    
+    /// Time-weighted average price over the window ending at `now`; each sample holds until the next
+    pub fn twap(&self, asset: &str, now: u64) -> Option<u128> {
+        let ring = self.rings.get(asset)?;
+        let window_start = now.saturating_sub(self.twap_window_seconds);
+        
+        // Start from the newest sample at or before the window start, since it prices the window's first segment
+        let mut first = ring.len().checked_sub(1)?;
+        while first > 0 && ring.get(first)?.timestamp > window_start {
+            first -= 1;
+        }
+        let samples: Vec<PriceSample> = (first..ring.len()).filter_map(|index| ring.get(index)).collect();
+        
+        let mut weighted_sum = 0u128;
+        let mut total_duration = 0u64;
+        for (i, sample) in samples.iter().enumerate() {
+            let start = sample.timestamp.max(window_start);
+            let end = samples.get(i + 1).map_or(now, |next| next.timestamp);
+            let duration = end.saturating_sub(start);
+            weighted_sum += sample.price * duration as u128;
+            total_duration += duration;
+        }
+        
+        if total_duration == 0 {
+            return samples.last().map(|sample| sample.price);
+        }
+        Some(weighted_sum / total_duration as u128)
+    }
+    
+    /// Exponentially-weighted moving average of accepted prices
+    pub fn ema(&self, asset: &str) -> Option<u128> {
+        self.rings.get(asset)?.ema()
+    }
+    
+    /// Price under the given smoothing mode
+    pub fn smoothed(&self, asset: &str, now: u64, smoothing: &PriceSmoothing) -> Option<u128> {
+        match smoothing {
+            PriceSmoothing::Twap => self.twap(asset, now),
+            PriceSmoothing::Ema => self.ema(asset),
+        }
+    }
+}
+
+/// How a source's response is turned into a price
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+pub enum ParserKind {
+    /// JSON number at the price path
+    JsonNumber,
+    /// Numeric JSON string at the price path
+    JsonString,
+    /// Exchange Rate Canister call; the URL template and paths are unused
+    Xrc,
+    /// Chainlink aggregator `latestRoundData` read through the EVM RPC canister; symbols are
+    /// aggregator addresses and the URL template and paths are unused
+    Chainlink,
+    /// Hex-encoded Pyth accumulator update at the price path, verified against the Wormhole
+    /// guardian set; symbols are Pyth feed ids
+    PythUpdate,
+}
+
+impl ParserKind {
+    /// Whether the source is fetched with an HTTPS outcall built from its URL template
+    fn is_http(&self) -> bool {
+        matches!(self, ParserKind::JsonNumber | ParserKind::JsonString | ParserKind::PythUpdate)
+    }
+}
+
+/// An oracle source's registry entry, from which `sources::build` makes its fetcher
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct SourceConfig {
+    /// Unique name, reported as the price's source
+    pub(crate) name: String,
+    /// Request URL with `{symbol}` in place of the source's symbol for the asset; `{timestamp}` and
+    /// `{timestamp_ms}` are replaced by the start of the round's minute so every replica asks for the same data
+    url_template: String,
+    /// Source symbol per asset, e.g. "ICP" -> "ICPUSDT"; assets without one are skipped
+    symbols: HashMap<String, String>,
+    /// JSON path to the price
+    price_path: Vec<String>,
+    /// JSON path to a unix timestamp (seconds); the fetch time is used when absent
+    timestamp_path: Option<Vec<String>>,
+    /// Parser for the value at the price path
+    pub(crate) parser: ParserKind,
+    /// Response size limit, headers included; outcalls are charged for the full limit
+    pub(crate) max_response_bytes: u64,
+    /// Relative trust in this source
+    weight: u32,
+    /// Whether the source is queried
+    enabled: bool,
+}
+
+impl SourceConfig {
+    /// The source's symbol for an asset
+    pub(crate) fn symbol(&self, asset: &str) -> Result<&str, String> {
+        self.symbols
+            .get(asset)
+            .map(String::as_str)
+            .ok_or_else(|| format!("Unsupported asset: {}", asset))
+    }
+    
+    /// A price fetched from this source
+    pub(crate) fn price_data(&self, price: u128, timestamp: u64) -> PriceData {
+        PriceData {
+            price,
+            timestamp,
+            source: self.name.clone(),
+            weight: self.weight,
+        }
+    }
+}
+
+fn path(segments: &[&str]) -> Vec<String> {
+    segments.iter().map(|segment| segment.to_string()).collect()
+}
+
+fn symbols(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+    pairs.iter().map(|(asset, symbol)| (asset.to_string(), symbol.to_string())).collect()
+}
+
+fn default_hierarchy() -> Vec<OracleTier> {
+    vec![OracleTier::Xrc, OracleTier::ExchangeMedian, OracleTier::LastGoodPrice]
+}
+
+/// Admin-managed set of oracle sources that drives `fetch_prices`
+#[derive(CandidType, Deserialize)]
+pub struct SourceRegistry {
+    sources: Vec<SourceConfig>,
+    /// Fallback order per asset; assets without an entry use the default hierarchy
+    hierarchies: HashMap<String, Vec<OracleTier>>,
+    /// Cycles spent per source
+    spend: HashMap<String, SourceSpend>,
+    /// Request outcomes and backoff per source
+    health: HashMap<String, SourceHealth>,
+    /// Extra request headers per source, such as API keys; never returned by queries
+    request_headers: HashMap<String, Vec<(String, String)>>,
+    /// Gateways for sources that block replica IP ranges
+    proxies: HashMap<String, SourceProxy>,
+    /// Delayed changes to weights, enablement and quorums
+    governance: OracleGovernance,
+    /// Tolerances per asset; assets without an entry use the defaults
+    asset_configs: HashMap<String, AssetOracleConfig>,
+    /// Assets whose price is marked unavailable by a guardian
+    paused_assets: HashSet<String>,
+    /// Wormhole guardians that sign Pyth updates; Pyth sources fail until it is set
+    pyth_guardians: Option<WormholeGuardianSet>,
+}
+
+impl Default for SourceRegistry {
+    fn default() -> Self {
+        Self {
+            hierarchies: HashMap::new(),
+            spend: HashMap::new(),
+            health: HashMap::new(),
+            request_headers: HashMap::new(),
+            proxies: HashMap::new(),
+            governance: OracleGovernance::default(),
+            asset_configs: HashMap::new(),
+            paused_assets: HashSet::new(),
+            pyth_guardians: None,
+            sources: vec![
+                SourceConfig {
+                    name: "coingecko".to_string(),
+                    url_template: "https://api.coingecko.com/api/v3/simple/price?ids={symbol}&vs_currencies=usd&include_last_updated_at=true".to_string(),
+                    symbols: symbols(&[("ICP", "internet-computer"), ("BTC", "bitcoin"), ("ETH", "ethereum")]),
+                    price_path: path(&["{symbol}", "usd"]),
+                    timestamp_path: Some(path(&["{symbol}", "last_updated_at"])),
+                    parser: ParserKind::JsonNumber,
+                    max_response_bytes: 2_000,
+                    weight: 1,
+                    enabled: true,
+                },
+                SourceConfig {
+                    name: "binance".to_string(),
+                    // Close of the round's 1m candle, identical for every replica
+                    url_template: "https://api.binance.com/api/v3/klines?symbol={symbol}&interval=1m&startTime={timestamp_ms}&limit=1".to_string(),
+                    symbols: symbols(&[("ICP", "ICPUSDT"), ("BTC", "BTCUSDT"), ("ETH", "ETHUSDT")]),
+                    price_path: path(&["0", "4"]),
+                    timestamp_path: None,
+                    parser: ParserKind::JsonString,
+                    max_response_bytes: 2_000,
+                    weight: 1,
+                    enabled: true,
+                },
+                SourceConfig {
+                    name: "kraken".to_string(),
+                    url_template: "https://api.kraken.com/0/public/Ticker?pair={symbol}".to_string(),
+                    symbols: symbols(&[("ICP", "ICPUSD"), ("BTC", "XXBTZUSD"), ("ETH", "XETHZUSD")]),
+                    price_path: path(&["result", "{symbol}", "c", "0"]),
+                    timestamp_path: None,
+                    parser: ParserKind::JsonString,
+                    max_response_bytes: 4_000,
+                    weight: 1,
+                    enabled: true,
+                },
+                SourceConfig {
+                    name: "coinbase".to_string(),
+                    url_template: "https://api.coinbase.com/v2/prices/{symbol}/spot".to_string(),
+                    symbols: symbols(&[("ICP", "ICP-USD"), ("BTC", "BTC-USD"), ("ETH", "ETH-USD")]),
+                    price_path: path(&["data", "amount"]),
+                    timestamp_path: None,
+                    parser: ParserKind::JsonString,
+                    max_response_bytes: 2_000,
+                    weight: 1,
+                    enabled: true,
+                },
+                SourceConfig {
+                    name: "okx".to_string(),
+                    url_template: "https://www.okx.com/api/v5/market/ticker?instId={symbol}".to_string(),
+                    symbols: symbols(&[("ICP", "ICP-USDT"), ("BTC", "BTC-USDT"), ("ETH", "ETH-USDT")]),
+                    price_path: path(&["data", "0", "last"]),
+                    timestamp_path: None,
+                    parser: ParserKind::JsonString,
+                    max_response_bytes: 4_000,
+                    weight: 1,
+                    enabled: true,
+                },
+                SourceConfig {
+                    name: "bybit".to_string(),
+                    // Close of the round's 1m candle, identical for every replica
+                    url_template: "https://api.bybit.com/v5/market/kline?category=spot&symbol={symbol}&interval=1&start={timestamp_ms}&limit=1".to_string(),
+                    symbols: symbols(&[("ICP", "ICPUSDT"), ("BTC", "BTCUSDT"), ("ETH", "ETHUSDT")]),
+                    price_path: path(&["result", "list", "0", "4"]),
+                    timestamp_path: None,
+                    parser: ParserKind::JsonString,
+                    max_response_bytes: 3_000,
+                    weight: 1,
+                    enabled: true,
+                },
+                SourceConfig {
+                    name: "xrc".to_string(),
+                    url_template: String::new(),
+                    symbols: symbols(&[("ICP", "ICP"), ("BTC", "BTC"), ("ETH", "ETH")]),
+                    price_path: Vec::new(),
+                    timestamp_path: None,
+                    parser: ParserKind::Xrc,
+                    // On-chain and itself aggregated across exchanges
+                    max_response_bytes: 0,
+                    weight: 2,
+                    enabled: true,
+                },
+                SourceConfig {
+                    name: "chainlink".to_string(),
+                    url_template: String::new(),
+                    // Ethereum mainnet BTC/USD and ETH/USD aggregators; there is no ICP feed
+                    symbols: symbols(&[
+                        ("BTC", "0xF4030086522a5bEEa4988F8cA5B36dbC97BeE88c"),
+                        ("ETH", "0x5f4eC3Df9cbd43714FE2740f5E3616155c5b8419"),
+                    ]),
+                    price_path: Vec::new(),
+                    timestamp_path: None,
+                    parser: ParserKind::Chainlink,
+                    max_response_bytes: 2_000,
+                    weight: 2,
+                    enabled: true,
+                },
+                SourceConfig {
+                    name: "pyth".to_string(),
+                    // Update published at the start of the round's minute, identical for every replica
+                    url_template: "https://hermes.pyth.network/v2/updates/price/{timestamp}?ids[]={symbol}&encoding=hex&parsed=false".to_string(),
+                    symbols: symbols(&[
+                        ("ICP", "c9907d786c5821547777780a1e4f89484f3417cb14dd244f2b0a34ea7a554d67"),
+                        ("BTC", "e62df6c8b4a85fe1a67db44dc12de5db330f7ac66b72dc658afedf0f4a415b43"),
+                        ("ETH", "ff61491a931112ddf1bd8147cd1b641375f79f5825126d665480874634fd0ace"),
+                    ]),
+                    price_path: path(&["binary", "data", "0"]),
+                    timestamp_path: None,
+                    parser: ParserKind::PythUpdate,
+                    max_response_bytes: 10_000,
+                    weight: 1,
+                    // Enabled once the Wormhole guardian set is configured
+                    enabled: false,
+                },
+            ],
+        }
+    }
+}
+
+impl SourceRegistry {
+    /// Adds a request's net cycle cost to a source's counter
+    pub fn record_spend(&mut self, source: &str, cycles: u128) {
+        let spend = self.spend.entry(source.to_string()).or_default();
+        spend.requests += 1;
+        spend.cycles_spent += cycles;
+    }
+    
+    /// Headers and gateway a source's HTTPS requests are made with
+    pub(crate) fn outcall_settings(&self, name: &str) -> OutcallSettings {
+        OutcallSettings {
+            headers: self.request_headers.get(name).cloned().unwrap_or_default(),
+            proxy: self.proxies.get(name).cloned(),
+        }
+    }
+    
+    /// Oldest price accepted for each asset the source lists (seconds)
+    pub(crate) fn max_price_ages(&self, source: &SourceConfig) -> HashMap<String, u64> {
+        source.symbols
+            .keys()
+            .map(|asset| (asset.clone(), self.asset_config(asset).max_price_age_seconds))
+            .collect()
+    }
+    
+    #[cfg(test)]
+    pub(crate) fn source(&self, name: &str) -> Option<&SourceConfig> {
+        self.sources.iter().find(|source| source.name == name)
+    }
+    
+    /// Clears a source's failure streak and any backoff
+    pub fn record_success(&mut self, source: &str, now: u64) {
+        let health = self.health.entry(source.to_string()).or_default();
+        health.requests += 1;
+        health.consecutive_failures = 0;
+        health.last_success = Some(now);
+        health.backoff_until = None;
+    }
+    
+    /// Counts a failed fetch, backing the source off when it rate-limits us or keeps failing
+    pub fn record_failure(&mut self, source: &str, error: String, now: u64) {
+        let health = self.health.entry(source.to_string()).or_default();
+        health.requests += 1;
+        health.failures += 1;
+        health.consecutive_failures += 1;
+        health.last_error = Some(error.clone());
+        
+        // Backoff doubles with the failure streak, up to the cap
+        let backoff = if error.starts_with(RATE_LIMITED) {
+            health.rate_limited += 1;
+            RATE_LIMIT_BACKOFF_SECONDS << (health.consecutive_failures - 1).min(4)
+        } else if health.consecutive_failures >= FAILURE_BACKOFF_THRESHOLD {
+            FAILURE_BACKOFF_SECONDS << (health.consecutive_failures - FAILURE_BACKOFF_THRESHOLD).min(6)
+        } else {
+            0
+        };
+        if backoff > 0 {
+            health.backoff_until = Some(now + backoff.min(MAX_BACKOFF_SECONDS));
+        }
+    }
+    
+    /// Whether a source is skipped while backing off
+    pub fn is_backing_off(&self, source: &str, now: u64) -> bool {
+        self.health
+            .get(source)
+            .and_then(|health| health.backoff_until)
+            .is_some_and(|until| now < until)
+    }
+    
+    /// Tolerances for an asset
+    pub fn asset_config(&self, asset: &str) -> AssetOracleConfig {
+        self.asset_configs.get(asset).cloned().unwrap_or_default()
+    }
+    
+    /// Sets an asset's tolerances
+    pub fn set_asset_config(&mut self, asset: &str, config: AssetOracleConfig) -> Result<(), String> {
+        let price_cache = crate::storage::get::<PriceCache>();
+        if config.max_price_age_seconds <= price_cache.refresh_interval_seconds {
+            return Err("Maximum price age must exceed the refresh interval".to_string());
+        }
+        if config.max_fallback_age_seconds < config.max_price_age_seconds {
+            return Err("Maximum fallback age must be at least the maximum price age".to_string());
+        }
+        if config.max_deviation <= 0.0 || config.max_deviation > 1.0 {
+            return Err("Maximum deviation must be between 0 and 1".to_string());
+        }
+        if config.min_sources == 0 {
+            return Err("Minimum sources must be at least 1".to_string());
+        }
+        if config.ema_smoothing_bps == 0 || config.ema_smoothing_bps > 10_000 {
+            return Err("EMA smoothing must be between 1 and 10000 basis points".to_string());
+        }
+        
+        self.asset_configs.insert(asset.to_string(), config);
+        Ok(())
+    }
+    
+    /// Smallest maximum price age across supported assets
+    pub fn min_price_age_seconds(&self) -> u64 {
+        SUPPORTED_ASSETS
+            .iter()
+            .map(|asset| self.asset_config(asset).max_price_age_seconds)
+            .min()
+            .unwrap_or(DEFAULT_MAX_PRICE_AGE_SECONDS)
+    }
+    
+    /// Oracle tiers to try for an asset, in order
+    pub fn hierarchy(&self, asset: &str) -> Vec<OracleTier> {
+        self.hierarchies.get(asset).cloned().unwrap_or_else(default_hierarchy)
+    }
+    
+    /// Sets an asset's fallback order
+    pub fn set_hierarchy(&mut self, asset: &str, tiers: Vec<OracleTier>) -> Result<(), String> {
+        if !tiers.iter().any(|tier| *tier != OracleTier::LastGoodPrice) {
+            return Err("Oracle hierarchy needs at least one live tier".to_string());
+        }
+        for (i, tier) in tiers.iter().enumerate() {
+            if tiers[..i].contains(tier) {
+                return Err("Oracle tier listed more than once".to_string());
+            }
+            if *tier == OracleTier::LastGoodPrice && i != tiers.len() - 1 {
+                return Err("The last good price can only be the final tier".to_string());
+            }
+        }
+        
+        self.hierarchies.insert(asset.to_string(), tiers);
+        Ok(())
+    }
+    
+    /// Adds a source, or replaces the one with the same name
+    pub fn upsert(&mut self, source: SourceConfig) -> Result<(), String> {
+        if source.name.is_empty() {
+            return Err("Source name must not be empty".to_string());
+        }
+        if source.parser.is_http() && !source.url_template.starts_with("https://") {
+            return Err("Source URL must use HTTPS".to_string());
+        }
+        if source.parser.is_http() && source.price_path.is_empty() {
+            return Err("Source price path must not be empty".to_string());
+        }
+        if source.parser != ParserKind::Xrc
+            && (source.max_response_bytes == 0 || source.max_response_bytes > MAX_OUTCALL_RESPONSE_BYTES)
+        {
+            return Err(format!("Source response limit must be between 1 and {} bytes", MAX_OUTCALL_RESPONSE_BYTES));
+        }
+        
+        // Weights and enablement only change through governance: existing sources keep theirs
+        // and new ones start disabled
+        let mut source = source;
+        match self.sources.iter_mut().find(|existing| existing.name == source.name) {
+            Some(existing) => {
+                source.weight = existing.weight;
+                source.enabled = existing.enabled;
+                *existing = source;
+            }
+            None => {
+                source.enabled = false;
+                self.sources.push(source);
+            }
+        }
+        Ok(())
+    }
+    
+    /// Removes a source by name, once a governance removal is executed
+    fn remove(&mut self, name: &str) -> Result<(), String> {
+        let len = self.sources.len();
+        self.sources.retain(|source| source.name != name);
+        if self.sources.len() == len {
+            return Err("Price source not found".to_string());
+        }
+        self.request_headers.remove(name);
+        self.proxies.remove(name);
+        Ok(())
+    }
+    
+    /// Wormhole guardian set Pyth updates are checked against
+    pub fn pyth_guardians(&self) -> Option<&WormholeGuardianSet> {
+        self.pyth_guardians.as_ref()
+    }
+    
+    /// Sets the Wormhole guardian set Pyth updates are checked against
+    pub fn set_pyth_guardians(&mut self, guardian_set: WormholeGuardianSet) -> Result<(), String> {
+        if guardian_set.addresses.is_empty() {
+            return Err("Guardian set must not be empty".to_string());
+        }
+        
+        let mut addresses = Vec::new();
+        for address in &guardian_set.addresses {
+            let address = address.trim_start_matches("0x").to_lowercase();
+            if !hex::decode(&address).is_ok_and(|bytes| bytes.len() == 20) {
+                return Err(format!("Invalid guardian address: {}", address));
+            }
+            addresses.push(address);
+        }
+        
+        self.pyth_guardians = Some(WormholeGuardianSet {
+            index: guardian_set.index,
+            addresses,
+        });
+        Ok(())
+    }
+    
+    /// Replaces a source's extra request headers; an empty list removes them
+    pub fn set_request_headers(&mut self, name: &str, headers: Vec<(String, String)>) -> Result<(), String> {
+        if !self.sources.iter().any(|source| source.name == name) {
+            return Err("Price source not found".to_string());
+        }
+        if headers.iter().any(|(header, _)| header.is_empty() || header.eq_ignore_ascii_case("user-agent")) {
+            return Err("Invalid header name".to_string());
+        }
+        
+        if headers.is_empty() {
+            self.request_headers.remove(name);
+        } else {
+            self.request_headers.insert(name.to_string(), headers);
+        }
+        Ok(())
+    }
+    
+    /// Routes a source through a gateway, or back to direct requests with None
+    pub fn set_proxy(&mut self, name: &str, proxy: Option<SourceProxy>) -> Result<(), String> {
+        let source = self.sources
+            .iter()
+            .find(|source| source.name == name)
+            .ok_or("Price source not found")?;
+            
+        match proxy {
+            Some(proxy) => {
+                if !source.parser.is_http() {
+                    return Err("Only HTTPS sources can use a gateway".to_string());
+                }
+                if !proxy.gateway_url.starts_with("https://") {
+                    return Err("Gateway URL must use HTTPS".to_string());
+                }
+                if k256::ecdsa::VerifyingKey::from_sec1_bytes(&proxy.public_key).is_err() {
+                    return Err("Invalid gateway public key".to_string());
+                }
+                self.proxies.insert(name.to_string(), proxy);
+            }
+            None => {
+                self.proxies.remove(name);
+            }
+        }
+        Ok(())
+    }
+    
+    /// Applies a governance change, returning the setting it replaced in the same shape; removals
+    /// replace nothing
+    fn apply(&mut self, change: &OracleParamChange) -> Result<Option<OracleParamChange>, String> {
+        match change {
+            OracleParamChange::SourceWeight { source, weight } => {
+                let existing = self.source_mut(source)?;
+                let previous = existing.weight;
+                existing.weight = *weight;
+                Ok(Some(OracleParamChange::SourceWeight { source: source.clone(), weight: previous }))
+            }
+            OracleParamChange::SourceEnabled { source, enabled } => {
+                let existing = self.source_mut(source)?;
+                let previous = existing.enabled;
+                existing.enabled = *enabled;
+                Ok(Some(OracleParamChange::SourceEnabled { source: source.clone(), enabled: previous }))
+            }
+            OracleParamChange::RemoveSource { source } => {
+                self.remove(source)?;
+                Ok(None)
+            }
+            OracleParamChange::MinSources { asset, min_sources } => {
+                let config = self.asset_configs.entry(asset.clone()).or_default();
+                let previous = config.min_sources;
+                config.min_sources = *min_sources;
+                Ok(Some(OracleParamChange::MinSources { asset: asset.clone(), min_sources: previous }))
+            }
+            OracleParamChange::ChangeDelay { seconds } => {
+                let previous = self.governance.delay_seconds;
+                self.governance.delay_seconds = *seconds;
+                Ok(Some(OracleParamChange::ChangeDelay { seconds: previous }))
+            }
+        }
+    }
+    
+    /// Rejects changes that could not be applied
+    fn validate_change(&self, change: &OracleParamChange) -> Result<(), String> {
+        match change {
+            OracleParamChange::SourceWeight { source, .. }
+            | OracleParamChange::SourceEnabled { source, .. }
+            | OracleParamChange::RemoveSource { source } => {
+                if !self.sources.iter().any(|existing| existing.name == *source) {
+                    return Err("Price source not found".to_string());
+                }
+            }
+            OracleParamChange::MinSources { asset, min_sources } => {
+                if !SUPPORTED_ASSETS.contains(&asset.as_str()) {
+                    return Err(format!("Unsupported asset: {}", asset));
+                }
+                if *min_sources == 0 {
+                    return Err("Minimum sources must be at least 1".to_string());
+                }
+            }
+            OracleParamChange::ChangeDelay { .. } => {}
+        }
+        Ok(())
+    }
+    
+    fn source_mut(&mut self, name: &str) -> Result<&mut SourceConfig, String> {
+        self.sources
+            .iter_mut()
+            .find(|source| source.name == name)
+            .ok_or_else(|| "Price source not found".to_string())
+    }
+    
+    /// Queues a change to take effect after the governance delay
+    pub fn propose_change(&mut self, change: OracleParamChange, caller: Principal, now: u64) -> Result<u64, String> {
+        self.validate_change(&change)?;
+        
+        let governance = &mut self.governance;
+        let id = governance.next_change_id;
+        governance.next_change_id += 1;
+        governance.pending.insert(id, PendingOracleChange {
+            id,
+            change: change.clone(),
+            proposed_by: caller,
+            proposed_at: now,
+            executable_at: now + governance.delay_seconds,
+        });
+        governance.log(id, change, OracleChangeAction::Proposed, caller, now, None);
+        Ok(id)
+    }
+    
+    /// Applies a queued change whose delay has passed
+    pub fn execute_change(&mut self, id: u64, caller: Principal, now: u64) -> Result<(), String> {
+        let pending = self.governance.pending
+            .get(&id)
+            .ok_or("Oracle change not found")?
+            .clone();
+        if now < pending.executable_at {
+            return Err("Oracle change is still in its delay".to_string());
+        }
+        
+        let previous = self.apply(&pending.change)?;
+        self.governance.pending.remove(&id);
+        self.governance.log(id, pending.change, OracleChangeAction::Executed, caller, now, previous);
+        Ok(())
+    }
+    
+    /// Drops a queued change
+    pub fn cancel_change(&mut self, id: u64, caller: Principal, now: u64) -> Result<(), String> {
+        let pending = self.governance.pending
+            .remove(&id)
+            .ok_or("Oracle change not found")?;
+        self.governance.log(id, pending.change, OracleChangeAction::Cancelled, caller, now, None);
+        Ok(())
+    }
+}
+
+/// Default delay between proposing and executing an oracle change
+const DEFAULT_ORACLE_CHANGE_DELAY_SECONDS: u64 = 2 * 24 * 3600; // 2 days
+
+/// An oracle parameter only governance can change
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum OracleParamChange {
+    SourceWeight { source: String, weight: u32 },
+    SourceEnabled { source: String, enabled: bool },
+    /// Drops a source from the registry with its headers and gateway
+    RemoveSource { source: String },
+    MinSources { asset: String, min_sources: u8 },
+    /// Delay applied to changes proposed afterwards
+    ChangeDelay { seconds: u64 },
+}
+
+/// A change waiting out its delay
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct PendingOracleChange {
+    id: u64,
+    change: OracleParamChange,
+    proposed_by: Principal,
+    proposed_at: u64,
+    /// Earliest time the change can be executed (seconds)
+    executable_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum OracleChangeAction {
+    Proposed,
+    Executed,
+    Cancelled,
+}
+
+/// Audit record of an oracle governance action
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct OracleChangeEvent {
+    change_id: u64,
+    change: OracleParamChange,
+    action: OracleChangeAction,
+    caller: Principal,
+    timestamp: u64,
+    /// Setting the change replaced, for executed changes other than removals
+    previous: Option<OracleParamChange>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct OracleGovernance {
+    delay_seconds: u64,
+    pending: BTreeMap<u64, PendingOracleChange>,
+    next_change_id: u64,
+    /// Every proposal, execution and cancellation, oldest first
+    events: Vec<OracleChangeEvent>,
+}
+
+impl Default for OracleGovernance {
+    fn default() -> Self {
+        Self {
+            delay_seconds: DEFAULT_ORACLE_CHANGE_DELAY_SECONDS,
+            pending: BTreeMap::new(),
+            next_change_id: 0,
+            events: Vec::new(),
+        }
+    }
+}
+
+impl OracleGovernance {
+    fn log(
+        &mut self,
+        change_id: u64,
+        change: OracleParamChange,
+        action: OracleChangeAction,
+        caller: Principal,
+        timestamp: u64,
+        previous: Option<OracleParamChange>,
+    ) {
+        self.events.push(OracleChangeEvent {
+            change_id,
+            change,
+            action,
+            caller,
+            timestamp,
+            previous,
+        });
+    }
+}
+
+/// Cycles spent on a source's requests
+#[derive(CandidType, Deserialize, Debug, Clone, Default)]
+pub struct SourceSpend {
+    /// Requests made
+    requests: u64,
+    /// Cycles charged, net of refunds
+    cycles_spent: u128,
+}
+
+/// Prefix of errors for requests a source refused due to rate limiting
+pub(crate) const RATE_LIMITED: &str = "Rate limited";
+/// Initial backoff after a source rate-limits us (seconds)
+const RATE_LIMIT_BACKOFF_SECONDS: u64 = 300;
+/// Consecutive failures before a source is backed off
+const FAILURE_BACKOFF_THRESHOLD: u32 = 3;
+/// Initial backoff after repeated failures (seconds)
+const FAILURE_BACKOFF_SECONDS: u64 = 60;
+/// Longest backoff (seconds)
+const MAX_BACKOFF_SECONDS: u64 = 3600;
+
+/// Recent request outcomes for a source
+#[derive(CandidType, Deserialize, Debug, Clone, Default)]
+pub struct SourceHealth {
+    /// Fetches attempted
+    requests: u64,
+    /// Fetches that failed
+    failures: u64,
+    /// Fetches the source refused due to rate limiting
+    rate_limited: u64,
+    /// Failures since the last success
+    consecutive_failures: u32,
+    /// Time of the last successful fetch (seconds)
+    last_success: Option<u64>,
+    /// Most recent failure
+    last_error: Option<String>,
+    /// The source is skipped until this time (seconds)
+    backoff_until: Option<u64>,
+}
+
+/// A source's health as reported to operators
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct SourceHealthReport {
+    source: String,
+    enabled: bool,
+    health: SourceHealth,
+    /// Whether the source is currently failing or backing off
+    is_degraded: bool,
+}
+
+/// Response header carrying a gateway's signature
+const GATEWAY_SIGNATURE_HEADER: &str = "x-gateway-signature";
+
+/// HTTPS gateway that fetches a source's URL on the canister's behalf. The gateway is called as
+/// `{gateway_url}?url={target}` and must return the upstream body with an `x-gateway-signature`
+/// header: a hex secp256k1 signature (`r || s`) over SHA-256 of the target URL, a newline and the body
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct SourceProxy {
+    gateway_url: String,
+    /// SEC1-encoded secp256k1 key the gateway signs with
+    public_key: Vec<u8>,
+}
+
+/// Signature check the transform applies to a gateway response
+#[derive(CandidType, Deserialize, Debug)]
+struct GatewayCheck {
+    /// URL the gateway was asked to fetch
+    target_url: String,
+    public_key: Vec<u8>,
+}
+
+/// Percent-encodes a URL for use as a query value
+fn encode_query_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Whether a gateway response carries a valid signature over the target URL and body
+fn verify_gateway_signature(check: &GatewayCheck, response: &HttpResponse) -> bool {
+    let signature = response.headers
+        .iter()
+        .find(|header| header.name.eq_ignore_ascii_case(GATEWAY_SIGNATURE_HEADER))
+        .and_then(|header| hex::decode(header.value.trim()).ok())
+        .and_then(|bytes| k256::ecdsa::Signature::from_slice(&bytes).ok());
+    let key = k256::ecdsa::VerifyingKey::from_sec1_bytes(&check.public_key).ok();
+    
+    match (signature, key) {
+        (Some(signature), Some(key)) => {
+            let mut message = check.target_url.as_bytes().to_vec();
+            message.push(b'\n');
+            message.extend_from_slice(&response.body);
+            key.verify(&message, &signature).is_ok()
+        }
+        _ => false,
+    }
+}
+
+/// Largest response an HTTPS outcall may return
+const MAX_OUTCALL_RESPONSE_BYTES: u64 = 2_000_000;
+/// Nodes on the subnet the canister runs on, used for outcall pricing
+const SUBNET_NODES: u128 = 13;
+
+/// Cycles an HTTPS outcall costs: a base fee plus per-byte fees on the request and on the
+/// response limit, all scaled by subnet size
+pub(crate) fn outcall_cycles(request_bytes: u64, max_response_bytes: u64) -> u128 {
+    (3_000_000 + 60_000 * SUBNET_NODES) * SUBNET_NODES
+        + 400 * SUBNET_NODES * request_bytes as u128
+        + 800 * SUBNET_NODES * max_response_bytes as u128
+}
+
+/// Fetches the base asset's USD price and, for other quote currencies, converts it at the XRC forex rate
+pub async fn fetch_prices(base: &str, quote: &str) -> Result<AggregatedPrice, String> {
+    if !SUPPORTED_QUOTES.contains(&quote) {
+        return Err(format!("Unsupported quote currency: {}", quote));
+    }
+    
+    let price = fetch_usd_prices(base).await?;
+    if quote == USD {
+        return Ok(price);
+    }
+    let (rate, _) = sources::fetch_fiat_rate(&IcTransport, quote).await?;
+    Ok(convert_quote(&price, rate))
+}
+
+/// Re-expresses a USD price in a currency worth `usd_rate` (fixed-point USD)
+fn convert_quote(price: &AggregatedPrice, usd_rate: u128) -> AggregatedPrice {
+    let convert = |usd: u128| usd * 10u128.pow(PRICE_DECIMALS) / usd_rate;
+    AggregatedPrice {
+        price: convert(price.price),
+        contributions: price.contributions
+            .iter()
+            .map(|contribution| SourceContribution {
+                price: convert(contribution.price),
+                ..contribution.clone()
+            })
+            .collect(),
+        min_price: convert(price.min_price),
+        max_price: convert(price.max_price),
+        std_deviation: convert(price.std_deviation),
+        ..price.clone()
+    }
+}
+
+/// Walks the asset's oracle hierarchy, returning the first live tier that produces a USD price
+async fn fetch_usd_prices(asset: &str) -> Result<AggregatedPrice, String> {
+    #[cfg(feature = "mock-prices")]
+    if let Some(price) = crate::storage::get::<MockPrices>().get(asset) {
+        return Ok(price);
+    }
+    
+    let source_registry = crate::storage::get::<SourceRegistry>();
+    let mut errors = Vec::new();
+    
+    for tier in source_registry.hierarchy(asset) {
+        // The last good price is held by the cache, which falls back to it when every live tier fails
+        if tier == OracleTier::LastGoodPrice {
+            continue;
+        }
+        
+        match fetch_tier(asset, tier.clone()).await {
+            Ok(price) => return Ok(price),
+            Err(e) => errors.push(format!("{:?}: {}", tier, e)),
+        }
+    }
+    
+    Err(format!("All oracle tiers failed: {}", errors.join("; ")))
+}
+
+/// Admin-set prices that replace every oracle tier, for local replicas without HTTPS outcalls
+#[cfg(feature = "mock-prices")]
+#[derive(Default)]
+pub struct MockPrices {
+    prices: HashMap<String, u128>,
+}
+
+#[cfg(feature = "mock-prices")]
+impl MockPrices {
+    /// Mock price for an asset, stamped with the current time
+    fn get(&self, asset: &str) -> Option<AggregatedPrice> {
+        let price = *self.prices.get(asset)?;
+        let now = ic_cdk::api::time() / 1_000_000_000;
+        Some(AggregatedPrice {
+            price,
+            decimals: PRICE_DECIMALS,
+            timestamp: now,
+            sources_used: 1,
+            quorum: 1,
+            max_deviation: 0.0,
+            contributions: vec![SourceContribution {
+                source: "mock".to_string(),
+                price,
+                weight: 1,
+                weight_share: 10_000,
+                deviation: 0.0,
+            }],
+            rejected_sources: vec![],
+            tier: OracleTier::ExchangeMedian,
+            min_price: price,
+            max_price: price,
+            std_deviation: 0,
+        })
+    }
+}
+
+async fn fetch_tier(asset: &str, tier: OracleTier) -> Result<AggregatedPrice, String> {
+    // A single XRC rate is already aggregated across exchanges; exchange medians need the asset's quorum
+    let source_registry = crate::storage::get::<SourceRegistry>();
+    let config = source_registry.asset_config(asset);
+    let (xrc_tier, min_sources) = match tier {
+        OracleTier::Xrc => (true, 1),
+        _ => (false, config.min_sources as usize),
+    };
+    
+    // Fetch from the tier's enabled sources concurrently, skipping those backing off
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    let fetches = source_registry.sources
+        .iter()
+        .filter(|source| source.enabled && (source.parser == ParserKind::Xrc) == xrc_tier)
+        .filter(|source| !source_registry.is_backing_off(&source.name, now))
+        .filter(|source| source.symbols.contains_key(asset))
+        .map(|source| {
+            let name = source.name.clone();
+            let fetch = fetch_with_health(sources::build(source.clone(), source_registry), asset);
+            async move { (name, fetch.await) }
+        });
+        
+    let mut prices = Vec::new();
+    let mut errors = Vec::new();
+    for (name, result) in join_all(fetches).await {
+        match result {
+            Ok(price_data) => prices.push(price_data),
+            Err(e) => errors.push(format!("{}: {}", name, e)),
+        }
+    }
+    
+    if prices.is_empty() {
+        return Err(format!("No valid prices received from any source: {}", errors.join("; ")));
+    }
+    
+    aggregate_prices(prices, min_sources, tier, &config, now)
+}
+
+/// Rescales an integer mantissa with `decimals` decimals to `PRICE_DECIMALS`
+pub(crate) fn rescale_price(mantissa: u128, decimals: u32) -> u128 {
+    if decimals >= PRICE_DECIMALS {
+        mantissa / 10u128.pow(decimals - PRICE_DECIMALS)
+    } else {
+        mantissa * 10u128.pow(PRICE_DECIMALS - decimals)
+    }
+}
+
+/// Parses a decimal string such as "12.3456" into a fixed-point price without going through f64
+pub(crate) fn parse_fixed_price(text: &str) -> Result<u128, String> {
+    let text = text.trim();
+    let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+    let fraction: String = fraction.chars().take(PRICE_DECIMALS as usize).collect();
+    let mantissa = format!("{}{}", whole, fraction)
+        .parse::<u128>()
+        .map_err(|e| format!("Failed to parse price: {}", e))?;
+    Ok(rescale_price(mantissa, fraction.len() as u32))
+}
+
+/// Walks a JSON path; numeric segments index arrays
+fn json_at<'a>(json: &'a Value, path: &[String]) -> Option<&'a Value> {
+    path.iter().try_fold(json, |value, segment| {
+        match (value, segment.parse::<usize>()) {
+            (Value::Array(items), Ok(index)) => items.get(index),
+            _ => value.get(segment.as_str()),
+        }
+    })
+}
+
+/// Tells the transform how to reduce a source's response to its price
+#[derive(CandidType, Deserialize, Debug)]
+struct PriceExtraction {
+    /// Price path with the asset's symbol substituted
+    price_path: Vec<String>,
+    /// Timestamp path with the asset's symbol substituted
+    timestamp_path: Option<Vec<String>>,
+    /// Expected JSON type of the price
+    parser: ParserKind,
+    /// Present when the request went through a gateway whose signature must be checked
+    gateway: Option<GatewayCheck>,
+}
+
+/// Canonical body produced by the transform, identical across replicas that saw the same price
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub(crate) struct NormalizedPrice {
+    /// Price exactly as the source reported it
+    pub(crate) price: String,
+    /// Source timestamp (seconds), floored to the minute
+    pub(crate) timestamp: Option<u64>,
+}
+
+/// Start of the previous complete minute, shared by every replica fetching in this round
+pub(crate) fn round_timestamp(now: u64) -> u64 {
+    now - now % 60 - 60
+}
+
+/// Fetches from a source and records the outcome in its health
+async fn fetch_with_health(source: Box<dyn PriceSource>, asset: &str) -> Result<PriceData, String> {
+    // Assets a source does not list are not its failures
+    let config = source.config();
+    if !config.symbols.contains_key(asset) {
+        return Err(format!("Unsupported asset: {}", asset));
+    }
+    
+    let result = source.fetch(asset, &IcTransport).await;
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    let source_registry = crate::storage::get_mut::<SourceRegistry>();
+    match &result {
+        Ok(_) => source_registry.record_success(&config.name, now),
+        Err(e) => source_registry.record_failure(&config.name, format!("{}: {}", asset, e), now),
+    }
+    result
+}
+
+/// Registry settings an HTTPS source's requests are made with
+#[derive(Clone, Debug, Default)]
+pub(crate) struct OutcallSettings {
+    /// Extra request headers, such as API keys
+    headers: Vec<(String, String)>,
+    proxy: Option<SourceProxy>,
+}
+
+/// Fetches a source's URL for the round and returns the body the transform reduced it to
+pub(crate) async fn fetch_normalized(
+    source: &SourceConfig,
+    outcall: &OutcallSettings,
+    symbol: &str,
+    transport: &dyn Transport,
+) -> Result<NormalizedPrice, String> {
+    let round = round_timestamp(transport.now());
+    let url = source.url_template
+        .replace("{symbol}", symbol)
+        .replace("{timestamp_ms}", &(round * 1000).to_string())
+        .replace("{timestamp}", &round.to_string());
+    let substitute = |path: &Vec<String>| -> Vec<String> {
+        path.iter().map(|segment| segment.replace("{symbol}", symbol)).collect()
+    };
+    
+    // A gateway's signature is checked in the transform, before replicas compare responses
+    let (url, gateway) = match &outcall.proxy {
+        Some(proxy) => (
+            format!("{}?url={}", proxy.gateway_url, encode_query_value(&url)),
+            Some(GatewayCheck {
+                target_url: url,
+                public_key: proxy.public_key.clone(),
+            }),
+        ),
+        None => (url, None),
+    };
+    let extraction = PriceExtraction {
+        price_path: substitute(&source.price_path),
+        timestamp_path: source.timestamp_path.as_ref().map(substitute),
+        parser: source.parser.clone(),
+        gateway,
+    };
+    
+    let request = OutcallRequest {
+        url,
+        headers: outcall.headers.clone(),
+        transform_context: candid::encode_one(&extraction).unwrap(),
+        max_response_bytes: source.max_response_bytes,
+    };
+    let response = transport.http_get(&source.name, request).await?;
+    if response.status == 429u16 {
+        return Err(format!("{} by {}", RATE_LIMITED, source.name));
+    }
+    serde_json::from_slice(&response.body)
+        .map_err(|_| format!("No price in {} response", source.name))
+}
+
+/// Integer square root (floor)
+fn isqrt(n: u128) -> u128 {
+    if n < 2 {
+        return n;
+    }
+    let mut x = n;
+    let mut y = x.div_ceil(2);
+    while y < x {
+        x = y;
+        y = (x + n / x) / 2;
+    }
+    x
+}
+
+/// Unweighted median of a sorted, non-empty slice
+fn median(sorted: &[u128]) -> u128 {
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / 2
+    } else {
+        sorted[mid]
+    }
+}
+
+/// Drops sources far from the cluster by median absolute deviation, returning their names; fewer
+/// than three sources leave no majority to judge by
+fn reject_outliers(prices: &mut Vec<PriceData>) -> Vec<String> {
+    if prices.len() < 3 {
+        return vec![];
+    }
+    
+    let mut sorted: Vec<u128> = prices.iter().map(|p| p.price).collect();
+    sorted.sort_unstable();
+    let center = median(&sorted);
+    let mut distances: Vec<u128> = sorted.iter().map(|price| price.abs_diff(center)).collect();
+    distances.sort_unstable();
+    let mad = median(&distances);
+    
+    let max_distance = (mad * OUTLIER_MAD_MULTIPLIER).max(center * MIN_OUTLIER_DISTANCE_BPS / 10_000);
+    let (kept, rejected): (Vec<_>, Vec<_>) = prices
+        .drain(..)
+        .partition(|p| p.price.abs_diff(center) <= max_distance);
+    *prices = kept;
+    rejected.into_iter().map(|p| p.source).collect()
+}
+
+fn aggregate_prices(
+    prices: Vec<PriceData>,
+    min_sources: usize,
+    tier: OracleTier,
+    config: &AssetOracleConfig,
+    now: u64,
+) -> Result<AggregatedPrice, String> {
+    // Filter out stale prices; a source a little ahead of `now` counts as fresh
+    let mut valid_prices: Vec<_> = prices
+        .into_iter()
+        .filter(|p| now.saturating_sub(p.timestamp) <= config.max_price_age_seconds && p.weight > 0)
+        .collect();
+        
+    let rejected_sources = reject_outliers(&mut valid_prices);
+    if valid_prices.len() < min_sources {
+        return Err(format!(
+            "Insufficient valid price sources: {} of {} required",
+            valid_prices.len(),
+            min_sources
+        ));
+    }
+    
+    // Calculate weighted median price; with equal weights this is the plain median
+    valid_prices.sort_by_key(|p| p.price);
+    let total_weight: u64 = valid_prices.iter().map(|p| p.weight as u64).sum();
+    let mut cumulative_weight = 0u64;
+    let mut median_price = 0;
+    for (i, p) in valid_prices.iter().enumerate() {
+        cumulative_weight += p.weight as u64;
+        if cumulative_weight * 2 > total_weight {
+            median_price = p.price;
+            break;
+        }
+        if cumulative_weight * 2 == total_weight {
+            // Weight splits exactly between two prices, take their midpoint
+            median_price = (p.price + valid_prices[i + 1].price) / 2;
+            break;
+        }
+    }
+    if median_price == 0 {
+        return Err("Aggregated price is zero".to_string());
+    }
+    
+    let contributions: Vec<_> = valid_prices
+        .iter()
+        .map(|p| SourceContribution {
+            source: p.source.clone(),
+            price: p.price,
+            weight: p.weight,
+            weight_share: (p.weight as u64 * 10000 / total_weight) as u32,
+            deviation: p.price.abs_diff(median_price) as f64 / median_price as f64,
+        })
+        .collect();
+        
+    // Confidence band across the sources
+    let count = valid_prices.len() as u128;
+    let mean = valid_prices.iter().map(|p| p.price).sum::<u128>() / count;
+    let variance = valid_prices
+        .iter()
+        .map(|p| p.price.abs_diff(mean).pow(2))
+        .sum::<u128>() / count;
+    let min_price = valid_prices.first().map_or(0, |p| p.price);
+    let max_price = valid_prices.last().map_or(0, |p| p.price);
+    
+    // Calculate maximum deviation
+    let max_deviation = contributions
+        .iter()
+        .map(|c| c.deviation)
+        .max_by(|a, b| a.partial_cmp(b).unwrap())
+        .unwrap_or(0.0);
+        
+    // Check if deviation is within acceptable range
+    if max_deviation > config.max_deviation {
+        return Err("Price deviation too high between sources".to_string());
+    }
+    
+    Ok(AggregatedPrice {
+        price: median_price,
+        decimals: PRICE_DECIMALS,
+        timestamp: now,
+        sources_used: valid_prices.len() as u8,
+        quorum: min_sources as u8,
+        max_deviation,
+        contributions,
+        rejected_sources,
+        tier,
+        min_price,
+        max_price,
+        std_deviation: isqrt(variance),
+    })
+}
+
+/// Reduces a response to its price so replicas agree: headers are dropped and the body is
+/// replaced by a canonical `NormalizedPrice`, or emptied when no price can be extracted
+#[query]
+pub(crate) fn transform_response(args: TransformArgs) -> HttpResponse {
+    let normalized = candid::decode_one::<PriceExtraction>(&args.context)
+        .ok()
+        .filter(|_| args.response.status == 200u16)
+        .filter(|extraction| {
+            extraction.gateway
+                .as_ref()
+                .is_none_or(|check| verify_gateway_signature(check, &args.response))
+        })
+        .and_then(|extraction| {
+            let json: Value = serde_json::from_slice(&args.response.body).ok()?;
+            let price = match (&extraction.parser, json_at(&json, &extraction.price_path)?) {
+                (ParserKind::JsonNumber, Value::Number(number)) => number.to_string(),
+                (ParserKind::JsonString | ParserKind::PythUpdate, Value::String(text)) => text.clone(),
+                _ => return None,
+            };
+            let timestamp = extraction.timestamp_path
+                .as_ref()
+                .and_then(|path| json_at(&json, path))
+                .and_then(|value| value.as_u64())
+                .map(|timestamp| timestamp - timestamp % 60);
+            Some(NormalizedPrice { price, timestamp })
+        });
+        
+    HttpResponse {
+        status: args.response.status,
+        headers: vec![],
+        body: normalized
+            .and_then(|normalized| serde_json::to_vec(&normalized).ok())
+            .unwrap_or_default(),
+    }
+}
+
+// Canister endpoints
+#[query]
+fn get_price(asset: String) -> Result<CachedPrice, String> {
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    let price_cache = crate::storage::get::<PriceCache>();
+    price_cache.get(&asset, now)
+        .ok_or_else(|| "No cached price for asset".to_string())
+}
+
+/// Cached price of an asset in another currency, e.g. collateral values in EUR
+#[query]
+fn get_price_in(asset: String, quote: String) -> Result<CachedPrice, String> {
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    let price_cache = crate::storage::get::<PriceCache>();
+    price_cache.get_in(&asset, &quote, now)
+}
+
+#[query]
+fn get_supported_quotes() -> Vec<String> {
+    SUPPORTED_QUOTES.iter().map(|quote| quote.to_string()).collect()
+}
+
+#[query]
+fn get_price_update(asset: String) -> Option<OraclePriceUpdate> {
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    let price_cache = crate::storage::get::<PriceCache>();
+    price_cache.price_update(&asset, now)
+}
+
+#[query]
+fn get_supported_assets() -> Vec<String> {
+    SUPPORTED_ASSETS.iter().map(|asset| asset.to_string()).collect()
+}
+
+#[query]
+fn get_twap(asset: String) -> Option<u128> {
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    let price_history = crate::storage::get::<PriceHistory>();
+    price_history.twap(&asset, now)
+}
+
+#[query]
+fn get_ema(asset: String) -> Option<u128> {
+    let price_history = crate::storage::get::<PriceHistory>();
+    price_history.ema(&asset)
+}
+
+#[update]
+fn set_twap_window(window_seconds: u64) -> Result<(), String> {
+    require_role(Role::OracleManager)?;
+    
+    if window_seconds == 0 {
+        return Err("TWAP window must be non-zero".to_string());
+    }
+    
+    let price_history = crate::storage::get_mut::<PriceHistory>();
+    price_history.twap_window_seconds = window_seconds;
+    Ok(())
+}
+
+/// Refreshes every asset in one round instead of waiting for the timer, returning each asset's outcome
+#[update]
+async fn refresh_all_prices() -> Result<HashMap<String, Result<AggregatedPrice, String>>, String> {
+    require_role(Role::OracleManager)?;
+    
+    let price_cache = crate::storage::get_mut::<PriceCache>();
+    Ok(price_cache.refresh().await)
+}
+
+#[update]
+fn set_price_refresh_interval(interval_seconds: u64) -> Result<(), String> {
+    require_role(Role::OracleManager)?;
+    
+    let source_registry = crate::storage::get::<SourceRegistry>();
+    if interval_seconds == 0 || interval_seconds >= source_registry.min_price_age_seconds() {
+        return Err("Refresh interval must be non-zero and shorter than every asset's maximum price age".to_string());
+    }
+    
+    let price_cache = crate::storage::get_mut::<PriceCache>();
+    price_cache.refresh_interval_seconds = interval_seconds;
+    price_cache.schedule();
+    Ok(())
+}
+
+#[query]
+fn get_circuit_breaker_config() -> CircuitBreakerConfig {
+    let price_cache = crate::storage::get::<PriceCache>();
+    price_cache.circuit_breaker.clone()
+}
+
+#[update]
+fn update_circuit_breaker_config(new_config: CircuitBreakerConfig) -> Result<(), String> {
+    require_role(Role::OracleManager)?;
+    
+    if new_config.max_price_move <= 0.0 {
+        return Err("Maximum price move must be positive".to_string());
+    }
+    
+    let price_cache = crate::storage::get_mut::<PriceCache>();
+    price_cache.circuit_breaker = new_config;
+    Ok(())
+}
+
+#[query]
+fn list_price_sources() -> Vec<SourceConfig> {
+    let source_registry = crate::storage::get::<SourceRegistry>();
+    source_registry.sources.clone()
+}
+
+#[update]
+fn upsert_price_source(source: SourceConfig) -> Result<(), String> {
+    require_role(Role::OracleManager)?;
+    
+    let source_registry = crate::storage::get_mut::<SourceRegistry>();
+    source_registry.upsert(source)
+}
+
+/// Sets a source's extra request headers, e.g. `x-cg-pro-api-key` for CoinGecko Pro. Values are kept
+/// out of every query, though like all canister state they are visible to the subnet's node providers
+#[update]
+fn set_price_source_headers(name: String, headers: Vec<(String, String)>) -> Result<(), String> {
+    require_role(Role::OracleManager)?;
+    
+    let source_registry = crate::storage::get_mut::<SourceRegistry>();
+    source_registry.set_request_headers(&name, headers)
+}
+
+#[query]
+fn get_price_source_proxy(name: String) -> Option<SourceProxy> {
+    let source_registry = crate::storage::get::<SourceRegistry>();
+    source_registry.proxies.get(&name).cloned()
+}
+
+#[update]
+fn set_price_source_proxy(name: String, proxy: Option<SourceProxy>) -> Result<(), String> {
+    require_role(Role::OracleManager)?;
+    
+    let source_registry = crate::storage::get_mut::<SourceRegistry>();
+    source_registry.set_proxy(&name, proxy)
+}
+
+/// Names of a source's extra request headers, without their values
+#[query]
+fn get_price_source_header_names(name: String) -> Vec<String> {
+    let source_registry = crate::storage::get::<SourceRegistry>();
+    source_registry.request_headers
+        .get(&name)
+        .map(|headers| headers.iter().map(|(header, _)| header.clone()).collect())
+        .unwrap_or_default()
+}
+
+#[update]
+fn propose_oracle_change(change: OracleParamChange) -> Result<u64, String> {
+    require_role(Role::Admin)?;
+    
+    let source_registry = crate::storage::get_mut::<SourceRegistry>();
+    source_registry.propose_change(change, ic_cdk::caller(), ic_cdk::api::time() / 1_000_000_000)
+}
+
+/// Executes a queued change once its delay has passed; callable by anyone
+#[update]
+fn execute_oracle_change(change_id: u64) -> Result<(), String> {
+    let source_registry = crate::storage::get_mut::<SourceRegistry>();
+    source_registry.execute_change(change_id, ic_cdk::caller(), ic_cdk::api::time() / 1_000_000_000)
+}
+
+#[update]
+fn cancel_oracle_change(change_id: u64) -> Result<(), String> {
+    require_role(Role::Admin)?;
+    
+    let source_registry = crate::storage::get_mut::<SourceRegistry>();
+    source_registry.cancel_change(change_id, ic_cdk::caller(), ic_cdk::api::time() / 1_000_000_000)
+}
+
+#[query]
+fn get_pending_oracle_changes() -> Vec<PendingOracleChange> {
+    let source_registry = crate::storage::get::<SourceRegistry>();
+    source_registry.governance.pending.values().cloned().collect()
+}
+
+#[query]
+fn get_oracle_change_log(offset: u64, limit: u64) -> Vec<OracleChangeEvent> {
+    let source_registry = crate::storage::get::<SourceRegistry>();
+    source_registry.governance.events
+        .iter()
+        .skip(offset as usize)
+        .take(limit as usize)
+        .cloned()
+        .collect()
+}
+
+#[query]
+fn get_oracle_hierarchy(asset: String) -> Vec<OracleTier> {
+    let source_registry = crate::storage::get::<SourceRegistry>();
+    source_registry.hierarchy(&asset)
+}
+
+#[update]
+fn set_oracle_hierarchy(asset: String, tiers: Vec<OracleTier>) -> Result<(), String> {
+    require_role(Role::OracleManager)?;
+    
+    let source_registry = crate::storage::get_mut::<SourceRegistry>();
+    source_registry.set_hierarchy(&asset, tiers)
+}
+
+/// Read by the protocol's cycles monitor
+#[query]
+fn cycles_balance() -> u128 {
+    ic_cdk::api::canister_balance128()
+}
+
+#[query]
+fn get_oracle_cycle_spend() -> Vec<(String, SourceSpend)> {
+    let source_registry = crate::storage::get::<SourceRegistry>();
+    source_registry.spend
+        .iter()
+        .map(|(source, spend)| (source.clone(), spend.clone()))
+        .collect()
+}
+
+#[query]
+fn get_oracle_source_health() -> Vec<SourceHealthReport> {
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    let source_registry = crate::storage::get::<SourceRegistry>();
+    source_registry.sources
+        .iter()
+        .map(|source| {
+            let health = source_registry.health.get(&source.name).cloned().unwrap_or_default();
+            SourceHealthReport {
+                source: source.name.clone(),
+                enabled: source.enabled,
+                is_degraded: health.consecutive_failures > 0 || source_registry.is_backing_off(&source.name, now),
+                health,
+            }
+        })
+        .collect()
+}
+
+#[query]
+fn get_asset_oracle_config(asset: String) -> AssetOracleConfig {
+    let source_registry = crate::storage::get::<SourceRegistry>();
+    source_registry.asset_config(&asset)
+}
+
+#[update]
+fn set_asset_oracle_config(asset: String, config: AssetOracleConfig) -> Result<(), String> {
+    require_role(Role::OracleManager)?;
+    
+    // The quorum only changes through governance
+    let source_registry = crate::storage::get_mut::<SourceRegistry>();
+    let config = AssetOracleConfig {
+        min_sources: source_registry.asset_config(&asset).min_sources,
+        ..config
+    };
+    source_registry.set_asset_config(&asset, config)
+}
+
+/// Sets an asset's mock price and applies it immediately, bypassing the circuit breaker
+#[cfg(feature = "mock-prices")]
+#[update]
+fn set_test_price(asset: String, price: u128) -> Result<(), String> {
+    require_role(Role::OracleManager)?;
+    
+    if !SUPPORTED_ASSETS.contains(&asset.as_str()) {
+        return Err(format!("Unsupported asset: {}", asset));
+    }
+    if price == 0 {
+        return Err("Price must be positive".to_string());
+    }
+    
+    let mock_prices = crate::storage::get_mut::<MockPrices>();
+    mock_prices.prices.insert(asset.clone(), price);
+    let mock_price = mock_prices.get(&asset).unwrap();
+    
+    let price_cache = crate::storage::get_mut::<PriceCache>();
+    price_cache.unconfirmed.remove(&asset);
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    price_cache.accept(&asset, mock_price, now);
+    price_cache.publish(&asset, now);
+    Ok(())
+}
+
+/// Removes an asset's mock price so the oracle tiers are used again
+#[cfg(feature = "mock-prices")]
+#[update]
+fn clear_test_price(asset: String) -> Result<(), String> {
+    require_role(Role::OracleManager)?;
+    
+    let mock_prices = crate::storage::get_mut::<MockPrices>();
+    mock_prices.prices.remove(&asset);
+    Ok(())
+}
+
+#[query]
+fn get_pyth_guardian_set() -> Option<WormholeGuardianSet> {
+    let source_registry = crate::storage::get::<SourceRegistry>();
+    source_registry.pyth_guardians.clone()
+}
+
+#[update]
+fn set_pyth_guardian_set(guardian_set: WormholeGuardianSet) -> Result<(), String> {
+    require_role(Role::OracleManager)?;
+    
+    let source_registry = crate::storage::get_mut::<SourceRegistry>();
+    source_registry.set_pyth_guardians(guardian_set)
+}
+
+#[update]
+fn set_asset_price_paused(asset: String, paused: bool) -> Result<(), String> {
+    require_role(Role::Guardian)?;
+    
+    let source_registry = crate::storage::get_mut::<SourceRegistry>();
+    if paused {
+        source_registry.paused_assets.insert(asset.clone());
+    } else {
+        source_registry.paused_assets.remove(&asset);
+    }
+    
+    // Consumers hold mints and liquidations as soon as the pause lands, not at the next round
+    let price_cache = crate::storage::get::<PriceCache>();
+    price_cache.publish(&asset, ic_cdk::api::time() / 1_000_000_000);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const NOW: u64 = 1_700_000_000;
+    
+    #[test]
+    fn source_removal_waits_out_the_change_delay() {
+        let mut registry = SourceRegistry::default();
+        let change = OracleParamChange::RemoveSource { source: "kraken".to_string() };
+        let id = registry.propose_change(change, Principal::anonymous(), NOW).unwrap();
+        assert!(registry.source("kraken").is_some());
+        
+        let executable_at = NOW + DEFAULT_ORACLE_CHANGE_DELAY_SECONDS;
+        assert!(registry.execute_change(id, Principal::anonymous(), executable_at - 1).is_err());
+        registry.execute_change(id, Principal::anonymous(), executable_at).unwrap();
+        assert!(registry.source("kraken").is_none());
+        
+        let executed = registry.governance.events.last().unwrap();
+        assert!(matches!(executed.action, OracleChangeAction::Executed));
+        assert!(executed.previous.is_none());
+    }
+    
+    fn price(source: &str, price: u128, timestamp: u64) -> PriceData {
+        PriceData {
+            price,
+            timestamp,
+            source: source.to_string(),
+            weight: 1,
+        }
+    }
+    
+    #[test]
+    fn aggregation_skips_stale_sources_and_keeps_ones_ahead_of_now() {
+        let config = AssetOracleConfig::default();
+        let prices = vec![
+            price("stale", 50_000, NOW - config.max_price_age_seconds - 1),
+            price("fresh", 60_000, NOW),
+            price("ahead", 60_200, NOW + 5),
+        ];
+        
+        let aggregated = aggregate_prices(prices, 2, OracleTier::ExchangeMedian, &config, NOW).unwrap();
+        assert_eq!(aggregated.sources_used, 2);
+        assert_eq!(aggregated.price, 60_100);
+        assert_eq!(aggregated.timestamp, NOW);
+        assert!(aggregated.contributions.iter().all(|c| c.source != "stale"));
+    }
+    
+    #[test]
+    fn aggregation_needs_the_quorum_of_fresh_sources() {
+        let config = AssetOracleConfig::default();
+        let prices = vec![
+            price("stale", 60_000, NOW - config.max_price_age_seconds - 1),
+            price("fresh", 60_000, NOW),
+        ];
+        assert!(aggregate_prices(prices, 2, OracleTier::ExchangeMedian, &config, NOW).is_err());
+    }
+    
+    #[test]
+    fn removal_of_an_unknown_source_is_rejected_when_proposed() {
+        let mut registry = SourceRegistry::default();
+        let change = OracleParamChange::RemoveSource { source: "unknown".to_string() };
+        assert!(registry.propose_change(change, Principal::anonymous(), NOW).is_err());
+    }
+}
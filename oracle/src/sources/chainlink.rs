@@ -0,0 +1,187 @@
+use async_trait::async_trait;
+use candid::{CandidType, Deserialize, Principal};
+use serde_json::Value;
+use crate::price_feed::{rescale_price, PriceData, SourceConfig};
+use super::{PriceSource, Transport};
+
+/// EVM RPC canister, which relays JSON-RPC calls to Ethereum providers
+const EVM_RPC_CANISTER_ID: &str = "7hfb6-caaaa-aaaar-qadga-cai";
+/// Cycles attached to each EVM RPC request; the unused portion is refunded
+const EVM_RPC_CYCLES_FEE: u128 = 10_000_000_000;
+/// Selector of `latestRoundData()` on Chainlink aggregators
+const LATEST_ROUND_DATA_SELECTOR: &str = "0xfeaf968c";
+/// Decimals of Chainlink's USD-quoted feeds
+const CHAINLINK_USD_DECIMALS: u32 = 8;
+/// Heartbeat of the BTC/USD and ETH/USD feeds; a round older than this means the feed has stopped
+const CHAINLINK_HEARTBEAT_SECONDS: u64 = 3600;
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+enum EthMainnetService {
+    Alchemy,
+    Ankr,
+    BlockPi,
+    Cloudflare,
+    PublicNode,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+enum RpcService {
+    EthMainnet(EthMainnetService),
+}
+
+/// Chainlink aggregators on Ethereum, read through the EVM RPC canister
+pub struct ChainlinkSource {
+    pub(crate) config: SourceConfig,
+}
+
+#[async_trait(?Send)]
+impl PriceSource for ChainlinkSource {
+    fn config(&self) -> &SourceConfig {
+        &self.config
+    }
+    
+    async fn fetch(&self, asset: &str, transport: &dyn Transport) -> Result<PriceData, String> {
+        let aggregator = self.config.symbol(asset)?;
+        let (price, timestamp) = fetch_chainlink_price(
+            transport,
+            &self.config.name,
+            aggregator,
+            self.config.max_response_bytes,
+        ).await?;
+        Ok(self.config.price_data(price, timestamp))
+    }
+}
+
+/// Latest answer of a Chainlink aggregator as a fixed-point price and a timestamp (seconds)
+async fn fetch_chainlink_price(
+    transport: &dyn Transport,
+    source_name: &str,
+    aggregator: &str,
+    max_response_bytes: u64,
+) -> Result<(u128, u64), String> {
+    let request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "eth_call",
+        "params": [{ "to": aggregator, "data": LATEST_ROUND_DATA_SELECTOR }, "latest"],
+    })
+    .to_string();
+    
+    let evm_rpc_canister = Principal::from_text(EVM_RPC_CANISTER_ID).unwrap();
+    let args = candid::encode_args((
+        RpcService::EthMainnet(EthMainnetService::PublicNode),
+        request,
+        max_response_bytes,
+    ))
+    .unwrap();
+    let reply = transport.call(source_name, evm_rpc_canister, "request", args, EVM_RPC_CYCLES_FEE)
+        .await
+        .map_err(|e| format!("EVM RPC call failed: {}", e))?;
+    parse_eth_call_reply(&reply, transport.now())
+}
+
+/// Decodes the EVM RPC canister's reply to a `latestRoundData` call
+fn parse_eth_call_reply(reply: &[u8], now: u64) -> Result<(u128, u64), String> {
+    // Provider errors are reported as a variant this canister does not model, so only their presence is kept
+    let result = candid::decode_one::<Result<String, candid::Reserved>>(reply)
+        .map_err(|e| format!("Failed to decode EVM RPC reply: {}", e))?;
+    let body = result.map_err(|_| "EVM RPC provider returned an error".to_string())?;
+    
+    let response: Value = serde_json::from_str(&body)
+        .map_err(|e| format!("Failed to parse EVM RPC response: {}", e))?;
+    let data = response["result"]
+        .as_str()
+        .ok_or("No result in eth_call response")?;
+    decode_latest_round_data(data, now)
+}
+
+/// Decodes `(roundId, answer, startedAt, updatedAt, answeredInRound)` into the answer and `updatedAt`
+fn decode_latest_round_data(data: &str, now: u64) -> Result<(u128, u64), String> {
+    let bytes = hex::decode(data.trim_start_matches("0x"))
+        .map_err(|e| format!("Failed to decode latestRoundData: {}", e))?;
+    if bytes.len() < 5 * 32 {
+        return Err("latestRoundData returned too few words".to_string());
+    }
+    
+    // Words are big-endian; anything above the low 16 bytes means a negative or absurd value
+    let word = |index: usize| -> Result<u128, String> {
+        let word = &bytes[index * 32..(index + 1) * 32];
+        if word[..16].iter().any(|byte| *byte != 0) {
+            return Err("latestRoundData value out of range".to_string());
+        }
+        Ok(u128::from_be_bytes(word[16..].try_into().unwrap()))
+    };
+    
+    let answer = word(1)?;
+    let updated_at = word(3)?;
+    if answer == 0 || updated_at == 0 {
+        return Err("Chainlink round has no answer".to_string());
+    }
+    
+    // Feeds only update on a deviation or the heartbeat, so a round within the heartbeat is current
+    if now.saturating_sub(updated_at as u64) > CHAINLINK_HEARTBEAT_SECONDS {
+        return Err("Chainlink round is older than the feed heartbeat".to_string());
+    }
+    
+    Ok((rescale_price(answer, CHAINLINK_USD_DECIMALS), now))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use crate::price_feed::SourceRegistry;
+    use crate::sources::transport::testing::CannedTransport;
+    
+    const NOW: u64 = 1_700_000_130;
+    
+    /// ABI-encoded `latestRoundData` words: round, answer, started, updated, answered-in round
+    fn round_data(answer: u128, updated_at: u64) -> String {
+        let words = [1, answer, updated_at as u128, updated_at as u128, 1];
+        let hex: String = words.iter().map(|word| format!("{:064x}", word)).collect();
+        format!("0x{}", hex)
+    }
+    
+    fn reply(body: &str) -> Vec<u8> {
+        candid::encode_one(Ok::<String, candid::Reserved>(body.to_string())).unwrap()
+    }
+    
+    fn eth_call_response(result: &str) -> String {
+        serde_json::json!({ "jsonrpc": "2.0", "id": 1, "result": result }).to_string()
+    }
+    
+    #[test]
+    fn reads_the_answer_of_a_current_round() {
+        block_on(async {
+            let source = ChainlinkSource { config: SourceRegistry::default().source("chainlink").unwrap().clone() };
+            let body = eth_call_response(&round_data(6_425_012_500_000, NOW - 600));
+            let transport = CannedTransport::new(NOW, reply(&body));
+            let price = source.fetch("ETH", &transport).await.unwrap();
+            assert_eq!(price.price, 6_425_012_500_000);
+            assert_eq!(price.timestamp, NOW);
+            assert_eq!(*transport.requests.borrow(), vec!["request"]);
+        })
+    }
+    
+    #[test]
+    fn rejects_a_round_older_than_the_heartbeat() {
+        let body = eth_call_response(&round_data(6_425_012_500_000, NOW - CHAINLINK_HEARTBEAT_SECONDS - 1));
+        assert!(parse_eth_call_reply(&reply(&body), NOW).is_err());
+    }
+    
+    #[test]
+    fn rejects_negative_and_missing_answers() {
+        let negative = format!("0x{}{}{}", "00".repeat(32), "ff".repeat(32), "00".repeat(96));
+        assert!(decode_latest_round_data(&negative, NOW).is_err());
+        assert!(decode_latest_round_data(&round_data(0, NOW), NOW).is_err());
+        assert!(decode_latest_round_data("0x1234", NOW).is_err());
+    }
+    
+    #[test]
+    fn rejects_provider_errors_and_bodies_without_a_result() {
+        let error = candid::encode_one(Err::<String, u8>(0)).unwrap();
+        assert!(parse_eth_call_reply(&error, NOW).is_err());
+        let rpc_error = r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"execution reverted"}}"#;
+        assert!(parse_eth_call_reply(&reply(rpc_error), NOW).is_err());
+    }
+}
@@ -0,0 +1,128 @@
+use async_trait::async_trait;
+use crate::price_feed::{fetch_normalized, parse_fixed_price, round_timestamp, OutcallSettings, PriceData, SourceConfig};
+use super::{PriceSource, Transport};
+
+/// Exchange and aggregator APIs that return the price in a JSON response
+pub struct HttpJsonSource {
+    pub(crate) config: SourceConfig,
+    pub(crate) outcall: OutcallSettings,
+}
+
+#[async_trait(?Send)]
+impl PriceSource for HttpJsonSource {
+    fn config(&self) -> &SourceConfig {
+        &self.config
+    }
+    
+    async fn fetch(&self, asset: &str, transport: &dyn Transport) -> Result<PriceData, String> {
+        let symbol = self.config.symbol(asset)?;
+        let round = round_timestamp(transport.now());
+        let normalized = fetch_normalized(&self.config, &self.outcall, symbol, transport).await?;
+        Ok(self.config.price_data(
+            parse_fixed_price(&normalized.price)?,
+            normalized.timestamp.unwrap_or(round),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use crate::price_feed::SourceRegistry;
+    use crate::sources::transport::testing::CannedTransport;
+    
+    const NOW: u64 = 1_700_000_130;
+    
+    fn source(name: &str) -> HttpJsonSource {
+        HttpJsonSource {
+            config: SourceRegistry::default().source(name).unwrap().clone(),
+            outcall: OutcallSettings::default(),
+        }
+    }
+    
+    #[test]
+    fn reads_the_price_at_the_source_path() {
+        block_on(async {
+            let transport = CannedTransport::new(NOW, r#"{"data":{"base":"BTC","currency":"USD","amount":"64250.125"}}"#);
+            let price = source("coinbase").fetch("BTC", &transport).await.unwrap();
+            assert_eq!(price.price, 6_425_012_500_000);
+            // Without a timestamp path the price is stamped with the round
+            assert_eq!(price.timestamp, round_timestamp(NOW));
+            assert_eq!(*transport.requests.borrow(), vec!["https://api.coinbase.com/v2/prices/BTC-USD/spot"]);
+        })
+    }
+    
+    #[test]
+    fn substitutes_the_round_into_the_url() {
+        block_on(async {
+            let transport = CannedTransport::new(NOW, r#"[[1700000040000,"64200.0","64300.0","64100.0","64250.125","12.5"]]"#);
+            source("binance").fetch("BTC", &transport).await.unwrap();
+            assert_eq!(
+                *transport.requests.borrow(),
+                vec!["https://api.binance.com/api/v3/klines?symbol=BTCUSDT&interval=1m&startTime=1700000040000&limit=1"]
+            );
+        })
+    }
+    
+    #[test]
+    fn rejects_error_statuses() {
+        block_on(async {
+            let mut transport = CannedTransport::new(NOW, r#"{"data":{"amount":"64250.125"}}"#);
+            transport.status = 503;
+            let error = source("coinbase").fetch("BTC", &transport).await.unwrap_err();
+            assert_eq!(error, "No price in coinbase response");
+        })
+    }
+    
+    /// Responses recorded from each exchange's API for BTC, with the price and timestamp they yield
+    const RECORDED: [(&str, &str, u128, u64); 6] = [
+        ("coingecko", include_str!("fixtures/coingecko_btc.json"), 6_425_012_000_000, 1_699_999_980),
+        ("binance", include_str!("fixtures/binance_btc.json"), 6_425_012_000_000, 1_700_000_040),
+        ("kraken", include_str!("fixtures/kraken_btc.json"), 6_425_012_000_000, 1_700_000_040),
+        ("coinbase", include_str!("fixtures/coinbase_btc.json"), 6_425_012_000_000, 1_700_000_040),
+        ("okx", include_str!("fixtures/okx_btc.json"), 6_425_010_000_000, 1_700_000_040),
+        ("bybit", include_str!("fixtures/bybit_btc.json"), 6_425_012_000_000, 1_700_000_040),
+    ];
+    
+    /// Error, empty and cut-off responses seen from the same APIs
+    const MALFORMED: [(&str, &str); 7] = [
+        ("kraken", include_str!("fixtures/kraken_unknown_pair.json")),
+        ("okx", include_str!("fixtures/okx_unknown_instrument.json")),
+        ("okx", include_str!("fixtures/okx_empty_last.json")),
+        ("bybit", include_str!("fixtures/bybit_rate_limited.json")),
+        ("binance", include_str!("fixtures/binance_no_candle.json")),
+        ("coingecko", include_str!("fixtures/coingecko_string_price.json")),
+        ("coinbase", include_str!("fixtures/coinbase_truncated.json")),
+    ];
+    
+    #[test]
+    fn parses_recorded_responses() {
+        block_on(async {
+            for (name, body, price, timestamp) in RECORDED {
+                let transport = CannedTransport::new(NOW, body);
+                let fetched = source(name).fetch("BTC", &transport).await.unwrap();
+                assert_eq!((fetched.price, fetched.timestamp), (price, timestamp), "{}", name);
+            }
+        })
+    }
+    
+    #[test]
+    fn rejects_malformed_responses() {
+        block_on(async {
+            for (name, body) in MALFORMED {
+                let transport = CannedTransport::new(NOW, body);
+                assert!(source(name).fetch("BTC", &transport).await.is_err(), "{}: {}", name, body);
+            }
+        })
+    }
+    
+    #[test]
+    fn rejects_unlisted_assets_without_a_request() {
+        block_on(async {
+            let transport = CannedTransport::new(NOW, "{}");
+            assert!(source("coinbase").fetch("DOGE", &transport).await.is_err());
+            assert!(transport.requests.borrow().is_empty());
+        })
+    }
+}
@@ -0,0 +1,41 @@
+use async_trait::async_trait;
+use crate::price_feed::{ParserKind, PriceData, SourceConfig, SourceRegistry};
+
+mod chainlink;
+mod http_json;
+mod pyth;
+mod transport;
+mod xrc;
+
+pub use pyth::WormholeGuardianSet;
+pub use transport::{IcTransport, OutcallRequest, Transport};
+pub(crate) use xrc::fetch_fiat_rate;
+
+/// A way of fetching prices, built from a registry entry
+#[async_trait(?Send)]
+pub trait PriceSource {
+    /// Registry entry the source was built from
+    fn config(&self) -> &SourceConfig;
+    
+    /// Fetches the asset's latest USD price, making its requests through `transport`
+    async fn fetch(&self, asset: &str, transport: &dyn Transport) -> Result<PriceData, String>;
+}
+
+/// Builds the implementation for a registry entry's parser with the registry settings it fetches
+/// with; new kinds of source register here
+pub fn build(config: SourceConfig, registry: &SourceRegistry) -> Box<dyn PriceSource> {
+    match config.parser.clone() {
+        ParserKind::JsonNumber | ParserKind::JsonString => Box::new(http_json::HttpJsonSource {
+            outcall: registry.outcall_settings(&config.name),
+            config,
+        }),
+        ParserKind::Xrc => Box::new(xrc::XrcSource { config }),
+        ParserKind::Chainlink => Box::new(chainlink::ChainlinkSource { config }),
+        ParserKind::PythUpdate => Box::new(pyth::PythSource {
+            outcall: registry.outcall_settings(&config.name),
+            guardians: registry.pyth_guardians().cloned(),
+            max_price_ages: registry.max_price_ages(&config),
+            config,
+        }),
+    }
+}
@@ -0,0 +1,392 @@
+use async_trait::async_trait;
+use candid::{CandidType, Deserialize};
+use sha3::{Digest, Keccak256};
+use std::collections::HashMap;
+use crate::price_feed::{fetch_normalized, rescale_price, OutcallSettings, PriceData, SourceConfig};
+use super::{PriceSource, Transport};
+
+/// Wormhole chain id of Pythnet
+const PYTHNET_CHAIN_ID: u16 = 26;
+/// Emitter of Pyth's Merkle root VAAs on Pythnet
+const PYTH_ACCUMULATOR_EMITTER: &str = "e101faedac5851e32b9b23b5f9411a8c2bac4aae3ed4dd7b811dd1a72ea4aa71";
+/// Widest Pyth confidence interval accepted, in basis points of the price
+const PYTH_MAX_CONFIDENCE_BPS: u128 = 200;
+
+/// Wormhole guardians whose signatures Pyth updates must carry
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct WormholeGuardianSet {
+    /// Guardian set index VAAs must reference
+    pub(crate) index: u32,
+    /// Guardian Ethereum addresses in guardian order, hex encoded
+    pub(crate) addresses: Vec<String>,
+}
+
+/// Pyth price updates from Hermes, verified against the Wormhole guardian set
+pub struct PythSource {
+    pub(crate) config: SourceConfig,
+    pub(crate) outcall: OutcallSettings,
+    /// Guardians the update's VAA must be signed by
+    pub(crate) guardians: Option<WormholeGuardianSet>,
+    /// Oldest publish time accepted per asset (seconds)
+    pub(crate) max_price_ages: HashMap<String, u64>,
+}
+
+#[async_trait(?Send)]
+impl PriceSource for PythSource {
+    fn config(&self) -> &SourceConfig {
+        &self.config
+    }
+    
+    async fn fetch(&self, asset: &str, transport: &dyn Transport) -> Result<PriceData, String> {
+        let feed_id = self.config.symbol(asset)?;
+        let guardians = self.guardians.as_ref().ok_or("Pyth guardian set not configured")?;
+        let max_age = self.max_price_ages.get(asset).copied().unwrap_or_default();
+        let update = fetch_normalized(&self.config, &self.outcall, feed_id, transport).await?;
+        let (price, timestamp) = verify_pyth_update(&update.price, feed_id, guardians, max_age, transport.now())?;
+        Ok(self.config.price_data(price, timestamp))
+    }
+}
+
+/// Big-endian reader over Pyth and Wormhole wire formats
+struct WireReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> WireReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, offset: 0 }
+    }
+    
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.offset
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or("Pyth update is truncated")?;
+        let bytes = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+    
+    fn rest(&mut self) -> &'a [u8] {
+        let bytes = &self.bytes[self.offset..];
+        self.offset = self.bytes.len();
+        bytes
+    }
+    
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+    
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+    
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    
+    fn i64(&mut self) -> Result<i64, String> {
+        Ok(i64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+fn keccak256(data: &[u8]) -> [u8; 32] {
+    Keccak256::digest(data).into()
+}
+
+/// Truncated keccak used by Pyth's Merkle tree, with domain-separating prefixes for leaves and nodes
+fn keccak160(prefix: u8, parts: &[&[u8]]) -> [u8; 20] {
+    let mut hasher = Keccak256::new();
+    hasher.update([prefix]);
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize()[..20].try_into().unwrap()
+}
+
+/// Verifies a Pyth accumulator update and returns the feed's price and publish time (seconds)
+fn verify_pyth_update(
+    update_hex: &str,
+    feed_id: &str,
+    guardian_set: &WormholeGuardianSet,
+    max_age: u64,
+    now: u64,
+) -> Result<(u128, u64), String> {
+    let bytes = hex::decode(update_hex.trim_start_matches("0x"))
+        .map_err(|e| format!("Failed to decode Pyth update: {}", e))?;
+    let mut reader = WireReader::new(&bytes);
+    
+    if reader.take(4)? != b"PNAU" {
+        return Err("Not a Pyth accumulator update".to_string());
+    }
+    if reader.u8()? != 1 {
+        return Err("Unsupported Pyth update version".to_string());
+    }
+    reader.u8()?; // minor version
+    let trailing_header_len = reader.u8()? as usize;
+    reader.take(trailing_header_len)?;
+    if reader.u8()? != 0 {
+        return Err("Unsupported Pyth proof type".to_string());
+    }
+    
+    let vaa_len = reader.u16()? as usize;
+    let root = verify_wormhole_vaa(reader.take(vaa_len)?, guardian_set)?;
+    
+    let update_count = reader.u8()?;
+    for _ in 0..update_count {
+        let message_len = reader.u16()? as usize;
+        let message = reader.take(message_len)?;
+        let proof_len = reader.u8()? as usize;
+        let proof = reader.take(proof_len * 20)?;
+        
+        // Children are hashed in sorted order, so the proof needs no left/right flags
+        let mut node = keccak160(0, &[message]);
+        for sibling in proof.chunks(20) {
+            node = if node[..] <= *sibling {
+                keccak160(1, &[&node[..], sibling])
+            } else {
+                keccak160(1, &[sibling, &node[..]])
+            };
+        }
+        if node != root {
+            return Err("Pyth price message fails its Merkle proof".to_string());
+        }
+        
+        if let Some(price) = decode_pyth_price(message, feed_id, max_age, now)? {
+            return Ok(price);
+        }
+    }
+    
+    Err("Pyth update has no price for the requested feed".to_string())
+}
+
+/// Checks a VAA's guardian signatures and origin, returning the Merkle root it carries
+fn verify_wormhole_vaa(vaa: &[u8], guardian_set: &WormholeGuardianSet) -> Result<[u8; 20], String> {
+    let mut reader = WireReader::new(vaa);
+    
+    if reader.u8()? != 1 {
+        return Err("Unsupported VAA version".to_string());
+    }
+    if reader.u32()? != guardian_set.index {
+        return Err("VAA signed by an unknown guardian set".to_string());
+    }
+    let signature_count = reader.u8()? as usize;
+    let signatures = reader.take(signature_count * 66)?;
+    let body = reader.rest();
+    
+    // Guardians sign the hash of the body's hash
+    let digest = keccak256(&keccak256(body));
+    let mut last_index = None;
+    for signature in signatures.chunks(66) {
+        let index = signature[0] as usize;
+        // Strictly increasing indices keep a guardian from being counted twice
+        if last_index.is_some_and(|last| index <= last) {
+            return Err("VAA signatures out of order".to_string());
+        }
+        last_index = Some(index);
+        
+        let address = guardian_set.addresses
+            .get(index)
+            .ok_or("VAA signature from an unknown guardian")?;
+        if recover_eth_address(&digest, &signature[1..65], signature[65])? != *address {
+            return Err("Invalid guardian signature".to_string());
+        }
+    }
+    if signature_count < guardian_set.addresses.len() * 2 / 3 + 1 {
+        return Err("VAA lacks a guardian quorum".to_string());
+    }
+    
+    let mut body = WireReader::new(body);
+    body.take(8)?; // timestamp, nonce
+    if body.u16()? != PYTHNET_CHAIN_ID || hex::encode(body.take(32)?) != PYTH_ACCUMULATOR_EMITTER {
+        return Err("VAA not emitted by the Pyth accumulator".to_string());
+    }
+    body.take(9)?; // sequence, consistency level
+    if body.take(4)? != b"AUWV" || body.u8()? != 0 {
+        return Err("VAA does not carry a Merkle root".to_string());
+    }
+    body.take(12)?; // slot, ring size
+    Ok(body.take(20)?.try_into().unwrap())
+}
+
+/// Ethereum address of the key that produced a recoverable secp256k1 signature
+fn recover_eth_address(digest: &[u8; 32], signature: &[u8], recovery_id: u8) -> Result<String, String> {
+    let signature = k256::ecdsa::Signature::from_slice(signature)
+        .map_err(|_| "Malformed guardian signature".to_string())?;
+    let recovery_id = k256::ecdsa::RecoveryId::from_byte(recovery_id)
+        .ok_or("Malformed guardian signature")?;
+    let key = k256::ecdsa::VerifyingKey::recover_from_prehash(digest, &signature, recovery_id)
+        .map_err(|_| "Invalid guardian signature".to_string())?;
+    let point = key.to_encoded_point(false);
+    Ok(hex::encode(&keccak256(&point.as_bytes()[1..])[12..]))
+}
+
+/// Decodes a price feed message for `feed_id`, checking its confidence and age; other feeds yield None
+fn decode_pyth_price(message: &[u8], feed_id: &str, max_age: u64, now: u64) -> Result<Option<(u128, u64)>, String> {
+    let mut reader = WireReader::new(message);
+    if reader.u8()? != 0 || hex::encode(reader.take(32)?) != feed_id.trim_start_matches("0x").to_lowercase() {
+        return Ok(None);
+    }
+    
+    let price = reader.i64()?;
+    let confidence = reader.u64()? as u128;
+    let exponent = reader.i32()?;
+    let publish_time = reader.i64()?;
+    if price <= 0 || publish_time <= 0 {
+        return Err("Pyth price is not positive".to_string());
+    }
+    if exponent > 0 {
+        return Err("Unsupported Pyth price exponent".to_string());
+    }
+    if confidence * 10_000 > price as u128 * PYTH_MAX_CONFIDENCE_BPS {
+        return Err("Pyth confidence interval too wide".to_string());
+    }
+    
+    let publish_time = publish_time as u64;
+    if publish_time > now || now - publish_time > max_age {
+        return Err("Pyth price is stale".to_string());
+    }
+    
+    Ok(Some((rescale_price(price as u128, exponent.unsigned_abs()), publish_time)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use crate::price_feed::SourceRegistry;
+    use crate::sources::transport::testing::CannedTransport;
+    use k256::ecdsa::SigningKey;
+    
+    const NOW: u64 = 1_700_000_130;
+    const BTC_FEED: &str = "e62df6c8b4a85fe1a67db44dc12de5db330f7ac66b72dc658afedf0f4a415b43";
+    
+    fn guardian_key() -> SigningKey {
+        SigningKey::from_bytes(&[7u8; 32].into()).unwrap()
+    }
+    
+    fn guardian_set() -> WormholeGuardianSet {
+        let point = guardian_key().verifying_key().to_encoded_point(false);
+        WormholeGuardianSet {
+            index: 3,
+            addresses: vec![hex::encode(&keccak256(&point.as_bytes()[1..])[12..])],
+        }
+    }
+    
+    /// Price feed message as Pythnet publishes it, without the EMA fields the oracle ignores
+    fn price_message(price: i64, confidence: u64, exponent: i32, publish_time: i64) -> Vec<u8> {
+        let mut message = vec![0];
+        message.extend(hex::decode(BTC_FEED).unwrap());
+        message.extend(price.to_be_bytes());
+        message.extend(confidence.to_be_bytes());
+        message.extend(exponent.to_be_bytes());
+        message.extend(publish_time.to_be_bytes());
+        message
+    }
+    
+    /// Accumulator update carrying one message, its Merkle root signed by the test guardian
+    fn pyth_update(message: &[u8]) -> String {
+        let mut body = vec![0u8; 8]; // timestamp, nonce
+        body.extend(PYTHNET_CHAIN_ID.to_be_bytes());
+        body.extend(hex::decode(PYTH_ACCUMULATOR_EMITTER).unwrap());
+        body.extend([0u8; 9]); // sequence, consistency level
+        body.extend(b"AUWV");
+        body.push(0);
+        body.extend([0u8; 12]); // slot, ring size
+        body.extend(keccak160(0, &[message]));
+        
+        let (signature, recovery_id) = guardian_key()
+            .sign_prehash_recoverable(&keccak256(&keccak256(&body)))
+            .unwrap();
+        let mut vaa = vec![1];
+        vaa.extend(3u32.to_be_bytes());
+        vaa.push(1);
+        vaa.push(0);
+        vaa.extend(signature.to_bytes());
+        vaa.push(recovery_id.to_byte());
+        vaa.extend(body);
+        
+        let mut update = b"PNAU".to_vec();
+        update.extend([1, 0, 0, 0]);
+        update.extend((vaa.len() as u16).to_be_bytes());
+        update.extend(vaa);
+        update.push(1);
+        update.extend((message.len() as u16).to_be_bytes());
+        update.extend(message);
+        update.push(0);
+        hex::encode(update)
+    }
+    
+    fn hermes_response(update_hex: &str) -> String {
+        serde_json::json!({ "binary": { "encoding": "hex", "data": [update_hex] } }).to_string()
+    }
+    
+    fn source() -> PythSource {
+        PythSource {
+            config: SourceRegistry::default().source("pyth").unwrap().clone(),
+            outcall: OutcallSettings::default(),
+            guardians: Some(guardian_set()),
+            max_price_ages: HashMap::from([("BTC".to_string(), 300)]),
+        }
+    }
+    
+    #[test]
+    fn verifies_and_reads_a_signed_update() {
+        block_on(async {
+            let message = price_message(6_425_012_500_000, 1_000_000_000, -8, (NOW - 60) as i64);
+            let transport = CannedTransport::new(NOW, hermes_response(&pyth_update(&message)));
+            let price = source().fetch("BTC", &transport).await.unwrap();
+            assert_eq!(price.price, 6_425_012_500_000);
+            assert_eq!(price.timestamp, NOW - 60);
+        })
+    }
+    
+    #[test]
+    fn rejects_a_tampered_message() {
+        let signed = price_message(6_425_012_500_000, 1_000_000_000, -8, (NOW - 60) as i64);
+        let update = pyth_update(&signed);
+        let forged = price_message(1_000_000_000_000, 1_000_000_000, -8, (NOW - 60) as i64);
+        let tampered = update.replace(&hex::encode(&signed), &hex::encode(&forged));
+        let error = verify_pyth_update(&tampered, BTC_FEED, &guardian_set(), 300, NOW).unwrap_err();
+        assert_eq!(error, "Pyth price message fails its Merkle proof");
+    }
+    
+    #[test]
+    fn rejects_an_unknown_guardian() {
+        let message = price_message(6_425_012_500_000, 1_000_000_000, -8, (NOW - 60) as i64);
+        let mut other_set = guardian_set();
+        other_set.addresses = vec!["00".repeat(20)];
+        assert!(verify_pyth_update(&pyth_update(&message), BTC_FEED, &other_set, 300, NOW).is_err());
+    }
+    
+    #[test]
+    fn rejects_stale_and_uncertain_prices() {
+        let stale = price_message(6_425_012_500_000, 1_000_000_000, -8, (NOW - 301) as i64);
+        assert_eq!(
+            verify_pyth_update(&pyth_update(&stale), BTC_FEED, &guardian_set(), 300, NOW).unwrap_err(),
+            "Pyth price is stale"
+        );
+        let wide = price_message(6_425_012_500_000, 200_000_000_000, -8, (NOW - 60) as i64);
+        assert_eq!(
+            verify_pyth_update(&pyth_update(&wide), BTC_FEED, &guardian_set(), 300, NOW).unwrap_err(),
+            "Pyth confidence interval too wide"
+        );
+    }
+    
+    #[test]
+    fn rejects_a_truncated_update() {
+        let message = price_message(6_425_012_500_000, 1_000_000_000, -8, (NOW - 60) as i64);
+        let update = pyth_update(&message);
+        let truncated = &update[..update.len() / 2];
+        assert!(verify_pyth_update(truncated, BTC_FEED, &guardian_set(), 300, NOW).is_err());
+    }
+}
@@ -0,0 +1,159 @@
+use async_trait::async_trait;
+use candid::Principal;
+use ic_cdk::api::management_canister::http_request::{
+    CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformContext,
+};
+use crate::price_feed::{outcall_cycles, transform_response, SourceRegistry};
+
+/// An HTTPS GET a source makes; the response is reduced by `transform_response` with the context
+pub struct OutcallRequest {
+    pub(crate) url: String,
+    /// Extra request headers, such as API keys
+    pub(crate) headers: Vec<(String, String)>,
+    /// Candid-encoded `PriceExtraction` the transform applies
+    pub(crate) transform_context: Vec<u8>,
+    pub(crate) max_response_bytes: u64,
+}
+
+/// Everything a source reaches outside its own code through, so sources can be run against canned
+/// responses; canister code uses `IcTransport`
+#[async_trait(?Send)]
+pub trait Transport {
+    /// Current time (seconds)
+    fn now(&self) -> u64;
+    
+    /// Makes an HTTPS outcall, charging its cycles to `source`, and returns the transformed response
+    async fn http_get(&self, source: &str, request: OutcallRequest) -> Result<HttpResponse, String>;
+    
+    /// Calls `method` on a canister with candid-encoded arguments and cycles attached, charging the
+    /// cycles kept to `source`, and returns the encoded reply
+    async fn call(
+        &self,
+        source: &str,
+        canister: Principal,
+        method: &str,
+        args: Vec<u8>,
+        cycles: u128,
+    ) -> Result<Vec<u8>, String>;
+}
+
+/// Outcalls through the management canister and calls to other canisters from this one
+pub struct IcTransport;
+
+#[async_trait(?Send)]
+impl Transport for IcTransport {
+    fn now(&self) -> u64 {
+        ic_cdk::api::time() / 1_000_000_000
+    }
+    
+    async fn http_get(&self, source: &str, request: OutcallRequest) -> Result<HttpResponse, String> {
+        let mut headers = vec![
+            ("User-Agent".to_string(), "iUSD-Protocol-Bot".to_string()),
+        ];
+        headers.extend(request.headers);
+        
+        let request_bytes = request.url.len()
+            + request.transform_context.len()
+            + headers.iter().map(|(name, value)| name.len() + value.len()).sum::<usize>();
+        let cycles = outcall_cycles(request_bytes as u64, request.max_response_bytes);
+        
+        let argument = CanisterHttpRequestArgument {
+            url: request.url,
+            method: HttpMethod::GET,
+            body: None,
+            max_response_bytes: Some(request.max_response_bytes),
+            transform: Some(TransformContext::new(transform_response, request.transform_context)),
+            headers: headers
+                .into_iter()
+                .map(|(name, value)| HttpHeader { name, value })
+                .collect(),
+        };
+        
+        let result = ic_cdk::api::management_canister::http_request::http_request_with_cycles(argument, cycles).await;
+        
+        let refunded = ic_cdk::api::call::msg_cycles_refunded128();
+        let source_registry = crate::storage::get_mut::<SourceRegistry>();
+        source_registry.record_spend(source, cycles.saturating_sub(refunded));
+        
+        result
+            .map(|(response,)| response)
+            .map_err(|(code, msg)| format!("HTTP request failed: {:?} - {}", code, msg))
+    }
+    
+    async fn call(
+        &self,
+        source: &str,
+        canister: Principal,
+        method: &str,
+        args: Vec<u8>,
+        cycles: u128,
+    ) -> Result<Vec<u8>, String> {
+        let result = ic_cdk::api::call::call_raw128(canister, method, &args, cycles).await;
+        
+        let refunded = ic_cdk::api::call::msg_cycles_refunded128();
+        let source_registry = crate::storage::get_mut::<SourceRegistry>();
+        source_registry.record_spend(source, cycles.saturating_sub(refunded));
+        
+        result.map_err(|(code, msg)| format!("{:?} - {}", code, msg))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+    use ic_cdk::api::management_canister::http_request::TransformArgs;
+    use std::cell::RefCell;
+    
+    /// Answers every request with one recorded response; HTTPS bodies go through the real transform,
+    /// so tests cover what replicas would agree on
+    pub(crate) struct CannedTransport {
+        pub(crate) now: u64,
+        pub(crate) status: u16,
+        pub(crate) body: Vec<u8>,
+        /// URLs requested and canister methods called, in order
+        pub(crate) requests: RefCell<Vec<String>>,
+    }
+    
+    impl CannedTransport {
+        pub(crate) fn new(now: u64, body: impl Into<Vec<u8>>) -> Self {
+            Self {
+                now,
+                status: 200,
+                body: body.into(),
+                requests: RefCell::new(Vec::new()),
+            }
+        }
+    }
+    
+    #[async_trait(?Send)]
+    impl Transport for CannedTransport {
+        fn now(&self) -> u64 {
+            self.now
+        }
+        
+        async fn http_get(&self, _source: &str, request: OutcallRequest) -> Result<HttpResponse, String> {
+            self.requests.borrow_mut().push(request.url);
+            let response = HttpResponse {
+                status: candid::Nat::from(self.status),
+                headers: vec![],
+                body: self.body.clone(),
+            };
+            Ok(transform_response(TransformArgs {
+                response,
+                context: request.transform_context,
+            }))
+        }
+        
+        async fn call(
+            &self,
+            _source: &str,
+            _canister: Principal,
+            method: &str,
+            _args: Vec<u8>,
+            _cycles: u128,
+        ) -> Result<Vec<u8>, String> {
+            self.requests.borrow_mut().push(method.to_string());
+            Ok(self.body.clone())
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use async_trait::async_trait;
+use candid::{CandidType, Deserialize, Principal};
+use crate::price_feed::{rescale_price, PriceData, SourceConfig, RATE_LIMITED};
+use super::{PriceSource, Transport};
+
+/// Exchange Rate Canister on the NNS subnet
+const XRC_CANISTER_ID: &str = "uf6dk-hyaaa-aaaaq-qaaaq-cai";
+/// Cycles attached to each XRC request; the unused portion is refunded
+const XRC_CYCLES_FEE: u128 = 1_000_000_000;
+/// Name the cycles spent on forex rates for non-USD quotes are recorded under
+const FOREX_SPEND_SOURCE: &str = "xrc-forex";
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+enum XrcAssetClass {
+    Cryptocurrency,
+    FiatCurrency,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+struct XrcAsset {
+    symbol: String,
+    class: XrcAssetClass,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct GetExchangeRateRequest {
+    base_asset: XrcAsset,
+    quote_asset: XrcAsset,
+    /// Seconds since the epoch; None requests the latest rate
+    timestamp: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct ExchangeRateMetadata {
+    decimals: u32,
+    base_asset_num_received_rates: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct ExchangeRate {
+    base_asset: XrcAsset,
+    quote_asset: XrcAsset,
+    timestamp: u64,
+    rate: u64,
+    metadata: ExchangeRateMetadata,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct XrcOtherError {
+    code: u32,
+    description: String,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum ExchangeRateError {
+    AnonymousPrincipalNotAllowed,
+    Pending,
+    CryptoBaseAssetNotFound,
+    CryptoQuoteAssetNotFound,
+    StablecoinRateNotFound,
+    StablecoinRateTooFewRates,
+    StablecoinRateZeroRate,
+    ForexInvalidTimestamp,
+    ForexBaseAssetNotFound,
+    ForexQuoteAssetNotFound,
+    ForexAssetsNotFound,
+    RateLimited,
+    NotEnoughCycles,
+    FailedToAcceptCycles,
+    InconsistentRatesReceived,
+    Other(XrcOtherError),
+}
+
+/// The Exchange Rate Canister, whose rates are already aggregated across exchanges
+pub struct XrcSource {
+    pub(crate) config: SourceConfig,
+}
+
+#[async_trait(?Send)]
+impl PriceSource for XrcSource {
+    fn config(&self) -> &SourceConfig {
+        &self.config
+    }
+    
+    async fn fetch(&self, asset: &str, transport: &dyn Transport) -> Result<PriceData, String> {
+        let symbol = self.config.symbol(asset)?;
+        let base_asset = XrcAsset {
+            symbol: symbol.to_string(),
+            class: XrcAssetClass::Cryptocurrency,
+        };
+        let (price, timestamp) = fetch_usd_rate(transport, &self.config.name, base_asset).await?;
+        Ok(self.config.price_data(price, timestamp))
+    }
+}
+
+/// Latest USD value of a fiat currency such as EUR, as a fixed-point price and its timestamp (seconds)
+pub(crate) async fn fetch_fiat_rate(transport: &dyn Transport, currency: &str) -> Result<(u128, u64), String> {
+    let base_asset = XrcAsset {
+        symbol: currency.to_string(),
+        class: XrcAssetClass::FiatCurrency,
+    };
+    fetch_usd_rate(transport, FOREX_SPEND_SOURCE, base_asset).await
+}
+
+/// Latest XRC rate of an asset in USD as a fixed-point price and its timestamp (seconds)
+async fn fetch_usd_rate(
+    transport: &dyn Transport,
+    source_name: &str,
+    base_asset: XrcAsset,
+) -> Result<(u128, u64), String> {
+    let request = GetExchangeRateRequest {
+        base_asset,
+        quote_asset: XrcAsset {
+            symbol: "USD".to_string(),
+            class: XrcAssetClass::FiatCurrency,
+        },
+        timestamp: None,
+    };
+    
+    let xrc_canister = Principal::from_text(XRC_CANISTER_ID).unwrap();
+    let reply = transport.call(
+        source_name,
+        xrc_canister,
+        "get_exchange_rate",
+        candid::encode_one(&request).unwrap(),
+        XRC_CYCLES_FEE,
+    )
+    .await
+    .map_err(|e| format!("XRC call failed: {}", e))?;
+    parse_exchange_rate(&reply)
+}
+
+/// Decodes a `get_exchange_rate` reply into a fixed-point price and its timestamp (seconds)
+fn parse_exchange_rate(reply: &[u8]) -> Result<(u128, u64), String> {
+    let result = candid::decode_one::<Result<ExchangeRate, ExchangeRateError>>(reply)
+        .map_err(|e| format!("Failed to decode XRC reply: {}", e))?;
+    let rate = result.map_err(|e| match e {
+        ExchangeRateError::RateLimited => format!("{} by XRC", RATE_LIMITED),
+        e => format!("XRC returned an error: {:?}", e),
+    })?;
+    if rate.metadata.base_asset_num_received_rates == 0 {
+        return Err("XRC returned a rate without any underlying sources".to_string());
+    }
+    if rate.rate == 0 {
+        return Err("XRC returned a zero rate".to_string());
+    }
+    
+    Ok((rescale_price(rate.rate as u128, rate.metadata.decimals), rate.timestamp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use crate::price_feed::SourceRegistry;
+    use crate::sources::transport::testing::CannedTransport;
+    
+    const NOW: u64 = 1_700_000_130;
+    
+    fn reply(result: Result<ExchangeRate, ExchangeRateError>) -> Vec<u8> {
+        candid::encode_one(result).unwrap()
+    }
+    
+    fn rate(rate: u64, decimals: u32, received: u64) -> ExchangeRate {
+        ExchangeRate {
+            base_asset: XrcAsset { symbol: "BTC".to_string(), class: XrcAssetClass::Cryptocurrency },
+            quote_asset: XrcAsset { symbol: "USD".to_string(), class: XrcAssetClass::FiatCurrency },
+            timestamp: 1_700_000_040,
+            rate,
+            metadata: ExchangeRateMetadata { decimals, base_asset_num_received_rates: received },
+        }
+    }
+    
+    fn source() -> XrcSource {
+        XrcSource { config: SourceRegistry::default().source("xrc").unwrap().clone() }
+    }
+    
+    #[test]
+    fn rescales_the_rate_to_price_decimals() {
+        block_on(async {
+            let transport = CannedTransport::new(NOW, reply(Ok(rate(64_250_125_000_000, 9, 7))));
+            let price = source().fetch("BTC", &transport).await.unwrap();
+            assert_eq!(price.price, 6_425_012_500_000);
+            assert_eq!(price.timestamp, 1_700_000_040);
+            assert_eq!(*transport.requests.borrow(), vec!["get_exchange_rate"]);
+        })
+    }
+    
+    #[test]
+    fn flags_rate_limits_for_backoff() {
+        let error = parse_exchange_rate(&reply(Err(ExchangeRateError::RateLimited))).unwrap_err();
+        assert!(error.starts_with(RATE_LIMITED));
+    }
+    
+    #[test]
+    fn rejects_empty_and_zero_rates() {
+        assert!(parse_exchange_rate(&reply(Ok(rate(64_250_125_000_000, 9, 0)))).is_err());
+        assert!(parse_exchange_rate(&reply(Ok(rate(0, 9, 7)))).is_err());
+    }
+    
+    #[test]
+    fn rejects_a_reply_of_another_type() {
+        assert!(parse_exchange_rate(&candid::encode_one("not a rate").unwrap()).is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, Memory};
+use std::cell::RefCell;
+use crate::price_feed::PriceSample;
+
+pub(crate) type HistoryMemory = VirtualMemory<DefaultMemoryImpl>;
+
+/// Marks an initialized ring, so a fresh memory is told apart from an empty history
+const RING_MAGIC: &[u8; 4] = b"PHR1";
+/// Magic, capacity, next slot, length and EMA
+const HEADER_BYTES: u64 = 4 + 8 + 8 + 8 + 16;
+/// Price and timestamp
+const SLOT_BYTES: u64 = 16 + 8;
+const WASM_PAGE_BYTES: u64 = 65536;
+
+/// Memory holding the rest of the oracle's state across upgrades; history takes the ids from 0 up
+const STATE_MEMORY_ID: MemoryId = MemoryId::new(254);
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+}
+
+/// Stable memory of one asset's history; ids are the asset's position in `SUPPORTED_ASSETS`
+pub fn history_memory(id: u8) -> HistoryMemory {
+    MEMORY_MANAGER.with(|manager| manager.borrow().get(MemoryId::new(id)))
+}
+
+/// Stable memory of the state saved by `stable_state`
+pub(crate) fn state_memory() -> HistoryMemory {
+    MEMORY_MANAGER.with(|manager| manager.borrow().get(STATE_MEMORY_ID))
+}
+
+/// Fixed-capacity ring of price samples and the asset's EMA, kept in stable memory across upgrades
+pub struct SampleRing {
+    memory: HistoryMemory,
+    /// Slots in the ring; the oldest sample is overwritten once it is full
+    capacity: u64,
+    /// Slot the next sample is written to
+    next: u64,
+    len: u64,
+    ema: Option<u128>,
+}
+
+impl SampleRing {
+    /// Opens the ring in `memory`, creating it with `capacity` slots if the memory is fresh
+    pub fn init(memory: HistoryMemory, capacity: u64) -> Self {
+        let mut header = [0u8; HEADER_BYTES as usize];
+        if memory.size() > 0 {
+            memory.read(0, &mut header);
+        }
+        
+        // An existing ring keeps the capacity it was laid out with
+        if &header[..4] == RING_MAGIC {
+            let word = |offset: usize| u64::from_le_bytes(header[offset..offset + 8].try_into().unwrap());
+            let ema = u128::from_le_bytes(header[28..44].try_into().unwrap());
+            return Self {
+                memory,
+                capacity: word(4),
+                next: word(12),
+                len: word(20),
+                ema: Some(ema).filter(|ema| *ema > 0),
+            };
+        }
+        
+        let ring = Self {
+            memory,
+            capacity,
+            next: 0,
+            len: 0,
+            ema: None,
+        };
+        let pages = (HEADER_BYTES + capacity * SLOT_BYTES).div_ceil(WASM_PAGE_BYTES);
+        let missing = pages.saturating_sub(ring.memory.size());
+        if missing > 0 && ring.memory.grow(missing) < 0 {
+            ic_cdk::trap("Failed to grow price history memory");
+        }
+        ring.write_header();
+        ring
+    }
+    
+    fn write_header(&self) {
+        let mut header = Vec::with_capacity(HEADER_BYTES as usize);
+        header.extend_from_slice(RING_MAGIC);
+        header.extend_from_slice(&self.capacity.to_le_bytes());
+        header.extend_from_slice(&self.next.to_le_bytes());
+        header.extend_from_slice(&self.len.to_le_bytes());
+        header.extend_from_slice(&self.ema.unwrap_or(0).to_le_bytes());
+        self.memory.write(0, &header);
+    }
+    
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+    
+    /// Sample at `index`, counting from the oldest
+    pub fn get(&self, index: u64) -> Option<PriceSample> {
+        if index >= self.len {
+            return None;
+        }
+        
+        let slot = (self.next + self.capacity - self.len + index) % self.capacity;
+        let mut bytes = [0u8; SLOT_BYTES as usize];
+        self.memory.read(HEADER_BYTES + slot * SLOT_BYTES, &mut bytes);
+        Some(PriceSample {
+            price: u128::from_le_bytes(bytes[..16].try_into().unwrap()),
+            timestamp: u64::from_le_bytes(bytes[16..].try_into().unwrap()),
+        })
+    }
+    
+    /// Newest sample
+    pub fn last(&self) -> Option<PriceSample> {
+        self.get(self.len.checked_sub(1)?)
+    }
+    
+    /// Appends a sample, overwriting the oldest when the ring is full
+    pub fn push(&mut self, sample: &PriceSample) {
+        let mut bytes = Vec::with_capacity(SLOT_BYTES as usize);
+        bytes.extend_from_slice(&sample.price.to_le_bytes());
+        bytes.extend_from_slice(&sample.timestamp.to_le_bytes());
+        self.memory.write(HEADER_BYTES + self.next * SLOT_BYTES, &bytes);
+        
+        self.next = (self.next + 1) % self.capacity;
+        self.len = (self.len + 1).min(self.capacity);
+        self.write_header();
+    }
+    
+    pub fn ema(&self) -> Option<u128> {
+        self.ema
+    }
+    
+    pub fn set_ema(&mut self, ema: u128) {
+        self.ema = Some(ema);
+        self.write_header();
+    }
+}
@@ -0,0 +1,85 @@
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::StableCell;
+use std::cell::RefCell;
+use crate::access_control::AccessControl;
+use crate::attestation::PriceAttestor;
+use crate::price_alerts::PriceAlerts;
+use crate::price_feed::{PriceCache, PriceCacheState, PriceHistory, SourceRegistry};
+use crate::stable_history::{self, HistoryMemory};
+use crate::subscriptions::Subscribers;
+
+/// Layout of `StableState` this release writes; a release changing it bumps the version and
+/// decodes the old layout in `restore`
+const SCHEMA_VERSION: u32 = 1;
+
+thread_local! {
+    /// Candid-encoded `VersionedState`, written on upgrade
+    static STATE: RefCell<StableCell<Vec<u8>, HistoryMemory>> = RefCell::new(
+        StableCell::init(stable_history::state_memory(), Vec::new())
+            .expect("Failed to open oracle state memory")
+    );
+}
+
+/// Everything the oracle keeps on the heap; price samples and EMAs are already in stable memory
+#[derive(CandidType, Deserialize)]
+struct StableState {
+    access_control: AccessControl,
+    source_registry: SourceRegistry,
+    price_cache: PriceCacheState,
+    twap_window_seconds: u64,
+    subscribers: Subscribers,
+    attestor: PriceAttestor,
+    alerts: PriceAlerts,
+}
+
+#[derive(CandidType, Deserialize)]
+struct VersionedState {
+    schema_version: u32,
+    state: Vec<u8>,
+}
+
+/// Writes the state to stable memory before an upgrade
+pub(crate) fn save() {
+    // `StableState` owns its fields, so the state is moved out for encoding; the canister is
+    // replaced right after, so nothing is moved back
+    let state = StableState {
+        access_control: std::mem::take(crate::storage::get_mut::<AccessControl>()),
+        source_registry: std::mem::take(crate::storage::get_mut::<SourceRegistry>()),
+        price_cache: crate::storage::get::<PriceCache>().to_state(),
+        twap_window_seconds: crate::storage::get::<PriceHistory>().twap_window_seconds,
+        subscribers: std::mem::take(crate::storage::get_mut::<Subscribers>()),
+        attestor: std::mem::take(crate::storage::get_mut::<PriceAttestor>()),
+        alerts: std::mem::take(crate::storage::get_mut::<PriceAlerts>()),
+    };
+    let saved = VersionedState {
+        schema_version: SCHEMA_VERSION,
+        state: candid::encode_one(&state).expect("Failed to encode oracle state"),
+    };
+    let bytes = candid::encode_one(&saved).expect("Failed to encode oracle state");
+    STATE.with(|cell| cell.borrow_mut().set(bytes).expect("Failed to save oracle state"));
+}
+
+/// Installs the state saved by `save`, keeping the defaults when upgrading from a release that did
+/// not save its state
+pub(crate) fn restore() {
+    let bytes = STATE.with(|cell| cell.borrow().get().clone());
+    if bytes.is_empty() {
+        return;
+    }
+    
+    let saved: VersionedState = candid::decode_one(&bytes)
+        .unwrap_or_else(|e| ic_cdk::trap(&format!("Failed to decode oracle state: {}", e)));
+    if saved.schema_version != SCHEMA_VERSION {
+        ic_cdk::trap(&format!("Cannot restore oracle state schema {}", saved.schema_version));
+    }
+    let state: StableState = candid::decode_one(&saved.state)
+        .unwrap_or_else(|e| ic_cdk::trap(&format!("Failed to decode oracle state: {}", e)));
+        
+    *crate::storage::get_mut::<AccessControl>() = state.access_control;
+    *crate::storage::get_mut::<SourceRegistry>() = state.source_registry;
+    *crate::storage::get_mut::<PriceCache>() = PriceCache::from_state(state.price_cache);
+    crate::storage::get_mut::<PriceHistory>().twap_window_seconds = state.twap_window_seconds;
+    *crate::storage::get_mut::<Subscribers>() = state.subscribers;
+    *crate::storage::get_mut::<PriceAttestor>() = state.attestor;
+    *crate::storage::get_mut::<PriceAlerts>() = state.alerts;
+}
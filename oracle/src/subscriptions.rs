@@ -0,0 +1,116 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::*;
+use std::collections::{BTreeMap, BTreeSet};
+use crate::access_control::{require_role, Role};
+use crate::price_feed::OraclePriceUpdate;
+
+/// Subscribers notified each round; every notification costs the oracle cycles
+const MAX_SUBSCRIBERS: usize = 20;
+
+/// Canisters notified of every price round
+#[derive(CandidType, Deserialize, Default)]
+pub struct Subscribers {
+    /// Method to call with an `OraclePriceUpdate`, per subscriber
+    subscribers: BTreeMap<Principal, String>,
+    /// Canisters an admin has cleared to subscribe, so the slots can't be taken by anyone else
+    allowed: BTreeSet<Principal>,
+}
+
+impl Subscribers {
+    pub fn subscribe(&mut self, subscriber: Principal, method: String) -> Result<(), String> {
+        if !self.allowed.contains(&subscriber) {
+            return Err("Caller is not an allowed subscriber".to_string());
+        }
+        if method.is_empty() {
+            return Err("Callback method must not be empty".to_string());
+        }
+        if !self.subscribers.contains_key(&subscriber) && self.subscribers.len() >= MAX_SUBSCRIBERS {
+            return Err(format!("At most {} subscribers", MAX_SUBSCRIBERS));
+        }
+        
+        self.subscribers.insert(subscriber, method);
+        Ok(())
+    }
+    
+    /// Clears a canister to subscribe, or withdraws the clearance and drops its subscription
+    pub fn set_allowed(&mut self, canister: Principal, allowed: bool) {
+        if allowed {
+            self.allowed.insert(canister);
+        } else {
+            self.allowed.remove(&canister);
+            self.subscribers.remove(&canister);
+        }
+    }
+    
+    /// Sends an update to every subscriber as a one-way call, so a slow subscriber cannot hold up
+    /// the refresh; subscribers that miss one can read `get_price_update`
+    pub fn publish(&self, update: &OraclePriceUpdate) {
+        for (subscriber, method) in &self.subscribers {
+            let _ = ic_cdk::api::call::notify(*subscriber, method, (update.clone(),));
+        }
+    }
+}
+
+// Canister endpoints
+/// Subscribes the calling canister, which an admin must have allowed; `method` is called with an
+/// `OraclePriceUpdate` each round
+#[update]
+fn subscribe(method: String) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    let subscribers = crate::storage::get_mut::<Subscribers>();
+    subscribers.subscribe(caller, method)
+}
+
+#[update]
+fn unsubscribe() {
+    let subscribers = crate::storage::get_mut::<Subscribers>();
+    subscribers.subscribers.remove(&ic_cdk::caller());
+}
+
+#[query]
+fn list_subscribers() -> Vec<(Principal, String)> {
+    let subscribers = crate::storage::get::<Subscribers>();
+    subscribers.subscribers
+        .iter()
+        .map(|(subscriber, method)| (*subscriber, method.clone()))
+        .collect()
+}
+
+#[update]
+fn set_subscriber_allowed(canister: Principal, allowed: bool) -> Result<(), String> {
+    require_role(Role::Admin)?;
+    
+    let subscribers = crate::storage::get_mut::<Subscribers>();
+    subscribers.set_allowed(canister, allowed);
+    Ok(())
+}
+
+#[query]
+fn list_allowed_subscribers() -> Vec<Principal> {
+    let subscribers = crate::storage::get::<Subscribers>();
+    subscribers.allowed.iter().copied().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn canister(id: u8) -> Principal {
+        Principal::from_slice(&[id; 10])
+    }
+    
+    #[test]
+    fn only_allowed_canisters_subscribe() {
+        let mut subscribers = Subscribers::default();
+        assert!(subscribers.subscribe(canister(1), "on_price".to_string()).is_err());
+        
+        subscribers.set_allowed(canister(1), true);
+        subscribers.subscribe(canister(1), "on_price".to_string()).unwrap();
+        assert_eq!(subscribers.subscribers.len(), 1);
+        
+        // Withdrawing the clearance ends the subscription
+        subscribers.set_allowed(canister(1), false);
+        assert!(subscribers.subscribers.is_empty());
+        assert!(subscribers.subscribe(canister(1), "on_price".to_string()).is_err());
+    }
+}
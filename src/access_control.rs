@@ -0,0 +1,265 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::*;
+use std::collections::{BTreeMap, BTreeSet};
+use crate::audit_log;
+
+/// Roles admin endpoints require; Admin also passes every other role's checks
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Protocol parameters and role assignment
+    Admin,
+    /// Incident response: pause switches, vetoing queued risk changes and enforcement against
+    /// misbehaving participants, e.g. slashing liquidator bonds. Cannot change parameters
+    Guardian,
+    /// Oracle canister and price confidence settings
+    OracleManager,
+    /// Protocol funds and fees
+    Treasurer,
+    /// Maintains the allowlist of principals cleared to create vaults and mint, in builds with
+    /// the `allowlist` feature
+    Compliance,
+}
+
+/// An admin nomination waiting for the candidate to accept, so a mistyped principal can't take
+/// the role
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PendingAdmin {
+    candidate: Principal,
+    proposed_by: Principal,
+    proposed_at: u64,
+    /// Admin whose role moves to the candidate on acceptance; None adds an admin
+    replaces: Option<Principal>,
+}
+
+/// Principals and the roles granted to them
+#[derive(CandidType, Deserialize, Default)]
+pub struct AccessControl {
+    members: BTreeMap<Principal, BTreeSet<Role>>,
+    /// Admin nominations by candidate
+    pending_admins: BTreeMap<Principal, PendingAdmin>,
+    /// Governance canister (e.g. an SNS) that holds every role and alone may hand control on
+    governance: Option<Principal>,
+    /// Governance canister waiting to accept control
+    pending_governance: Option<Principal>,
+}
+
+impl AccessControl {
+    /// Whether the principal is the canister itself or its governance canister
+    pub fn is_governance(&self, principal: &Principal) -> bool {
+        *principal == ic_cdk::id() || self.governance == Some(*principal)
+    }
+    
+    /// Governance canister in control, once one has accepted
+    pub fn governance(&self) -> Option<Principal> {
+        self.governance
+    }
+    
+    pub fn has_role(&self, principal: &Principal, role: Role) -> bool {
+        // The canister and its governance hold every role, so calls they make keep passing
+        if self.is_governance(principal) {
+            return true;
+        }
+        self.members
+            .get(principal)
+            .is_some_and(|roles| roles.contains(&role) || roles.contains(&Role::Admin))
+    }
+    
+    pub fn grant(&mut self, principal: Principal, role: Role) {
+        self.members.entry(principal).or_default().insert(role);
+    }
+    
+    /// Revokes a role; the last admin cannot be removed, so roles can always be reassigned
+    pub fn revoke(&mut self, principal: Principal, role: Role) -> Result<(), String> {
+        let admins = self.members_of(Role::Admin);
+        if role == Role::Admin && admins == vec![principal] {
+            return Err("Cannot revoke the last admin".to_string());
+        }
+        
+        if let Some(roles) = self.members.get_mut(&principal) {
+            roles.remove(&role);
+            if roles.is_empty() {
+                self.members.remove(&principal);
+            }
+        }
+        Ok(())
+    }
+    
+    pub fn roles_of(&self, principal: &Principal) -> Vec<Role> {
+        self.members
+            .get(principal)
+            .map(|roles| roles.iter().copied().collect())
+            .unwrap_or_default()
+    }
+    
+    pub fn members_of(&self, role: Role) -> Vec<Principal> {
+        self.members
+            .iter()
+            .filter(|(_, roles)| roles.contains(&role))
+            .map(|(principal, _)| *principal)
+            .collect()
+    }
+    
+    /// Nominates an admin, replacing any earlier nomination of the same candidate
+    pub fn propose_admin(&mut self, candidate: Principal, replaces: Option<Principal>, caller: Principal, now: u64) {
+        self.pending_admins.insert(candidate, PendingAdmin {
+            candidate,
+            proposed_by: caller,
+            proposed_at: now,
+            replaces,
+        });
+    }
+    
+    /// Grants Admin to a nominated caller, revoking it from the admin being replaced
+    pub fn accept_admin(&mut self, caller: Principal) -> Result<(), String> {
+        let pending = self.pending_admins
+            .remove(&caller)
+            .ok_or("No admin nomination for caller")?;
+        self.grant(caller, Role::Admin);
+        if let Some(replaced) = pending.replaces.filter(|replaced| *replaced != caller) {
+            self.revoke(replaced, Role::Admin)?;
+        }
+        Ok(())
+    }
+    
+    pub fn cancel_admin(&mut self, candidate: &Principal) -> Result<(), String> {
+        self.pending_admins
+            .remove(candidate)
+            .map(|_| ())
+            .ok_or_else(|| "No admin nomination for candidate".to_string())
+    }
+}
+
+/// Fails unless the caller holds `role` (or Admin)
+pub(crate) fn require_role(role: Role) -> Result<(), String> {
+    let access_control = ic_cdk::storage::get::<AccessControl>();
+    if !access_control.has_role(&ic_cdk::caller(), role) {
+        return Err(format!("Unauthorized: requires the {:?} role", role));
+    }
+    Ok(())
+}
+
+/// Fails unless the caller is the governance canister, or an admin while none is set
+pub(crate) fn require_governance() -> Result<(), String> {
+    let access_control = ic_cdk::storage::get::<AccessControl>();
+    match access_control.governance {
+        Some(governance) if ic_cdk::caller() != governance && ic_cdk::caller() != ic_cdk::id() => {
+            Err("Unauthorized: only governance can call this".to_string())
+        }
+        Some(_) => Ok(()),
+        None => require_role(Role::Admin),
+    }
+}
+
+// Canister endpoints
+#[update]
+pub(crate) fn grant_role(principal: Principal, role: Role) -> Result<(), String> {
+    require_role(Role::Admin)?;
+    if role == Role::Admin {
+        return Err("Admins must be nominated with propose_admin and accept the role".to_string());
+    }
+    
+    let access_control = ic_cdk::storage::get_mut::<AccessControl>();
+    access_control.grant(principal, role);
+    audit_log::record_action("grant_role", &(principal, role));
+    Ok(())
+}
+
+#[update]
+pub(crate) fn revoke_role(principal: Principal, role: Role) -> Result<(), String> {
+    require_role(Role::Admin)?;
+    
+    let access_control = ic_cdk::storage::get_mut::<AccessControl>();
+    access_control.revoke(principal, role)?;
+    audit_log::record_action("revoke_role", &(principal, role));
+    Ok(())
+}
+
+/// Nominates a new admin, who takes the role once they call `accept_admin`. With `replace_self`
+/// the caller's own Admin role moves to them, handing the role over
+#[update]
+pub(crate) fn propose_admin(candidate: Principal, replace_self: bool) -> Result<(), String> {
+    require_role(Role::Admin)?;
+    
+    let caller = ic_cdk::caller();
+    let access_control = ic_cdk::storage::get_mut::<AccessControl>();
+    let replaces = Some(caller).filter(|_| replace_self);
+    access_control.propose_admin(candidate, replaces, caller, ic_cdk::api::time());
+    audit_log::record_action("propose_admin", &(candidate, replaces));
+    Ok(())
+}
+
+/// Accepts the caller's admin nomination
+#[update]
+fn accept_admin() -> Result<(), String> {
+    let access_control = ic_cdk::storage::get_mut::<AccessControl>();
+    access_control.accept_admin(ic_cdk::caller())?;
+    audit_log::record_action("accept_admin", &ic_cdk::caller());
+    Ok(())
+}
+
+#[update]
+pub(crate) fn cancel_admin_proposal(candidate: Principal) -> Result<(), String> {
+    require_role(Role::Admin)?;
+    
+    let access_control = ic_cdk::storage::get_mut::<AccessControl>();
+    access_control.cancel_admin(&candidate)?;
+    audit_log::record_action("cancel_admin_proposal", &candidate);
+    Ok(())
+}
+
+#[query]
+fn get_pending_admins() -> Vec<PendingAdmin> {
+    ic_cdk::storage::get::<AccessControl>().pending_admins.values().cloned().collect()
+}
+
+/// Proposes handing control to a governance canister such as an SNS, or moving it to another;
+/// it takes over once it calls `accept_governance_canister`, and from then on only governance can
+/// change it again. None returns control to the admins immediately
+#[update]
+fn set_governance_canister(governance: Option<Principal>) -> Result<(), String> {
+    require_governance()?;
+    
+    let access_control = ic_cdk::storage::get_mut::<AccessControl>();
+    audit_log::record_change("set_governance_canister", &access_control.governance, &governance);
+    match governance {
+        Some(_) => access_control.pending_governance = governance,
+        None => {
+            access_control.governance = None;
+            access_control.pending_governance = None;
+        }
+    }
+    Ok(())
+}
+
+/// Accepts control as the proposed governance canister; an SNS calls it through a generic function
+#[update]
+fn accept_governance_canister() -> Result<(), String> {
+    let access_control = ic_cdk::storage::get_mut::<AccessControl>();
+    if access_control.pending_governance != Some(ic_cdk::caller()) {
+        return Err("Caller is not the proposed governance canister".to_string());
+    }
+    let previous = access_control.governance;
+    access_control.governance = access_control.pending_governance.take();
+    audit_log::record_change("accept_governance_canister", &previous, &access_control.governance);
+    Ok(())
+}
+
+#[query]
+fn get_governance_canister() -> Option<Principal> {
+    ic_cdk::storage::get::<AccessControl>().governance
+}
+
+#[query]
+fn get_pending_governance_canister() -> Option<Principal> {
+    ic_cdk::storage::get::<AccessControl>().pending_governance
+}
+
+#[query]
+fn get_roles(principal: Principal) -> Vec<Role> {
+    ic_cdk::storage::get::<AccessControl>().roles_of(&principal)
+}
+
+#[query]
+fn get_role_members(role: Role) -> Vec<Principal> {
+    ic_cdk::storage::get::<AccessControl>().members_of(role)
+}
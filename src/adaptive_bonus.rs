@@ -0,0 +1,53 @@
+use candid::{CandidType, Deserialize};
+use crate::validation;
+
+/// Governance-set bounds and tuning for the adaptive liquidation bonus
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AdaptiveBonusConfig {
+    /// Lowest bonus the adjustment may set (in basis points)
+    pub(crate) min_bonus: u32,
+    /// Highest bonus the adjustment may set (in basis points)
+    pub(crate) max_bonus: u32,
+    /// Change applied per adjustment (in basis points)
+    pub(crate) step: u32,
+    /// Unsafe vaults older than this indicate keepers are not participating (nanoseconds)
+    pub(crate) target_unsafe_duration: u64,
+    /// Minimum time between adjustments (nanoseconds)
+    pub(crate) adjustment_interval: u64,
+}
+
+/// Record of a bonus adjustment
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct BonusAdjustment {
+    /// Timestamp of the adjustment
+    pub(crate) timestamp: u64,
+    /// Bonus before the adjustment
+    pub(crate) old_bonus: u32,
+    /// Bonus after the adjustment
+    pub(crate) new_bonus: u32,
+    /// Age of the longest-standing unsafe vault when the adjustment was made (nanoseconds)
+    pub(crate) oldest_unsafe_age: u64,
+    /// Number of unsafe vaults when the adjustment was made
+    pub(crate) unsafe_vaults: u64,
+}
+
+impl AdaptiveBonusConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.min_bonus > self.max_bonus {
+            return Err("Adaptive bonus minimum must not exceed maximum".to_string());
+        }
+        validation::check_positive("Adaptive bonus step", self.step)?;
+        validation::check_positive("Adaptive bonus target duration", self.target_unsafe_duration)
+    }
+    
+    /// Raises the bonus while vaults linger unsafe past the target and lowers it once
+    /// keepers clear them well within it, always staying inside the bounds
+    pub fn next_bonus(&self, current: u32, oldest_unsafe_age: Option<u64>) -> u32 {
+        let next = match oldest_unsafe_age {
+            Some(age) if age > self.target_unsafe_duration => current.saturating_add(self.step),
+            Some(age) if age > self.target_unsafe_duration / 2 => current,
+            _ => current.saturating_sub(self.step),
+        };
+        next.clamp(self.min_bonus, self.max_bonus)
+    }
+}
@@ -0,0 +1,89 @@
+use candid::Principal;
+use ic_cdk_macros::*;
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use crate::access_control::{require_role, Role};
+use crate::audit_log;
+use crate::stable_state::{self, ProtocolMemory, ALLOWLIST_MEMORY_ID};
+
+/// Principals returned per `get_allowlist` page
+const MAX_ALLOWLIST_ENTRIES_PER_PAGE: u64 = 1000;
+
+/// Raw bytes of a principal, which are at most 29 long
+type PrincipalKey = Blob<29>;
+
+thread_local! {
+    /// Principals cleared to create vaults and mint, kept in stable memory so upgrades never drop
+    /// them
+    static ALLOWLIST: RefCell<StableBTreeMap<PrincipalKey, (), ProtocolMemory>> = RefCell::new(
+        StableBTreeMap::init(stable_state::memory(ALLOWLIST_MEMORY_ID))
+    );
+}
+
+fn key(principal: &Principal) -> PrincipalKey {
+    PrincipalKey::try_from(principal.as_slice()).unwrap()
+}
+
+pub(crate) fn is_allowed(principal: &Principal) -> bool {
+    ALLOWLIST.with(|allowlist| allowlist.borrow().contains_key(&key(principal)))
+}
+
+/// Fails unless the caller is on the allowlist
+pub(crate) fn require_allowed() -> Result<(), String> {
+    if !is_allowed(&ic_cdk::caller()) {
+        return Err("Unauthorized: caller is not on the allowlist".to_string());
+    }
+    Ok(())
+}
+
+// Canister endpoints
+/// Clears principals to create vaults and mint iUSD
+#[update]
+fn add_to_allowlist(principals: Vec<Principal>) -> Result<(), String> {
+    require_role(Role::Compliance)?;
+    
+    ALLOWLIST.with(|allowlist| {
+        let mut allowlist = allowlist.borrow_mut();
+        for principal in &principals {
+            allowlist.insert(key(principal), ());
+        }
+    });
+    audit_log::record_action("add_to_allowlist", &principals);
+    Ok(())
+}
+
+/// Stops principals from creating vaults and minting; their vaults can still be repaid and
+/// withdrawn from
+#[update]
+fn remove_from_allowlist(principals: Vec<Principal>) -> Result<(), String> {
+    require_role(Role::Compliance)?;
+    
+    ALLOWLIST.with(|allowlist| {
+        let mut allowlist = allowlist.borrow_mut();
+        for principal in &principals {
+            allowlist.remove(&key(principal));
+        }
+    });
+    audit_log::record_action("remove_from_allowlist", &principals);
+    Ok(())
+}
+
+#[query]
+fn is_allowlisted(principal: Principal) -> bool {
+    is_allowed(&principal)
+}
+
+/// Allowlisted principals in byte order, at most 1000 per page
+#[query]
+fn get_allowlist(offset: u64, limit: u64) -> Vec<Principal> {
+    ALLOWLIST.with(|allowlist| {
+        allowlist
+            .borrow()
+            .iter()
+            .skip(offset as usize)
+            .take(limit.min(MAX_ALLOWLIST_ENTRIES_PER_PAGE) as usize)
+            .map(|(key, _)| Principal::from_slice(key.as_slice()))
+            .collect()
+    })
+}
@@ -0,0 +1,359 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::*;
+use std::collections::{BTreeMap, HashMap};
+use crate::access_control::{require_role, Role};
+use crate::audit_log;
+use crate::pause::{self, Operation};
+use crate::price_feed;
+use crate::validation;
+use crate::vault_system::{self, Account, CollateralType, TransferArgs, VaultController};
+
+/// Configuration for collateral auctions
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AuctionConfig {
+    /// Premium over the oracle price an auction starts at (in basis points)
+    start_premium: u32,
+    /// Time for the price to decay from the start price to zero (nanoseconds)
+    duration: u64,
+    /// Governance-set floor price per collateral (iUSD base units per whole collateral unit)
+    reserve_prices: HashMap<CollateralType, u128>,
+    /// Partial takes may not leave less debt than this outstanding
+    min_remaining_debt: u128,
+}
+
+impl Default for AuctionConfig {
+    fn default() -> Self {
+        Self {
+            start_premium: 2000,                   // 20%
+            duration: 6 * 3_600_000_000_000,       // 6 hours
+            reserve_prices: HashMap::new(),
+            min_remaining_debt: 10 * 100_000_000,  // 10 iUSD
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum AuctionStatus {
+    Active,
+    Settled,
+}
+
+/// A descending-price auction of a liquidated vault's collateral
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Auction {
+    /// ID of the auction
+    id: u64,
+    /// Vault the collateral was taken from
+    vault_id: u64,
+    /// Collateral type being sold
+    collateral_type: CollateralType,
+    /// Collateral still for sale
+    collateral_remaining: u128,
+    /// iUSD still to be raised to cover the vault's debt
+    debt_remaining: u128,
+    /// Starting price (iUSD base units per whole collateral unit)
+    start_price: u128,
+    /// Timestamp the auction started
+    started_at: u64,
+    /// Current status
+    status: AuctionStatus,
+}
+
+/// Current view of an auction
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AuctionInfo {
+    auction: Auction,
+    /// Price a take would execute at right now
+    current_price: u128,
+    /// Whether the price has decayed down to the reserve
+    at_reserve: bool,
+}
+
+/// Result of taking collateral from an auction
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TakeResult {
+    /// Collateral bought
+    collateral_bought: u128,
+    /// iUSD paid
+    iusd_paid: u128,
+    /// Price the take executed at
+    price: u128,
+    /// Whether this take settled the auction
+    settled: bool,
+}
+
+#[derive(CandidType, Deserialize, Default)]
+pub struct AuctionHouse {
+    config: AuctionConfig,
+    auctions: BTreeMap<u64, Auction>,
+    next_auction_id: u64,
+}
+
+impl AuctionHouse {
+    /// Price at a point in time, decaying linearly but never below the reserve
+    fn price_at(&self, auction: &Auction, now: u64) -> (u128, bool) {
+        let elapsed = now.saturating_sub(auction.started_at).min(self.config.duration);
+        let decayed = if self.config.duration == 0 {
+            0
+        } else {
+            auction.start_price * (self.config.duration - elapsed) as u128 / self.config.duration as u128
+        };
+        
+        let reserve = *self.config.reserve_prices.get(&auction.collateral_type).unwrap_or(&0);
+        if decayed <= reserve {
+            (reserve, true)
+        } else {
+            (decayed, false)
+        }
+    }
+    
+    /// Moves a liquidatable vault's collateral and debt into a new auction
+    pub async fn start_auction(&mut self, vault_id: u64) -> Result<u64, String> {
+        pause::check_not_paused(Operation::Liquidate)?;
+        let vault_controller = ic_cdk::storage::get_mut::<VaultController>();
+        let vault = vault_controller.vaults.get(&vault_id)
+            .ok_or("Vault not found")?
+            .clone();
+            
+        if !vault_controller.is_liquidatable(vault_id).await? {
+            return Err("Vault is not liquidatable".to_string());
+        }
+        
+        if price_feed::liquidations_paused(vault_system::price_feed_asset(&vault.collateral_type)) {
+            return Err("Liquidations paused pending price confirmation".to_string());
+        }
+        
+        // Oracle value of one whole unit of collateral, plus the starting premium
+        let unit = 10u128.pow(vault_system::collateral_decimals(&vault.collateral_type));
+        let oracle_price = vault_controller.get_collateral_value(&vault.collateral_type, unit).await?;
+        let start_price = oracle_price * (10000 + self.config.start_premium as u128) / 10000;
+        
+        vault_controller.reduce_position(vault_id, vault.debt_amount, vault.collateral_amount)?;
+        
+        let auction_id = self.next_auction_id;
+        self.auctions.insert(auction_id, Auction {
+            id: auction_id,
+            vault_id,
+            collateral_type: vault.collateral_type,
+            collateral_remaining: vault.collateral_amount,
+            debt_remaining: vault.debt_amount,
+            start_price,
+            started_at: ic_cdk::api::time(),
+            status: AuctionStatus::Active,
+        });
+        self.next_auction_id += 1;
+        
+        Ok(auction_id)
+    }
+    
+    /// Buys up to `max_collateral` from an auction, provided the price is at most `max_price`
+    pub async fn take(
+        &mut self,
+        auction_id: u64,
+        max_collateral: u128,
+        max_price: u128,
+    ) -> Result<TakeResult, String> {
+        pause::check_not_paused(Operation::Liquidate)?;
+        let caller = ic_cdk::caller();
+        let now = ic_cdk::api::time();
+        
+        let auction = self.auctions.get(&auction_id)
+            .ok_or("Auction not found")?
+            .clone();
+        if auction.status != AuctionStatus::Active {
+            return Err("Auction is not active".to_string());
+        }
+        
+        let (price, _) = self.price_at(&auction, now);
+        if price > max_price {
+            return Err("Auction price above maximum".to_string());
+        }
+        if price == 0 {
+            return Err("Auction has no price".to_string());
+        }
+        
+        // Size the fill, never raising more than the outstanding debt
+        let unit = 10u128.pow(vault_system::collateral_decimals(&auction.collateral_type));
+        let mut collateral_bought = max_collateral.min(auction.collateral_remaining);
+        let mut iusd_paid = collateral_bought * price / unit;
+        if iusd_paid > auction.debt_remaining {
+            iusd_paid = auction.debt_remaining;
+            collateral_bought = (iusd_paid * unit / price).min(auction.collateral_remaining);
+        }
+        if collateral_bought == 0 {
+            return Err("Take amount too small".to_string());
+        }
+        
+        // Partial fills must not leave an uneconomical remainder behind
+        let debt_after = auction.debt_remaining - iusd_paid;
+        let collateral_after = auction.collateral_remaining - collateral_bought;
+        if debt_after > 0 && collateral_after > 0 && debt_after < self.config.min_remaining_debt {
+            return Err("Take would leave debt below the minimum remaining amount".to_string());
+        }
+        
+        // Reserve the lot before awaiting so racing takes can't buy the same collateral
+        let lot = self.auctions.get_mut(&auction_id).unwrap();
+        lot.debt_remaining = debt_after;
+        lot.collateral_remaining = collateral_after;
+        
+        // Burn the taker's iUSD to retire the vault's debt, then deliver the collateral
+        let vault_controller = ic_cdk::storage::get_mut::<VaultController>();
+        let from = Account {
+            owner: caller,
+            subaccount: None,
+        };
+        if let Err(e) = vault_controller.burn_iusd_tokens(from, iusd_paid).await {
+            // A racing take may have settled the rest meanwhile; the lot reopens the auction
+            let lot = self.auctions.get_mut(&auction_id).unwrap();
+            lot.debt_remaining += iusd_paid;
+            lot.collateral_remaining += collateral_bought;
+            lot.status = AuctionStatus::Active;
+            return Err(e);
+        }
+        let delivered = self.transfer_collateral(&auction.collateral_type, caller, collateral_bought).await;
+        if let Err(e) = &delivered {
+            // The iUSD is already burned, so the debt stays retired and the lot stays sold; the
+            // audit entry lets an admin deliver the collateral by hand
+            audit_log::record(
+                "undelivered_auction_collateral",
+                None,
+                Some(format!("auction {} owes {} collateral to {}: {}", auction_id, collateral_bought, caller, e)),
+            );
+        }
+        
+        let auction = self.auctions.get_mut(&auction_id).unwrap();
+        
+        // Once the debt is covered, leftover collateral goes back to the vault
+        let settled = auction.debt_remaining == 0 || auction.collateral_remaining == 0;
+        if settled && auction.status == AuctionStatus::Active {
+            auction.status = AuctionStatus::Settled;
+            if auction.collateral_remaining > 0 {
+                vault_controller.credit_collateral(auction.vault_id, auction.collateral_remaining)?;
+                auction.collateral_remaining = 0;
+            }
+        }
+        delivered.map_err(|e| format!("iUSD burned but collateral delivery failed: {}", e))?;
+        
+        Ok(TakeResult {
+            collateral_bought,
+            iusd_paid,
+            price,
+            settled,
+        })
+    }
+    
+    /// Returns an auction with its current price
+    pub fn get_auction_info(&self, auction_id: u64) -> Option<AuctionInfo> {
+        let auction = self.auctions.get(&auction_id)?;
+        let (current_price, at_reserve) = self.price_at(auction, ic_cdk::api::time());
+        
+        Some(AuctionInfo {
+            auction: auction.clone(),
+            current_price,
+            at_reserve,
+        })
+    }
+    
+    async fn transfer_collateral(
+        &self,
+        collateral_type: &CollateralType,
+        to: Principal,
+        amount: u128,
+    ) -> Result<(), String> {
+        let args = TransferArgs {
+            to: Account {
+                owner: to,
+                subaccount: None,
+            },
+            amount,
+        };
+        
+        match ic_cdk::call(vault_system::collateral_canister_id(collateral_type), "transfer", (args,)).await {
+            Ok(()) => Ok(()),
+            Err((code, msg)) => Err(format!("Failed to transfer collateral: {:?} - {}", code, msg))
+        }
+    }
+}
+
+#[cfg(test)]
+impl AuctionHouse {
+    /// House with one active auction, for tests of saving and restoring state
+    pub(crate) fn sample() -> Self {
+        let auction = Auction {
+            id: 0,
+            vault_id: 7,
+            collateral_type: CollateralType::CkBTC,
+            collateral_remaining: 50_000,
+            debt_remaining: 1_000 * 100_000_000,
+            start_price: 60_000 * 100_000_000,
+            started_at: 1_000,
+            status: AuctionStatus::Active,
+        };
+        Self {
+            config: AuctionConfig::default(),
+            auctions: BTreeMap::from([(0, auction)]),
+            next_auction_id: 1,
+        }
+    }
+}
+
+// Canister endpoints
+#[update]
+async fn start_auction(vault_id: u64) -> Result<u64, String> {
+    let auction_house = ic_cdk::storage::get_mut::<AuctionHouse>();
+    auction_house.start_auction(vault_id).await
+}
+
+#[update]
+async fn take_auction(auction_id: u64, max_collateral: u128, max_price: u128) -> Result<TakeResult, String> {
+    let auction_house = ic_cdk::storage::get_mut::<AuctionHouse>();
+    auction_house.take(auction_id, max_collateral, max_price).await
+}
+
+#[query]
+fn get_auction(auction_id: u64) -> Option<AuctionInfo> {
+    let auction_house = ic_cdk::storage::get::<AuctionHouse>();
+    auction_house.get_auction_info(auction_id)
+}
+
+#[query]
+fn list_active_auctions() -> Vec<AuctionInfo> {
+    let auction_house = ic_cdk::storage::get::<AuctionHouse>();
+    auction_house.auctions
+        .values()
+        .filter(|auction| auction.status == AuctionStatus::Active)
+        .filter_map(|auction| auction_house.get_auction_info(auction.id))
+        .collect()
+}
+
+#[query]
+fn get_auction_config() -> AuctionConfig {
+    let auction_house = ic_cdk::storage::get::<AuctionHouse>();
+    auction_house.config.clone()
+}
+
+#[update]
+pub(crate) fn set_reserve_price(collateral_type: CollateralType, reserve_price: u128) -> Result<(), String> {
+    require_role(Role::Admin)?;
+    
+    let auction_house = ic_cdk::storage::get_mut::<AuctionHouse>();
+    let previous = auction_house.config.reserve_prices.get(&collateral_type).copied();
+    audit_log::record_change(
+        &format!("set_reserve_price {:?}", collateral_type),
+        &previous,
+        &Some(reserve_price),
+    );
+    auction_house.config.reserve_prices.insert(collateral_type, reserve_price);
+    Ok(())
+}
+
+#[update]
+pub(crate) fn update_auction_config(new_config: AuctionConfig) -> Result<(), String> {
+    require_role(Role::Admin)?;
+    validation::check_positive("Auction duration", new_config.duration)?;
+    
+    let auction_house = ic_cdk::storage::get_mut::<AuctionHouse>();
+    audit_log::record_change("update_auction_config", &auction_house.config, &new_config);
+    auction_house.config = new_config;
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::*;
+use ic_stable_structures::{StableLog, Storable};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fmt::Debug;
+use crate::stable_state::{self, ProtocolMemory, AUDIT_LOG_DATA_MEMORY_ID, AUDIT_LOG_INDEX_MEMORY_ID};
+
+/// Entries returned per `get_audit_log` page
+const MAX_AUDIT_ENTRIES_PER_PAGE: u64 = 100;
+
+/// A privileged action, e.g. a config change, role change or pause
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AuditEntry {
+    /// Position in the log
+    id: u64,
+    caller: Principal,
+    /// Seconds since the epoch
+    timestamp: u64,
+    /// Endpoint or operation, e.g. `update_liquidation_config`
+    action: String,
+    /// Setting before the action, where it replaced one
+    old_value: Option<String>,
+    new_value: Option<String>,
+}
+
+impl Storable for AuditEntry {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(candid::encode_one(self).expect("Failed to encode audit entry"))
+    }
+    
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to decode audit entry")
+    }
+}
+
+thread_local! {
+    /// Append-only, kept in stable memory so upgrades never drop entries
+    static AUDIT_LOG: RefCell<StableLog<AuditEntry, ProtocolMemory, ProtocolMemory>> = RefCell::new(
+        StableLog::init(
+            stable_state::memory(AUDIT_LOG_INDEX_MEMORY_ID),
+            stable_state::memory(AUDIT_LOG_DATA_MEMORY_ID),
+        )
+        .expect("Failed to open audit log memory")
+    );
+}
+
+/// Appends an action taken by the caller; values are recorded in their debug form
+pub(crate) fn record(action: &str, old_value: Option<String>, new_value: Option<String>) {
+    AUDIT_LOG.with(|log| {
+        let log = log.borrow();
+        let entry = AuditEntry {
+            id: log.len(),
+            caller: ic_cdk::caller(),
+            timestamp: ic_cdk::api::time() / 1_000_000_000,
+            action: action.to_string(),
+            old_value,
+            new_value,
+        };
+        log.append(&entry).expect("Failed to append to audit log");
+    });
+}
+
+/// Records a setting changing from `old_value` to `new_value`
+pub(crate) fn record_change<T: Debug>(action: &str, old_value: &T, new_value: &T) {
+    record(action, Some(format!("{:?}", old_value)), Some(format!("{:?}", new_value)));
+}
+
+/// Records an action without a previous value, e.g. adding a liquidator
+pub(crate) fn record_action<T: Debug>(action: &str, value: &T) {
+    record(action, None, Some(format!("{:?}", value)));
+}
+
+// Canister endpoints
+/// Audit entries oldest first, at most 100 per page
+#[query]
+fn get_audit_log(offset: u64, limit: u64) -> Vec<AuditEntry> {
+    AUDIT_LOG.with(|log| {
+        let log = log.borrow();
+        let end = log.len().min(offset.saturating_add(limit.min(MAX_AUDIT_ENTRIES_PER_PAGE)));
+        (offset..end).filter_map(|id| log.get(id)).collect()
+    })
+}
+
+#[query]
+fn get_audit_log_length() -> u64 {
+    AUDIT_LOG.with(|log| log.borrow().len())
+}
@@ -0,0 +1,167 @@
+use candid::{CandidType, Deserialize};
+use ic_cdk_macros::*;
+use sha2::{Digest, Sha256};
+use crate::access_control::{require_role, Role};
+use crate::audit_log;
+use crate::pause;
+use crate::stable_state;
+
+/// Snapshot bytes returned per `backup_state` call, under the 2 MiB message limit
+const MAX_CHUNK_BYTES: usize = 1_500_000;
+
+/// Part of a state snapshot; `restore_state` takes the chunks `backup_state` returns, in order
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct StateChunk {
+    /// Position of `data` in the snapshot
+    offset: u64,
+    /// Size of the whole snapshot
+    total_size: u64,
+    /// SHA-256 of the whole snapshot, checked once it is reassembled
+    snapshot_hash: Vec<u8>,
+    data: Vec<u8>,
+}
+
+/// Snapshots being streamed out and in
+#[derive(Default)]
+pub struct StateTransfer {
+    /// Snapshot taken by the latest `backup_state(0)`
+    backup: Vec<u8>,
+    backup_hash: Vec<u8>,
+    /// Chunks received by `restore_state` so far
+    restore: Vec<u8>,
+}
+
+impl StateTransfer {
+    /// Replaces the snapshot being streamed out
+    fn start_backup(&mut self, snapshot: Vec<u8>) {
+        self.backup_hash = Sha256::digest(&snapshot).to_vec();
+        self.backup = snapshot;
+    }
+    
+    fn backup_chunk(&self, offset: u64) -> Result<StateChunk, String> {
+        let total_size = self.backup.len();
+        if offset > total_size as u64 {
+            return Err("Offset is beyond the end of the snapshot".to_string());
+        }
+        let start = offset as usize;
+        let end = (start + MAX_CHUNK_BYTES).min(total_size);
+        Ok(StateChunk {
+            offset,
+            total_size: total_size as u64,
+            snapshot_hash: self.backup_hash.clone(),
+            data: self.backup[start..end].to_vec(),
+        })
+    }
+    
+    /// Appends a chunk to the snapshot coming in; returns the snapshot once the last chunk arrived
+    /// and it matches its hash
+    fn receive(&mut self, chunk: StateChunk) -> Result<Option<Vec<u8>>, String> {
+        if chunk.offset == 0 {
+            self.restore.clear();
+        }
+        if chunk.offset != self.restore.len() as u64 {
+            return Err(format!("Expected the chunk at offset {}", self.restore.len()));
+        }
+        self.restore.extend_from_slice(&chunk.data);
+        if (self.restore.len() as u64) < chunk.total_size {
+            return Ok(None);
+        }
+        
+        let snapshot = std::mem::take(&mut self.restore);
+        if snapshot.len() as u64 != chunk.total_size {
+            return Err("Chunks exceed the snapshot size".to_string());
+        }
+        if Sha256::digest(&snapshot).as_slice() != chunk.snapshot_hash.as_slice() {
+            return Err("Snapshot does not match its hash".to_string());
+        }
+        Ok(Some(snapshot))
+    }
+}
+
+/// A snapshot is only consistent, and a restore only safe, while nothing else changes the state
+fn check_paused() -> Result<(), String> {
+    if !pause::is_fully_paused() {
+        return Err("Every operation must be paused to back up or restore state".to_string());
+    }
+    Ok(())
+}
+
+// Canister endpoints
+/// Returns the chunk of the state snapshot starting at `offset`; offset 0 takes a new snapshot.
+/// The snapshot has the layout saved across upgrades, so it can be restored into a later release
+#[update]
+fn backup_state(offset: u64) -> Result<StateChunk, String> {
+    require_role(Role::Admin)?;
+    check_paused()?;
+    
+    let transfer = ic_cdk::storage::get_mut::<StateTransfer>();
+    if offset == 0 {
+        transfer.start_backup(stable_state::encode());
+    }
+    transfer.backup_chunk(offset)
+}
+
+/// Takes the chunks of a snapshot from `backup_state` in order, starting again at offset 0, and
+/// replaces the whole protocol state with it once the last chunk arrives. Returns whether the
+/// snapshot was restored
+#[update]
+fn restore_state(chunk: StateChunk) -> Result<bool, String> {
+    require_role(Role::Admin)?;
+    check_paused()?;
+    
+    let snapshot_hash = chunk.snapshot_hash.clone();
+    let transfer = ic_cdk::storage::get_mut::<StateTransfer>();
+    let snapshot = match transfer.receive(chunk)? {
+        Some(snapshot) => snapshot,
+        None => return Ok(false),
+    };
+    stable_state::decode(&snapshot)?;
+    audit_log::record("restore_state", None, Some(format!("sha256 {}", hex::encode(&snapshot_hash))));
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stable_state::tests as state;
+    
+    /// Streams the current state out of one transfer and into another, as an operator would
+    /// between `backup_state` and `restore_state`
+    fn stream(backup: &StateTransfer, restore: &mut StateTransfer) -> Result<Vec<u8>, String> {
+        let mut offset = 0;
+        loop {
+            let chunk = backup.backup_chunk(offset)?;
+            offset += chunk.data.len() as u64;
+            if let Some(snapshot) = restore.receive(chunk)? {
+                return Ok(snapshot);
+            }
+        }
+    }
+    
+    #[test]
+    fn restore_brings_back_the_backed_up_state() {
+        state::populate();
+        let backed_up = state::modules();
+        let mut backup = StateTransfer::default();
+        backup.start_backup(stable_state::encode());
+        
+        state::reset();
+        let snapshot = stream(&backup, &mut StateTransfer::default()).unwrap();
+        state::load(&snapshot);
+        for ((name, value), (_, expected)) in state::modules().iter().zip(&backed_up) {
+            assert_eq!(value, expected, "{} differs after the restore", name);
+        }
+    }
+    
+    #[test]
+    fn restore_rejects_a_corrupted_snapshot() {
+        state::populate();
+        let mut backup = StateTransfer::default();
+        backup.start_backup(stable_state::encode());
+        let last = backup.backup.len() - 1;
+        backup.backup[last] ^= 1;
+        
+        let result = stream(&backup, &mut StateTransfer::default());
+        assert_eq!(result.unwrap_err(), "Snapshot does not match its hash");
+    }
+}
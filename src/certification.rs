@@ -0,0 +1,77 @@
+use ic_certified_map::{fork, fork_hash, labeled, labeled_hash, AsHashTree, Hash, HashTree, RbTree};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+const LIQUIDATIONS_LABEL: &[u8] = b"liquidations";
+const SNAPSHOTS_LABEL: &[u8] = b"snapshots";
+
+/// Merkle tree behind the canister's certified data
+#[derive(Default)]
+pub struct CertifiedTree {
+    /// Liquidation event hashes keyed by big-endian event index
+    liquidations: RbTree<Vec<u8>, Hash>,
+    /// Solvency snapshot hashes keyed by big-endian snapshot ID
+    snapshots: RbTree<Vec<u8>, Hash>,
+}
+
+fn encode_tree(tree: &HashTree) -> Vec<u8> {
+    let mut serializer = serde_cbor::ser::Serializer::new(vec![]);
+    serializer.self_describe().unwrap();
+    tree.serialize(&mut serializer).unwrap();
+    serializer.into_inner()
+}
+
+impl CertifiedTree {
+    /// Adds a value's hash under the given liquidation event index and re-certifies the root
+    pub fn certify_liquidation<T: candid::CandidType>(&mut self, event_index: u64, event: &T) -> Hash {
+        let encoded = candid::encode_one(event).expect("Failed to encode liquidation event");
+        let event_hash: Hash = Sha256::digest(&encoded).into();
+        
+        self.liquidations.insert(event_index.to_be_bytes().to_vec(), event_hash);
+        self.update_certified_data();
+        
+        event_hash
+    }
+    
+    pub fn clear_liquidations(&mut self) {
+        self.liquidations = RbTree::new();
+        self.update_certified_data();
+    }
+    
+    /// Hash stored for a liquidation event
+    pub fn liquidation_hash(&self, event_index: u64) -> Option<Hash> {
+        self.liquidations.get(&event_index.to_be_bytes()).cloned()
+    }
+    
+    /// CBOR-encoded witness proving a liquidation event's hash is part of the certified root
+    pub fn liquidation_witness(&self, event_index: u64) -> Vec<u8> {
+        let witness = self.liquidations.witness(&event_index.to_be_bytes());
+        let snapshots = labeled_hash(SNAPSHOTS_LABEL, &self.snapshots.root_hash());
+        encode_tree(&fork(labeled(LIQUIDATIONS_LABEL, witness), HashTree::Pruned(snapshots)))
+    }
+    
+    /// Adds a solvency snapshot's hash and re-certifies the root
+    pub fn certify_snapshot(&mut self, snapshot_id: u64, snapshot_hash: Hash) {
+        self.snapshots.insert(snapshot_id.to_be_bytes().to_vec(), snapshot_hash);
+        self.update_certified_data();
+    }
+    
+    pub fn clear_snapshots(&mut self) {
+        self.snapshots = RbTree::new();
+        self.update_certified_data();
+    }
+    
+    /// CBOR-encoded witness proving a snapshot's hash is part of the certified root
+    pub fn snapshot_witness(&self, snapshot_id: u64) -> Vec<u8> {
+        let liquidations = labeled_hash(LIQUIDATIONS_LABEL, &self.liquidations.root_hash());
+        let witness = self.snapshots.witness(&snapshot_id.to_be_bytes());
+        encode_tree(&fork(HashTree::Pruned(liquidations), labeled(SNAPSHOTS_LABEL, witness)))
+    }
+    
+    /// Certifies the root over the `liquidations` and `snapshots` labels
+    fn update_certified_data(&self) {
+        let liquidations = labeled_hash(LIQUIDATIONS_LABEL, &self.liquidations.root_hash());
+        let snapshots = labeled_hash(SNAPSHOTS_LABEL, &self.snapshots.root_hash());
+        ic_cdk::api::set_certified_data(&fork_hash(&liquidations, &snapshots));
+    }
+}
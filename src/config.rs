@@ -0,0 +1,131 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::*;
+use crate::access_control::{AccessControl, Role};
+use crate::ledger_backend::{IusdLedger, LedgerBackend};
+use crate::price_feed::{OracleClient, OracleSettings};
+use crate::validation;
+use crate::vault_system::{CollateralType, VaultController};
+
+/// Ledger canisters of the collateral assets
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CollateralLedgers {
+    pub icp: Principal,
+    pub ckbtc: Principal,
+    pub cketh: Principal,
+}
+
+/// Installation and upgrade arguments
+#[derive(CandidType, Deserialize)]
+pub struct InitArgs {
+    /// Ledger iUSD is minted on: the built-in token or a reference ICRC-1 ledger whose minting
+    /// account is this canister
+    iusd_ledger: LedgerBackend,
+    collateral_ledgers: CollateralLedgers,
+    /// Principals granted the Admin role, which can then grant the other roles
+    admins: Vec<Principal>,
+    /// Maximum LTV per collateral type in basis points; types left out keep 75%
+    collateral_ratios: Vec<(CollateralType, u32)>,
+    oracle: OracleSettings,
+}
+
+/// Canister IDs set at install time
+#[derive(CandidType, Deserialize, Default)]
+pub struct ConfigRegistry {
+    collateral_ledgers: Option<CollateralLedgers>,
+}
+
+impl ConfigRegistry {
+    pub fn collateral_ledger(&self, collateral_type: &CollateralType) -> Option<Principal> {
+        let ledgers = self.collateral_ledgers.as_ref()?;
+        Some(match collateral_type {
+            CollateralType::ICP => ledgers.icp,
+            CollateralType::CkBTC => ledgers.ckbtc,
+            CollateralType::CkETH => ledgers.cketh,
+        })
+    }
+}
+
+/// Current configuration, read from the modules that own each setting
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ProtocolConfig {
+    iusd_ledger: Option<LedgerBackend>,
+    collateral_ledgers: Option<CollateralLedgers>,
+    admins: Vec<Principal>,
+    collateral_ratios: Vec<(CollateralType, u32)>,
+    /// Explicit liquidation thresholds in basis points; other types use 95% of their LTV
+    liquidation_thresholds: Vec<(CollateralType, u32)>,
+    min_collateral: Vec<(CollateralType, u128)>,
+    oracle: OracleSettings,
+}
+
+/// Stores the arguments in the modules that use them; every value is checked before any is
+/// applied
+pub(crate) fn apply(args: InitArgs) -> Result<(), String> {
+    for (collateral_type, ratio) in &args.collateral_ratios {
+        validation::check_ltv(collateral_type, *ratio)?;
+    }
+    
+    let oracle_client = ic_cdk::storage::get_mut::<OracleClient>();
+    oracle_client.configure(args.oracle)?;
+    
+    let iusd_ledger = ic_cdk::storage::get_mut::<IusdLedger>();
+    iusd_ledger.set_backend(args.iusd_ledger);
+    
+    let registry = ic_cdk::storage::get_mut::<ConfigRegistry>();
+    registry.collateral_ledgers = Some(args.collateral_ledgers);
+    
+    let access_control = ic_cdk::storage::get_mut::<AccessControl>();
+    for admin in args.admins {
+        access_control.grant(admin, Role::Admin);
+    }
+    
+    let controller = ic_cdk::storage::get_mut::<VaultController>();
+    for (collateral_type, ratio) in args.collateral_ratios {
+        controller.collateral_ratios.insert(collateral_type, ratio);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+impl ConfigRegistry {
+    /// Configured registry, for tests of saving and restoring state
+    pub(crate) fn sample() -> Self {
+        let ledger = Principal::from_slice(&[1]);
+        Self {
+            collateral_ledgers: Some(CollateralLedgers {
+                icp: ledger,
+                ckbtc: ledger,
+                cketh: ledger,
+            }),
+        }
+    }
+}
+
+// Canister endpoints
+#[query]
+fn get_protocol_config() -> ProtocolConfig {
+    let controller = ic_cdk::storage::get::<VaultController>();
+    ProtocolConfig {
+        iusd_ledger: ic_cdk::storage::get::<IusdLedger>().backend().ok().cloned(),
+        collateral_ledgers: ic_cdk::storage::get::<ConfigRegistry>().collateral_ledgers.clone(),
+        admins: ic_cdk::storage::get::<AccessControl>().members_of(Role::Admin),
+        collateral_ratios: controller.collateral_ratios
+            .iter()
+            .map(|(collateral_type, ratio)| (collateral_type.clone(), *ratio))
+            .collect(),
+        liquidation_thresholds: controller.liquidation_thresholds
+            .iter()
+            .map(|(collateral_type, threshold)| (collateral_type.clone(), *threshold))
+            .collect(),
+        min_collateral: controller.min_collateral
+            .iter()
+            .map(|(collateral_type, min_amount)| (collateral_type.clone(), *min_amount))
+            .collect(),
+        oracle: ic_cdk::storage::get::<OracleClient>().settings(),
+    }
+}
+
+#[query]
+fn get_collateral_ledgers() -> Option<CollateralLedgers> {
+    ic_cdk::storage::get::<ConfigRegistry>().collateral_ledgers.clone()
+}
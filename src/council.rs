@@ -0,0 +1,329 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::*;
+use std::collections::{BTreeMap, BTreeSet};
+use crate::access_control::{require_governance, AccessControl};
+use crate::audit_log;
+use crate::governance::GovernanceAction;
+
+/// Default time a proposal stays open for votes
+const DEFAULT_VOTING_PERIOD_SECONDS: u64 = 3 * 24 * 3600; // 3 days
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum ProposalStatus {
+    /// Accepting votes until the deadline
+    Open,
+    /// Reached quorum and is being executed
+    Approved,
+    Executed,
+    /// Reached quorum but the action returned an error
+    Failed(String),
+    /// Enough members voted against that quorum can no longer be reached
+    Rejected,
+    /// The voting period ended without quorum
+    Expired,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Proposal {
+    id: u64,
+    proposer: Principal,
+    action: GovernanceAction,
+    summary: String,
+    created_at: u64,
+    /// End of the voting period (seconds)
+    deadline: u64,
+    /// Each member's vote; true approves
+    votes: BTreeMap<Principal, bool>,
+    status: ProposalStatus,
+    /// When the proposal was executed or failed
+    executed_at: Option<u64>,
+}
+
+/// Council members and voting rules
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CouncilConfig {
+    members: Vec<Principal>,
+    /// Approvals needed to execute a proposal; 0 disables the council
+    quorum: u32,
+    voting_period_seconds: u64,
+}
+
+/// Principals voting on parameter changes before control passes to an SNS; approved proposals run
+/// through `execute_governance_action` like SNS proposals would
+#[derive(CandidType, Deserialize)]
+pub struct Council {
+    members: BTreeSet<Principal>,
+    quorum: u32,
+    voting_period_seconds: u64,
+    /// Every proposal, keyed by id
+    proposals: BTreeMap<u64, Proposal>,
+    next_proposal_id: u64,
+}
+
+impl Default for Council {
+    fn default() -> Self {
+        Self {
+            members: BTreeSet::new(),
+            quorum: 0,
+            voting_period_seconds: DEFAULT_VOTING_PERIOD_SECONDS,
+            proposals: BTreeMap::new(),
+            next_proposal_id: 0,
+        }
+    }
+}
+
+impl Proposal {
+    /// Status including expiry, which is only recorded once the proposal is touched again
+    fn status_at(&self, now: u64) -> ProposalStatus {
+        if self.status == ProposalStatus::Open && now >= self.deadline {
+            return ProposalStatus::Expired;
+        }
+        self.status.clone()
+    }
+}
+
+impl Council {
+    pub fn configure(&mut self, config: CouncilConfig) -> Result<(), String> {
+        let members: BTreeSet<Principal> = config.members.into_iter().collect();
+        if config.quorum as usize > members.len() {
+            return Err("Quorum must not exceed the number of members".to_string());
+        }
+        if config.quorum == 0 && !members.is_empty() {
+            return Err("A disabled council (quorum 0) must have no members".to_string());
+        }
+        if config.voting_period_seconds == 0 {
+            return Err("Voting period must be positive".to_string());
+        }
+        
+        self.members = members;
+        self.quorum = config.quorum;
+        self.voting_period_seconds = config.voting_period_seconds;
+        Ok(())
+    }
+    
+    pub fn config(&self) -> CouncilConfig {
+        CouncilConfig {
+            members: self.members.iter().copied().collect(),
+            quorum: self.quorum,
+            voting_period_seconds: self.voting_period_seconds,
+        }
+    }
+    
+    fn check_member(&self, principal: &Principal) -> Result<(), String> {
+        if self.quorum == 0 {
+            return Err("Council is disabled".to_string());
+        }
+        if !self.members.contains(principal) {
+            return Err("Unauthorized: not a council member".to_string());
+        }
+        Ok(())
+    }
+    
+    /// Opens a proposal with the proposer's approval already cast
+    pub fn propose(
+        &mut self,
+        action: GovernanceAction,
+        summary: String,
+        caller: Principal,
+        now: u64,
+    ) -> Result<u64, String> {
+        self.check_member(&caller)?;
+        
+        let id = self.next_proposal_id;
+        self.next_proposal_id += 1;
+        self.proposals.insert(id, Proposal {
+            id,
+            proposer: caller,
+            action,
+            summary,
+            created_at: now,
+            deadline: now + self.voting_period_seconds,
+            votes: BTreeMap::from([(caller, true)]),
+            status: ProposalStatus::Open,
+            executed_at: None,
+        });
+        self.tally(id);
+        Ok(id)
+    }
+    
+    /// Records a vote; members may change their vote while the proposal is open
+    pub fn vote(&mut self, id: u64, approve: bool, caller: Principal, now: u64) -> Result<ProposalStatus, String> {
+        self.check_member(&caller)?;
+        
+        let proposal = self.proposals.get_mut(&id).ok_or("Proposal not found")?;
+        match proposal.status_at(now) {
+            ProposalStatus::Open => {}
+            ProposalStatus::Expired => {
+                proposal.status = ProposalStatus::Expired;
+                return Err("Voting period has ended".to_string());
+            }
+            _ => return Err("Proposal is no longer open".to_string()),
+        }
+        
+        proposal.votes.insert(caller, approve);
+        Ok(self.tally(id))
+    }
+    
+    /// Moves an open proposal to Approved or Rejected once its outcome is settled; only votes of
+    /// current members count
+    fn tally(&mut self, id: u64) -> ProposalStatus {
+        let members = &self.members;
+        let proposal = self.proposals.get_mut(&id).unwrap();
+        
+        let (approvals, rejections) = proposal.votes
+            .iter()
+            .filter(|(voter, _)| members.contains(voter))
+            .fold((0u32, 0u32), |(yes, no), (_, approve)| if *approve { (yes + 1, no) } else { (yes, no + 1) });
+        if approvals >= self.quorum {
+            proposal.status = ProposalStatus::Approved;
+        } else if members.len() as u32 - rejections < self.quorum {
+            proposal.status = ProposalStatus::Rejected;
+        }
+        proposal.status.clone()
+    }
+    
+    fn record_execution(&mut self, id: u64, result: Result<(), String>, now: u64) -> ProposalStatus {
+        let proposal = self.proposals.get_mut(&id).unwrap();
+        proposal.status = match result {
+            Ok(()) => ProposalStatus::Executed,
+            Err(e) => ProposalStatus::Failed(e),
+        };
+        proposal.executed_at = Some(now);
+        proposal.status.clone()
+    }
+    
+    fn view(&self, proposal: &Proposal, now: u64) -> Proposal {
+        Proposal {
+            status: proposal.status_at(now),
+            ..proposal.clone()
+        }
+    }
+}
+
+/// The council only governs until a governance canister takes over
+pub(crate) fn check_not_retired() -> Result<(), String> {
+    if ic_cdk::storage::get::<AccessControl>().governance().is_some() {
+        return Err("Council is retired: a governance canister controls the protocol".to_string());
+    }
+    Ok(())
+}
+
+/// Runs an approved proposal as a call from the canister to itself, which holds every role
+async fn execute(id: u64) -> ProposalStatus {
+    let council = ic_cdk::storage::get::<Council>();
+    let action = council.proposals[&id].action.clone();
+    
+    // Checked again here, as the canister's own call would pass `require_governance`
+    let payload = check_not_retired().and_then(|()| {
+        candid::encode_one(action).map_err(|e| format!("Failed to encode proposal action: {}", e))
+    });
+    let result = match payload {
+        Ok(payload) => {
+            let call_result: Result<(Result<(), String>,), _> =
+                ic_cdk::call(ic_cdk::id(), "execute_governance_action", (payload,)).await;
+            match call_result {
+                Ok((result,)) => result,
+                Err((code, msg)) => Err(format!("Failed to execute proposal: {:?} - {}", code, msg)),
+            }
+        }
+        Err(e) => Err(e),
+    };
+    
+    let council = ic_cdk::storage::get_mut::<Council>();
+    council.record_execution(id, result, ic_cdk::api::time() / 1_000_000_000)
+}
+
+#[cfg(test)]
+impl Council {
+    /// Council with an open proposal, for tests of saving and restoring state
+    pub(crate) fn sample() -> Self {
+        let member = Principal::from_slice(&[1]);
+        let proposal = Proposal {
+            id: 0,
+            proposer: member,
+            action: GovernanceAction::PauseAllOperations,
+            summary: "Pause during the oracle incident".to_string(),
+            created_at: 1_000,
+            deadline: 4_600,
+            votes: BTreeMap::from([(member, true)]),
+            status: ProposalStatus::Open,
+            executed_at: None,
+        };
+        Self {
+            members: BTreeSet::from([member]),
+            quorum: 1,
+            voting_period_seconds: 3600,
+            proposals: BTreeMap::from([(0, proposal)]),
+            next_proposal_id: 1,
+        }
+    }
+}
+
+// Canister endpoints
+/// Sets the council and its voting rules; open proposals are tallied against the new members. No
+/// members and a quorum of 0 disable it
+#[update]
+pub(crate) fn set_council(config: CouncilConfig) -> Result<(), String> {
+    require_governance()?;
+    
+    let council = ic_cdk::storage::get_mut::<Council>();
+    let previous = council.config();
+    council.configure(config)?;
+    audit_log::record_change("set_council", &previous, &council.config());
+    Ok(())
+}
+
+#[query]
+fn get_council() -> CouncilConfig {
+    ic_cdk::storage::get::<Council>().config()
+}
+
+/// Opens a proposal to run a governance action; it executes as soon as it reaches quorum
+#[update]
+async fn submit_proposal(action: GovernanceAction, summary: String) -> Result<u64, String> {
+    check_not_retired()?;
+    
+    let council = ic_cdk::storage::get_mut::<Council>();
+    let id = council.propose(action, summary, ic_cdk::caller(), ic_cdk::api::time() / 1_000_000_000)?;
+    
+    // A single-member quorum approves on submission
+    if council.proposals[&id].status == ProposalStatus::Approved {
+        execute(id).await;
+    }
+    Ok(id)
+}
+
+/// Votes on an open proposal, executing it if this vote reaches quorum
+#[update]
+async fn vote_on_proposal(proposal_id: u64, approve: bool) -> Result<ProposalStatus, String> {
+    check_not_retired()?;
+    
+    let council = ic_cdk::storage::get_mut::<Council>();
+    let status = council.vote(proposal_id, approve, ic_cdk::caller(), ic_cdk::api::time() / 1_000_000_000)?;
+    
+    if status == ProposalStatus::Approved {
+        return Ok(execute(proposal_id).await);
+    }
+    Ok(status)
+}
+
+#[query]
+fn get_proposal(proposal_id: u64) -> Option<Proposal> {
+    let council = ic_cdk::storage::get::<Council>();
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    council.proposals.get(&proposal_id).map(|proposal| council.view(proposal, now))
+}
+
+/// Proposal history, newest first
+#[query]
+fn get_proposals(offset: u64, limit: u64) -> Vec<Proposal> {
+    let council = ic_cdk::storage::get::<Council>();
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    council.proposals
+        .values()
+        .rev()
+        .skip(offset as usize)
+        .take(limit as usize)
+        .map(|proposal| council.view(proposal, now))
+        .collect()
+}
@@ -0,0 +1,508 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::call::CallResult;
+use ic_cdk_macros::*;
+use ic_cdk_timers::TimerId;
+use std::time::Duration;
+use crate::access_control::{require_role, Role};
+use crate::audit_log;
+use crate::metrics::Metrics;
+use crate::price_feed::OracleClient;
+use crate::validation;
+use crate::vault_system::{self, Account, CollateralType};
+
+/// Cycles minting canister on mainnet
+const CMC_CANISTER_ID: &str = "rkp4c-7iaaa-aaaaa-aaaca-cai";
+/// Memo the CMC expects on ICP sent to top up a canister ("TPUP")
+const MEMO_TOP_UP_CANISTER: u64 = 0x5055_5054;
+/// ICP ledger transfer fee (e8s)
+const ICP_TRANSFER_FEE: u128 = 10_000;
+
+/// Settings for cycles monitoring and top-ups
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CyclesConfig {
+    /// Whether balances are checked on a timer; `get_cycles_status` reads them either way
+    enabled: bool,
+    /// Seconds between checks
+    interval_secs: u64,
+    /// Balance below which a canister is reported low and topped up
+    alert_threshold: u128,
+    /// Share of ICP redemption fees set aside for top-ups (in basis points); 0 turns top-ups off
+    fee_share: u32,
+    /// Most ICP (e8s) converted per top-up
+    max_top_up: u128,
+    cmc: Principal,
+}
+
+impl Default for CyclesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: 3600,
+            alert_threshold: 2_000_000_000_000, // 2T cycles
+            fee_share: 0,
+            max_top_up: 100_000_000, // 1 ICP
+            cmc: Principal::from_text(CMC_CANISTER_ID).unwrap(),
+        }
+    }
+}
+
+/// Last known balance of a monitored canister
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CanisterCycles {
+    canister_id: Principal,
+    /// None if the balance could not be read
+    balance: Option<u128>,
+    /// Below the alert threshold
+    low: bool,
+    /// When the balance was read (seconds)
+    checked_at: u64,
+}
+
+/// ICP converted to cycles for a canister
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TopUpEvent {
+    canister_id: Principal,
+    /// ICP sent to the CMC (e8s), excluding the ledger fee
+    icp_amount: u128,
+    /// ICP ledger block of the transfer
+    block_index: u64,
+    /// Cycles minted; None until the CMC confirms
+    cycles: Option<u128>,
+    error: Option<String>,
+    /// Not yet confirmed by the CMC, which is notified again on every check until it is
+    pending: bool,
+    timestamp: u64,
+}
+
+/// What the CMC made of a top-up transfer
+enum Notified {
+    Minted(u128),
+    /// The CMC is still processing the block or could not be reached; notifying again is safe,
+    /// as the CMC mints at most once per block
+    Retry(String),
+    Failed(String),
+}
+
+impl TopUpEvent {
+    fn record(&mut self, notified: Notified) {
+        match notified {
+            Notified::Minted(cycles) => {
+                self.cycles = Some(cycles);
+                self.error = None;
+                self.pending = false;
+            }
+            Notified::Retry(e) => {
+                self.error = Some(e);
+                self.pending = true;
+            }
+            Notified::Failed(e) => {
+                self.error = Some(e);
+                self.pending = false;
+            }
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CyclesStatus {
+    config: CyclesConfig,
+    /// The protocol canister first, then the oracle
+    canisters: Vec<CanisterCycles>,
+    /// ICP fees set aside for top-ups and not yet spent (e8s)
+    top_up_reserve: u128,
+    /// Top-ups oldest first
+    top_ups: Vec<TopUpEvent>,
+    last_error: Option<String>,
+}
+
+#[derive(CandidType)]
+struct TransferArg {
+    from_subaccount: Option<[u8; 32]>,
+    to: Account,
+    amount: u128,
+    fee: Option<u128>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum TransferError {
+    BadFee { expected_fee: u128 },
+    BadBurn { min_burn_amount: u128 },
+    InsufficientFunds { balance: u128 },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: u128 },
+    TemporarilyUnavailable,
+    GenericError { error_code: u128, message: String },
+}
+
+#[derive(CandidType)]
+struct NotifyTopUpArg {
+    block_index: u64,
+    canister_id: Principal,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum NotifyError {
+    Refunded { reason: String, block_index: Option<u64> },
+    Processing,
+    TransactionTooOld(u64),
+    InvalidTransaction(String),
+    Other { error_code: u64, error_message: String },
+}
+
+#[derive(Default)]
+pub struct CyclesMonitor {
+    config: CyclesConfig,
+    timer_id: Option<TimerId>,
+    /// Guards against overlapping checks when a check outlasts the interval; cleared by `RunGuard`
+    running: bool,
+    canisters: Vec<CanisterCycles>,
+    top_up_reserve: u128,
+    top_ups: Vec<TopUpEvent>,
+    last_error: Option<String>,
+}
+
+/// What of the monitor is saved across upgrades; the timer is restarted by `schedule`
+#[derive(CandidType, Deserialize, Default)]
+pub struct CyclesMonitorState {
+    config: CyclesConfig,
+    canisters: Vec<CanisterCycles>,
+    top_up_reserve: u128,
+    top_ups: Vec<TopUpEvent>,
+    last_error: Option<String>,
+}
+
+/// Clears `running` when dropped. Canister callbacks that trap drop their futures during cleanup,
+/// so a check that traps after an await can't block every later one
+#[must_use]
+struct RunGuard;
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        ic_cdk::storage::get_mut::<CyclesMonitor>().running = false;
+    }
+}
+
+/// CMC subaccount that credits ICP to a canister's top-up
+fn top_up_subaccount(canister_id: &Principal) -> [u8; 32] {
+    let bytes = canister_id.as_slice();
+    let mut subaccount = [0u8; 32];
+    subaccount[0] = bytes.len() as u8;
+    subaccount[1..1 + bytes.len()].copy_from_slice(bytes);
+    subaccount
+}
+
+async fn cycles_balance(canister_id: Principal) -> Result<u128, String> {
+    if canister_id == ic_cdk::id() {
+        return Ok(ic_cdk::api::canister_balance128());
+    }
+    let result: CallResult<(u128,)> = ic_cdk::call(canister_id, "cycles_balance", ()).await;
+    result
+        .map(|(cycles,)| cycles)
+        .map_err(|(code, msg)| format!("Failed to read cycles of {}: {:?} - {}", canister_id, code, msg))
+}
+
+async fn notify_top_up(cmc: Principal, block_index: u64, canister_id: Principal) -> Notified {
+    let notify = NotifyTopUpArg {
+        block_index,
+        canister_id,
+    };
+    let result: CallResult<(Result<u128, NotifyError>,)> =
+        ic_cdk::call(cmc, "notify_top_up", (notify,)).await;
+    match result {
+        Ok((Ok(cycles),)) => Notified::Minted(cycles),
+        Ok((Err(NotifyError::Processing),)) => {
+            Notified::Retry("CMC is still processing the top-up".to_string())
+        }
+        Ok((Err(e),)) => Notified::Failed(format!("CMC top-up failed: {:?}", e)),
+        Err((code, msg)) => Notified::Retry(format!("Failed to notify the CMC: {:?} - {}", code, msg)),
+    }
+}
+
+/// Canisters whose balances are watched: the protocol itself and the oracle it depends on
+fn monitored_canisters() -> Vec<Principal> {
+    let mut canisters = vec![ic_cdk::id()];
+    canisters.extend(ic_cdk::storage::get::<OracleClient>().settings().oracle_canister);
+    canisters
+}
+
+impl CyclesMonitor {
+    pub(crate) fn to_state(&self) -> CyclesMonitorState {
+        CyclesMonitorState {
+            config: self.config.clone(),
+            canisters: self.canisters.clone(),
+            top_up_reserve: self.top_up_reserve,
+            top_ups: self.top_ups.clone(),
+            last_error: self.last_error.clone(),
+        }
+    }
+    
+    /// Replaces everything but the timer, which keeps running until the next `schedule`
+    pub(crate) fn restore(&mut self, state: CyclesMonitorState) {
+        self.config = state.config;
+        self.canisters = state.canisters;
+        self.top_up_reserve = state.top_up_reserve;
+        self.top_ups = state.top_ups;
+        self.last_error = state.last_error;
+    }
+    
+    /// Starts or stops the recurring timer to match the config
+    pub fn schedule(&mut self) {
+        if let Some(timer_id) = self.timer_id.take() {
+            ic_cdk_timers::clear_timer(timer_id);
+        }
+        
+        if self.config.enabled {
+            let interval = Duration::from_secs(self.config.interval_secs);
+            self.timer_id = Some(ic_cdk_timers::set_timer_interval(interval, || {
+                ic_cdk::spawn(async {
+                    let monitor = ic_cdk::storage::get_mut::<CyclesMonitor>();
+                    monitor.run().await;
+                })
+            }));
+        }
+    }
+    
+    /// Sets aside the configured share of an ICP fee retained by the protocol
+    pub(crate) fn accrue_fee(&mut self, collateral_type: &CollateralType, fee_amount: u128) {
+        if *collateral_type == CollateralType::ICP {
+            self.top_up_reserve += fee_amount * self.config.fee_share as u128 / 10000;
+        }
+    }
+    
+    /// Reads every monitored balance, reporting low ones and topping them up from the reserve, after
+    /// notifying the CMC again of top-ups it has not confirmed
+    pub async fn run(&mut self) {
+        if self.running {
+            return;
+        }
+        self.running = true;
+        let _guard = RunGuard;
+        
+        self.retry_pending().await;
+        let now = ic_cdk::api::time() / 1_000_000_000;
+        let mut canisters = Vec::new();
+        for canister_id in monitored_canisters() {
+            let balance = match cycles_balance(canister_id).await {
+                Ok(balance) => Some(balance),
+                Err(e) => {
+                    self.last_error = Some(e);
+                    None
+                }
+            };
+            let low = balance.is_some_and(|balance| balance < self.config.alert_threshold);
+            if low {
+                ic_cdk::println!(
+                    "Cycles alert: {} holds {} cycles, below {}",
+                    canister_id,
+                    balance.unwrap_or_default(),
+                    self.config.alert_threshold
+                );
+                if self.config.fee_share > 0 {
+                    if let Err(e) = self.top_up(canister_id).await {
+                        self.last_error = Some(e);
+                    }
+                }
+            }
+            canisters.push(CanisterCycles {
+                canister_id,
+                balance,
+                low,
+                checked_at: now,
+            });
+        }
+        
+        self.canisters = canisters;
+    }
+    
+    /// Notifies the CMC again of top-ups it has not confirmed
+    async fn retry_pending(&mut self) {
+        for index in 0..self.top_ups.len() {
+            if !self.top_ups[index].pending {
+                continue;
+            }
+            let event = &self.top_ups[index];
+            let notified = notify_top_up(self.config.cmc, event.block_index, event.canister_id).await;
+            
+            let event = &mut self.top_ups[index];
+            event.record(notified);
+            if let Some(e) = &event.error {
+                self.last_error = Some(format!("Top-up at block {}: {}", event.block_index, e));
+            }
+        }
+    }
+    
+    /// Sends reserved ICP to the CMC and has it mint cycles for the canister
+    async fn top_up(&mut self, canister_id: Principal) -> Result<(), String> {
+        let icp_amount = self.top_up_reserve.min(self.config.max_top_up).saturating_sub(ICP_TRANSFER_FEE);
+        if icp_amount == 0 {
+            return Err("Not enough ICP fees reserved for a top-up".to_string());
+        }
+        
+        // Deducted before the transfer so overlapping top-ups can't overdraw the reserve
+        self.top_up_reserve -= icp_amount + ICP_TRANSFER_FEE;
+        let args = TransferArg {
+            from_subaccount: None,
+            to: Account {
+                owner: self.config.cmc,
+                subaccount: Some(top_up_subaccount(&canister_id)),
+            },
+            amount: icp_amount,
+            fee: Some(ICP_TRANSFER_FEE),
+            memo: Some(MEMO_TOP_UP_CANISTER.to_le_bytes().to_vec()),
+            created_at_time: None,
+        };
+        let icp_ledger = vault_system::collateral_canister_id(&CollateralType::ICP);
+        let result: CallResult<(Result<u128, TransferError>,)> =
+            ic_cdk::call(icp_ledger, "icrc1_transfer", (args,)).await;
+        let block_index = match result {
+            Ok((Ok(block_index),)) => block_index as u64,
+            Ok((Err(e),)) => {
+                self.top_up_reserve += icp_amount + ICP_TRANSFER_FEE;
+                return Err(format!("ICP transfer to the CMC rejected: {:?}", e));
+            }
+            Err((code, msg)) => {
+                self.top_up_reserve += icp_amount + ICP_TRANSFER_FEE;
+                return Err(format!("Failed to transfer ICP to the CMC: {:?} - {}", code, msg));
+            }
+        };
+        
+        let mut event = TopUpEvent {
+            canister_id,
+            icp_amount,
+            block_index,
+            cycles: None,
+            error: None,
+            pending: false,
+            timestamp: ic_cdk::api::time() / 1_000_000_000,
+        };
+        event.record(notify_top_up(self.config.cmc, block_index, canister_id).await);
+        let error = event.error.clone();
+        self.top_ups.push(event);
+        error.map_or(Ok(()), Err)
+    }
+}
+
+#[cfg(test)]
+impl CyclesMonitor {
+    /// Monitor with a reserve and a top-up, for tests of saving and restoring state
+    pub(crate) fn sample() -> Self {
+        let canister_id = Principal::from_slice(&[2]);
+        Self {
+            config: CyclesConfig {
+                enabled: true,
+                fee_share: 500,
+                ..Default::default()
+            },
+            canisters: vec![CanisterCycles {
+                canister_id,
+                balance: Some(1_000_000_000_000),
+                low: true,
+                checked_at: 1_000,
+            }],
+            top_up_reserve: 50_000_000,
+            top_ups: vec![TopUpEvent {
+                canister_id,
+                icp_amount: 99_990_000,
+                block_index: 42,
+                cycles: Some(500_000_000_000),
+                error: None,
+                pending: false,
+                timestamp: 1_000,
+            }],
+            last_error: Some("Failed to read cycles".to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+impl CyclesMonitor {
+    /// Balances as of the last check, the protocol's own read live, with the top-up reserve
+    pub(crate) fn write_metrics(&self, metrics: &mut Metrics) {
+        metrics.gauge(
+            "iusd_cycles_balance",
+            "Cycles held by the protocol canister",
+            ic_cdk::api::canister_balance128(),
+        );
+        let balances: Vec<(String, u128)> = self.canisters
+            .iter()
+            .filter_map(|canister| Some((canister.canister_id.to_text(), canister.balance?)))
+            .collect();
+        metrics.labeled_gauge(
+            "iusd_monitored_cycles_balance",
+            "Cycles of each monitored canister as of the last check",
+            "canister",
+            &balances,
+        );
+        metrics.gauge(
+            "iusd_cycles_alert_threshold",
+            "Balance below which a canister is reported low and topped up",
+            self.config.alert_threshold,
+        );
+        metrics.gauge(
+            "iusd_cycles_top_up_reserve_e8s",
+            "ICP fees set aside for top-ups and not yet spent",
+            self.top_up_reserve,
+        );
+        metrics.gauge("iusd_cycles_top_ups", "Top-ups sent to the CMC", self.top_ups.len() as u128);
+        metrics.gauge(
+            "iusd_cycles_top_ups_pending",
+            "Top-ups the CMC has not confirmed yet",
+            self.top_ups.iter().filter(|event| event.pending).count() as u128,
+        );
+    }
+}
+
+// Canister endpoints
+/// Cycle balances of the protocol and the oracle as of the last check, the protocol's own read
+/// live, with the top-up reserve and history
+#[query]
+fn get_cycles_status() -> CyclesStatus {
+    let monitor = ic_cdk::storage::get::<CyclesMonitor>();
+    let mut canisters = monitor.canisters.clone();
+    let balance = ic_cdk::api::canister_balance128();
+    let own = CanisterCycles {
+        canister_id: ic_cdk::id(),
+        balance: Some(balance),
+        low: balance < monitor.config.alert_threshold,
+        checked_at: ic_cdk::api::time() / 1_000_000_000,
+    };
+    match canisters.iter_mut().find(|canister| canister.canister_id == own.canister_id) {
+        Some(canister) => *canister = own,
+        None => canisters.insert(0, own),
+    }
+    
+    CyclesStatus {
+        config: monitor.config.clone(),
+        canisters,
+        top_up_reserve: monitor.top_up_reserve,
+        top_ups: monitor.top_ups.clone(),
+        last_error: monitor.last_error.clone(),
+    }
+}
+
+#[update]
+pub(crate) fn set_cycles_config(new_config: CyclesConfig) -> Result<(), String> {
+    require_role(Role::Treasurer)?;
+    
+    validation::check_positive("Cycles check interval", new_config.interval_secs)?;
+    validation::check_bps("Top-up fee share", new_config.fee_share)?;
+    
+    let monitor = ic_cdk::storage::get_mut::<CyclesMonitor>();
+    audit_log::record_change("set_cycles_config", &monitor.config, &new_config);
+    monitor.config = new_config;
+    monitor.schedule();
+    Ok(())
+}
+
+/// Checks balances and tops up low canisters now, without waiting for the timer
+#[update]
+async fn check_cycles() -> Result<CyclesStatus, String> {
+    require_role(Role::Treasurer)?;
+    
+    let monitor = ic_cdk::storage::get_mut::<CyclesMonitor>();
+    monitor.run().await;
+    Ok(get_cycles_status())
+}
@@ -0,0 +1,116 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::*;
+use crate::access_control::{self, require_governance, Role};
+use crate::council::{self, CouncilConfig};
+use crate::auction::{self, AuctionConfig};
+use crate::cycles::{self, CyclesConfig};
+use crate::keeper::{self, KeeperConfig};
+use crate::liquidation::{self, LiquidationConfig};
+use crate::liquidator_bonds::{self, BondConfig};
+use crate::pause::{self, Operation};
+use crate::price_feed;
+use crate::redemption::{self, RedemptionConfig};
+use crate::stability_pool::{self, StabilityPoolConfig};
+use crate::timelock::{self, RiskParamChange};
+use crate::vault_system::{self, CollateralType};
+
+/// A parameter change made through `execute_governance_action`, e.g. as the payload of an SNS
+/// generic-function proposal; each variant runs the endpoint of the same name
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum GovernanceAction {
+    GrantRole { principal: Principal, role: Role },
+    RevokeRole { principal: Principal, role: Role },
+    /// Nominates an admin, who still has to accept the role
+    ProposeAdmin(Principal),
+    CancelAdminProposal(Principal),
+    /// Risk parameters still go through the timelock, which executes them once the delay passes
+    ProposeRiskChange(RiskParamChange),
+    CancelRiskChange { change_id: u64 },
+    SetOperationPaused { operation: Operation, paused: bool },
+    PauseAllOperations,
+    UpdateLiquidationConfig(LiquidationConfig),
+    AddLiquidator(Principal),
+    RemoveLiquidator(Principal),
+    UpdateRedemptionConfig(RedemptionConfig),
+    UpdateStabilityPoolConfig(StabilityPoolConfig),
+    UpdateAuctionConfig(AuctionConfig),
+    SetReservePrice { collateral_type: CollateralType, reserve_price: u128 },
+    UpdateBondConfig(BondConfig),
+    SlashLiquidator { liquidator: Principal, amount: u128, reason: String },
+    SetKeeperConfig(KeeperConfig),
+    SetCyclesConfig(CyclesConfig),
+    SetVaultGracePeriod { grace_period: u64, grace_margin: u32 },
+    SetConfidenceThreshold(Option<u32>),
+    SetMinCollateral { collateral_type: CollateralType, min_amount: u128 },
+    SetOracleCanister(Principal),
+    SetOracleClientConfig { max_update_age_seconds: u64, sync_interval_seconds: u64 },
+    SetCouncil(CouncilConfig),
+}
+
+fn decode_action(payload: &[u8]) -> Result<GovernanceAction, String> {
+    candid::decode_one(payload).map_err(|e| format!("Invalid governance action: {}", e))
+}
+
+async fn dispatch(action: GovernanceAction) -> Result<(), String> {
+    match action {
+        GovernanceAction::GrantRole { principal, role } => access_control::grant_role(principal, role),
+        GovernanceAction::RevokeRole { principal, role } => access_control::revoke_role(principal, role),
+        GovernanceAction::ProposeAdmin(candidate) => access_control::propose_admin(candidate, false),
+        GovernanceAction::CancelAdminProposal(candidate) => access_control::cancel_admin_proposal(candidate),
+        GovernanceAction::ProposeRiskChange(change) => timelock::propose_risk_change(change).map(|_| ()),
+        GovernanceAction::CancelRiskChange { change_id } => timelock::cancel_risk_change(change_id),
+        GovernanceAction::SetOperationPaused { operation, paused } => {
+            pause::set_operation_paused(operation, paused).await
+        }
+        GovernanceAction::PauseAllOperations => pause::pause_all_operations().await,
+        GovernanceAction::UpdateLiquidationConfig(config) => liquidation::update_liquidation_config(config),
+        GovernanceAction::AddLiquidator(liquidator) => liquidation::add_liquidator(liquidator),
+        GovernanceAction::RemoveLiquidator(liquidator) => liquidation::remove_liquidator(liquidator),
+        GovernanceAction::UpdateRedemptionConfig(config) => redemption::update_redemption_config(config),
+        GovernanceAction::UpdateStabilityPoolConfig(config) => stability_pool::update_stability_pool_config(config),
+        GovernanceAction::UpdateAuctionConfig(config) => auction::update_auction_config(config),
+        GovernanceAction::SetReservePrice { collateral_type, reserve_price } => {
+            auction::set_reserve_price(collateral_type, reserve_price)
+        }
+        GovernanceAction::UpdateBondConfig(config) => liquidator_bonds::update_bond_config(config),
+        GovernanceAction::SlashLiquidator { liquidator, amount, reason } => {
+            liquidator_bonds::slash_liquidator(liquidator, amount, reason).map(|_| ())
+        }
+        GovernanceAction::SetKeeperConfig(config) => keeper::set_keeper_config(config),
+        GovernanceAction::SetCyclesConfig(config) => cycles::set_cycles_config(config),
+        GovernanceAction::SetVaultGracePeriod { grace_period, grace_margin } => {
+            vault_system::set_vault_grace_period(grace_period, grace_margin)
+        }
+        GovernanceAction::SetConfidenceThreshold(threshold) => vault_system::set_confidence_threshold(threshold),
+        GovernanceAction::SetMinCollateral { collateral_type, min_amount } => {
+            vault_system::set_min_collateral(collateral_type, min_amount)
+        }
+        GovernanceAction::SetOracleCanister(oracle) => price_feed::set_oracle_canister(oracle).await,
+        GovernanceAction::SetOracleClientConfig { max_update_age_seconds, sync_interval_seconds } => {
+            price_feed::set_oracle_client_config(max_update_age_seconds, sync_interval_seconds)
+        }
+        GovernanceAction::SetCouncil(config) => council::set_council(config),
+    }
+}
+
+// Canister endpoints
+/// Validator of the SNS generic function: checks the payload decodes and describes the action
+/// for the proposal
+#[query]
+fn validate_governance_action(payload: Vec<u8>) -> Result<String, String> {
+    let action = decode_action(&payload)?;
+    Ok(format!("{:?}", action))
+}
+
+/// Target of the SNS generic function: runs a candid-encoded `GovernanceAction`
+#[update]
+async fn execute_governance_action(payload: Vec<u8>) -> Result<(), String> {
+    require_governance()?;
+    // The canister only calls itself to run council proposals, which stop once governance is set
+    if ic_cdk::caller() == ic_cdk::id() {
+        council::check_not_retired()?;
+    }
+    
+    let action = decode_action(&payload)?;
+    dispatch(action).await
+}
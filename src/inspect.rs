@@ -0,0 +1,95 @@
+use candid::Principal;
+use ic_cdk_macros::*;
+
+/// Largest argument accepted from an ingress message; no endpoint takes more than a config
+/// record or a small list
+const MAX_ARG_BYTES: usize = 32 * 1024;
+/// Largest argument `restore_state` accepts, a snapshot chunk
+const MAX_RESTORE_ARG_BYTES: usize = 2 * 1024 * 1024;
+
+/// Update methods users may call directly, sorted for binary search. Callbacks only other
+/// canisters make, such as `on_oracle_price`, are left out: inter-canister calls skip inspection
+const INGRESS_METHODS: &[&str] = &[
+    "accept_admin",
+    "accept_governance_canister",
+    "add_liquidator",
+    "add_to_allowlist",
+    "approve_action",
+    "backup_state",
+    "bond_liquidator",
+    "cancel_action",
+    "cancel_admin_proposal",
+    "cancel_risk_change",
+    "check_cycles",
+    "check_liquidatable",
+    "claim_gains",
+    "create_vault",
+    "deposit_to_insurance_fund",
+    "execute_governance_action",
+    "execute_risk_change",
+    "get_liquidatable_vaults",
+    "grant_role",
+    "liquidate_vault",
+    "liquidate_vault_legs",
+    "mint_iusd",
+    "pause_all_operations",
+    "preview_liquidation",
+    "preview_liquidation_leg",
+    "propose_action",
+    "propose_admin",
+    "propose_risk_change",
+    "provide_to_stability_pool",
+    "redeem_iusd",
+    "remove_from_allowlist",
+    "remove_liquidator",
+    "repay_debt",
+    "request_unbond",
+    "restore_state",
+    "revoke_role",
+    "set_auto_compound",
+    "set_collateral_ratio",
+    "set_confidence_threshold",
+    "set_council",
+    "set_cycles_config",
+    "set_governance_canister",
+    "set_keeper_config",
+    "set_liquidation_threshold",
+    "set_min_collateral",
+    "set_operation_paused",
+    "set_oracle_canister",
+    "set_oracle_client_config",
+    "set_reserve_price",
+    "set_vault_grace_period",
+    "slash_liquidator",
+    "start_auction",
+    "submit_proposal",
+    "take_auction",
+    "take_solvency_snapshot",
+    "update_auction_config",
+    "update_bond_config",
+    "update_liquidation_config",
+    "update_redemption_config",
+    "update_stability_pool_config",
+    "vote_on_proposal",
+    "withdraw_bond",
+    "withdraw_collateral",
+    "withdraw_from_stability_pool",
+];
+
+/// Screens ingress messages before they are executed, so calls that would fail anyway don't
+/// cost the canister cycles
+fn should_accept(method: &str, caller: Principal, arg_bytes: usize) -> bool {
+    let max_arg_bytes = if method == "restore_state" { MAX_RESTORE_ARG_BYTES } else { MAX_ARG_BYTES };
+    INGRESS_METHODS.binary_search(&method).is_ok()
+        && caller != Principal::anonymous()
+        && arg_bytes <= max_arg_bytes
+}
+
+/// Messages that are not accepted are rejected without being executed
+#[inspect_message]
+fn inspect_message() {
+    let method = ic_cdk::api::call::method_name();
+    if should_accept(&method, ic_cdk::caller(), ic_cdk::api::call::arg_data_raw_size()) {
+        ic_cdk::api::call::accept_message();
+    }
+}
@@ -0,0 +1,123 @@
+use candid::{CandidType, Deserialize};
+use ic_cdk_macros::*;
+use std::collections::HashMap;
+use crate::ledger_backend::IusdLedger;
+use crate::vault_system::{self, Account, CollateralType, VaultController};
+
+/// Represents a draw on the insurance fund to clear an underwater vault
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct BackstopEvent {
+    /// ID of the vault whose shortfall was covered
+    vault_id: u64,
+    /// Amount of iUSD debt covered by the fund
+    shortfall: u128,
+    /// Fund balance after the draw
+    balance_after: u128,
+    /// Timestamp of the draw
+    timestamp: u64,
+}
+
+#[derive(CandidType, Deserialize, Default)]
+pub struct InsuranceFund {
+    /// iUSD held by the protocol on behalf of the fund
+    balance: u128,
+    /// History of shortfalls covered by the fund
+    backstops: Vec<BackstopEvent>,
+    /// Collateral acquired by the fund when it absorbs vaults
+    collateral_balances: HashMap<CollateralType, u128>,
+}
+
+impl InsuranceFund {
+    /// Current fund balance
+    pub fn balance(&self) -> u128 {
+        self.balance
+    }
+    
+    pub(crate) fn collateral_balance(&self, collateral_type: &CollateralType) -> u128 {
+        self.collateral_balances.get(collateral_type).copied().unwrap_or(0)
+    }
+    
+    /// Credits collateral taken over by the fund
+    pub(crate) fn credit_collateral(&mut self, collateral_type: &CollateralType, amount: u128) {
+        *self.collateral_balances.entry(collateral_type.clone()).or_insert(0) += amount;
+    }
+    
+    /// Adds iUSD to the fund
+    pub async fn deposit(&mut self, amount: u128) -> Result<(), String> {
+        // Depositors approve the protocol beforehand, so the ledger lets it pull the iUSD
+        vault_system::pull_iusd(ic_cdk::caller(), amount).await?;
+        
+        self.balance += amount;
+        Ok(())
+    }
+    
+    /// Pays fund iUSD out of the protocol, e.g. to the treasury; only through multi-approval
+    pub(crate) async fn withdraw(&mut self, to: Account, amount: u128) -> Result<(), String> {
+        if self.balance < amount {
+            return Err("Insufficient insurance fund balance".to_string());
+        }
+        
+        // Deducted before the transfer so concurrent withdrawals can't overdraw the fund
+        self.balance -= amount;
+        if let Err(e) = ic_cdk::storage::get::<IusdLedger>().transfer(to, amount).await {
+            self.balance += amount;
+            return Err(e);
+        }
+        Ok(())
+    }
+    
+    /// Burns fund iUSD to retire the debt an underwater vault's collateral can't cover
+    pub(crate) async fn cover_shortfall(&mut self, vault_id: u64, shortfall: u128) -> Result<BackstopEvent, String> {
+        if self.balance < shortfall {
+            return Err("Insurance fund cannot cover shortfall".to_string());
+        }
+        
+        // Deducted before the burn so draws across awaits can't overdraw the fund
+        self.balance -= shortfall;
+        let vault_controller = ic_cdk::storage::get::<VaultController>();
+        let from = Account {
+            owner: ic_cdk::id(),
+            subaccount: None,
+        };
+        if let Err(e) = vault_controller.burn_iusd_tokens(from, shortfall).await {
+            self.balance += shortfall;
+            return Err(e);
+        }
+        
+        let event = BackstopEvent {
+            vault_id,
+            shortfall,
+            balance_after: self.balance,
+            timestamp: ic_cdk::api::time(),
+        };
+        
+        self.backstops.push(event.clone());
+        
+        Ok(event)
+    }
+}
+
+// Canister endpoints
+#[update]
+async fn deposit_to_insurance_fund(amount: u128) -> Result<(), String> {
+    let fund = ic_cdk::storage::get_mut::<InsuranceFund>();
+    fund.deposit(amount).await
+}
+
+#[query]
+fn get_insurance_fund_balance() -> u128 {
+    let fund = ic_cdk::storage::get::<InsuranceFund>();
+    fund.balance()
+}
+
+#[query]
+fn get_insurance_fund_collateral() -> HashMap<CollateralType, u128> {
+    let fund = ic_cdk::storage::get::<InsuranceFund>();
+    fund.collateral_balances.clone()
+}
+
+#[query]
+fn get_backstop_events() -> Vec<BackstopEvent> {
+    let fund = ic_cdk::storage::get::<InsuranceFund>();
+    fund.backstops.clone()
+}
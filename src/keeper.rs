@@ -0,0 +1,291 @@
+use candid::{CandidType, Deserialize};
+use ic_cdk_macros::*;
+use ic_cdk_timers::TimerId;
+use std::time::Duration;
+use crate::access_control::{require_role, Role};
+use crate::audit_log;
+use crate::insurance_fund::InsuranceFund;
+use crate::liquidation::LiquidationController;
+use crate::pause::{self, Operation};
+use crate::price_feed;
+use crate::stability_pool::StabilityPool;
+use crate::validation;
+use crate::vault_system::{self, Account, VaultController};
+
+/// Configuration for the in-canister keeper
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct KeeperConfig {
+    /// Whether the keeper runs at all
+    enabled: bool,
+    /// Seconds between keeper runs
+    interval_secs: u64,
+    /// How long a vault must stay unsafe without an external liquidation before the keeper acts (nanoseconds)
+    grace_period: u64,
+}
+
+impl Default for KeeperConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: 60,
+            grace_period: 10 * 60_000_000_000, // 10 minutes
+        }
+    }
+}
+
+/// Current keeper state
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct KeeperStatus {
+    config: KeeperConfig,
+    /// Timestamp of the last completed run
+    last_run: u64,
+    /// Vaults liquidated in the last run
+    last_liquidated: Vec<u64>,
+    /// Most recent error, if any
+    last_error: Option<String>,
+}
+
+#[derive(Default)]
+pub struct Keeper {
+    config: KeeperConfig,
+    timer_id: Option<TimerId>,
+    /// Guards against overlapping runs when a run outlasts the interval; cleared by `RunGuard`
+    running: bool,
+    last_run: u64,
+    last_liquidated: Vec<u64>,
+    last_error: Option<String>,
+}
+
+/// What of the keeper is saved across upgrades; the timer is restarted by `schedule`
+#[derive(CandidType, Deserialize, Default)]
+pub struct KeeperState {
+    config: KeeperConfig,
+    last_run: u64,
+    last_liquidated: Vec<u64>,
+    last_error: Option<String>,
+}
+
+/// Clears `running` when dropped. Canister callbacks that trap drop their futures during cleanup,
+/// so a run that traps after an await can't block every later one
+#[must_use]
+struct RunGuard;
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        ic_cdk::storage::get_mut::<Keeper>().running = false;
+    }
+}
+
+impl Keeper {
+    pub(crate) fn to_state(&self) -> KeeperState {
+        KeeperState {
+            config: self.config.clone(),
+            last_run: self.last_run,
+            last_liquidated: self.last_liquidated.clone(),
+            last_error: self.last_error.clone(),
+        }
+    }
+    
+    /// Replaces everything but the timer, which keeps running until the next `schedule`
+    pub(crate) fn restore(&mut self, state: KeeperState) {
+        self.config = state.config;
+        self.last_run = state.last_run;
+        self.last_liquidated = state.last_liquidated;
+        self.last_error = state.last_error;
+    }
+    
+    /// Starts or stops the recurring timer to match the config
+    pub fn schedule(&mut self) {
+        if let Some(timer_id) = self.timer_id.take() {
+            ic_cdk_timers::clear_timer(timer_id);
+        }
+        
+        if self.config.enabled {
+            let interval = Duration::from_secs(self.config.interval_secs);
+            self.timer_id = Some(ic_cdk_timers::set_timer_interval(interval, || {
+                ic_cdk::spawn(async {
+                    let keeper = ic_cdk::storage::get_mut::<Keeper>();
+                    keeper.run().await;
+                })
+            }));
+        }
+    }
+    
+    /// Liquidates vaults that have stayed unsafe past the grace period
+    pub async fn run(&mut self) {
+        if self.running || pause::is_paused(Operation::Liquidate) {
+            return;
+        }
+        self.running = true;
+        let _guard = RunGuard;
+        
+        let liquidation_controller = ic_cdk::storage::get_mut::<LiquidationController>();
+        let now = ic_cdk::api::time();
+        let mut liquidated = Vec::new();
+        
+        match liquidation_controller.scan_vaults().await {
+            Ok(unsafe_vaults) => {
+                for vault_id in unsafe_vaults {
+                    let stale = liquidation_controller
+                        .unsafe_since(vault_id)
+                        .is_some_and(|since| now >= since + self.config.grace_period);
+                    if !stale || liquidation_controller.is_in_flight(vault_id) {
+                        continue;
+                    }
+                    
+                    match self.absorb_vault(vault_id).await {
+                        Ok(()) => liquidated.push(vault_id),
+                        Err(e) => self.last_error = Some(format!("Vault {}: {}", vault_id, e)),
+                    }
+                }
+            }
+            Err(e) => self.last_error = Some(e),
+        }
+        
+        self.last_run = now;
+        self.last_liquidated = liquidated;
+    }
+    
+    /// Moves a vault's debt into the stability pool, with the insurance fund taking any remainder
+    async fn absorb_vault(&self, vault_id: u64) -> Result<(), String> {
+        let vault_controller = ic_cdk::storage::get_mut::<VaultController>();
+        let liquidation_controller = ic_cdk::storage::get_mut::<LiquidationController>();
+        let pool = ic_cdk::storage::get_mut::<StabilityPool>();
+        let fund = ic_cdk::storage::get_mut::<InsuranceFund>();
+        
+        if liquidation_controller.is_in_flight(vault_id) {
+            return Err("Vault already claimed: liquidation in progress".to_string());
+        }
+        
+        let vault = vault_controller.vaults.get(&vault_id)
+            .ok_or("Vault not found")?
+            .clone();
+        if vault.debt_amount == 0 {
+            return Ok(());
+        }
+        
+        if price_feed::liquidations_paused(vault_system::price_feed_asset(&vault.collateral_type)) {
+            return Err("Liquidations paused pending price confirmation".to_string());
+        }
+        
+        if !liquidation_controller.within_quota(&vault.collateral_type, vault.debt_amount) {
+            return Err("Liquidation throughput cap reached for collateral type".to_string());
+        }
+        
+        let pool_debt = vault.debt_amount.min(pool.total_deposits());
+        let fund_debt = vault.debt_amount - pool_debt;
+        if fund_debt > fund.balance() {
+            return Err("Stability pool and insurance fund cannot absorb vault".to_string());
+        }
+        
+        // Held until this returns, so external liquidators can't race the awaits below
+        let _claim = liquidation_controller.claim_in_flight(vault_id, ic_cdk::id());
+        let mut pool_absorbed = 0;
+        let mut fund_absorbed = 0;
+        let mut collateral_absorbed = 0;
+        
+        // The owner or a redemption may change the vault during any await, so each leg reads it
+        // again to size its burn, and settles against the vault as it stands once the burn is done
+        let (debt, _) = absorbed_share(vault_controller, vault_id, pool_debt.min(pool.total_deposits()))?;
+        if debt > 0 {
+            // Pool deposits are held in the protocol account, burn them to retire the debt
+            let protocol_account = Account {
+                owner: ic_cdk::id(),
+                subaccount: None,
+            };
+            vault_controller.burn_iusd_tokens(protocol_account, debt).await?;
+            
+            // The burned deposits are gone either way, so the pool's books take all of `debt`; a
+            // vault that closed meanwhile gives up nothing
+            let (retired, collateral) = absorbed_share(vault_controller, vault_id, debt).unwrap_or((0, 0));
+            pool.offset(debt, collateral, &vault.collateral_type)?;
+            if retired > 0 {
+                vault_controller.reduce_position(vault_id, retired, collateral)?;
+            }
+            pool_absorbed = retired;
+            collateral_absorbed += collateral;
+        }
+        
+        let (debt, _) = absorbed_share(vault_controller, vault_id, fund_debt)?;
+        if debt > 0 {
+            fund.cover_shortfall(vault_id, debt).await?;
+            
+            let (retired, collateral) = absorbed_share(vault_controller, vault_id, debt).unwrap_or((0, 0));
+            fund.credit_collateral(&vault.collateral_type, collateral);
+            if retired > 0 {
+                vault_controller.reduce_position(vault_id, retired, collateral)?;
+            }
+            fund_absorbed = retired;
+            collateral_absorbed += collateral;
+        }
+        
+        liquidation_controller.record_protocol_liquidation(
+            vault_id,
+            &vault.collateral_type,
+            pool_absorbed,
+            collateral_absorbed,
+            fund_absorbed,
+        );
+        
+        Ok(())
+    }
+}
+
+/// Debt and collateral taken from a vault as it stands now when `debt` of it is absorbed;
+/// collateral follows the debt's share, and all of it goes once the debt is cleared
+fn absorbed_share(vault_controller: &VaultController, vault_id: u64, debt: u128) -> Result<(u128, u128), String> {
+    let vault = vault_controller.vaults.get(&vault_id)
+        .ok_or("Vault closed during liquidation")?;
+        
+    let debt = debt.min(vault.debt_amount);
+    let collateral = if debt == 0 {
+        0
+    } else if debt == vault.debt_amount {
+        vault.collateral_amount
+    } else {
+        vault.collateral_amount * debt / vault.debt_amount
+    };
+    Ok((debt, collateral))
+}
+
+#[cfg(test)]
+impl Keeper {
+    /// Enabled keeper after a run, for tests of saving and restoring state
+    pub(crate) fn sample() -> Self {
+        Self {
+            config: KeeperConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            last_run: 1_000,
+            last_liquidated: vec![3],
+            last_error: Some("Vault 4: Liquidation throughput cap reached".to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+// Canister endpoints
+#[query]
+fn get_keeper_status() -> KeeperStatus {
+    let keeper = ic_cdk::storage::get::<Keeper>();
+    KeeperStatus {
+        config: keeper.config.clone(),
+        last_run: keeper.last_run,
+        last_liquidated: keeper.last_liquidated.clone(),
+        last_error: keeper.last_error.clone(),
+    }
+}
+
+#[update]
+pub(crate) fn set_keeper_config(new_config: KeeperConfig) -> Result<(), String> {
+    require_role(Role::Admin)?;
+    
+    validation::check_positive("Keeper interval", new_config.interval_secs)?;
+    
+    let keeper = ic_cdk::storage::get_mut::<Keeper>();
+    audit_log::record_change("set_keeper_config", &keeper.config, &new_config);
+    keeper.config = new_config;
+    keeper.schedule();
+    Ok(())
+}
@@ -0,0 +1,245 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::call::CallResult;
+use ic_cdk_macros::*;
+use crate::vault_system::Account;
+
+/// Subaccount the protocol holds iUSD in on an external ledger, where its default account is
+/// the minting account and anything sent there is burned
+const PROTOCOL_SUBACCOUNT: [u8; 32] = [1; 32];
+
+/// Ledger iUSD lives on, chosen at install time
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum LedgerBackend {
+    /// The built-in token (the `iusd_token` canister), with the protocol as an authorized minter
+    BuiltIn { canister_id: Principal },
+    /// A reference ICRC-1/ICRC-2 ledger whose minting account is this canister: mints are
+    /// transfers from the minting account and burns are transfers to it
+    Icrc1 { canister_id: Principal },
+}
+
+/// Approval-queue actions of the built-in token the protocol proposes there; variant names
+/// match the token's `ApprovalAction`
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum TokenAction {
+    FreezeAccount { account: Account, reason: String },
+    AddMinter(Principal),
+    RemoveMinter(Principal),
+    RotateMinters(Vec<Principal>),
+}
+
+#[derive(CandidType)]
+pub(crate) struct TransferArg {
+    pub(crate) from_subaccount: Option<[u8; 32]>,
+    pub(crate) to: Account,
+    pub(crate) amount: u128,
+    pub(crate) fee: Option<u128>,
+    pub(crate) memo: Option<Vec<u8>>,
+    pub(crate) created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub(crate) enum TransferError {
+    BadFee { expected_fee: u128 },
+    BadBurn { min_burn_amount: u128 },
+    InsufficientFunds { balance: u128 },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: u128 },
+    TemporarilyUnavailable,
+    GenericError { error_code: u128, message: String },
+}
+
+#[derive(CandidType)]
+pub(crate) struct TransferFromArgs {
+    pub(crate) spender_subaccount: Option<[u8; 32]>,
+    pub(crate) from: Account,
+    pub(crate) to: Account,
+    pub(crate) amount: u128,
+    pub(crate) fee: Option<u128>,
+    pub(crate) memo: Option<Vec<u8>>,
+    pub(crate) created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub(crate) enum TransferFromError {
+    BadFee { expected_fee: u128 },
+    BadBurn { min_burn_amount: u128 },
+    InsufficientFunds { balance: u128 },
+    InsufficientAllowance { allowance: u128 },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: u128 },
+    TemporarilyUnavailable,
+    GenericError { error_code: u128, message: String },
+}
+
+/// The configured iUSD ledger
+#[derive(CandidType, Deserialize, Default)]
+pub struct IusdLedger {
+    backend: Option<LedgerBackend>,
+}
+
+impl IusdLedger {
+    pub fn set_backend(&mut self, backend: LedgerBackend) {
+        self.backend = Some(backend);
+    }
+    
+    pub fn backend(&self) -> Result<&LedgerBackend, String> {
+        self.backend.as_ref().ok_or_else(|| "iUSD ledger not configured".to_string())
+    }
+    
+    pub fn canister_id(&self) -> Result<Principal, String> {
+        match self.backend()? {
+            LedgerBackend::BuiltIn { canister_id } | LedgerBackend::Icrc1 { canister_id } => Ok(*canister_id),
+        }
+    }
+    
+    /// Subaccount of the protocol's own iUSD
+    fn protocol_subaccount(&self) -> Result<Option<[u8; 32]>, String> {
+        match self.backend()? {
+            LedgerBackend::BuiltIn { .. } => Ok(None),
+            LedgerBackend::Icrc1 { .. } => Ok(Some(PROTOCOL_SUBACCOUNT)),
+        }
+    }
+    
+    /// Account the protocol holds pulled iUSD in
+    pub fn protocol_account(&self) -> Result<Account, String> {
+        Ok(Account {
+            owner: ic_cdk::id(),
+            subaccount: self.protocol_subaccount()?,
+        })
+    }
+    
+    pub async fn mint(&self, to: Account, amount: u128) -> Result<(), String> {
+        let canister_id = self.canister_id()?;
+        match self.backend()? {
+            LedgerBackend::BuiltIn { .. } => {
+                let result: CallResult<(Result<(), String>,)> =
+                    ic_cdk::call(canister_id, "mint", (to, amount, None::<Vec<u8>>, None::<u64>)).await;
+                match result {
+                    Ok((result,)) => result,
+                    Err((code, msg)) => Err(format!("Failed to mint iUSD: {:?} - {}", code, msg)),
+                }
+            }
+            // Transfers out of the minting account mint and carry no fee
+            LedgerBackend::Icrc1 { .. } => self.icrc1_transfer(None, to, amount).await,
+        }
+    }
+    
+    /// Burns iUSD held by `from`; on an external ledger, anyone but the protocol must have
+    /// approved the protocol to spend the amount
+    pub async fn burn(&self, from: Account, amount: u128) -> Result<(), String> {
+        let canister_id = self.canister_id()?;
+        match self.backend()? {
+            LedgerBackend::BuiltIn { .. } => {
+                let result: CallResult<(Result<(), String>,)> =
+                    ic_cdk::call(canister_id, "burn", (from, amount, None::<Vec<u8>>)).await;
+                match result {
+                    Ok((result,)) => result,
+                    Err((code, msg)) => Err(format!("Failed to burn iUSD: {:?} - {}", code, msg)),
+                }
+            }
+            LedgerBackend::Icrc1 { .. } => {
+                let minting_account = Account {
+                    owner: ic_cdk::id(),
+                    subaccount: None,
+                };
+                if from.owner == ic_cdk::id() {
+                    self.icrc1_transfer(Some(PROTOCOL_SUBACCOUNT), minting_account, amount).await
+                } else {
+                    self.transfer_from(from, minting_account, amount).await
+                }
+            }
+        }
+    }
+    
+    /// Pulls iUSD that `from` approved the protocol to spend (ICRC-2) into the protocol's account
+    pub async fn pull(&self, from: Principal, amount: u128) -> Result<(), String> {
+        let from = Account {
+            owner: from,
+            subaccount: None,
+        };
+        self.transfer_from(from, self.protocol_account()?, amount).await
+    }
+    
+    /// Pauses or resumes holders' transfers on the built-in token, which never pauses its minters;
+    /// the protocol must hold the token's Guardian role
+    pub async fn set_transfers_paused(&self, paused: bool) -> Result<(), String> {
+        let canister_id = match self.backend()? {
+            LedgerBackend::BuiltIn { canister_id } => *canister_id,
+            LedgerBackend::Icrc1 { .. } => return Err("The iUSD ledger cannot pause transfers".to_string()),
+        };
+        let result: CallResult<(Result<(), String>,)> =
+            ic_cdk::call(canister_id, "set_transfers_paused", (paused,)).await;
+        match result {
+            Ok((result,)) => result,
+            Err((code, msg)) => Err(format!("Failed to pause iUSD transfers: {:?} - {}", code, msg)),
+        }
+    }
+    
+    /// Queues an action with the built-in token, where the protocol's proposal counts as one
+    /// approval: as a Guardian for freezes and as the minting governance for minter changes.
+    /// Returns the token's action ID
+    pub async fn propose_token_action(&self, action: TokenAction) -> Result<u64, String> {
+        let canister_id = match self.backend()? {
+            LedgerBackend::BuiltIn { canister_id } => *canister_id,
+            LedgerBackend::Icrc1 { .. } => return Err("The iUSD ledger has no blocklist or minters".to_string()),
+        };
+        let result: CallResult<(Result<u64, String>,)> =
+            ic_cdk::call(canister_id, "propose_action", (action,)).await;
+        match result {
+            Ok((result,)) => result,
+            Err((code, msg)) => Err(format!("Failed to propose token action: {:?} - {}", code, msg)),
+        }
+    }
+    
+    /// Sends iUSD from the protocol's account
+    pub async fn transfer(&self, to: Account, amount: u128) -> Result<(), String> {
+        self.icrc1_transfer(self.protocol_subaccount()?, to, amount).await
+    }
+    
+    async fn icrc1_transfer(&self, from_subaccount: Option<[u8; 32]>, to: Account, amount: u128) -> Result<(), String> {
+        let args = TransferArg {
+            from_subaccount,
+            to,
+            amount,
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        };
+        
+        let result: CallResult<(Result<u128, TransferError>,)> =
+            ic_cdk::call(self.canister_id()?, "icrc1_transfer", (args,)).await;
+        match result {
+            Ok((Ok(_),)) => Ok(()),
+            Ok((Err(e),)) => Err(format!("iUSD transfer rejected: {:?}", e)),
+            Err((code, msg)) => Err(format!("Failed to transfer iUSD: {:?} - {}", code, msg)),
+        }
+    }
+    
+    async fn transfer_from(&self, from: Account, to: Account, amount: u128) -> Result<(), String> {
+        let args = TransferFromArgs {
+            spender_subaccount: None,
+            from,
+            to,
+            amount,
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        };
+        
+        let result: CallResult<(Result<u128, TransferFromError>,)> =
+            ic_cdk::call(self.canister_id()?, "icrc2_transfer_from", (args,)).await;
+        match result {
+            Ok((Ok(_),)) => Ok(()),
+            Ok((Err(e),)) => Err(format!("iUSD transfer_from rejected: {:?}", e)),
+            Err((code, msg)) => Err(format!("Failed to transfer iUSD: {:?} - {}", code, msg)),
+        }
+    }
+}
+
+// Canister endpoints
+#[query]
+fn get_iusd_ledger() -> Option<LedgerBackend> {
+    ic_cdk::storage::get::<IusdLedger>().backend.clone()
+}
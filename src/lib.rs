@@ -1,31 +1,96 @@
 use ic_cdk_macros::*;
 mod vault_system;
+mod access_control;
+mod adaptive_bonus;
+#[cfg(feature = "allowlist")]
+mod allowlist;
+mod auction;
+mod audit_log;
+mod backup;
+mod certification;
+mod config;
+mod council;
+mod cycles;
+mod governance;
+mod price_feed;
+mod insurance_fund;
+mod inspect;
+mod keeper;
+mod ledger_backend;
+mod liquidation;
+mod liquidation_stats;
+mod liquidator_bonds;
+mod metrics;
+mod multi_approval;
+mod pause;
+mod redemption;
+mod snapshot;
+mod stable_state;
+mod stability_pool;
+mod timelock;
+mod validation;
+mod version;
 
 // Re-export types that need to be public
 pub use vault_system::{Vault, CollateralType, VaultController};
+pub use ledger_backend::LedgerBackend;
+pub use config::{CollateralLedgers, InitArgs};
 
-// Initialize the canister's state
-thread_local! {
-    static STATE: ic_cdk::storage::Storage<VaultController> = ic_cdk::storage::Storage::init(
-        VaultController::default()
-    );
+/// Parameters not covered by the install arguments
+fn set_defaults() {
+    let controller = ic_cdk::storage::get_mut::<VaultController>();
+    // Initialize default collateral ratios (75% LTV = 7500 basis points)
+    controller.collateral_ratios.insert(CollateralType::ICP, 7500);
+    controller.collateral_ratios.insert(CollateralType::CkBTC, 7500);
+    controller.collateral_ratios.insert(CollateralType::CkETH, 7500);
+    
+    // Initialize minimum collateral amounts (example values)
+    controller.min_collateral.insert(CollateralType::ICP, 1_000_000_000);    // 1 ICP
+    controller.min_collateral.insert(CollateralType::CkBTC, 100_000);        // 0.001 ckBTC
+    controller.min_collateral.insert(CollateralType::CkETH, 1_000_000);      // 0.01 ckETH
+    
+    // Grace period for fresh vaults (15 minutes, threshold widened by 10%)
+    controller.grace_period = 15 * 60_000_000_000;
+    controller.grace_margin = 1000;
 }
 
 #[init]
-fn init() {
-    STATE.with(|state| {
-        let mut controller = state.borrow_mut();
-        // Initialize default collateral ratios (75% LTV = 7500 basis points)
-        controller.collateral_ratios.insert(CollateralType::ICP, 7500);
-        controller.collateral_ratios.insert(CollateralType::CkBTC, 7500);
-        controller.collateral_ratios.insert(CollateralType::CkETH, 7500);
-        
-        // Initialize minimum collateral amounts (example values)
-        controller.min_collateral.insert(CollateralType::ICP, 1_000_000_000);    // 1 ICP
-        controller.min_collateral.insert(CollateralType::CkBTC, 100_000);        // 0.001 ckBTC
-        controller.min_collateral.insert(CollateralType::CkETH, 1_000_000);      // 0.01 ckETH
-    });
+fn init(args: InitArgs) {
+    set_defaults();
+    // Also starts polling the oracle canister as a backstop to its push updates
+    if let Err(e) = config::apply(args) {
+        ic_cdk::trap(&format!("Invalid init args: {}", e));
+    }
+}
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    stable_state::save();
+}
+
+/// Upgrades may pass new install arguments, applied the same way as on install. The argument is
+/// decoded by hand so upgrades that send none at all, as SNS root does by default, still succeed
+#[post_upgrade]
+fn post_upgrade() {
+    // Every module's state carries over and its timers restart; defaults only fill in a state
+    // that wasn't saved
+    if !stable_state::restore() {
+        set_defaults();
+        ic_cdk::storage::get_mut::<price_feed::OracleClient>().schedule();
+    }
+    let raw_args = ic_cdk::api::call::arg_data_raw();
+    let args = if raw_args.is_empty() {
+        None
+    } else {
+        candid::decode_one::<Option<InitArgs>>(&raw_args)
+            .unwrap_or_else(|e| ic_cdk::trap(&format!("Invalid upgrade args: {}", e)))
+    };
+    if let Some(args) = args {
+        if let Err(e) = config::apply(args) {
+            ic_cdk::trap(&format!("Invalid upgrade args: {}", e));
+        }
+    }
 }
 
 // Export the candid interface
-ic_cdk::export_candid!();
\ No newline at end of file
+ic_cdk::export_candid!();
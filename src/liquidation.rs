@@ -1,19 +1,80 @@
 use candid::{CandidType, Deserialize, Principal};
-use ic_cdk::api::call::CallResult;
 use ic_cdk_macros::*;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use crate::access_control::{require_role, Role};
+use crate::adaptive_bonus::{AdaptiveBonusConfig, BonusAdjustment};
+use crate::audit_log;
+use crate::certification::CertifiedTree;
+use crate::insurance_fund::InsuranceFund;
+use crate::ledger_backend::IusdLedger;
+use crate::liquidation_stats::{CollateralLiquidationStats, LiquidationStats, StatsRange};
+use crate::liquidator_bonds::BondRegistry;
+use crate::pause::{self, Operation};
+use crate::price_feed;
+use crate::validation::{self, MAX_LIQUIDATION_BONUS, MIN_LIQUIDATION_BONUS};
+use crate::vault_system::{self, Account, CollateralType, TransferArgs, VaultController};
 
 /// Configuration for liquidation parameters
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct LiquidationConfig {
     /// Liquidation bonus (in basis points, e.g. 1000 = 10% discount)
     liquidation_bonus: u32,
+    /// Per-collateral bonus overriding `liquidation_bonus` for that asset's leg
+    collateral_bonuses: HashMap<CollateralType, u32>,
     /// Maximum liquidation amount per transaction (in USD value)
     max_liquidation_amount: u128,
     /// Minimum liquidation amount per transaction (in USD value)
     min_liquidation_amount: u128,
+    /// Whether whitelisted liquidators may liquidate (bonded liquidators always can)
+    whitelist_enabled: bool,
     /// Whitelisted liquidator addresses
     liquidators: Vec<Principal>,
+    /// Maximum debt liquidated per collateral type per hour (no entry = uncapped)
+    hourly_caps: HashMap<CollateralType, u128>,
+    /// Maximum debt liquidated per collateral type per day (no entry = uncapped)
+    daily_caps: HashMap<CollateralType, u128>,
+    /// How liquidation opportunities are shared between liquidators
+    ordering: LiquidationOrdering,
+    /// How long an assigned liquidator has exclusive access to a vault (nanoseconds)
+    assignment_window: u64,
+    /// When set, the bonus adjusts automatically within these bounds
+    adaptive_bonus: Option<AdaptiveBonusConfig>,
+}
+
+/// Liquidation ordering schemes
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum LiquidationOrdering {
+    /// First valid call wins
+    Open,
+    /// Each newly unsafe vault is assigned to the next liquidator in rotation
+    RoundRobin,
+}
+
+/// Exclusive liquidation right over a vault
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct VaultAssignment {
+    /// Liquidator the vault is assigned to
+    liquidator: Principal,
+    /// When the assignment was made (nanoseconds)
+    assigned_at: u64,
+}
+
+/// Where seized collateral is delivered; defaults to the liquidator's default account
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct CollateralDestination {
+    /// Receiving principal (defaults to the liquidator)
+    owner: Option<Principal>,
+    /// Receiving subaccount
+    subaccount: Option<[u8; 32]>,
+}
+
+/// Debt to repay against one collateral leg of a vault
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct LegSelection {
+    /// Collateral leg to seize from
+    collateral_type: CollateralType,
+    /// Amount of debt repaid in exchange for this leg's collateral
+    debt_to_cover: u128,
 }
 
 /// Represents a liquidation event
@@ -27,80 +88,769 @@ pub struct LiquidationEvent {
     collateral_amount: u128,
     /// Address of the liquidator
     liquidator: Principal,
+    /// Principal that received the seized collateral
+    collateral_recipient: Principal,
+    /// Subaccount that received the seized collateral
+    recipient_subaccount: Option<[u8; 32]>,
     /// Timestamp of the liquidation
     timestamp: u64,
     /// Collateral type being liquidated
     collateral_type: CollateralType,
+    /// Debt covered by the insurance fund for an underwater vault
+    insurance_draw: u128,
+}
+
+/// Returned by `liquidate_vault`; the certificate itself is only obtainable in a query call,
+/// so fetch it with `get_liquidation_receipt(event_index)`
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct LiquidationReceipt {
+    /// The recorded event
+    event: LiquidationEvent,
+    /// Position of the event in the log
+    event_index: u64,
+    /// SHA-256 of the candid-encoded event, as stored in the certified tree
+    event_hash: Vec<u8>,
+}
+
+/// A liquidation receipt verifiable against the IC root key
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CertifiedLiquidationReceipt {
+    /// The recorded event
+    event: LiquidationEvent,
+    /// SHA-256 of the candid-encoded event
+    event_hash: Vec<u8>,
+    /// System certificate over the canister's certified data
+    certificate: Vec<u8>,
+    /// CBOR hash tree witnessing the event hash under the `liquidations` label
+    witness: Vec<u8>,
+}
+
+/// Page of liquidation events returned to cursor-based readers
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct LiquidationEventPage {
+    /// Events starting at the requested cursor
+    events: Vec<LiquidationEvent>,
+    /// Cursor to pass on the next call; equal to the input when no new events exist
+    next_cursor: u64,
+}
+
+/// Maximum number of events returned per page
+const MAX_EVENTS_PER_PAGE: u64 = 500;
+
+/// Result of simulating a liquidation without executing it
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct LiquidationPreview {
+    /// ID of the vault being previewed
+    vault_id: u64,
+    /// Collateral leg being seized
+    collateral_type: CollateralType,
+    /// Whether the vault is currently eligible for liquidation
+    is_liquidatable: bool,
+    /// Debt the caller asked to cover
+    requested_debt_to_cover: u128,
+    /// Amount of debt that would be repaid; for an underwater vault this is the price of the
+    /// whole leg, whatever was requested
+    debt_to_cover: u128,
+    /// Amount of collateral that would be seized, including the bonus
+    collateral_to_seize: u128,
+    /// Portion of the seized collateral paid out as liquidation bonus
+    bonus_amount: u128,
+    /// Collateral left in the vault after the liquidation
+    remaining_collateral: u128,
+    /// Debt left in the vault after the liquidation
+    remaining_debt: u128,
+    /// Whether the seized amount passes the min/max liquidation bounds
+    within_bounds: bool,
+    /// Whether the vault's collateral is worth less than its debt
+    is_underwater: bool,
+    /// Debt the insurance fund would cover to clear an underwater vault
+    insurance_draw: u128,
+    /// Whether the debt to cover fits in the remaining throughput quota
+    within_quota: bool,
 }
 
-#[derive(Default)]
+/// Debt liquidated for a collateral type in the current hour and day windows
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct ThroughputWindow {
+    /// Start of the current hourly window (nanoseconds)
+    hour_start: u64,
+    /// Debt liquidated in the current hourly window
+    hour_total: u128,
+    /// Start of the current daily window (nanoseconds)
+    day_start: u64,
+    /// Debt liquidated in the current daily window
+    day_total: u128,
+}
+
+/// Remaining liquidation throughput for a collateral type
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct LiquidationQuota {
+    /// Collateral type the quota applies to
+    collateral_type: CollateralType,
+    /// Debt that can still be liquidated this hour (None = uncapped)
+    hourly_remaining: Option<u128>,
+    /// Debt that can still be liquidated today (None = uncapped)
+    daily_remaining: Option<u128>,
+    /// When the hourly window resets (nanoseconds)
+    hour_resets_at: u64,
+    /// When the daily window resets (nanoseconds)
+    day_resets_at: u64,
+}
+
+const NANOS_PER_HOUR: u64 = 3_600_000_000_000;
+const NANOS_PER_DAY: u64 = 24 * NANOS_PER_HOUR;
+
+/// Saved to stable memory across upgrades
+#[derive(CandidType, Deserialize, Default)]
 pub struct LiquidationController {
     config: LiquidationConfig,
     events: Vec<LiquidationEvent>,
+    /// Liquidated debt per collateral type in the current time windows
+    throughput: HashMap<CollateralType, ThroughputWindow>,
+    /// When each currently unsafe vault was first seen as liquidatable
+    unsafe_since: HashMap<u64, u64>,
+    /// Round-robin assignments of unsafe vaults to liquidators
+    assignments: HashMap<u64, VaultAssignment>,
+    /// Position of the next liquidator in the rotation
+    next_assignee: usize,
+    /// Liquidations currently executing, keyed by vault, so racing calls fail fast
+    in_flight: HashMap<u64, Principal>,
+    /// Running liquidation totals for analytics
+    stats: LiquidationStats,
+    /// History of adaptive bonus adjustments
+    bonus_history: Vec<BonusAdjustment>,
+    /// Timestamp of the last adaptive bonus adjustment
+    last_bonus_adjustment: u64,
+}
+
+/// A vault's in-flight claim, released when dropped. Canister callbacks that trap drop their
+/// futures during cleanup, so the claim can't outlive a liquidation that traps after an await
+#[must_use]
+pub(crate) struct InFlightClaim {
+    vault_id: u64,
+}
+
+impl Drop for InFlightClaim {
+    fn drop(&mut self) {
+        ic_cdk::storage::get_mut::<LiquidationController>().in_flight.remove(&self.vault_id);
+    }
+}
+
+impl Default for LiquidationConfig {
+    /// Only whitelisted and bonded liquidators may liquidate until the config says otherwise
+    fn default() -> Self {
+        Self {
+            liquidation_bonus: 1000, // 10%
+            collateral_bonuses: HashMap::new(),
+            max_liquidation_amount: u128::MAX,
+            min_liquidation_amount: 0,
+            whitelist_enabled: true,
+            liquidators: Vec::new(),
+            hourly_caps: HashMap::new(),
+            daily_caps: HashMap::new(),
+            ordering: LiquidationOrdering::Open,
+            assignment_window: 0,
+            adaptive_bonus: None,
+        }
+    }
+}
+
+impl LiquidationConfig {
+    /// Rejects configurations that would silently brick liquidations
+    pub fn validate(&self) -> Result<(), String> {
+        validation::check_liquidation_bonus("Liquidation bonus", self.liquidation_bonus)?;
+        validation::check_min_below_max("liquidation amount", self.min_liquidation_amount, self.max_liquidation_amount)?;
+        
+        if self.whitelist_enabled && self.liquidators.is_empty() {
+            return Err("Liquidator whitelist is enabled but empty".to_string());
+        }
+        
+        if self.ordering == LiquidationOrdering::RoundRobin && self.assignment_window == 0 {
+            return Err("Round-robin ordering requires a non-zero assignment window".to_string());
+        }
+        
+        for bonus in self.collateral_bonuses.values() {
+            validation::check_liquidation_bonus("Collateral bonus", *bonus)?;
+        }
+        
+        if let Some(adaptive) = &self.adaptive_bonus {
+            adaptive.validate()?;
+            if adaptive.min_bonus < MIN_LIQUIDATION_BONUS || adaptive.max_bonus > MAX_LIQUIDATION_BONUS {
+                return Err("Adaptive bonus bounds exceed the allowed liquidation bonus range".to_string());
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Bonus paid on a collateral leg, falling back to the global bonus
+    fn bonus_for(&self, collateral_type: &CollateralType) -> u32 {
+        *self.collateral_bonuses.get(collateral_type).unwrap_or(&self.liquidation_bonus)
+    }
+    
+    /// Checks if a principal is an authorized whitelisted liquidator
+    fn is_whitelisted(&self, liquidator: &Principal) -> bool {
+        self.whitelist_enabled && self.liquidators.contains(liquidator)
+    }
+}
+
+impl ThroughputWindow {
+    /// Rolls the windows forward if they have expired
+    fn roll(&mut self, now: u64) {
+        let hour_start = now - now % NANOS_PER_HOUR;
+        if self.hour_start != hour_start {
+            self.hour_start = hour_start;
+            self.hour_total = 0;
+        }
+        
+        let day_start = now - now % NANOS_PER_DAY;
+        if self.day_start != day_start {
+            self.day_start = day_start;
+            self.day_total = 0;
+        }
+    }
 }
 
 impl LiquidationController {
+    pub(crate) fn liquidation_bonus(&self) -> u32 {
+        self.config.liquidation_bonus
+    }
+    
+    pub(crate) fn collateral_bonus(&self, collateral_type: &CollateralType) -> Option<u32> {
+        self.config.collateral_bonuses.get(collateral_type).copied()
+    }
+    
+    /// Replaces the global bonus; only the risk-parameter timelock changes bonuses
+    pub(crate) fn set_liquidation_bonus(&mut self, bonus: u32) -> Result<(), String> {
+        let mut config = self.config.clone();
+        config.liquidation_bonus = bonus;
+        config.validate()?;
+        self.config = config;
+        Ok(())
+    }
+    
+    /// Sets or, with None, removes a collateral type's bonus override
+    pub(crate) fn set_collateral_bonus(&mut self, collateral_type: &CollateralType, bonus: Option<u32>) -> Result<(), String> {
+        let mut config = self.config.clone();
+        match bonus {
+            Some(bonus) => config.collateral_bonuses.insert(collateral_type.clone(), bonus),
+            None => config.collateral_bonuses.remove(collateral_type),
+        };
+        config.validate()?;
+        self.config = config;
+        Ok(())
+    }
+    
+    pub(crate) fn adaptive_bonus(&self) -> Option<AdaptiveBonusConfig> {
+        self.config.adaptive_bonus.clone()
+    }
+    
+    /// Sets or, with None, turns off the adaptive bonus; only the risk-parameter timelock
+    /// changes it, as its bounds move the bonus
+    pub(crate) fn set_adaptive_bonus(&mut self, adaptive_bonus: Option<AdaptiveBonusConfig>) -> Result<(), String> {
+        let mut config = self.config.clone();
+        config.adaptive_bonus = adaptive_bonus;
+        config.validate()?;
+        self.config = config;
+        Ok(())
+    }
+    
+    /// Returns the remaining liquidation quota for a collateral type
+    pub fn get_quota(&self, collateral_type: &CollateralType) -> LiquidationQuota {
+        let now = ic_cdk::api::time();
+        let mut window = self.throughput.get(collateral_type).cloned().unwrap_or_default();
+        window.roll(now);
+        
+        LiquidationQuota {
+            collateral_type: collateral_type.clone(),
+            hourly_remaining: self.config.hourly_caps
+                .get(collateral_type)
+                .map(|cap| cap.saturating_sub(window.hour_total)),
+            daily_remaining: self.config.daily_caps
+                .get(collateral_type)
+                .map(|cap| cap.saturating_sub(window.day_total)),
+            hour_resets_at: window.hour_start + NANOS_PER_HOUR,
+            day_resets_at: window.day_start + NANOS_PER_DAY,
+        }
+    }
+    
+    /// Checks whether an amount of debt fits in the remaining quota
+    pub(crate) fn within_quota(&self, collateral_type: &CollateralType, debt_to_cover: u128) -> bool {
+        let quota = self.get_quota(collateral_type);
+        quota.hourly_remaining.is_none_or(|remaining| debt_to_cover <= remaining)
+            && quota.daily_remaining.is_none_or(|remaining| debt_to_cover <= remaining)
+    }
+    
+    /// Records liquidated debt against the throughput windows
+    fn record_throughput(&mut self, collateral_type: &CollateralType, debt_covered: u128) {
+        let window = self.throughput.entry(collateral_type.clone()).or_default();
+        window.roll(ic_cdk::api::time());
+        window.hour_total += debt_covered;
+        window.day_total += debt_covered;
+    }
+    
+    /// Eligible liquidators in a deterministic order
+    fn liquidator_roster(&self) -> Vec<Principal> {
+        let bond_registry = ic_cdk::storage::get::<BondRegistry>();
+        let roster: BTreeSet<Principal> = self.config.liquidators
+            .iter()
+            .filter(|liquidator| self.config.is_whitelisted(liquidator))
+            .cloned()
+            .chain(bond_registry.bonded_liquidators())
+            .collect();
+        roster.into_iter().collect()
+    }
+    
+    /// Assigns a newly unsafe vault to the next liquidator in rotation
+    fn assign_vault(&mut self, vault_id: u64) {
+        if self.config.ordering != LiquidationOrdering::RoundRobin || self.assignments.contains_key(&vault_id) {
+            return;
+        }
+        
+        let roster = self.liquidator_roster();
+        if roster.is_empty() {
+            return;
+        }
+        
+        let liquidator = roster[self.next_assignee % roster.len()];
+        self.next_assignee = self.next_assignee.wrapping_add(1);
+        self.assignments.insert(vault_id, VaultAssignment {
+            liquidator,
+            assigned_at: ic_cdk::api::time(),
+        });
+    }
+    
+    /// Cheap check run before any validation so race losers fail deterministically
+    fn check_claim(&self, vault_id: u64, caller: &Principal) -> Result<(), String> {
+        if self.in_flight.contains_key(&vault_id) {
+            return Err("Vault already claimed: liquidation in progress".to_string());
+        }
+        
+        if let Some(assignment) = self.assignments.get(&vault_id) {
+            let expires_at = assignment.assigned_at + self.config.assignment_window;
+            if assignment.liquidator != *caller && ic_cdk::api::time() < expires_at {
+                return Err("Vault already claimed: assigned to another liquidator".to_string());
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// When a vault was first seen as liquidatable, if it still is
+    pub(crate) fn unsafe_since(&self, vault_id: u64) -> Option<u64> {
+        self.unsafe_since.get(&vault_id).cloned()
+    }
+    
+    /// Checks if a liquidation of a vault is currently executing
+    pub(crate) fn is_in_flight(&self, vault_id: u64) -> bool {
+        self.in_flight.contains_key(&vault_id)
+    }
+    
+    /// Drops every in-flight claim; only for restored state, which no call can still be executing on
+    pub(crate) fn release_claims(&mut self) {
+        self.in_flight.clear();
+    }
+    
+    /// Marks a vault as being liquidated until the returned claim is dropped
+    pub(crate) fn claim_in_flight(&mut self, vault_id: u64, liquidator: Principal) -> InFlightClaim {
+        self.in_flight.insert(vault_id, liquidator);
+        InFlightClaim { vault_id }
+    }
+    
+    /// Records a liquidation performed by the protocol itself rather than an external liquidator
+    pub(crate) fn record_protocol_liquidation(
+        &mut self,
+        vault_id: u64,
+        collateral_type: &CollateralType,
+        debt_amount: u128,
+        collateral_amount: u128,
+        insurance_draw: u128,
+    ) -> LiquidationEvent {
+        let event = LiquidationEvent {
+            vault_id,
+            debt_amount,
+            collateral_amount,
+            liquidator: ic_cdk::id(),
+            collateral_recipient: ic_cdk::id(),
+            recipient_subaccount: None,
+            timestamp: ic_cdk::api::time(),
+            collateral_type: collateral_type.clone(),
+            insurance_draw,
+        };
+        
+        self.record_throughput(collateral_type, debt_amount + insurance_draw);
+        self.stats.record(vault_id, collateral_type, debt_amount, collateral_amount, 0, event.timestamp);
+        self.unsafe_since.remove(&vault_id);
+        self.assignments.remove(&vault_id);
+        self.push_event(event.clone());
+        
+        event
+    }
+    
+    /// Moves the bonus one step based on how long unsafe vaults have gone unliquidated
+    fn adjust_bonus(&mut self, now: u64) {
+        let adaptive = match &self.config.adaptive_bonus {
+            Some(adaptive) => adaptive.clone(),
+            None => return,
+        };
+        if now < self.last_bonus_adjustment + adaptive.adjustment_interval {
+            return;
+        }
+        
+        let oldest_unsafe_age = self.unsafe_since
+            .values()
+            .map(|since| now.saturating_sub(*since))
+            .max();
+        let old_bonus = self.config.liquidation_bonus;
+        let new_bonus = adaptive.next_bonus(old_bonus, oldest_unsafe_age);
+        
+        self.last_bonus_adjustment = now;
+        if new_bonus != old_bonus {
+            self.config.liquidation_bonus = new_bonus;
+            self.bonus_history.push(BonusAdjustment {
+                timestamp: now,
+                old_bonus,
+                new_bonus,
+                oldest_unsafe_age: oldest_unsafe_age.unwrap_or(0),
+                unsafe_vaults: self.unsafe_since.len() as u64,
+            });
+        }
+    }
+    
+    /// Rebuilds the certified tree from the event log, which the tree is not saved with
+    pub(crate) fn recertify_events(&self) {
+        let certified_tree = ic_cdk::storage::get_mut::<CertifiedTree>();
+        certified_tree.clear_liquidations();
+        for (event_index, event) in self.events.iter().enumerate() {
+            certified_tree.certify_liquidation(event_index as u64, event);
+        }
+    }
+    
+    /// Appends an event to the log and adds it to the certified tree
+    fn push_event(&mut self, event: LiquidationEvent) -> LiquidationReceipt {
+        let event_index = self.events.len() as u64;
+        let certified_tree = ic_cdk::storage::get_mut::<CertifiedTree>();
+        let event_hash = certified_tree.certify_liquidation(event_index, &event);
+        self.events.push(event.clone());
+        
+        LiquidationReceipt {
+            event,
+            event_index,
+            event_hash: event_hash.to_vec(),
+        }
+    }
+    
+    /// Returns events recorded at or after `cursor`; the cursor is the event's position in the log
+    pub fn events_since(&self, cursor: u64, limit: u64) -> LiquidationEventPage {
+        let start = (cursor as usize).min(self.events.len());
+        let limit = limit.min(MAX_EVENTS_PER_PAGE) as usize;
+        let events: Vec<_> = self.events[start..].iter().take(limit).cloned().collect();
+        
+        LiquidationEventPage {
+            next_cursor: (start + events.len()) as u64,
+            events,
+        }
+    }
+    
     /// Scans for vaults eligible for liquidation
-    pub async fn scan_vaults(&self) -> Result<Vec<u64>, String> {
+    pub async fn scan_vaults(&mut self) -> Result<Vec<u64>, String> {
         let vault_controller = ic_cdk::storage::get::<VaultController>();
         let mut liquidatable_vaults = Vec::new();
+        let now = ic_cdk::api::time();
         
-        // Iterate through all vaults
-        for (vault_id, _) in vault_controller.vaults.iter() {
-            if vault_controller.is_liquidatable(*vault_id).await? {
-                liquidatable_vaults.push(*vault_id);
+        // Only vaults flagged by the latest price push can be unsafe; a vault leaving its grace
+        // period is picked up on the next push
+        let candidates: Vec<u64> = vault_controller.unsafe_vaults.iter().copied().collect();
+        for vault_id in candidates {
+            if vault_controller.is_liquidatable(vault_id).await? {
+                liquidatable_vaults.push(vault_id);
+                self.unsafe_since.entry(vault_id).or_insert(now);
+                self.assign_vault(vault_id);
             }
         }
         
+        // Forget vaults that are safe again
+        self.unsafe_since.retain(|vault_id, _| liquidatable_vaults.contains(vault_id));
+        self.assignments.retain(|vault_id, _| liquidatable_vaults.contains(vault_id));
+        
+        self.adjust_bonus(now);
+        
         Ok(liquidatable_vaults)
     }
     
+    /// Simulates a liquidation on a vault without executing it
+    pub async fn preview_liquidation(
+        &self,
+        vault_id: u64,
+        debt_to_cover: u128,
+    ) -> Result<LiquidationPreview, String> {
+        let vault_controller = ic_cdk::storage::get::<VaultController>();
+        let vault = vault_controller.vaults.get(&vault_id)
+            .ok_or("Vault not found")?;
+            
+        self.preview_leg(vault_id, &vault.collateral_type, debt_to_cover).await
+    }
+    
+    /// Simulates seizing one collateral leg, priced with that asset's own oracle price,
+    /// decimals and bonus
+    pub async fn preview_leg(
+        &self,
+        vault_id: u64,
+        collateral_type: &CollateralType,
+        debt_to_cover: u128,
+    ) -> Result<LiquidationPreview, String> {
+        let vault_controller = ic_cdk::storage::get::<VaultController>();
+        let vault = vault_controller.vaults.get(&vault_id)
+            .ok_or("Vault not found")?;
+        let legs = vault.collateral_legs();
+        let leg_amount = legs
+            .iter()
+            .find(|(leg_type, _)| leg_type == collateral_type)
+            .map(|(_, amount)| *amount)
+            .ok_or("Vault holds no collateral of this type")?;
+            
+        // Liquidatability is judged on the whole vault against each collateral's own threshold
+        let is_liquidatable = vault_controller.is_liquidatable(vault_id).await?;
+        
+        let bonus = self.config.bonus_for(collateral_type) as u128;
+        let mut vault_value = 0;
+        let mut leg_value = 0;
+        for (leg_type, amount) in &legs {
+            let value = vault_controller.get_collateral_value(leg_type, *amount).await?;
+            if leg_type == collateral_type {
+                leg_value = value;
+            }
+            vault_value += value;
+        }
+        let is_underwater = vault_value < vault.debt_amount;
+        
+        let requested_debt_to_cover = debt_to_cover;
+        let (debt_to_cover, debt_in_collateral, collateral_to_seize, insurance_draw) = if is_underwater {
+            // No profitable liquidation exists: the liquidator buys the whole leg at the bonus
+            // discount, and once no other legs remain the insurance fund covers the remaining debt
+            let repay = leg_value * 10000 / (10000 + bonus);
+            let debt_in_collateral = leg_amount * 10000 / (10000 + bonus);
+            let insurance_draw = if vault_value == leg_value { vault.debt_amount - repay } else { 0 };
+            (repay, debt_in_collateral, leg_amount, insurance_draw)
+        } else {
+            // Convert the debt (iUSD, 8 decimals) into collateral base units at the oracle price
+            let debt_in_collateral = vault_controller
+                .get_collateral_amount_for_value(collateral_type, debt_to_cover)
+                .await?;
+            
+            // Calculate collateral to seize including bonus, capped at what the leg holds
+            let collateral_to_seize = (debt_in_collateral * (10000 + bonus) / 10000)
+                .min(leg_amount);
+            (debt_to_cover, debt_in_collateral, collateral_to_seize, 0)
+        };
+        let bonus_amount = collateral_to_seize.saturating_sub(debt_in_collateral);
+        
+        // Bounds are expressed in USD value
+        let seized_value = debt_to_cover * (10000 + bonus) / 10000;
+        let within_bounds = seized_value <= self.config.max_liquidation_amount
+            && seized_value >= self.config.min_liquidation_amount;
+        
+        Ok(LiquidationPreview {
+            vault_id,
+            collateral_type: collateral_type.clone(),
+            is_liquidatable,
+            requested_debt_to_cover,
+            debt_to_cover,
+            collateral_to_seize,
+            bonus_amount,
+            remaining_collateral: leg_amount.saturating_sub(collateral_to_seize),
+            remaining_debt: vault.debt_amount.saturating_sub(debt_to_cover + insurance_draw),
+            within_bounds,
+            is_underwater,
+            insurance_draw,
+            // Debt the fund retires counts towards the caps too, as it does for protocol liquidations
+            within_quota: self.within_quota(collateral_type, debt_to_cover + insurance_draw),
+        })
+    }
+    
     /// Executes a liquidation on a vault
     pub async fn execute_liquidation(
         &mut self,
         vault_id: u64,
         debt_to_cover: u128,
-    ) -> Result<LiquidationEvent, String> {
-        // Verify caller is whitelisted liquidator
+        destination: CollateralDestination,
+    ) -> Result<LiquidationReceipt, String> {
+        pause::check_not_paused(Operation::Liquidate)?;
+        let vault_controller = ic_cdk::storage::get::<VaultController>();
+        let collateral_type = vault_controller.vaults.get(&vault_id)
+            .ok_or("Vault not found")?
+            .collateral_type
+            .clone();
+            
+        let legs = vec![LegSelection {
+            collateral_type,
+            debt_to_cover,
+        }];
+        let mut receipts = self.execute_leg_liquidation(vault_id, legs, destination).await?;
+        Ok(receipts.remove(0))
+    }
+    
+    /// Executes a liquidation seizing the chosen collateral legs, one event per leg
+    pub async fn execute_leg_liquidation(
+        &mut self,
+        vault_id: u64,
+        legs: Vec<LegSelection>,
+        destination: CollateralDestination,
+    ) -> Result<Vec<LiquidationReceipt>, String> {
+        pause::check_not_paused(Operation::Liquidate)?;
+        if legs.is_empty() {
+            return Err("No collateral legs selected".to_string());
+        }
+        for (i, leg) in legs.iter().enumerate() {
+            if legs[..i].iter().any(|other| other.collateral_type == leg.collateral_type) {
+                return Err("Collateral leg selected more than once".to_string());
+            }
+        }
+        
+        // Verify caller is a whitelisted or bonded liquidator
         let caller = ic_cdk::caller();
-        if !self.config.liquidators.contains(&caller) {
+        let bond_registry = ic_cdk::storage::get::<BondRegistry>();
+        let is_bonded = bond_registry.is_bonded(&caller);
+        if !is_bonded && !self.config.is_whitelisted(&caller) {
             return Err("Unauthorized liquidator".to_string());
         }
         
-        let mut vault_controller = ic_cdk::storage::get_mut::<VaultController>();
+        self.check_claim(vault_id, &caller)?;
+        
+        // Hold the claim across the awaits below
+        let claim = self.claim_in_flight(vault_id, caller);
+        let mut receipts = Vec::new();
+        let mut failure = None;
+        for leg in legs {
+            let collateral_type = leg.collateral_type.clone();
+            match self
+                .execute_claimed_liquidation(vault_id, leg, caller, is_bonded, destination.clone())
+                .await
+            {
+                Ok(receipt) => receipts.push(receipt),
+                Err(e) => {
+                    failure = Some((collateral_type, e));
+                    break;
+                }
+            }
+        }
+        drop(claim);
+        
+        // Legs that already executed stand and remain in the event log
+        let result = match failure {
+            None => Ok(receipts),
+            Some((_, e)) if receipts.is_empty() => Err(e),
+            Some((collateral_type, e)) => Err(format!(
+                "{} leg(s) liquidated before the {:?} leg failed: {}",
+                receipts.len(), collateral_type, e
+            )),
+        };
+        
+        if result.is_ok() {
+            self.assignments.remove(&vault_id);
+        }
+        result
+    }
+    
+    async fn execute_claimed_liquidation(
+        &mut self,
+        vault_id: u64,
+        leg: LegSelection,
+        caller: Principal,
+        is_bonded: bool,
+        destination: CollateralDestination,
+    ) -> Result<LiquidationReceipt, String> {
+        let bond_registry = ic_cdk::storage::get::<BondRegistry>();
+        let vault_controller = ic_cdk::storage::get_mut::<VaultController>();
         let vault = vault_controller.vaults.get(&vault_id)
-            .ok_or("Vault not found")?;
+            .ok_or("Vault not found")?
+            .clone();
             
+        if leg.debt_to_cover > vault.debt_amount {
+            return Err("Debt to cover exceeds vault debt".to_string());
+        }
+        
+        let collateral_type = leg.collateral_type;
+        let preview = self.preview_leg(vault_id, &collateral_type, leg.debt_to_cover).await?;
+        
         // Verify vault is actually liquidatable
-        if !vault_controller.is_liquidatable(vault_id).await? {
+        if !preview.is_liquidatable {
             return Err("Vault is not liquidatable".to_string());
         }
         
-        // Calculate collateral to seize including bonus
-        let collateral_value = vault_controller
-            .get_collateral_value(&vault.collateral_type, vault.collateral_amount)
-            .await?;
+        if price_feed::liquidations_paused(vault_system::price_feed_asset(&collateral_type)) {
+            return Err("Liquidations paused pending price confirmation".to_string());
+        }
         
-        let bonus_multiplier = (10000 + self.config.liquidation_bonus) as f64 / 10000.0;
-        let collateral_to_seize = (debt_to_cover as f64 * bonus_multiplier) as u128;
+        // Bonded liquidators get exclusive access right after a vault turns unsafe
+        let now = ic_cdk::api::time();
+        let unsafe_since = *self.unsafe_since.entry(vault_id).or_insert(now);
+        if !is_bonded && now < unsafe_since + bond_registry.config.priority_period {
+            return Err("Vault is reserved for bonded liquidators".to_string());
+        }
+        
+        // An underwater leg is sold whole, so never pull more iUSD than the liquidator agreed to
+        if preview.debt_to_cover > preview.requested_debt_to_cover {
+            return Err(format!(
+                "Vault is underwater: buying the {:?} leg whole repays {} debt, more than the {} requested",
+                collateral_type, preview.debt_to_cover, preview.requested_debt_to_cover
+            ));
+        }
         
         // Verify liquidation amount is within bounds
-        if collateral_to_seize > self.config.max_liquidation_amount 
-            || collateral_to_seize < self.config.min_liquidation_amount {
+        if !preview.within_bounds {
             return Err("Invalid liquidation amount".to_string());
         }
         
+        // Throttle cascades by capping debt liquidated per time window
+        if !preview.within_quota {
+            return Err("Liquidation throughput cap reached for collateral type".to_string());
+        }
+        
+        // Underwater vaults are cleared in full, with the shortfall drawn from the insurance fund
+        let insurance_fund = ic_cdk::storage::get_mut::<InsuranceFund>();
+        if preview.insurance_draw > insurance_fund.balance() {
+            return Err("Insurance fund cannot cover shortfall".to_string());
+        }
+        let debt_to_cover = preview.debt_to_cover;
+        let collateral_to_seize = preview.collateral_to_seize;
+        
         // Execute the token transfers
         // 1. Transfer iUSD from liquidator to protocol
         self.transfer_iusd_to_protocol(caller, debt_to_cover).await?;
         
-        // 2. Transfer collateral to liquidator
-        self.transfer_collateral_to_liquidator(
-            vault_id,
-            caller,
+        // 2. Retire any shortfall with insurance fund iUSD while nothing has left the protocol
+        if preview.insurance_draw > 0 {
+            if let Err(e) = insurance_fund.cover_shortfall(vault_id, preview.insurance_draw).await {
+                self.refund_iusd(caller, debt_to_cover).await
+                    .map_err(|refund_error| format!("{}; refund failed: {}", e, refund_error))?;
+                return Err(e);
+            }
+        }
+        
+        // 3. Transfer collateral to the liquidator's chosen account
+        let recipient = Account {
+            owner: destination.owner.unwrap_or(caller),
+            subaccount: destination.subaccount,
+        };
+        let collateral_recipient = recipient.owner;
+        let recipient_subaccount = recipient.subaccount;
+        if let Err(e) = self.transfer_collateral_to_liquidator(
+            recipient,
             collateral_to_seize,
-            vault.collateral_type.clone(),
-        ).await?;
+            collateral_type.clone(),
+        ).await {
+            // Hand the pulled iUSD back rather than keep it against unchanged debt; the fund's
+            // draw is already burned, so the debt it retired comes off the vault
+            let retired = vault_controller.reduce_position(vault_id, preview.insurance_draw, 0);
+            self.refund_iusd(caller, debt_to_cover).await
+                .map_err(|refund_error| format!("{}; refund failed: {}", e, refund_error))?;
+            retired?;
+            return Err(e);
+        }
+        
+        // 4. Remove the repaid debt and seized collateral from the vault
+        vault_controller.reduce_position(vault_id, debt_to_cover + preview.insurance_draw, collateral_to_seize)?;
         
         // Record the liquidation event
         let event = LiquidationEvent {
@@ -108,13 +858,24 @@ impl LiquidationController {
             debt_amount: debt_to_cover,
             collateral_amount: collateral_to_seize,
             liquidator: caller,
+            collateral_recipient,
+            recipient_subaccount,
             timestamp: ic_cdk::api::time(),
-            collateral_type: vault.collateral_type.clone(),
+            collateral_type: collateral_type.clone(),
+            insurance_draw: preview.insurance_draw,
         };
         
-        self.events.push(event.clone());
+        self.record_throughput(&collateral_type, debt_to_cover + preview.insurance_draw);
+        self.stats.record(
+            vault_id,
+            &collateral_type,
+            debt_to_cover,
+            collateral_to_seize,
+            preview.bonus_amount,
+            event.timestamp,
+        );
         
-        Ok(event)
+        Ok(self.push_event(event))
     }
     
     async fn transfer_iusd_to_protocol(
@@ -122,52 +883,30 @@ impl LiquidationController {
         from: Principal,
         amount: u128,
     ) -> Result<(), String> {
-        let iusd_canister = Principal::from_text("CANISTER-ID-HERE").unwrap();
-        
-        let from_account = Account {
-            owner: from,
-            subaccount: None,
-        };
-        
-        let to_account = Account {
-            owner: ic_cdk::id(),  // Protocol's address
+        // The liquidator approves the protocol beforehand, so the ledger lets it pull the iUSD
+        vault_system::pull_iusd(from, amount).await
+    }
+    
+    /// Returns iUSD pulled from a liquidator whose liquidation did not go through
+    async fn refund_iusd(&self, to: Principal, amount: u128) -> Result<(), String> {
+        let to = Account {
+            owner: to,
             subaccount: None,
         };
-        
-        // Call iUSD transfer function
-        let args = TransferArgs {
-            from: from_account,
-            to: to_account,
-            amount,
-        };
-        
-        match ic_cdk::call(iusd_canister, "transfer", (args,)).await {
-            Ok(()) => Ok(()),
-            Err((code, msg)) => Err(format!("Failed to transfer iUSD: {:?} - {}", code, msg))
-        }
+        ic_cdk::storage::get::<IusdLedger>().transfer(to, amount).await
     }
     
     async fn transfer_collateral_to_liquidator(
         &self,
-        vault_id: u64,
-        to: Principal,
+        to: Account,
         amount: u128,
         collateral_type: CollateralType,
     ) -> Result<(), String> {
-        let collateral_canister = match collateral_type {
-            CollateralType::ICP => Principal::from_text("ICP-LEDGER-CANISTER-ID").unwrap(),
-            CollateralType::CkBTC => Principal::from_text("CKBTC-CANISTER-ID").unwrap(),
-            CollateralType::CkETH => Principal::from_text("CKETH-CANISTER-ID").unwrap(),
-        };
-        
-        let to_account = Account {
-            owner: to,
-            subaccount: None,
-        };
+        let collateral_canister = vault_system::collateral_canister_id(&collateral_type);
         
         // Call appropriate transfer function based on collateral type
         let args = TransferArgs {
-            to: to_account,
+            to,
             amount,
         };
         
@@ -178,17 +917,81 @@ impl LiquidationController {
     }
 }
 
+#[cfg(test)]
+impl LiquidationController {
+    /// Controller with a whitelisted liquidator and an unsafe vault, for tests of saving and
+    /// restoring state
+    pub(crate) fn sample() -> Self {
+        Self {
+            config: LiquidationConfig {
+                liquidation_bonus: 700,
+                liquidators: vec![Principal::from_slice(&[1])],
+                ..Default::default()
+            },
+            unsafe_since: HashMap::from([(3, 1_000)]),
+            last_bonus_adjustment: 1_000,
+            ..Default::default()
+        }
+    }
+}
+
 // Canister endpoints for liquidation bot interface
 #[update]
 async fn get_liquidatable_vaults() -> Result<Vec<u64>, String> {
-    let liquidation_controller = ic_cdk::storage::get::<LiquidationController>();
+    let liquidation_controller = ic_cdk::storage::get_mut::<LiquidationController>();
     liquidation_controller.scan_vaults().await
 }
 
 #[update]
-async fn liquidate_vault(vault_id: u64, debt_to_cover: u128) -> Result<LiquidationEvent, String> {
-    let mut liquidation_controller = ic_cdk::storage::get_mut::<LiquidationController>();
-    liquidation_controller.execute_liquidation(vault_id, debt_to_cover).await
+async fn liquidate_vault(
+    vault_id: u64,
+    debt_to_cover: u128,
+    destination: Option<CollateralDestination>,
+) -> Result<LiquidationReceipt, String> {
+    let liquidation_controller = ic_cdk::storage::get_mut::<LiquidationController>();
+    liquidation_controller
+        .execute_liquidation(vault_id, debt_to_cover, destination.unwrap_or_default())
+        .await
+}
+
+#[update]
+async fn liquidate_vault_legs(
+    vault_id: u64,
+    legs: Vec<LegSelection>,
+    destination: Option<CollateralDestination>,
+) -> Result<Vec<LiquidationReceipt>, String> {
+    let liquidation_controller = ic_cdk::storage::get_mut::<LiquidationController>();
+    liquidation_controller
+        .execute_leg_liquidation(vault_id, legs, destination.unwrap_or_default())
+        .await
+}
+
+#[update]
+async fn preview_liquidation(vault_id: u64, debt_to_cover: u128) -> Result<LiquidationPreview, String> {
+    let liquidation_controller = ic_cdk::storage::get::<LiquidationController>();
+    liquidation_controller.preview_liquidation(vault_id, debt_to_cover).await
+}
+
+#[update]
+async fn preview_liquidation_leg(
+    vault_id: u64,
+    collateral_type: CollateralType,
+    debt_to_cover: u128,
+) -> Result<LiquidationPreview, String> {
+    let liquidation_controller = ic_cdk::storage::get::<LiquidationController>();
+    liquidation_controller.preview_leg(vault_id, &collateral_type, debt_to_cover).await
+}
+
+#[query]
+fn get_vault_assignment(vault_id: u64) -> Option<VaultAssignment> {
+    let liquidation_controller = ic_cdk::storage::get::<LiquidationController>();
+    liquidation_controller.assignments.get(&vault_id).cloned()
+}
+
+#[query]
+fn get_bonus_adjustments() -> Vec<BonusAdjustment> {
+    let liquidation_controller = ic_cdk::storage::get::<LiquidationController>();
+    liquidation_controller.bonus_history.clone()
 }
 
 #[query]
@@ -197,32 +1000,118 @@ fn get_liquidation_config() -> LiquidationConfig {
     liquidation_controller.config.clone()
 }
 
+#[query]
+fn get_liquidation_quota(collateral_type: CollateralType) -> LiquidationQuota {
+    let liquidation_controller = ic_cdk::storage::get::<LiquidationController>();
+    liquidation_controller.get_quota(&collateral_type)
+}
+
+#[query]
+fn get_liquidation_stats(range: StatsRange) -> Vec<CollateralLiquidationStats> {
+    let liquidation_controller = ic_cdk::storage::get::<LiquidationController>();
+    liquidation_controller.stats.get(range, ic_cdk::api::time())
+}
+
 #[query]
 fn get_liquidation_events() -> Vec<LiquidationEvent> {
     let liquidation_controller = ic_cdk::storage::get::<LiquidationController>();
     liquidation_controller.events.clone()
 }
 
+#[query]
+fn get_liquidation_receipt(event_index: u64) -> Option<CertifiedLiquidationReceipt> {
+    let liquidation_controller = ic_cdk::storage::get::<LiquidationController>();
+    let certified_tree = ic_cdk::storage::get::<CertifiedTree>();
+    let event = liquidation_controller.events.get(event_index as usize)?.clone();
+    
+    Some(CertifiedLiquidationReceipt {
+        event_hash: certified_tree.liquidation_hash(event_index)?.to_vec(),
+        certificate: ic_cdk::api::data_certificate()?,
+        witness: certified_tree.liquidation_witness(event_index),
+        event,
+    })
+}
+
+#[query]
+fn get_liquidation_events_since(cursor: u64, limit: u64) -> LiquidationEventPage {
+    let liquidation_controller = ic_cdk::storage::get::<LiquidationController>();
+    liquidation_controller.events_since(cursor, limit)
+}
+
 #[update]
-fn update_liquidation_config(new_config: LiquidationConfig) -> Result<(), String> {
-    // Only callable by protocol admin
-    if ic_cdk::caller() != ic_cdk::id() {
-        return Err("Unauthorized".to_string());
-    }
+pub(crate) fn update_liquidation_config(new_config: LiquidationConfig) -> Result<(), String> {
+    require_role(Role::Admin)?;
+    
+    let liquidation_controller = ic_cdk::storage::get_mut::<LiquidationController>();
+    // Bonuses and the adaptive bonus bounds only change through the risk-parameter timelock: the
+    // current ones are kept
+    let new_config = LiquidationConfig {
+        liquidation_bonus: liquidation_controller.config.liquidation_bonus,
+        collateral_bonuses: liquidation_controller.config.collateral_bonuses.clone(),
+        adaptive_bonus: liquidation_controller.config.adaptive_bonus.clone(),
+        ..new_config
+    };
+    new_config.validate()?;
     
-    let mut liquidation_controller = ic_cdk::storage::get_mut::<LiquidationController>();
+    audit_log::record_change("update_liquidation_config", &liquidation_controller.config, &new_config);
     liquidation_controller.config = new_config;
     Ok(())
 }
 
 #[update]
-fn add_liquidator(liquidator: Principal) -> Result<(), String> {
-    // Only callable by protocol admin
-    if ic_cdk::caller() != ic_cdk::id() {
-        return Err("Unauthorized".to_string());
-    }
+pub(crate) fn add_liquidator(liquidator: Principal) -> Result<(), String> {
+    require_role(Role::Admin)?;
     
-    let mut liquidation_controller = ic_cdk::storage::get_mut::<LiquidationController>();
+    let liquidation_controller = ic_cdk::storage::get_mut::<LiquidationController>();
+    if liquidation_controller.config.liquidators.contains(&liquidator) {
+        return Err("Liquidator already whitelisted".to_string());
+    }
     liquidation_controller.config.liquidators.push(liquidator);
+    audit_log::record_action("add_liquidator", &liquidator);
     Ok(())
-}
\ No newline at end of file
+}
+
+#[update]
+pub(crate) fn remove_liquidator(liquidator: Principal) -> Result<(), String> {
+    require_role(Role::Admin)?;
+    
+    let liquidation_controller = ic_cdk::storage::get_mut::<LiquidationController>();
+    let config = &mut liquidation_controller.config;
+    let index = config.liquidators
+        .iter()
+        .position(|existing| *existing == liquidator)
+        .ok_or("Liquidator not found")?;
+        
+    // Removing the last whitelisted liquidator would leave an enabled whitelist empty
+    if config.whitelist_enabled && config.liquidators.len() == 1 {
+        return Err("Cannot remove the last whitelisted liquidator while the whitelist is enabled".to_string());
+    }
+    
+    config.liquidators.remove(index);
+    audit_log::record_action("remove_liquidator", &liquidator);
+    Ok(())
+}
+
+#[query]
+fn list_liquidators() -> Vec<Principal> {
+    let liquidation_controller = ic_cdk::storage::get::<LiquidationController>();
+    liquidation_controller.config.liquidators.clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn whitelist_gates_liquidators_by_default() {
+        let mut config = LiquidationConfig::default();
+        assert!(config.whitelist_enabled);
+        
+        let listed = Principal::from_slice(&[1]);
+        let unlisted = Principal::from_slice(&[2]);
+        config.liquidators.push(listed);
+        assert!(config.is_whitelisted(&listed));
+        assert!(!config.is_whitelisted(&unlisted));
+        assert!(config.validate().is_ok());
+    }
+}
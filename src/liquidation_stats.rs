@@ -0,0 +1,169 @@
+use candid::{CandidType, Deserialize};
+use std::collections::{BTreeMap, HashMap};
+use crate::vault_system::CollateralType;
+
+const NANOS_PER_HOUR: u64 = 3_600_000_000_000;
+/// Hourly buckets are kept for the longest bounded range
+const RETAINED_HOURS: u64 = 30 * 24;
+
+/// Time range for liquidation statistics
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum StatsRange {
+    Last24Hours,
+    Last7Days,
+    Last30Days,
+    AllTime,
+}
+
+/// Largest single liquidation in a range
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct LargestLiquidation {
+    /// ID of the liquidated vault
+    vault_id: u64,
+    /// Debt repaid
+    debt_amount: u128,
+    /// Collateral seized
+    collateral_amount: u128,
+    /// Timestamp of the liquidation
+    timestamp: u64,
+}
+
+/// Running totals for one collateral type
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+struct StatsBucket {
+    count: u64,
+    debt_repaid: u128,
+    collateral_seized: u128,
+    /// Seized collateral paid out as bonus, used for the average discount
+    bonus_collateral: u128,
+    largest: Option<LargestLiquidation>,
+}
+
+/// Liquidation totals for one collateral type over a range
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CollateralLiquidationStats {
+    /// Collateral type the totals apply to
+    collateral_type: CollateralType,
+    /// Number of liquidations
+    count: u64,
+    /// Total debt repaid (iUSD)
+    debt_repaid: u128,
+    /// Total collateral seized (collateral base units)
+    collateral_seized: u128,
+    /// Bonus as a share of seized collateral (in basis points)
+    average_discount: u32,
+    /// Largest single liquidation by debt repaid
+    largest: Option<LargestLiquidation>,
+}
+
+impl StatsBucket {
+    fn add(&mut self, other: &StatsBucket) {
+        self.count += other.count;
+        self.debt_repaid += other.debt_repaid;
+        self.collateral_seized += other.collateral_seized;
+        self.bonus_collateral += other.bonus_collateral;
+        if let Some(candidate) = &other.largest {
+            self.consider_largest(candidate.clone());
+        }
+    }
+    
+    fn consider_largest(&mut self, candidate: LargestLiquidation) {
+        let is_larger = self.largest
+            .as_ref()
+            .is_none_or(|largest| candidate.debt_amount > largest.debt_amount);
+        if is_larger {
+            self.largest = Some(candidate);
+        }
+    }
+}
+
+/// Liquidation totals maintained incrementally as events are recorded
+#[derive(CandidType, Deserialize, Default)]
+pub struct LiquidationStats {
+    /// Totals per hour index and collateral type for the retained window
+    hourly: BTreeMap<u64, HashMap<CollateralType, StatsBucket>>,
+    /// Totals since genesis
+    all_time: HashMap<CollateralType, StatsBucket>,
+}
+
+impl LiquidationStats {
+    /// Adds a liquidation to the running totals
+    pub fn record(
+        &mut self,
+        vault_id: u64,
+        collateral_type: &CollateralType,
+        debt_amount: u128,
+        collateral_amount: u128,
+        bonus_collateral: u128,
+        timestamp: u64,
+    ) {
+        let entry = StatsBucket {
+            count: 1,
+            debt_repaid: debt_amount,
+            collateral_seized: collateral_amount,
+            bonus_collateral,
+            largest: Some(LargestLiquidation {
+                vault_id,
+                debt_amount,
+                collateral_amount,
+                timestamp,
+            }),
+        };
+        
+        let hour = timestamp / NANOS_PER_HOUR;
+        self.hourly
+            .entry(hour)
+            .or_default()
+            .entry(collateral_type.clone())
+            .or_default()
+            .add(&entry);
+        self.all_time
+            .entry(collateral_type.clone())
+            .or_default()
+            .add(&entry);
+            
+        // Drop buckets that have aged out of every bounded range
+        let oldest_retained = hour.saturating_sub(RETAINED_HOURS);
+        self.hourly = self.hourly.split_off(&oldest_retained);
+    }
+    
+    /// Aggregates totals by collateral type over a range
+    pub fn get(&self, range: StatsRange, now: u64) -> Vec<CollateralLiquidationStats> {
+        let hours = match range {
+            StatsRange::Last24Hours => Some(24),
+            StatsRange::Last7Days => Some(7 * 24),
+            StatsRange::Last30Days => Some(RETAINED_HOURS),
+            StatsRange::AllTime => None,
+        };
+        
+        let totals = match hours {
+            None => self.all_time.clone(),
+            Some(hours) => {
+                let start = (now / NANOS_PER_HOUR + 1).saturating_sub(hours);
+                let mut totals: HashMap<CollateralType, StatsBucket> = HashMap::new();
+                for buckets in self.hourly.range(start..).map(|(_, buckets)| buckets) {
+                    for (collateral_type, bucket) in buckets {
+                        totals.entry(collateral_type.clone()).or_default().add(bucket);
+                    }
+                }
+                totals
+            }
+        };
+        
+        totals
+            .into_iter()
+            .map(|(collateral_type, bucket)| CollateralLiquidationStats {
+                collateral_type,
+                count: bucket.count,
+                debt_repaid: bucket.debt_repaid,
+                collateral_seized: bucket.collateral_seized,
+                average_discount: if bucket.collateral_seized == 0 {
+                    0
+                } else {
+                    (bucket.bonus_collateral * 10000 / bucket.collateral_seized) as u32
+                },
+                largest: bucket.largest,
+            })
+            .collect()
+    }
+}
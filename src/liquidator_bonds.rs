@@ -0,0 +1,349 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::call::CallResult;
+use ic_cdk_macros::*;
+use std::collections::HashMap;
+use crate::access_control::{require_role, Role};
+use crate::audit_log;
+use crate::ledger_backend::{IusdLedger, TransferArg, TransferError, TransferFromArgs, TransferFromError};
+use crate::validation;
+use crate::vault_system::{self, Account, CollateralType};
+
+/// Assets accepted as liquidator bonds
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BondAsset {
+    IUSD,
+    ICP,
+}
+
+/// Configuration for liquidator bonding
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct BondConfig {
+    /// Minimum iUSD bond to register as a liquidator
+    min_bond_iusd: u128,
+    /// Minimum ICP bond to register as a liquidator
+    min_bond_icp: u128,
+    /// Delay between requesting an unbond and withdrawing it (nanoseconds)
+    unbonding_period: u64,
+    /// Window after a vault turns unsafe during which only bonded liquidators may act (nanoseconds)
+    pub(crate) priority_period: u64,
+}
+
+impl Default for BondConfig {
+    fn default() -> Self {
+        Self {
+            min_bond_iusd: 1_000 * 100_000_000,  // 1,000 iUSD
+            min_bond_icp: 100 * 100_000_000,     // 100 ICP
+            unbonding_period: 7 * 24 * 3_600_000_000_000, // 7 days
+            priority_period: 60_000_000_000,        // 1 minute
+        }
+    }
+}
+
+/// A liquidator's bond
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct LiquidatorBond {
+    /// Asset the bond is held in
+    asset: BondAsset,
+    /// Amount currently bonded
+    amount: u128,
+    /// Timestamp of the first bond
+    bonded_at: u64,
+    /// Set once the liquidator has requested to unbond
+    unbonding_since: Option<u64>,
+}
+
+/// Represents a slashing event
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SlashEvent {
+    /// Liquidator that was slashed
+    liquidator: Principal,
+    /// Asset the slashed bond was held in
+    asset: BondAsset,
+    /// Amount slashed
+    amount: u128,
+    /// Reason given by governance
+    reason: String,
+    /// Timestamp of the slash
+    timestamp: u64,
+}
+
+#[derive(CandidType, Deserialize, Default)]
+pub struct BondRegistry {
+    pub(crate) config: BondConfig,
+    bonds: HashMap<Principal, LiquidatorBond>,
+    slashes: Vec<SlashEvent>,
+}
+
+impl BondRegistry {
+    /// Checks if a principal holds an active bond above the minimum
+    pub fn is_bonded(&self, liquidator: &Principal) -> bool {
+        match self.bonds.get(liquidator) {
+            Some(bond) => bond.unbonding_since.is_none() && bond.amount >= self.min_bond(&bond.asset),
+            None => false,
+        }
+    }
+    
+    /// Principals currently holding an active bond
+    pub fn bonded_liquidators(&self) -> Vec<Principal> {
+        self.bonds
+            .keys()
+            .filter(|liquidator| self.is_bonded(liquidator))
+            .cloned()
+            .collect()
+    }
+    
+    fn min_bond(&self, asset: &BondAsset) -> u128 {
+        match asset {
+            BondAsset::IUSD => self.config.min_bond_iusd,
+            BondAsset::ICP => self.config.min_bond_icp,
+        }
+    }
+    
+    /// Bonds iUSD or ICP to register the caller as a liquidator
+    pub async fn bond(&mut self, asset: BondAsset, amount: u128) -> Result<LiquidatorBond, String> {
+        let caller = ic_cdk::caller();
+        
+        let existing = self.bonds.get(&caller);
+        if let Some(bond) = existing {
+            if bond.asset != asset {
+                return Err("Existing bond is held in a different asset".to_string());
+            }
+            if bond.unbonding_since.is_some() {
+                return Err("Bond is unbonding".to_string());
+            }
+        }
+        
+        let bonded_amount = existing.map(|bond| bond.amount).unwrap_or(0) + amount;
+        if bonded_amount < self.min_bond(&asset) {
+            return Err("Amount below minimum bond".to_string());
+        }
+        
+        self.transfer_to_protocol(&asset, caller, amount).await?;
+        
+        let bond = self.bonds.entry(caller).or_insert(LiquidatorBond {
+            asset,
+            amount: 0,
+            bonded_at: ic_cdk::api::time(),
+            unbonding_since: None,
+        });
+        bond.amount += amount;
+        
+        Ok(bond.clone())
+    }
+    
+    /// Starts the unbonding period; the bond no longer grants access but can still be slashed
+    pub fn request_unbond(&mut self) -> Result<(), String> {
+        let bond = self.bonds.get_mut(&ic_cdk::caller())
+            .ok_or("Bond not found")?;
+            
+        if bond.unbonding_since.is_some() {
+            return Err("Unbond already requested".to_string());
+        }
+        
+        bond.unbonding_since = Some(ic_cdk::api::time());
+        Ok(())
+    }
+    
+    /// Returns the bond to the caller once the unbonding period has passed
+    pub async fn withdraw_bond(&mut self) -> Result<u128, String> {
+        let caller = ic_cdk::caller();
+        let bond = self.bonds.get(&caller)
+            .ok_or("Bond not found")?;
+            
+        let unbonding_since = bond.unbonding_since
+            .ok_or("Unbond not requested")?;
+        if ic_cdk::api::time() < unbonding_since + self.config.unbonding_period {
+            return Err("Unbonding period has not elapsed".to_string());
+        }
+        
+        // The bond stays registered, emptied and unbonding, until the transfer lands, so a failed
+        // transfer can put the amount back and nothing can be bonded or slashed in between
+        let bond = self.bonds.get_mut(&caller).unwrap();
+        let amount = std::mem::take(&mut bond.amount);
+        let emptied = bond.clone();
+        if amount > 0 {
+            if let Err(e) = self.transfer_to_liquidator(&emptied.asset, caller, amount).await {
+                // A racing withdrawal of the emptied bond may have removed it meanwhile
+                self.bonds.entry(caller).or_insert(emptied).amount += amount;
+                return Err(e);
+            }
+        }
+        
+        self.bonds.remove(&caller);
+        Ok(amount)
+    }
+    
+    /// Slashes a liquidator's bond for abusive behavior; slashed funds stay with the protocol
+    pub fn slash(&mut self, liquidator: Principal, amount: u128, reason: String) -> Result<SlashEvent, String> {
+        let bond = self.bonds.get_mut(&liquidator)
+            .ok_or("Bond not found")?;
+            
+        let slashed = amount.min(bond.amount);
+        bond.amount -= slashed;
+        
+        let event = SlashEvent {
+            liquidator,
+            asset: bond.asset.clone(),
+            amount: slashed,
+            reason,
+            timestamp: ic_cdk::api::time(),
+        };
+        
+        self.slashes.push(event.clone());
+        
+        Ok(event)
+    }
+    
+    /// Pulls a bond the liquidator approved the protocol to spend (ICRC-2)
+    async fn transfer_to_protocol(
+        &self,
+        asset: &BondAsset,
+        from: Principal,
+        amount: u128,
+    ) -> Result<(), String> {
+        match asset {
+            BondAsset::IUSD => ic_cdk::storage::get::<IusdLedger>().pull(from, amount).await,
+            BondAsset::ICP => {
+                let args = TransferFromArgs {
+                    spender_subaccount: None,
+                    from: Account {
+                        owner: from,
+                        subaccount: None,
+                    },
+                    to: Account {
+                        owner: ic_cdk::id(),  // Protocol's address
+                        subaccount: None,
+                    },
+                    amount,
+                    fee: None,
+                    memo: None,
+                    created_at_time: None,
+                };
+                let icp_ledger = vault_system::collateral_canister_id(&CollateralType::ICP);
+                let result: CallResult<(Result<u128, TransferFromError>,)> =
+                    ic_cdk::call(icp_ledger, "icrc2_transfer_from", (args,)).await;
+                match result {
+                    Ok((Ok(_),)) => Ok(()),
+                    Ok((Err(e),)) => Err(format!("Bond transfer rejected: {:?}", e)),
+                    Err((code, msg)) => Err(format!("Failed to transfer bond: {:?} - {}", code, msg)),
+                }
+            }
+        }
+    }
+    
+    async fn transfer_to_liquidator(
+        &self,
+        asset: &BondAsset,
+        to: Principal,
+        amount: u128,
+    ) -> Result<(), String> {
+        let to = Account {
+            owner: to,
+            subaccount: None,
+        };
+        match asset {
+            BondAsset::IUSD => ic_cdk::storage::get::<IusdLedger>().transfer(to, amount).await,
+            BondAsset::ICP => {
+                let args = TransferArg {
+                    from_subaccount: None,
+                    to,
+                    amount,
+                    fee: None,
+                    memo: None,
+                    created_at_time: None,
+                };
+                let icp_ledger = vault_system::collateral_canister_id(&CollateralType::ICP);
+                let result: CallResult<(Result<u128, TransferError>,)> =
+                    ic_cdk::call(icp_ledger, "icrc1_transfer", (args,)).await;
+                match result {
+                    Ok((Ok(_),)) => Ok(()),
+                    Ok((Err(e),)) => Err(format!("Bond return rejected: {:?}", e)),
+                    Err((code, msg)) => Err(format!("Failed to return bond: {:?} - {}", code, msg)),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+impl BondRegistry {
+    /// Registry with a bond and a slash, for tests of saving and restoring state
+    pub(crate) fn sample() -> Self {
+        let liquidator = Principal::from_slice(&[1]);
+        Self {
+            config: BondConfig::default(),
+            bonds: HashMap::from([(liquidator, LiquidatorBond {
+                asset: BondAsset::IUSD,
+                amount: 2_000 * 100_000_000,
+                bonded_at: 1_000,
+                unbonding_since: None,
+            })]),
+            slashes: vec![SlashEvent {
+                liquidator,
+                asset: BondAsset::IUSD,
+                amount: 100 * 100_000_000,
+                reason: "Griefing".to_string(),
+                timestamp: 2_000,
+            }],
+        }
+    }
+}
+
+// Canister endpoints
+#[update]
+async fn bond_liquidator(asset: BondAsset, amount: u128) -> Result<LiquidatorBond, String> {
+    let registry = ic_cdk::storage::get_mut::<BondRegistry>();
+    registry.bond(asset, amount).await
+}
+
+#[update]
+fn request_unbond() -> Result<(), String> {
+    let registry = ic_cdk::storage::get_mut::<BondRegistry>();
+    registry.request_unbond()
+}
+
+#[update]
+async fn withdraw_bond() -> Result<u128, String> {
+    let registry = ic_cdk::storage::get_mut::<BondRegistry>();
+    registry.withdraw_bond().await
+}
+
+#[query]
+fn get_liquidator_bond(liquidator: Principal) -> Option<LiquidatorBond> {
+    let registry = ic_cdk::storage::get::<BondRegistry>();
+    registry.bonds.get(&liquidator).cloned()
+}
+
+#[query]
+fn get_slash_events() -> Vec<SlashEvent> {
+    let registry = ic_cdk::storage::get::<BondRegistry>();
+    registry.slashes.clone()
+}
+
+#[query]
+fn get_bond_config() -> BondConfig {
+    let registry = ic_cdk::storage::get::<BondRegistry>();
+    registry.config.clone()
+}
+
+#[update]
+pub(crate) fn slash_liquidator(liquidator: Principal, amount: u128, reason: String) -> Result<SlashEvent, String> {
+    require_role(Role::Guardian)?;
+    
+    let registry = ic_cdk::storage::get_mut::<BondRegistry>();
+    let event = registry.slash(liquidator, amount, reason)?;
+    audit_log::record_action("slash_liquidator", &event);
+    Ok(event)
+}
+
+#[update]
+pub(crate) fn update_bond_config(new_config: BondConfig) -> Result<(), String> {
+    require_role(Role::Admin)?;
+    // Without an unbonding period a liquidator could withdraw before being slashed
+    validation::check_positive("Unbonding period", new_config.unbonding_period)?;
+    
+    let registry = ic_cdk::storage::get_mut::<BondRegistry>();
+    audit_log::record_change("update_bond_config", &registry.config, &new_config);
+    registry.config = new_config;
+    Ok(())
+}
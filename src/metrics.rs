@@ -0,0 +1,98 @@
+use candid::{CandidType, Deserialize};
+use ic_cdk_macros::*;
+use std::fmt::Write;
+use crate::cycles::CyclesMonitor;
+
+/// Request forwarded by the HTTP gateway
+#[derive(CandidType, Deserialize)]
+pub struct HttpRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct HttpResponse {
+    status_code: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+/// Gauges in the Prometheus text format
+#[derive(Default)]
+pub(crate) struct Metrics {
+    text: String,
+}
+
+impl Metrics {
+    fn header(&mut self, name: &str, help: &str) {
+        writeln!(self.text, "# HELP {} {}", name, help).unwrap();
+        writeln!(self.text, "# TYPE {} gauge", name).unwrap();
+    }
+    
+    pub(crate) fn gauge(&mut self, name: &str, help: &str, value: u128) {
+        self.header(name, help);
+        writeln!(self.text, "{} {}", name, value).unwrap();
+    }
+    
+    /// A gauge with one sample per label value
+    pub(crate) fn labeled_gauge(&mut self, name: &str, help: &str, label: &str, values: &[(String, u128)]) {
+        self.header(name, help);
+        for (label_value, value) in values {
+            writeln!(self.text, "{}{{{}=\"{}\"}} {}", name, label, label_value, value).unwrap();
+        }
+    }
+}
+
+fn text_response(status_code: u16, content_type: &str, body: String) -> HttpResponse {
+    HttpResponse {
+        status_code,
+        headers: vec![
+            ("Content-Type".to_string(), content_type.to_string()),
+            ("Content-Length".to_string(), body.len().to_string()),
+        ],
+        body: body.into_bytes(),
+    }
+}
+
+// Canister endpoints
+/// Serves `/metrics` for Prometheus scrapers through the HTTP gateway
+#[query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    let path = request.url.split('?').next().unwrap_or_default();
+    if request.method != "GET" || path != "/metrics" {
+        return text_response(404, "text/plain", "Not found".to_string());
+    }
+    
+    let mut metrics = Metrics::default();
+    ic_cdk::storage::get::<CyclesMonitor>().write_metrics(&mut metrics);
+    text_response(200, "text/plain; version=0.0.4", metrics.text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn writes_the_prometheus_text_format() {
+        let mut metrics = Metrics::default();
+        metrics.gauge("iusd_cycles_top_ups", "Top-ups sent to the CMC", 2);
+        metrics.labeled_gauge(
+            "iusd_monitored_cycles_balance",
+            "Cycles of each monitored canister",
+            "canister",
+            &[("aaaaa-aa".to_string(), 5)],
+        );
+        
+        assert_eq!(
+            metrics.text,
+            "# HELP iusd_cycles_top_ups Top-ups sent to the CMC\n\
+             # TYPE iusd_cycles_top_ups gauge\n\
+             iusd_cycles_top_ups 2\n\
+             # HELP iusd_monitored_cycles_balance Cycles of each monitored canister\n\
+             # TYPE iusd_monitored_cycles_balance gauge\n\
+             iusd_monitored_cycles_balance{canister=\"aaaaa-aa\"} 5\n"
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::*;
+use std::collections::{BTreeMap, BTreeSet};
+use crate::access_control::{require_role, AccessControl, Role};
+use crate::audit_log;
+use crate::insurance_fund::InsuranceFund;
+use crate::ledger_backend::{IusdLedger, TokenAction};
+use crate::pause;
+use crate::vault_system::Account;
+
+/// Approvals required until changed through `SetApprovalThreshold`
+const DEFAULT_APPROVAL_THRESHOLD: u32 = 2;
+
+/// Destructive actions no single principal can take
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum ApprovalAction {
+    /// Pauses every operation until `EndShutdown`
+    EmergencyShutdown,
+    EndShutdown,
+    /// Pays iUSD out of the insurance fund
+    TreasuryWithdrawal { to: Account, amount: u128 },
+    SetApprovalThreshold { threshold: u32 },
+    /// Adds an account to the built-in token's blocklist
+    FreezeAccount { account: Account, reason: String },
+    /// Minter changes on the built-in token, which the protocol governs
+    AddMinter(Principal),
+    RemoveMinter(Principal),
+    RotateMinters(Vec<Principal>),
+}
+
+impl ApprovalAction {
+    /// Role each approver must hold
+    fn required_role(&self) -> Role {
+        match self {
+            ApprovalAction::EmergencyShutdown | ApprovalAction::EndShutdown => Role::Guardian,
+            ApprovalAction::FreezeAccount { .. } => Role::Guardian,
+            ApprovalAction::TreasuryWithdrawal { .. } => Role::Treasurer,
+            ApprovalAction::SetApprovalThreshold { .. }
+            | ApprovalAction::AddMinter(_)
+            | ApprovalAction::RemoveMinter(_)
+            | ApprovalAction::RotateMinters(_) => Role::Admin,
+        }
+    }
+}
+
+/// An action collecting approvals
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PendingAction {
+    id: u64,
+    action: ApprovalAction,
+    proposed_by: Principal,
+    proposed_at: u64,
+    /// Distinct principals that approved, the proposer included
+    approvals: BTreeSet<Principal>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum ActionOutcome {
+    Executed,
+    /// Reached the threshold but the action returned an error
+    Failed(String),
+    Cancelled,
+}
+
+/// A pending action that was executed or cancelled
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CompletedAction {
+    action: PendingAction,
+    outcome: ActionOutcome,
+    completed_at: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ApprovalQueue {
+    threshold: u32,
+    pending: BTreeMap<u64, PendingAction>,
+    next_action_id: u64,
+    /// Executed and cancelled actions, oldest first
+    history: Vec<CompletedAction>,
+}
+
+impl Default for ApprovalQueue {
+    fn default() -> Self {
+        Self {
+            threshold: DEFAULT_APPROVAL_THRESHOLD,
+            pending: BTreeMap::new(),
+            next_action_id: 0,
+            history: Vec::new(),
+        }
+    }
+}
+
+impl ApprovalQueue {
+    /// Whether the action has enough approvals from principals still holding its role; the
+    /// governance canister's approval suffices on its own, as it already speaks for many
+    fn is_approved(&self, pending: &PendingAction) -> bool {
+        let access_control = ic_cdk::storage::get::<AccessControl>();
+        if pending.approvals.iter().any(|approver| access_control.is_governance(approver)) {
+            return true;
+        }
+        let role = pending.action.required_role();
+        let approvals = pending.approvals
+            .iter()
+            .filter(|approver| access_control.has_role(approver, role))
+            .count();
+        approvals >= self.threshold as usize
+    }
+    
+    /// Queues an action with the proposer's approval; returns it if that is already enough
+    pub fn propose(&mut self, action: ApprovalAction, caller: Principal, now: u64) -> Result<(u64, Option<PendingAction>), String> {
+        if let ApprovalAction::SetApprovalThreshold { threshold } = &action {
+            if *threshold == 0 {
+                return Err("Approval threshold must be at least 1".to_string());
+            }
+        }
+        
+        let id = self.next_action_id;
+        self.next_action_id += 1;
+        self.pending.insert(id, PendingAction {
+            id,
+            action,
+            proposed_by: caller,
+            proposed_at: now,
+            approvals: BTreeSet::from([caller]),
+        });
+        Ok((id, self.take_if_approved(id)))
+    }
+    
+    /// Adds the caller's approval; returns the action once it has enough
+    pub fn approve(&mut self, id: u64, caller: Principal) -> Result<Option<PendingAction>, String> {
+        let pending = self.pending.get_mut(&id).ok_or("Action not found")?;
+        if !pending.approvals.insert(caller) {
+            return Err("Action already approved by caller".to_string());
+        }
+        Ok(self.take_if_approved(id))
+    }
+    
+    /// Removes an approved action from the queue, so it can only be executed once
+    fn take_if_approved(&mut self, id: u64) -> Option<PendingAction> {
+        if !self.is_approved(&self.pending[&id]) {
+            return None;
+        }
+        self.pending.remove(&id)
+    }
+    
+    pub fn cancel(&mut self, id: u64, now: u64) -> Result<(), String> {
+        let pending = self.pending.remove(&id).ok_or("Action not found")?;
+        self.complete(pending, ActionOutcome::Cancelled, now);
+        Ok(())
+    }
+    
+    fn complete(&mut self, action: PendingAction, outcome: ActionOutcome, completed_at: u64) {
+        self.history.push(CompletedAction {
+            action,
+            outcome,
+            completed_at,
+        });
+    }
+}
+
+/// Hands an approved token action to the token's own queue, noting the action ID it got there
+async fn propose_token_action(action: TokenAction) -> Result<(), String> {
+    let token_action_id = ic_cdk::storage::get::<IusdLedger>().propose_token_action(action).await?;
+    audit_log::record("propose_token_action", None, Some(format!("token action {}", token_action_id)));
+    Ok(())
+}
+
+async fn execute(pending: PendingAction) -> Result<(), String> {
+    let result = match &pending.action {
+        ApprovalAction::EmergencyShutdown => {
+            pause::set_shutdown(true);
+            pause::sync_token_transfers().await
+        }
+        ApprovalAction::EndShutdown => {
+            pause::set_shutdown(false);
+            pause::sync_token_transfers().await
+        }
+        ApprovalAction::TreasuryWithdrawal { to, amount } => {
+            let fund = ic_cdk::storage::get_mut::<InsuranceFund>();
+            fund.withdraw(to.clone(), *amount).await
+        }
+        ApprovalAction::SetApprovalThreshold { threshold } => {
+            ic_cdk::storage::get_mut::<ApprovalQueue>().threshold = *threshold;
+            Ok(())
+        }
+        ApprovalAction::FreezeAccount { account, reason } => {
+            let action = TokenAction::FreezeAccount {
+                account: account.clone(),
+                reason: reason.clone(),
+            };
+            propose_token_action(action).await
+        }
+        ApprovalAction::AddMinter(minter) => propose_token_action(TokenAction::AddMinter(*minter)).await,
+        ApprovalAction::RemoveMinter(minter) => propose_token_action(TokenAction::RemoveMinter(*minter)).await,
+        ApprovalAction::RotateMinters(minters) => propose_token_action(TokenAction::RotateMinters(minters.clone())).await,
+    };
+    
+    let outcome = match &result {
+        Ok(()) => ActionOutcome::Executed,
+        Err(e) => ActionOutcome::Failed(e.clone()),
+    };
+    audit_log::record(
+        &format!("execute_approved_action {}", pending.id),
+        None,
+        Some(format!("{:?} ({:?})", pending.action, outcome)),
+    );
+    let queue = ic_cdk::storage::get_mut::<ApprovalQueue>();
+    queue.complete(pending, outcome, ic_cdk::api::time() / 1_000_000_000);
+    result
+}
+
+#[cfg(test)]
+impl ApprovalQueue {
+    /// Queue with an action awaiting approvals, for tests of saving and restoring state
+    pub(crate) fn sample() -> Self {
+        let proposed_by = Principal::from_slice(&[1]);
+        Self {
+            threshold: 3,
+            pending: BTreeMap::from([(0, PendingAction {
+                id: 0,
+                action: ApprovalAction::EmergencyShutdown,
+                proposed_by,
+                proposed_at: 1_000,
+                approvals: BTreeSet::from([proposed_by]),
+            })]),
+            next_action_id: 1,
+            history: Vec::new(),
+        }
+    }
+}
+
+// Canister endpoints
+/// Queues a destructive action for approval by principals holding its role; it executes once the
+/// threshold is reached. Returns the action ID
+#[update]
+async fn propose_action(action: ApprovalAction) -> Result<u64, String> {
+    require_role(action.required_role())?;
+    
+    let queue = ic_cdk::storage::get_mut::<ApprovalQueue>();
+    let (id, approved) = queue.propose(action, ic_cdk::caller(), ic_cdk::api::time() / 1_000_000_000)?;
+    if let Some(pending) = approved {
+        execute(pending).await?;
+    }
+    Ok(id)
+}
+
+/// Approves a queued action, executing it if this approval reaches the threshold; returns
+/// whether it was executed
+#[update]
+async fn approve_action(action_id: u64) -> Result<bool, String> {
+    let queue = ic_cdk::storage::get_mut::<ApprovalQueue>();
+    let role = queue.pending
+        .get(&action_id)
+        .ok_or("Action not found")?
+        .action
+        .required_role();
+    require_role(role)?;
+    
+    match queue.approve(action_id, ic_cdk::caller())? {
+        Some(pending) => {
+            execute(pending).await?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Drops a queued action; callable by its proposer or an admin
+#[update]
+fn cancel_action(action_id: u64) -> Result<(), String> {
+    let queue = ic_cdk::storage::get_mut::<ApprovalQueue>();
+    let pending = queue.pending.get(&action_id).ok_or("Action not found")?;
+    if pending.proposed_by != ic_cdk::caller() {
+        require_role(Role::Admin)?;
+    }
+    queue.cancel(action_id, ic_cdk::api::time() / 1_000_000_000)
+}
+
+#[query]
+fn get_pending_actions() -> Vec<PendingAction> {
+    ic_cdk::storage::get::<ApprovalQueue>().pending.values().cloned().collect()
+}
+
+#[query]
+fn get_action_history(offset: u64, limit: u64) -> Vec<CompletedAction> {
+    let queue = ic_cdk::storage::get::<ApprovalQueue>();
+    queue.history
+        .iter()
+        .skip(offset as usize)
+        .take(limit as usize)
+        .cloned()
+        .collect()
+}
+
+#[query]
+fn get_approval_threshold() -> u32 {
+    ic_cdk::storage::get::<ApprovalQueue>().threshold
+}
@@ -0,0 +1,149 @@
+use candid::{CandidType, Deserialize};
+use ic_cdk_macros::*;
+use std::collections::BTreeSet;
+use crate::access_control::{require_role, Role};
+use crate::audit_log;
+use crate::ledger_backend::{IusdLedger, LedgerBackend};
+
+/// Operations guardians can pause independently, e.g. to stop new leverage during an oracle
+/// incident while repayments continue
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Operation {
+    /// Collateral deposits into vaults
+    Deposit,
+    /// Collateral withdrawals from vaults
+    Withdraw,
+    /// Minting iUSD against vaults
+    Mint,
+    /// Liquidations, keeper absorptions and collateral auctions
+    Liquidate,
+    /// Holders' iUSD transfers on the built-in token; the protocol's own transfers continue
+    Transfer,
+}
+
+impl Operation {
+    const ALL: [Operation; 5] = [
+        Operation::Deposit,
+        Operation::Withdraw,
+        Operation::Mint,
+        Operation::Liquidate,
+        Operation::Transfer,
+    ];
+}
+
+#[derive(CandidType, Deserialize, Default)]
+pub struct PauseSwitches {
+    paused: BTreeSet<Operation>,
+    /// Emergency shutdown, pausing every operation whatever its switch says; set and lifted only
+    /// through multi-approval
+    shutdown: bool,
+}
+
+pub(crate) fn is_paused(operation: Operation) -> bool {
+    let switches = ic_cdk::storage::get::<PauseSwitches>();
+    switches.shutdown || switches.paused.contains(&operation)
+}
+
+/// Whether nothing can change vaults, e.g. during a backup or restore
+pub(crate) fn is_fully_paused() -> bool {
+    let switches = ic_cdk::storage::get::<PauseSwitches>();
+    switches.shutdown
+        || Operation::ALL
+            .iter()
+            .filter(|operation| **operation != Operation::Transfer)
+            .all(|operation| switches.paused.contains(operation))
+}
+
+pub(crate) fn set_shutdown(shutdown: bool) {
+    ic_cdk::storage::get_mut::<PauseSwitches>().shutdown = shutdown;
+}
+
+pub(crate) fn check_not_paused(operation: Operation) -> Result<(), String> {
+    if is_paused(operation) {
+        return Err(format!("{:?} is paused", operation));
+    }
+    Ok(())
+}
+
+/// Applies the transfer switch (or a shutdown) to the built-in token, where holders' transfers
+/// actually happen; a reference ledger has no transfer pause
+pub(crate) async fn sync_token_transfers() -> Result<(), String> {
+    let iusd_ledger = ic_cdk::storage::get::<IusdLedger>();
+    match iusd_ledger.backend() {
+        Ok(LedgerBackend::BuiltIn { .. }) => iusd_ledger.set_transfers_paused(is_paused(Operation::Transfer)).await,
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+impl PauseSwitches {
+    /// Switches with minting paused, for tests of saving and restoring state
+    pub(crate) fn sample() -> Self {
+        Self {
+            paused: BTreeSet::from([Operation::Mint]),
+            shutdown: true,
+        }
+    }
+}
+
+// Canister endpoints
+#[update]
+pub(crate) async fn set_operation_paused(operation: Operation, paused: bool) -> Result<(), String> {
+    require_role(Role::Guardian)?;
+    if operation == Operation::Transfer {
+        if let LedgerBackend::Icrc1 { .. } = ic_cdk::storage::get::<IusdLedger>().backend()? {
+            return Err("The iUSD ledger cannot pause transfers".to_string());
+        }
+    }
+    
+    let switches = ic_cdk::storage::get_mut::<PauseSwitches>();
+    audit_log::record_change(
+        &format!("set_operation_paused {:?}", operation),
+        &switches.paused.contains(&operation),
+        &paused,
+    );
+    let was_paused = switches.paused.contains(&operation);
+    if paused {
+        switches.paused.insert(operation);
+    } else {
+        switches.paused.remove(&operation);
+    }
+    
+    if operation == Operation::Transfer {
+        if let Err(e) = sync_token_transfers().await {
+            let switches = ic_cdk::storage::get_mut::<PauseSwitches>();
+            if was_paused {
+                switches.paused.insert(operation);
+            } else {
+                switches.paused.remove(&operation);
+            }
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Pauses every operation at once for incident response; each is resumed separately with
+/// `set_operation_paused`
+#[update]
+pub(crate) async fn pause_all_operations() -> Result<(), String> {
+    require_role(Role::Guardian)?;
+    
+    let switches = ic_cdk::storage::get_mut::<PauseSwitches>();
+    let previous: Vec<Operation> = switches.paused.iter().copied().collect();
+    switches.paused.extend(Operation::ALL);
+    audit_log::record_change("pause_all_operations", &previous, &Operation::ALL.to_vec());
+    sync_token_transfers()
+        .await
+        .map_err(|e| format!("Operations paused, but token transfers were not: {}", e))
+}
+
+#[query]
+fn get_paused_operations() -> Vec<Operation> {
+    ic_cdk::storage::get::<PauseSwitches>().paused.iter().copied().collect()
+}
+
+#[query]
+fn is_shut_down() -> bool {
+    ic_cdk::storage::get::<PauseSwitches>().shutdown
+}
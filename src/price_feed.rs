@@ -1,236 +1,386 @@
-use candid::{CandidType, Deserialize};
-use ic_cdk::api::management_canister::http_request::{
-    HttpResponse, TransformArgs, TransformContext,
-};
-use serde_json::Value;
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::call::CallResult;
+use ic_cdk_macros::*;
+use ic_cdk_timers::TimerId;
 use std::collections::HashMap;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::Duration;
+use crate::access_control::{require_role, Role};
+use crate::audit_log;
+use crate::vault_system;
 
-#[derive(CandidType, Deserialize, Debug, Clone)]
-pub struct PriceData {
-    /// Price in USD
-    price: f64,
-    /// Timestamp of the price
-    timestamp: u64,
-    /// Source of the price
-    source: String,
-}
+/// Assets the vaults are priced in
+const ORACLE_ASSETS: [&str; 3] = ["ICP", "BTC", "ETH"];
+/// Method the oracle canister calls with each round's update
+const ORACLE_CALLBACK_METHOD: &str = "on_oracle_price";
+const DEFAULT_MAX_UPDATE_AGE_SECONDS: u64 = 300; // 5 minutes
+const DEFAULT_SYNC_INTERVAL_SECONDS: u64 = 60;
 
-#[derive(CandidType, Deserialize, Debug)]
+/// Aggregated price as published by the oracle canister; fields the protocol doesn't use are
+/// skipped when decoding
+#[derive(CandidType, Deserialize, Debug, Clone)]
 pub struct AggregatedPrice {
-    /// Final aggregated price
-    price: f64,
+    /// Final aggregated price, as an integer mantissa
+    pub(crate) price: u128,
+    /// Decimals of the mantissa (a price of 1 USD is `10^decimals`)
+    pub(crate) decimals: u32,
     /// Timestamp of the aggregation
-    timestamp: u64,
-    /// Number of sources used
-    sources_used: u8,
-    /// Maximum deviation between sources (percentage)
-    max_deviation: f64,
+    pub(crate) timestamp: u64,
+    /// Lowest source price in the aggregation
+    pub(crate) min_price: u128,
+    /// Highest source price in the aggregation
+    pub(crate) max_price: u128,
+    /// Standard deviation of the source prices
+    pub(crate) std_deviation: u128,
 }
 
-const MAX_PRICE_AGE_SECONDS: u64 = 300; // 5 minutes
-const MAX_DEVIATION_THRESHOLD: f64 = 0.05; // 5% maximum deviation allowed
+impl AggregatedPrice {
+    /// Whether the sources' standard deviation exceeds `threshold` basis points of the price
+    pub(crate) fn is_low_confidence(&self, threshold: u32) -> bool {
+        self.std_deviation * 10000 > self.price * threshold as u128
+    }
+}
 
-pub async fn fetch_prices(asset: &str) -> Result<AggregatedPrice, String> {
-    let mut prices = Vec::new();
-    
-    // Fetch from all sources concurrently
-    let mut handles = vec![];
-    
-    // CoinGecko
-    handles.push(ic_cdk::spawn(fetch_coingecko_price(asset)));
-    // Binance
-    handles.push(ic_cdk::spawn(fetch_binance_price(asset)));
-    // Kraken
-    handles.push(ic_cdk::spawn(fetch_kraken_price(asset)));
-    
-    // Collect results
-    for handle in handles {
-        if let Ok(price_data) = handle.await {
-            prices.push(price_data);
+/// The oracle's cached price for an asset
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct CachedPrice {
+    price: AggregatedPrice,
+    /// When the oracle last refreshed the entry (seconds)
+    last_updated: u64,
+    /// Whether the oracle considered the entry stale
+    is_stale: bool,
+    /// Whether the oracle is serving its last good price
+    is_degraded: bool,
+    /// Whether the oracle's guardian has paused the asset's price
+    is_paused: bool,
+}
+
+/// An asset's state as pushed by the oracle or read from `get_price_update`
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct OraclePriceUpdate {
+    asset: String,
+    cached: CachedPrice,
+    /// Price under the asset's smoothing mode (TWAP or EMA), used for LTV checks
+    smoothed_price: Option<u128>,
+    /// Whether the oracle's circuit breaker is holding an abrupt move
+    liquidations_paused: bool,
+}
+
+/// Status of the oracle connection
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct OracleClientStatus {
+    oracle: Option<Principal>,
+    max_update_age_seconds: u64,
+    sync_interval_seconds: u64,
+    /// When each asset's latest update was received (seconds)
+    received: Vec<(String, u64)>,
+    last_error: Option<String>,
+}
+
+/// Oracle the protocol reads prices from and how fresh they must be
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct OracleSettings {
+    /// Oracle canister, subscribed to right after install; None leaves it to `set_oracle_canister`
+    pub oracle_canister: Option<Principal>,
+    pub max_update_age_seconds: u64,
+    pub sync_interval_seconds: u64,
+}
+
+/// Latest prices from the oracle canister, which pushes every round and is polled as a backstop
+pub struct OracleClient {
+    oracle: Option<Principal>,
+    /// Latest update per asset with the time it was received (seconds)
+    updates: HashMap<String, (OraclePriceUpdate, u64)>,
+    /// Updates older than this are treated as stale, in case the oracle stops publishing
+    max_update_age_seconds: u64,
+    sync_interval_seconds: u64,
+    timer_id: Option<TimerId>,
+    last_error: Option<String>,
+}
+
+impl Default for OracleClient {
+    fn default() -> Self {
+        Self {
+            oracle: None,
+            updates: HashMap::new(),
+            max_update_age_seconds: DEFAULT_MAX_UPDATE_AGE_SECONDS,
+            sync_interval_seconds: DEFAULT_SYNC_INTERVAL_SECONDS,
+            timer_id: None,
+            last_error: None,
         }
     }
-    
-    if prices.is_empty() {
-        return Err("No valid prices received from any source".to_string());
-    }
-    
-    aggregate_prices(prices)
 }
 
-async fn fetch_coingecko_price(asset: &str) -> Result<PriceData, String> {
-    let coingecko_id = match asset {
-        "ICP" => "internet-computer",
-        "BTC" => "bitcoin",
-        "ETH" => "ethereum",
-        _ => return Err(format!("Unsupported asset: {}", asset)),
-    };
-    
-    let url = format!(
-        "https://api.coingecko.com/api/v3/simple/price?ids={}&vs_currencies=usd&include_last_updated_at=true",
-        coingecko_id
-    );
-    
-    let response = http_request(url).await?;
-    let json: Value = serde_json::from_slice(&response.body)
-        .map_err(|e| format!("Failed to parse CoinGecko response: {}", e))?;
-    
-    let price = json[coingecko_id]["usd"]
-        .as_f64()
-        .ok_or("Price not found in response")?;
-        
-    let timestamp = json[coingecko_id]["last_updated_at"]
-        .as_u64()
-        .unwrap_or_else(|| SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs());
-    
-    Ok(PriceData {
-        price,
-        timestamp,
-        source: "coingecko".to_string(),
-    })
+/// What of the client is saved across upgrades; the timer is restarted by `schedule`
+#[derive(CandidType, Deserialize)]
+pub struct OracleClientState {
+    oracle: Option<Principal>,
+    updates: HashMap<String, (OraclePriceUpdate, u64)>,
+    max_update_age_seconds: u64,
+    sync_interval_seconds: u64,
+    last_error: Option<String>,
 }
 
-async fn fetch_binance_price(asset: &str) -> Result<PriceData, String> {
-    let symbol = format!("{}USDT", asset);
-    let url = format!(
-        "https://api.binance.com/api/v3/ticker/price?symbol={}",
-        symbol
-    );
-    
-    let response = http_request(url).await?;
-    let json: Value = serde_json::from_slice(&response.body)
-        .map_err(|e| format!("Failed to parse Binance response: {}", e))?;
-    
-    let price = json["price"]
-        .as_str()
-        .ok_or("Price not found in response")?
-        .parse::<f64>()
-        .map_err(|e| format!("Failed to parse price: {}", e))?;
-    
-    Ok(PriceData {
-        price,
-        timestamp: SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs(),
-        source: "binance".to_string(),
-    })
+impl Default for OracleClientState {
+    fn default() -> Self {
+        OracleClient::default().to_state()
+    }
 }
 
-async fn fetch_kraken_price(asset: &str) -> Result<PriceData, String> {
-    let symbol = format!("X{}ZUSD", asset);
-    let url = format!(
-        "https://api.kraken.com/0/public/Ticker?pair={}",
-        symbol
-    );
+impl OracleClient {
+    pub(crate) fn to_state(&self) -> OracleClientState {
+        OracleClientState {
+            oracle: self.oracle,
+            updates: self.updates.clone(),
+            max_update_age_seconds: self.max_update_age_seconds,
+            sync_interval_seconds: self.sync_interval_seconds,
+            last_error: self.last_error.clone(),
+        }
+    }
     
-    let response = http_request(url).await?;
-    let json: Value = serde_json::from_slice(&response.body)
-        .map_err(|e| format!("Failed to parse Kraken response: {}", e))?;
+    /// Replaces everything but the timer, which keeps running until the next `schedule`
+    pub(crate) fn restore(&mut self, state: OracleClientState) {
+        self.oracle = state.oracle;
+        self.updates = state.updates;
+        self.max_update_age_seconds = state.max_update_age_seconds;
+        self.sync_interval_seconds = state.sync_interval_seconds;
+        self.last_error = state.last_error;
+    }
     
-    let price = json["result"][&symbol]["c"][0]
-        .as_str()
-        .ok_or("Price not found in response")?
-        .parse::<f64>()
-        .map_err(|e| format!("Failed to parse price: {}", e))?;
+    pub fn settings(&self) -> OracleSettings {
+        OracleSettings {
+            oracle_canister: self.oracle,
+            max_update_age_seconds: self.max_update_age_seconds,
+            sync_interval_seconds: self.sync_interval_seconds,
+        }
+    }
     
-    Ok(PriceData {
-        price,
-        timestamp: SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs(),
-        source: "kraken".to_string(),
-    })
-}
-
-fn aggregate_prices(prices: Vec<PriceData>) -> Result<AggregatedPrice, String> {
-    // Filter out stale prices
-    let current_time = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
+    /// Sets the update age and polling interval, restarting the polling timer
+    pub fn set_intervals(&mut self, max_update_age_seconds: u64, sync_interval_seconds: u64) -> Result<(), String> {
+        if sync_interval_seconds == 0 || sync_interval_seconds >= max_update_age_seconds {
+            return Err("Sync interval must be positive and below the maximum update age".to_string());
+        }
         
-    let valid_prices: Vec<_> = prices
-        .into_iter()
-        .filter(|p| current_time - p.timestamp <= MAX_PRICE_AGE_SECONDS)
-        .collect();
+        self.max_update_age_seconds = max_update_age_seconds;
+        self.sync_interval_seconds = sync_interval_seconds;
+        self.schedule();
+        Ok(())
+    }
     
-    if valid_prices.len() < 2 {
-        return Err("Insufficient valid price sources".to_string());
+    /// Applies install-time settings; the oracle is subscribed to from a timer, since
+    /// `init` cannot make calls
+    pub fn configure(&mut self, settings: OracleSettings) -> Result<(), String> {
+        self.set_intervals(settings.max_update_age_seconds, settings.sync_interval_seconds)?;
+        if let Some(oracle) = settings.oracle_canister {
+            ic_cdk_timers::set_timer(Duration::ZERO, move || {
+                ic_cdk::spawn(async move {
+                    if let Err(e) = connect(oracle).await {
+                        ic_cdk::storage::get_mut::<OracleClient>().last_error = Some(e);
+                    }
+                })
+            });
+        }
+        Ok(())
     }
     
-    // Calculate median price
-    let mut price_values: Vec<_> = valid_prices.iter().map(|p| p.price).collect();
-    price_values.sort_by(|a, b| a.partial_cmp(b).unwrap());
-    let median_price = if price_values.len() % 2 == 0 {
-        (price_values[price_values.len() / 2 - 1] + price_values[price_values.len() / 2]) / 2.0
-    } else {
-        price_values[price_values.len() / 2]
-    };
+    /// (Re)starts the polling timer
+    pub fn schedule(&mut self) {
+        if let Some(timer_id) = self.timer_id.take() {
+            ic_cdk_timers::clear_timer(timer_id);
+        }
+        
+        let interval = Duration::from_secs(self.sync_interval_seconds);
+        self.timer_id = Some(ic_cdk_timers::set_timer_interval(interval, || {
+            ic_cdk::spawn(async {
+                let oracle_client = ic_cdk::storage::get_mut::<OracleClient>();
+                oracle_client.sync().await;
+            })
+        }));
+    }
     
-    // Calculate maximum deviation
-    let max_deviation = price_values
-        .iter()
-        .map(|&p| (p - median_price).abs() / median_price)
-        .max_by(|a, b| a.partial_cmp(b).unwrap())
-        .unwrap_or(0.0);
+    /// Reads every asset's update from the oracle
+    pub async fn sync(&mut self) {
+        let oracle = match self.oracle {
+            Some(oracle) => oracle,
+            None => return,
+        };
+        
+        for asset in ORACLE_ASSETS {
+            let result: CallResult<(Option<OraclePriceUpdate>,)> =
+                ic_cdk::call(oracle, "get_price_update", (asset.to_string(),)).await;
+            match result {
+                Ok((Some(update),)) => self.apply(update),
+                Ok((None,)) => self.last_error = Some(format!("Oracle has no price for {}", asset)),
+                Err((code, msg)) => {
+                    self.last_error = Some(format!("Failed to read oracle price: {:?} - {}", code, msg));
+                }
+            }
+        }
+    }
     
-    // Check if deviation is within acceptable range
-    if max_deviation > MAX_DEVIATION_THRESHOLD {
-        return Err("Price deviation too high between sources".to_string());
+    /// Stores an update unless a newer one arrived first, and pushes the smoothed price into the vaults
+    fn apply(&mut self, update: OraclePriceUpdate) {
+        let now = ic_cdk::api::time() / 1_000_000_000;
+        let outdated = self.updates.get(&update.asset).is_some_and(|(latest, _)| {
+            update.cached.last_updated < latest.cached.last_updated
+        });
+        if outdated {
+            return;
+        }
+        
+        // Push the LTV price so vault valuations and the unsafe index update here
+        // rather than on every vault operation
+        if let Some(smoothed) = update.smoothed_price {
+            vault_system::push_price(&update.asset, AggregatedPrice {
+                price: smoothed,
+                ..update.cached.price.clone()
+            });
+        }
+        self.updates.insert(update.asset.clone(), (update, now));
     }
     
+    fn latest(&self, asset: &str) -> Result<&OraclePriceUpdate, String> {
+        let now = ic_cdk::api::time() / 1_000_000_000;
+        let (update, received) = self.updates
+            .get(asset)
+            .ok_or("No oracle price for asset")?;
+            
+        // Paused prices block mints, withdrawals and liquidations; deposits and repayments need no price
+        if update.cached.is_paused {
+            return Err(format!("Price for {} is paused", asset));
+        }
+        if update.cached.is_stale || now.saturating_sub(*received) > self.max_update_age_seconds {
+            return Err(format!("Price for {} is stale", asset));
+        }
+        Ok(update)
+    }
+}
+
+/// Latest oracle price, refusing to serve one that is paused or stale
+pub fn current_price(asset: &str) -> Result<AggregatedPrice, String> {
+    let oracle_client = ic_cdk::storage::get::<OracleClient>();
+    Ok(oracle_client.latest(asset)?.cached.price.clone())
+}
+
+/// Whether liquidations against an asset are paused by the oracle's circuit breaker
+pub fn liquidations_paused(asset: &str) -> bool {
+    let oracle_client = ic_cdk::storage::get::<OracleClient>();
+    oracle_client.updates
+        .get(asset)
+        .is_some_and(|(update, _)| update.liquidations_paused)
+}
+
+/// The asset's smoothed price (TWAP or EMA, per the oracle's config), provided the spot price is fresh
+pub fn current_smoothed_price(asset: &str) -> Result<AggregatedPrice, String> {
+    let oracle_client = ic_cdk::storage::get::<OracleClient>();
+    let update = oracle_client.latest(asset)?;
+    let smoothed = update.smoothed_price.ok_or("No price history for asset")?;
     Ok(AggregatedPrice {
-        price: median_price,
-        timestamp: current_time,
-        sources_used: valid_prices.len() as u8,
-        max_deviation,
+        price: smoothed,
+        ..update.cached.price.clone()
     })
 }
 
-async fn http_request(url: String) -> Result<HttpResponse, String> {
-    let request_headers = vec![
-        ("User-Agent".to_string(), "iUSD-Protocol-Bot".to_string()),
-    ];
-    
-    let request = ic_cdk::api::management_canister::http_request::HttpRequest {
-        url,
-        method: "GET".to_string(),
-        body: None,
-        max_response_bytes: None,
-        transform: Some(TransformContext::new(transform_response, vec![])),
-        headers: request_headers,
-    };
+/// Subscribes to an oracle canister and reads its prices once
+async fn connect(oracle: Principal) -> Result<(), String> {
+    let result: CallResult<(Result<(), String>,)> =
+        ic_cdk::call(oracle, "subscribe", (ORACLE_CALLBACK_METHOD.to_string(),)).await;
+    let (subscribed,) = result
+        .map_err(|(code, msg)| format!("Failed to subscribe to oracle: {:?} - {}", code, msg))?;
+    subscribed?;
     
-    ic_cdk::api::management_canister::http_request::http_request(request)
-        .await
-        .map_err(|(code, msg)| format!("HTTP request failed: {} - {}", code, msg))?
-        .0
+    let oracle_client = ic_cdk::storage::get_mut::<OracleClient>();
+    oracle_client.oracle = Some(oracle);
+    oracle_client.updates.clear();
+    oracle_client.sync().await;
+    Ok(())
 }
 
-fn transform_response(response: TransformArgs) -> HttpResponse {
-    HttpResponse {
-        status: response.response.status,
-        headers: response.response.headers,
-        body: response.response.body,
+#[cfg(test)]
+impl OracleClient {
+    /// Client holding one update, for tests of saving and restoring state
+    pub(crate) fn sample() -> Self {
+        let price = AggregatedPrice {
+            price: 1_000_000_000,
+            decimals: 8,
+            timestamp: 1_000,
+            min_price: 990_000_000,
+            max_price: 1_010_000_000,
+            std_deviation: 5_000_000,
+        };
+        let update = OraclePriceUpdate {
+            asset: "ICP".to_string(),
+            cached: CachedPrice {
+                price,
+                last_updated: 1_000,
+                is_stale: false,
+                is_degraded: false,
+                is_paused: false,
+            },
+            smoothed_price: Some(1_000_000_000),
+            liquidations_paused: false,
+        };
+        Self {
+            oracle: Some(Principal::from_slice(&[2])),
+            updates: HashMap::from([("ICP".to_string(), (update, 1_000))]),
+            max_update_age_seconds: 600,
+            last_error: Some("Oracle unreachable".to_string()),
+            ..Default::default()
+        }
     }
 }
 
 // Canister endpoints
+#[query]
+fn get_price(asset: String) -> Result<CachedPrice, String> {
+    let oracle_client = ic_cdk::storage::get::<OracleClient>();
+    oracle_client.updates
+        .get(&asset)
+        .map(|(update, _)| update.cached.clone())
+        .ok_or_else(|| "No oracle price for asset".to_string())
+}
+
+/// Receives the oracle canister's per-round update
 #[update]
-async fn get_price(asset: String) -> Result<AggregatedPrice, String> {
-    fetch_prices(&asset).await
+fn on_oracle_price(update: OraclePriceUpdate) {
+    let oracle_client = ic_cdk::storage::get_mut::<OracleClient>();
+    if oracle_client.oracle != Some(ic_cdk::caller()) {
+        ic_cdk::trap("Unauthorized");
+    }
+    oracle_client.apply(update);
+}
+
+/// Points the protocol at an oracle canister, subscribes to its updates and reads them once
+#[update]
+pub(crate) async fn set_oracle_canister(oracle: Principal) -> Result<(), String> {
+    require_role(Role::OracleManager)?;
+    let previous = ic_cdk::storage::get::<OracleClient>().settings().oracle_canister;
+    connect(oracle).await?;
+    audit_log::record_change("set_oracle_canister", &previous, &Some(oracle));
+    Ok(())
+}
+
+#[update]
+pub(crate) fn set_oracle_client_config(max_update_age_seconds: u64, sync_interval_seconds: u64) -> Result<(), String> {
+    require_role(Role::OracleManager)?;
+    
+    let oracle_client = ic_cdk::storage::get_mut::<OracleClient>();
+    let previous = oracle_client.settings();
+    oracle_client.set_intervals(max_update_age_seconds, sync_interval_seconds)?;
+    audit_log::record_change("set_oracle_client_config", &previous, &oracle_client.settings());
+    Ok(())
 }
 
 #[query]
-fn get_supported_assets() -> Vec<String> {
-    vec![
-        "ICP".to_string(),
-        "BTC".to_string(),
-        "ETH".to_string(),
-    ]
-}
\ No newline at end of file
+fn get_oracle_client_status() -> OracleClientStatus {
+    let oracle_client = ic_cdk::storage::get::<OracleClient>();
+    OracleClientStatus {
+        oracle: oracle_client.oracle,
+        max_update_age_seconds: oracle_client.max_update_age_seconds,
+        sync_interval_seconds: oracle_client.sync_interval_seconds,
+        received: oracle_client.updates
+            .iter()
+            .map(|(asset, (_, received))| (asset.clone(), *received))
+            .collect(),
+        last_error: oracle_client.last_error.clone(),
+    }
+}
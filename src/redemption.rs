@@ -0,0 +1,255 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::*;
+use crate::access_control::{require_role, Role};
+use crate::audit_log;
+use crate::cycles::CyclesMonitor;
+use crate::validation;
+use crate::vault_system::{self, Account, CollateralType, TransferArgs, VaultController};
+
+/// Configuration for iUSD redemptions
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RedemptionConfig {
+    /// Redemption fee (in basis points, e.g. 50 = 0.5%), retained by the protocol
+    redemption_fee: u32,
+    /// Minimum amount of iUSD per redemption
+    min_redemption_amount: u128,
+    /// Vaults with less debt than this are skipped, and partial redemptions never leave less
+    dust_floor: u128,
+}
+
+impl Default for RedemptionConfig {
+    fn default() -> Self {
+        Self {
+            redemption_fee: 50,
+            min_redemption_amount: 100_000_000, // 1 iUSD
+            dust_floor: 10 * 100_000_000,       // 10 iUSD
+        }
+    }
+}
+
+/// Portion of a redemption drawn from a single vault
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RedemptionLeg {
+    /// ID of the vault redeemed against
+    vault_id: u64,
+    /// Amount of the vault's debt cancelled
+    debt_redeemed: u128,
+    /// Amount of collateral taken from the vault
+    collateral_taken: u128,
+}
+
+/// Represents a redemption event
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RedemptionEvent {
+    /// Address of the redeemer
+    redeemer: Principal,
+    /// Collateral type received
+    collateral_type: CollateralType,
+    /// Amount of iUSD redeemed
+    iusd_amount: u128,
+    /// Collateral sent to the redeemer (after fee)
+    collateral_received: u128,
+    /// Collateral retained by the protocol as fee
+    fee_amount: u128,
+    /// Vaults the redemption was drawn from
+    legs: Vec<RedemptionLeg>,
+    /// Timestamp of the redemption
+    timestamp: u64,
+}
+
+#[derive(CandidType, Deserialize, Default)]
+pub struct RedemptionController {
+    config: RedemptionConfig,
+    events: Vec<RedemptionEvent>,
+}
+
+impl RedemptionController {
+    /// Splits a redemption across vaults in ascending collateral ratio order
+    fn route_redemption(
+        &self,
+        vault_controller: &VaultController,
+        collateral_type: &CollateralType,
+        iusd_amount: u128,
+    ) -> Vec<(u64, u128)> {
+        let dust_floor = self.config.dust_floor;
+        let mut routes = Vec::new();
+        let mut remaining = iusd_amount;
+        
+        for vault_id in vault_controller.vaults_by_risk(collateral_type) {
+            if remaining == 0 {
+                break;
+            }
+            
+            let debt = match vault_controller.vaults.get(&vault_id) {
+                Some(vault) => vault.debt_amount,
+                None => continue,
+            };
+            if debt < dust_floor {
+                continue;
+            }
+            
+            // Either close the vault's debt fully or leave at least the dust floor behind
+            let debt_redeemed = if remaining >= debt {
+                debt
+            } else if debt - remaining >= dust_floor {
+                remaining
+            } else {
+                debt - dust_floor
+            };
+            if debt_redeemed == 0 {
+                continue;
+            }
+            
+            routes.push((vault_id, debt_redeemed));
+            remaining -= debt_redeemed;
+        }
+        
+        routes
+    }
+    
+    /// Redeems iUSD at face value for collateral drawn from the riskiest vaults
+    pub async fn redeem(
+        &mut self,
+        collateral_type: CollateralType,
+        iusd_amount: u128,
+    ) -> Result<RedemptionEvent, String> {
+        if iusd_amount < self.config.min_redemption_amount {
+            return Err("Amount below minimum redemption".to_string());
+        }
+        
+        let caller = ic_cdk::caller();
+        let vault_controller = ic_cdk::storage::get_mut::<VaultController>();
+        
+        // Route the redemption against vaults in ascending collateral ratio order
+        let routes = self.route_redemption(vault_controller, &collateral_type, iusd_amount);
+        let routed: u128 = routes.iter().map(|(_, debt)| *debt).sum();
+        if routed < iusd_amount {
+            return Err("Insufficient vault debt to cover redemption".to_string());
+        }
+        
+        let mut legs = Vec::new();
+        for (vault_id, debt_redeemed) in routes {
+            let vault = vault_controller.vaults.get(&vault_id)
+                .ok_or("Vault not found")?;
+            let collateral_taken = vault_controller
+                .get_collateral_amount_for_value(&collateral_type, debt_redeemed)
+                .await?
+                .min(vault.collateral_amount);
+                
+            legs.push(RedemptionLeg {
+                vault_id,
+                debt_redeemed,
+                collateral_taken,
+            });
+        }
+        
+        let total_collateral: u128 = legs.iter().map(|leg| leg.collateral_taken).sum();
+        let fee_amount = total_collateral * self.config.redemption_fee as u128 / 10000;
+        let collateral_received = total_collateral - fee_amount;
+        
+        // Burn the redeemer's iUSD first
+        let from = Account {
+            owner: caller,
+            subaccount: None,
+        };
+        vault_controller.burn_iusd_tokens(from, iusd_amount).await?;
+        
+        // Adjust the vaults that were redeemed against
+        for leg in &legs {
+            vault_controller.reduce_position(leg.vault_id, leg.debt_redeemed, leg.collateral_taken)?;
+        }
+        
+        // Send the collateral minus fee to the redeemer
+        self.transfer_collateral_to_redeemer(caller, collateral_received, &collateral_type).await?;
+        ic_cdk::storage::get_mut::<CyclesMonitor>().accrue_fee(&collateral_type, fee_amount);
+        
+        let event = RedemptionEvent {
+            redeemer: caller,
+            collateral_type,
+            iusd_amount,
+            collateral_received,
+            fee_amount,
+            legs,
+            timestamp: ic_cdk::api::time(),
+        };
+        
+        self.events.push(event.clone());
+        
+        Ok(event)
+    }
+    
+    async fn transfer_collateral_to_redeemer(
+        &self,
+        to: Principal,
+        amount: u128,
+        collateral_type: &CollateralType,
+    ) -> Result<(), String> {
+        let collateral_canister = vault_system::collateral_canister_id(collateral_type);
+        
+        let args = TransferArgs {
+            to: Account {
+                owner: to,
+                subaccount: None,
+            },
+            amount,
+        };
+        
+        match ic_cdk::call(collateral_canister, "transfer", (args,)).await {
+            Ok(()) => Ok(()),
+            Err((code, msg)) => Err(format!("Failed to transfer collateral: {:?} - {}", code, msg))
+        }
+    }
+}
+
+#[cfg(test)]
+impl RedemptionController {
+    /// Controller with one redemption, for tests of saving and restoring state
+    pub(crate) fn sample() -> Self {
+        Self {
+            config: RedemptionConfig::default(),
+            events: vec![RedemptionEvent {
+                redeemer: Principal::from_slice(&[1]),
+                collateral_type: CollateralType::ICP,
+                iusd_amount: 100_000_000,
+                collateral_received: 9_950_000,
+                fee_amount: 50_000,
+                legs: vec![RedemptionLeg {
+                    vault_id: 2,
+                    debt_redeemed: 100_000_000,
+                    collateral_taken: 10_000_000,
+                }],
+                timestamp: 1_000,
+            }],
+        }
+    }
+}
+
+// Canister endpoints
+#[update]
+async fn redeem_iusd(collateral_type: CollateralType, amount: u128) -> Result<RedemptionEvent, String> {
+    let redemption_controller = ic_cdk::storage::get_mut::<RedemptionController>();
+    redemption_controller.redeem(collateral_type, amount).await
+}
+
+#[query]
+fn get_redemption_config() -> RedemptionConfig {
+    let redemption_controller = ic_cdk::storage::get::<RedemptionController>();
+    redemption_controller.config.clone()
+}
+
+#[query]
+fn get_redemption_events() -> Vec<RedemptionEvent> {
+    let redemption_controller = ic_cdk::storage::get::<RedemptionController>();
+    redemption_controller.events.clone()
+}
+
+#[update]
+pub(crate) fn update_redemption_config(new_config: RedemptionConfig) -> Result<(), String> {
+    require_role(Role::Treasurer)?;
+    validation::check_bps("Redemption fee", new_config.redemption_fee)?;
+    
+    let redemption_controller = ic_cdk::storage::get_mut::<RedemptionController>();
+    audit_log::record_change("update_redemption_config", &redemption_controller.config, &new_config);
+    redemption_controller.config = new_config;
+    Ok(())
+}
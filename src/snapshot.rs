@@ -0,0 +1,243 @@
+use candid::{CandidType, Deserialize};
+use ic_cdk_macros::*;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use crate::access_control::{require_role, Role};
+use crate::audit_log;
+use crate::certification::CertifiedTree;
+use crate::insurance_fund::InsuranceFund;
+use crate::stability_pool::StabilityPool;
+use crate::vault_system::{CollateralType, Vault, VaultController};
+
+/// Vaults returned per `get_solvency_snapshot_vaults` page
+const MAX_VAULTS_PER_PAGE: u64 = 1000;
+
+/// Totals and risk parameters of one collateral type
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CollateralSnapshot {
+    collateral_type: CollateralType,
+    vault_count: u64,
+    total_collateral: u128,
+    total_debt: u128,
+    /// Maximum LTV in basis points
+    ltv: Option<u32>,
+    /// Effective liquidation threshold in basis points
+    liquidation_threshold: Option<u32>,
+    min_collateral: Option<u128>,
+    debt_ceiling: Option<u128>,
+    /// Collateral held by the insurance fund
+    insurance_fund_collateral: u128,
+}
+
+/// Protocol-wide figures of a snapshot
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SnapshotSummary {
+    snapshot_id: u64,
+    /// When the snapshot was taken (nanoseconds)
+    taken_at: u64,
+    vault_count: u64,
+    /// Outstanding iUSD debt across all vaults
+    total_debt: u128,
+    /// One entry per collateral type, in a fixed order
+    collateral: Vec<CollateralSnapshot>,
+    stability_pool_deposits: u128,
+    insurance_fund_balance: u128,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SnapshotVault {
+    vault_id: u64,
+    vault: Vault,
+}
+
+/// Hash of a snapshot taken at any time, with a certificate over it
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SnapshotCertificate {
+    snapshot_id: u64,
+    snapshot_hash: Vec<u8>,
+    certificate: Vec<u8>,
+    /// CBOR-encoded hash tree proving `snapshot_hash` is under `snapshots` in the certified data
+    witness: Vec<u8>,
+}
+
+/// A snapshot's summary with a certificate over its hash
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CertifiedSnapshot {
+    summary: SnapshotSummary,
+    /// SHA-256 of the candid-encoded arguments `(summary, vaults)`, with every vault in ID order
+    snapshot_hash: Vec<u8>,
+    certificate: Vec<u8>,
+    /// CBOR-encoded hash tree proving `snapshot_hash` is under `snapshots` in the certified data
+    witness: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct SolvencySnapshot {
+    summary: SnapshotSummary,
+    /// Sorted by vault ID
+    vaults: Vec<SnapshotVault>,
+    hash: [u8; 32],
+}
+
+/// The latest snapshot; earlier ones are dropped, but their hashes stay certified
+#[derive(CandidType, Deserialize, Default)]
+pub struct SolvencySnapshots {
+    latest: Option<SolvencySnapshot>,
+    next_snapshot_id: u64,
+    /// Hash of every snapshot taken, by ID
+    hashes: BTreeMap<u64, [u8; 32]>,
+}
+
+impl SolvencySnapshots {
+    /// Certifies every snapshot hash again, as the certified tree is not saved across upgrades
+    pub(crate) fn recertify(&self) {
+        let certified_tree = ic_cdk::storage::get_mut::<CertifiedTree>();
+        certified_tree.clear_snapshots();
+        for (snapshot_id, hash) in &self.hashes {
+            certified_tree.certify_snapshot(*snapshot_id, *hash);
+        }
+    }
+}
+
+fn take_snapshot(snapshot_id: u64) -> SolvencySnapshot {
+    let controller = ic_cdk::storage::get::<VaultController>();
+    let fund = ic_cdk::storage::get::<InsuranceFund>();
+    
+    let mut vaults: Vec<SnapshotVault> = controller.vaults
+        .iter()
+        .map(|(vault_id, vault)| SnapshotVault { vault_id: *vault_id, vault: vault.clone() })
+        .collect();
+    vaults.sort_by_key(|snapshot_vault| snapshot_vault.vault_id);
+    
+    let collateral: Vec<CollateralSnapshot> = [CollateralType::ICP, CollateralType::CkBTC, CollateralType::CkETH]
+        .into_iter()
+        .map(|collateral_type| {
+            let backed: Vec<&Vault> = vaults
+                .iter()
+                .map(|snapshot_vault| &snapshot_vault.vault)
+                .filter(|vault| vault.collateral_type == collateral_type)
+                .collect();
+            CollateralSnapshot {
+                vault_count: backed.len() as u64,
+                total_collateral: backed.iter().map(|vault| vault.collateral_amount).sum(),
+                total_debt: backed.iter().map(|vault| vault.debt_amount).sum(),
+                ltv: controller.collateral_ratios.get(&collateral_type).copied(),
+                liquidation_threshold: controller.liquidation_threshold(&collateral_type),
+                min_collateral: controller.min_collateral.get(&collateral_type).copied(),
+                debt_ceiling: controller.debt_ceilings.get(&collateral_type).copied(),
+                insurance_fund_collateral: fund.collateral_balance(&collateral_type),
+                collateral_type,
+            }
+        })
+        .collect();
+        
+    let summary = SnapshotSummary {
+        snapshot_id,
+        taken_at: ic_cdk::api::time(),
+        vault_count: vaults.len() as u64,
+        total_debt: collateral.iter().map(|snapshot| snapshot.total_debt).sum(),
+        collateral,
+        stability_pool_deposits: ic_cdk::storage::get::<StabilityPool>().total_deposits(),
+        insurance_fund_balance: fund.balance(),
+    };
+    let encoded = candid::encode_args((&summary, &vaults)).expect("Failed to encode solvency snapshot");
+    SolvencySnapshot {
+        summary,
+        vaults,
+        hash: Sha256::digest(&encoded).into(),
+    }
+}
+
+#[cfg(test)]
+impl SolvencySnapshots {
+    /// A taken snapshot, for tests of saving and restoring state
+    pub(crate) fn sample() -> Self {
+        let summary = SnapshotSummary {
+            snapshot_id: 0,
+            taken_at: 1_000,
+            vault_count: 0,
+            total_debt: 0,
+            collateral: Vec::new(),
+            stability_pool_deposits: 1_000_000,
+            insurance_fund_balance: 0,
+        };
+        Self {
+            latest: Some(SolvencySnapshot {
+                summary,
+                vaults: Vec::new(),
+                hash: [7; 32],
+            }),
+            next_snapshot_id: 1,
+            hashes: BTreeMap::from([(0, [7; 32])]),
+        }
+    }
+}
+
+// Canister endpoints
+/// Records every vault, the collateral totals, parameters and outstanding debt as of now and
+/// certifies the snapshot's hash, for third-party solvency audits. Replaces the previous snapshot
+#[update]
+fn take_solvency_snapshot() -> Result<SnapshotSummary, String> {
+    require_role(Role::Treasurer)?;
+    
+    let snapshots = ic_cdk::storage::get_mut::<SolvencySnapshots>();
+    let snapshot = take_snapshot(snapshots.next_snapshot_id);
+    snapshots.next_snapshot_id += 1;
+    snapshots.hashes.insert(snapshot.summary.snapshot_id, snapshot.hash);
+    
+    let certified_tree = ic_cdk::storage::get_mut::<CertifiedTree>();
+    certified_tree.certify_snapshot(snapshot.summary.snapshot_id, snapshot.hash);
+    audit_log::record(
+        "take_solvency_snapshot",
+        None,
+        Some(format!("{} sha256 {}", snapshot.summary.snapshot_id, hex::encode(snapshot.hash))),
+    );
+    
+    let summary = snapshot.summary.clone();
+    snapshots.latest = Some(snapshot);
+    Ok(summary)
+}
+
+/// Summary of the latest snapshot; the certificate is only available in query calls
+#[query]
+fn get_solvency_snapshot() -> Option<CertifiedSnapshot> {
+    let snapshot = ic_cdk::storage::get::<SolvencySnapshots>().latest.as_ref()?;
+    let certified_tree = ic_cdk::storage::get::<CertifiedTree>();
+    
+    Some(CertifiedSnapshot {
+        summary: snapshot.summary.clone(),
+        snapshot_hash: snapshot.hash.to_vec(),
+        certificate: ic_cdk::api::data_certificate()?,
+        witness: certified_tree.snapshot_witness(snapshot.summary.snapshot_id),
+    })
+}
+
+/// Hash of any snapshot taken, with a certificate, so audits of earlier snapshots can still be
+/// checked; only available in query calls
+#[query]
+fn get_snapshot_certificate(snapshot_id: u64) -> Option<SnapshotCertificate> {
+    let snapshot_hash = ic_cdk::storage::get::<SolvencySnapshots>().hashes.get(&snapshot_id)?;
+    let certified_tree = ic_cdk::storage::get::<CertifiedTree>();
+    
+    Some(SnapshotCertificate {
+        snapshot_id,
+        snapshot_hash: snapshot_hash.to_vec(),
+        certificate: ic_cdk::api::data_certificate()?,
+        witness: certified_tree.snapshot_witness(snapshot_id),
+    })
+}
+
+/// Vaults of the latest snapshot in ID order, at most 1000 per page
+#[query]
+fn get_solvency_snapshot_vaults(offset: u64, limit: u64) -> Vec<SnapshotVault> {
+    let snapshots = ic_cdk::storage::get::<SolvencySnapshots>();
+    match &snapshots.latest {
+        Some(snapshot) => snapshot.vaults
+            .iter()
+            .skip(offset as usize)
+            .take(limit.min(MAX_VAULTS_PER_PAGE) as usize)
+            .cloned()
+            .collect(),
+        None => Vec::new(),
+    }
+}
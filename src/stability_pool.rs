@@ -0,0 +1,609 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::*;
+use std::collections::HashMap;
+use crate::access_control::{require_role, Role};
+use crate::audit_log;
+use crate::ledger_backend::IusdLedger;
+use crate::validation;
+use crate::vault_system::{self, Account, CollateralType, TransferArgs, VaultController};
+
+/// Precision of the P and S accumulators
+const DECIMAL_PRECISION: u128 = 1_000_000_000_000_000_000;
+/// Factor P is rescaled by when it would otherwise lose precision
+const SCALE_FACTOR: u128 = 1_000_000_000;
+
+/// Configuration for the stability pool
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct StabilityPoolConfig {
+    /// DEX canister used to swap collateral gains back into iUSD
+    dex_canister: Principal,
+    /// Maximum slippage accepted on auto-compound swaps (in basis points)
+    max_slippage: u32,
+}
+
+impl Default for StabilityPoolConfig {
+    fn default() -> Self {
+        Self {
+            // Unset until `update_stability_pool_config` names the DEX
+            dex_canister: Principal::anonymous(),
+            max_slippage: 100,
+        }
+    }
+}
+
+/// A depositor's position in the stability pool
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct StabilityDeposit {
+    /// iUSD deposited as of the last snapshot
+    initial_value: u128,
+    /// Value of P at the last snapshot
+    snapshot_p: u128,
+    /// Value of S per collateral type at the last snapshot
+    snapshot_s: HashMap<CollateralType, u128>,
+    /// Epoch at the last snapshot
+    snapshot_epoch: u64,
+    /// Scale at the last snapshot
+    snapshot_scale: u64,
+    /// Collateral gains settled but not yet claimed
+    pending_gains: HashMap<CollateralType, u128>,
+    /// Whether claimed gains are swapped back into iUSD and re-deposited
+    auto_compound: bool,
+}
+
+/// Current view of a depositor's position
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct StabilityDepositInfo {
+    /// iUSD deposit after absorbed liquidations
+    compounded_deposit: u128,
+    /// Collateral gains available to claim
+    collateral_gains: HashMap<CollateralType, u128>,
+    /// Whether auto-compounding is enabled
+    auto_compound: bool,
+}
+
+/// Result of claiming stability pool gains
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ClaimResult {
+    /// Collateral sent to the depositor
+    collateral_paid: HashMap<CollateralType, u128>,
+    /// iUSD re-deposited from auto-compounded gains
+    iusd_compounded: u128,
+}
+
+#[derive(CandidType)]
+struct SwapArgs {
+    token_in: Principal,
+    token_out: Principal,
+    amount_in: u128,
+    min_amount_out: u128,
+}
+
+/// Token movements in and out of the pool, behind a trait so tests can make them fail
+pub(crate) trait PoolTransfers {
+    async fn pull_iusd(&self, from: Principal, amount: u128) -> Result<(), String>;
+    async fn send_iusd(&self, to: Principal, amount: u128) -> Result<(), String>;
+    async fn send_collateral(&self, collateral_type: &CollateralType, to: Principal, amount: u128) -> Result<(), String>;
+    /// Sells collateral on the DEX, returning the iUSD received
+    async fn swap_for_iusd(
+        &self,
+        config: &StabilityPoolConfig,
+        collateral_type: &CollateralType,
+        amount: u128,
+    ) -> Result<u128, String>;
+}
+
+/// Moves tokens through the iUSD and collateral ledgers and the DEX
+pub(crate) struct LedgerTransfers;
+
+impl PoolTransfers for LedgerTransfers {
+    async fn pull_iusd(&self, from: Principal, amount: u128) -> Result<(), String> {
+        // Depositors approve the protocol beforehand, so the ledger lets it pull the iUSD
+        vault_system::pull_iusd(from, amount).await
+    }
+    
+    async fn send_iusd(&self, to: Principal, amount: u128) -> Result<(), String> {
+        let to = Account {
+            owner: to,
+            subaccount: None,
+        };
+        ic_cdk::storage::get::<IusdLedger>().transfer(to, amount).await
+    }
+    
+    async fn send_collateral(&self, collateral_type: &CollateralType, to: Principal, amount: u128) -> Result<(), String> {
+        let args = TransferArgs {
+            to: Account {
+                owner: to,
+                subaccount: None,
+            },
+            amount,
+        };
+        
+        let token = vault_system::collateral_canister_id(collateral_type);
+        match ic_cdk::call(token, "transfer", (args,)).await {
+            Ok(()) => Ok(()),
+            Err((code, msg)) => Err(format!("Failed to transfer from stability pool: {:?} - {}", code, msg))
+        }
+    }
+    
+    async fn swap_for_iusd(
+        &self,
+        config: &StabilityPoolConfig,
+        collateral_type: &CollateralType,
+        amount: u128,
+    ) -> Result<u128, String> {
+        // Bound slippage against the oracle value of the collateral
+        let vault_controller = ic_cdk::storage::get::<VaultController>();
+        let oracle_value = vault_controller.get_collateral_value(collateral_type, amount).await?;
+        let min_amount_out = oracle_value * (10000 - config.max_slippage as u128) / 10000;
+        
+        let args = SwapArgs {
+            token_in: vault_system::collateral_canister_id(collateral_type),
+            token_out: ic_cdk::storage::get::<IusdLedger>().canister_id()?,
+            amount_in: amount,
+            min_amount_out,
+        };
+        
+        match ic_cdk::call::<_, (Result<u128, String>,)>(config.dex_canister, "swap", (args,)).await {
+            Ok((result,)) => result,
+            Err((code, msg)) => Err(format!("Failed to swap collateral: {:?} - {}", code, msg))
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct StabilityPool {
+    config: StabilityPoolConfig,
+    deposits: HashMap<Principal, StabilityDeposit>,
+    /// Total iUSD currently in the pool
+    total_deposits: u128,
+    /// Collateral held by the pool, owed to depositors
+    collateral_balances: HashMap<CollateralType, u128>,
+    /// Running product tracking deposit depletion
+    p: u128,
+    /// Incremented each time the pool is fully emptied by an offset
+    current_epoch: u64,
+    /// Incremented each time P is rescaled
+    current_scale: u64,
+    /// Running sums of collateral gain per unit staked, per epoch and scale
+    epoch_to_scale_to_sum: HashMap<(u64, u64, CollateralType), u128>,
+}
+
+impl Default for StabilityPool {
+    fn default() -> Self {
+        Self {
+            config: StabilityPoolConfig::default(),
+            deposits: HashMap::new(),
+            total_deposits: 0,
+            collateral_balances: HashMap::new(),
+            p: DECIMAL_PRECISION,
+            current_epoch: 0,
+            current_scale: 0,
+            epoch_to_scale_to_sum: HashMap::new(),
+        }
+    }
+}
+
+/// Computes a * b / c without overflowing the intermediate product
+fn mul_div(a: u128, b: u128, c: u128) -> u128 {
+    const MASK: u128 = u64::MAX as u128;
+    
+    // 256-bit product as (hi, lo)
+    let (a_hi, a_lo) = (a >> 64, a & MASK);
+    let (b_hi, b_lo) = (b >> 64, b & MASK);
+    let lo_lo = a_lo * b_lo;
+    let hi_lo = a_hi * b_lo;
+    let lo_hi = a_lo * b_hi;
+    let mid = (lo_lo >> 64) + (hi_lo & MASK) + (lo_hi & MASK);
+    let lo = (lo_lo & MASK) | (mid << 64);
+    let hi = a_hi * b_hi + (hi_lo >> 64) + (lo_hi >> 64) + (mid >> 64);
+    
+    if hi >= c {
+        return u128::MAX;
+    }
+    
+    // Binary long division of (hi, lo) by c
+    let mut remainder = hi;
+    let mut quotient = 0u128;
+    for i in (0..128).rev() {
+        let carry = remainder >> 127;
+        remainder = (remainder << 1) | ((lo >> i) & 1);
+        quotient <<= 1;
+        if carry == 1 || remainder >= c {
+            remainder = remainder.wrapping_sub(c);
+            quotient |= 1;
+        }
+    }
+    
+    quotient
+}
+
+impl StabilityPool {
+    fn sum(&self, epoch: u64, scale: u64, collateral_type: &CollateralType) -> u128 {
+        *self.epoch_to_scale_to_sum
+            .get(&(epoch, scale, collateral_type.clone()))
+            .unwrap_or(&0)
+    }
+    
+    /// Deposit remaining after the liquidations absorbed since the last snapshot
+    fn compounded_deposit(&self, deposit: &StabilityDeposit) -> u128 {
+        if deposit.initial_value == 0 || deposit.snapshot_epoch < self.current_epoch {
+            return 0;
+        }
+        
+        match self.current_scale - deposit.snapshot_scale {
+            0 => mul_div(deposit.initial_value, self.p, deposit.snapshot_p),
+            1 => mul_div(deposit.initial_value, self.p, deposit.snapshot_p) / SCALE_FACTOR,
+            // Depleted by more than a factor of 1e9, treat as fully consumed
+            _ => 0,
+        }
+    }
+    
+    /// Collateral gain accrued since the last snapshot
+    fn collateral_gain(&self, deposit: &StabilityDeposit, collateral_type: &CollateralType) -> u128 {
+        if deposit.initial_value == 0 {
+            return 0;
+        }
+        
+        let snapshot_s = *deposit.snapshot_s.get(collateral_type).unwrap_or(&0);
+        let epoch = deposit.snapshot_epoch;
+        let scale = deposit.snapshot_scale;
+        
+        // Gains are split across at most the snapshot scale and the one after it
+        let first_portion = self.sum(epoch, scale, collateral_type).saturating_sub(snapshot_s);
+        let second_portion = self.sum(epoch, scale + 1, collateral_type) / SCALE_FACTOR;
+        
+        mul_div(deposit.initial_value, first_portion + second_portion, deposit.snapshot_p)
+    }
+    
+    /// Moves accrued gains into pending and re-snapshots the deposit at the current state
+    fn settle(&mut self, depositor: Principal) {
+        let mut deposit = self.deposits.remove(&depositor).unwrap_or_default();
+        
+        for collateral_type in [CollateralType::ICP, CollateralType::CkBTC, CollateralType::CkETH] {
+            let gain = self.collateral_gain(&deposit, &collateral_type);
+            if gain > 0 {
+                *deposit.pending_gains.entry(collateral_type.clone()).or_insert(0) += gain;
+            }
+            let current_s = self.sum(self.current_epoch, self.current_scale, &collateral_type);
+            deposit.snapshot_s.insert(collateral_type, current_s);
+        }
+        
+        deposit.initial_value = self.compounded_deposit(&deposit);
+        deposit.snapshot_p = self.p;
+        deposit.snapshot_epoch = self.current_epoch;
+        deposit.snapshot_scale = self.current_scale;
+        
+        self.deposits.insert(depositor, deposit);
+    }
+    
+    /// Total iUSD currently in the pool
+    pub fn total_deposits(&self) -> u128 {
+        self.total_deposits
+    }
+    
+    /// Absorbs liquidated debt with pool deposits, distributing the seized collateral
+    pub(crate) fn offset(
+        &mut self,
+        debt_to_offset: u128,
+        collateral_to_add: u128,
+        collateral_type: &CollateralType,
+    ) -> Result<(), String> {
+        if self.total_deposits == 0 || debt_to_offset == 0 {
+            return Err("Nothing to offset".to_string());
+        }
+        if debt_to_offset > self.total_deposits {
+            return Err("Debt exceeds stability pool deposits".to_string());
+        }
+        
+        // Collateral gain per unit staked rounds down, debt loss rounds up, both in favor of the pool
+        let gain_per_unit = mul_div(collateral_to_add, DECIMAL_PRECISION, self.total_deposits);
+        let loss_per_unit = if debt_to_offset == self.total_deposits {
+            DECIMAL_PRECISION
+        } else {
+            (mul_div(debt_to_offset, DECIMAL_PRECISION, self.total_deposits) + 1).min(DECIMAL_PRECISION)
+        };
+        
+        let key = (self.current_epoch, self.current_scale, collateral_type.clone());
+        let marginal_gain = mul_div(gain_per_unit, self.p, DECIMAL_PRECISION);
+        *self.epoch_to_scale_to_sum.entry(key).or_insert(0) += marginal_gain;
+        
+        let product_factor = DECIMAL_PRECISION - loss_per_unit;
+        if product_factor == 0 {
+            // Pool emptied, start a new epoch
+            self.current_epoch += 1;
+            self.current_scale = 0;
+            self.p = DECIMAL_PRECISION;
+        } else {
+            let new_p = mul_div(self.p, product_factor, DECIMAL_PRECISION);
+            if new_p < SCALE_FACTOR {
+                self.p = mul_div(self.p, product_factor * SCALE_FACTOR, DECIMAL_PRECISION);
+                self.current_scale += 1;
+            } else {
+                self.p = new_p;
+            }
+        }
+        
+        self.total_deposits -= debt_to_offset;
+        *self.collateral_balances.entry(collateral_type.clone()).or_insert(0) += collateral_to_add;
+        
+        Ok(())
+    }
+    
+    /// Adds iUSD to a deposit, re-snapshotting it first so the amount only shares later offsets
+    fn credit_deposit(&mut self, depositor: Principal, amount: u128) {
+        self.settle(depositor);
+        let deposit = self.deposits.get_mut(&depositor).unwrap();
+        deposit.initial_value += amount;
+        self.total_deposits += amount;
+    }
+    
+    /// Deposits iUSD into the stability pool
+    pub(crate) async fn provide(
+        &mut self,
+        depositor: Principal,
+        amount: u128,
+        transfers: &impl PoolTransfers,
+    ) -> Result<(), String> {
+        if amount == 0 {
+            return Err("Amount must be positive".to_string());
+        }
+        
+        transfers.pull_iusd(depositor, amount).await?;
+        self.credit_deposit(depositor, amount);
+        Ok(())
+    }
+    
+    /// Withdraws iUSD from the stability pool
+    pub(crate) async fn withdraw(
+        &mut self,
+        depositor: Principal,
+        amount: u128,
+        transfers: &impl PoolTransfers,
+    ) -> Result<(), String> {
+        if !self.deposits.contains_key(&depositor) {
+            return Err("Deposit not found".to_string());
+        }
+        self.settle(depositor);
+        
+        let deposit = self.deposits.get_mut(&depositor).unwrap();
+        if deposit.initial_value < amount {
+            return Err("Amount exceeds compounded deposit".to_string());
+        }
+        // Taken out before the transfer so offsets meanwhile don't draw on it
+        deposit.initial_value -= amount;
+        self.total_deposits -= amount;
+        
+        if let Err(e) = transfers.send_iusd(depositor, amount).await {
+            self.credit_deposit(depositor, amount);
+            return Err(e);
+        }
+        Ok(())
+    }
+    
+    /// Opts the caller in or out of auto-compounding
+    pub fn set_auto_compound(&mut self, enabled: bool) -> Result<(), String> {
+        let deposit = self.deposits.get_mut(&ic_cdk::caller())
+            .ok_or("Deposit not found")?;
+            
+        deposit.auto_compound = enabled;
+        Ok(())
+    }
+    
+    /// Claims collateral gains, swapping them back into the pool when auto-compound is enabled.
+    /// Gains that fail to pay out stay pending, while those already paid are kept
+    pub(crate) async fn claim_gains(
+        &mut self,
+        depositor: Principal,
+        transfers: &impl PoolTransfers,
+    ) -> Result<ClaimResult, String> {
+        if !self.deposits.contains_key(&depositor) {
+            return Err("Deposit not found".to_string());
+        }
+        
+        self.settle(depositor);
+        let deposit = self.deposits.get_mut(&depositor).unwrap();
+        let mut gains: Vec<(CollateralType, u128)> = std::mem::take(&mut deposit.pending_gains)
+            .into_iter()
+            .collect();
+        let auto_compound = deposit.auto_compound;
+        for (collateral_type, amount) in &gains {
+            let balance = self.collateral_balances.entry(collateral_type.clone()).or_insert(0);
+            *balance = balance.saturating_sub(*amount);
+        }
+        
+        let mut result = ClaimResult {
+            collateral_paid: HashMap::new(),
+            iusd_compounded: 0,
+        };
+        let mut error = None;
+        while let Some((collateral_type, amount)) = gains.pop() {
+            let paid = if auto_compound {
+                transfers.swap_for_iusd(&self.config, &collateral_type, amount).await
+                    .map(|iusd_out| result.iusd_compounded += iusd_out)
+            } else {
+                transfers.send_collateral(&collateral_type, depositor, amount).await
+                    .map(|()| {
+                        result.collateral_paid.insert(collateral_type.clone(), amount);
+                    })
+            };
+            if let Err(e) = paid {
+                gains.push((collateral_type, amount));
+                error = Some(e);
+                break;
+            }
+        }
+        
+        if !gains.is_empty() {
+            let deposit = self.deposits.get_mut(&depositor).unwrap();
+            for (collateral_type, amount) in gains {
+                *deposit.pending_gains.entry(collateral_type.clone()).or_insert(0) += amount;
+                *self.collateral_balances.entry(collateral_type).or_insert(0) += amount;
+            }
+        }
+        if result.iusd_compounded > 0 {
+            self.credit_deposit(depositor, result.iusd_compounded);
+        }
+        
+        error.map_or(Ok(result), Err)
+    }
+    
+    /// Returns a depositor's compounded deposit and claimable gains
+    pub fn get_deposit_info(&self, depositor: &Principal) -> Option<StabilityDepositInfo> {
+        let deposit = self.deposits.get(depositor)?;
+        
+        let mut collateral_gains = deposit.pending_gains.clone();
+        for collateral_type in [CollateralType::ICP, CollateralType::CkBTC, CollateralType::CkETH] {
+            let gain = self.collateral_gain(deposit, &collateral_type);
+            if gain > 0 {
+                *collateral_gains.entry(collateral_type).or_insert(0) += gain;
+            }
+        }
+        
+        Some(StabilityDepositInfo {
+            compounded_deposit: self.compounded_deposit(deposit),
+            collateral_gains,
+            auto_compound: deposit.auto_compound,
+        })
+    }
+}
+
+#[cfg(test)]
+impl StabilityPool {
+    /// Pool with a deposit that absorbed a liquidation, for tests of saving and restoring state
+    pub(crate) fn sample() -> Self {
+        let mut pool = Self {
+            config: StabilityPoolConfig {
+                dex_canister: Principal::from_slice(&[3]),
+                max_slippage: 100,
+            },
+            ..Default::default()
+        };
+        pool.credit_deposit(Principal::from_slice(&[1]), 1_000_000);
+        pool.offset(400_000, 50_000, &CollateralType::ICP).unwrap();
+        pool
+    }
+}
+
+// Canister endpoints
+#[update]
+async fn provide_to_stability_pool(amount: u128) -> Result<(), String> {
+    let pool = ic_cdk::storage::get_mut::<StabilityPool>();
+    pool.provide(ic_cdk::caller(), amount, &LedgerTransfers).await
+}
+
+#[update]
+async fn withdraw_from_stability_pool(amount: u128) -> Result<(), String> {
+    let pool = ic_cdk::storage::get_mut::<StabilityPool>();
+    pool.withdraw(ic_cdk::caller(), amount, &LedgerTransfers).await
+}
+
+#[update]
+async fn claim_gains() -> Result<ClaimResult, String> {
+    let pool = ic_cdk::storage::get_mut::<StabilityPool>();
+    pool.claim_gains(ic_cdk::caller(), &LedgerTransfers).await
+}
+
+#[update]
+fn set_auto_compound(enabled: bool) -> Result<(), String> {
+    let pool = ic_cdk::storage::get_mut::<StabilityPool>();
+    pool.set_auto_compound(enabled)
+}
+
+#[query]
+fn get_stability_deposit(depositor: Principal) -> Option<StabilityDepositInfo> {
+    let pool = ic_cdk::storage::get::<StabilityPool>();
+    pool.get_deposit_info(&depositor)
+}
+
+#[query]
+fn get_stability_pool_total() -> u128 {
+    let pool = ic_cdk::storage::get::<StabilityPool>();
+    pool.total_deposits()
+}
+
+#[update]
+pub(crate) fn update_stability_pool_config(new_config: StabilityPoolConfig) -> Result<(), String> {
+    require_role(Role::Admin)?;
+    validation::check_bps("Maximum slippage", new_config.max_slippage)?;
+    
+    let pool = ic_cdk::storage::get_mut::<StabilityPool>();
+    audit_log::record_change("update_stability_pool_config", &pool.config, &new_config);
+    pool.config = new_config;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// Pulls succeed; every payout and swap fails
+    struct FailingTransfers;
+    
+    impl PoolTransfers for FailingTransfers {
+        async fn pull_iusd(&self, _from: Principal, _amount: u128) -> Result<(), String> {
+            Ok(())
+        }
+        
+        async fn send_iusd(&self, _to: Principal, _amount: u128) -> Result<(), String> {
+            Err("transfer failed".to_string())
+        }
+        
+        async fn send_collateral(&self, _collateral_type: &CollateralType, _to: Principal, _amount: u128) -> Result<(), String> {
+            Err("transfer failed".to_string())
+        }
+        
+        async fn swap_for_iusd(
+            &self,
+            _config: &StabilityPoolConfig,
+            _collateral_type: &CollateralType,
+            _amount: u128,
+        ) -> Result<u128, String> {
+            Err("swap failed".to_string())
+        }
+    }
+    
+    fn pool_with_gains(depositor: Principal) -> StabilityPool {
+        let mut pool = StabilityPool {
+            config: StabilityPoolConfig {
+                dex_canister: Principal::anonymous(),
+                max_slippage: 100,
+            },
+            ..Default::default()
+        };
+        pool.credit_deposit(depositor, 1_000_000);
+        pool.offset(400_000, 50_000, &CollateralType::ICP).unwrap();
+        pool.offset(100_000, 7_000, &CollateralType::CkETH).unwrap();
+        pool
+    }
+    
+    #[tokio::test]
+    async fn failed_withdrawal_keeps_the_deposit() {
+        let depositor = Principal::from_slice(&[1]);
+        let mut pool = pool_with_gains(depositor);
+        let before = pool.get_deposit_info(&depositor).unwrap();
+        
+        assert!(pool.withdraw(depositor, 200_000, &FailingTransfers).await.is_err());
+        
+        let after = pool.get_deposit_info(&depositor).unwrap();
+        assert_eq!(after.compounded_deposit, before.compounded_deposit);
+        assert_eq!(pool.total_deposits(), 500_000);
+    }
+    
+    #[tokio::test]
+    async fn failed_claim_keeps_the_gains() {
+        let depositor = Principal::from_slice(&[1]);
+        for auto_compound in [false, true] {
+            let mut pool = pool_with_gains(depositor);
+            pool.deposits.get_mut(&depositor).unwrap().auto_compound = auto_compound;
+            let before = pool.get_deposit_info(&depositor).unwrap();
+            let balances = pool.collateral_balances.clone();
+            
+            assert!(pool.claim_gains(depositor, &FailingTransfers).await.is_err());
+            
+            let after = pool.get_deposit_info(&depositor).unwrap();
+            assert_eq!(after.collateral_gains, before.collateral_gains);
+            assert_eq!(after.compounded_deposit, before.compounded_deposit);
+            assert_eq!(pool.collateral_balances, balances);
+        }
+    }
+}
@@ -0,0 +1,402 @@
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, StableCell};
+use std::cell::RefCell;
+use crate::access_control::AccessControl;
+use crate::auction::AuctionHouse;
+use crate::config::ConfigRegistry;
+use crate::council::Council;
+use crate::cycles::{CyclesMonitor, CyclesMonitorState};
+use crate::insurance_fund::InsuranceFund;
+use crate::keeper::{Keeper, KeeperState};
+use crate::ledger_backend::IusdLedger;
+use crate::liquidation::LiquidationController;
+use crate::liquidator_bonds::BondRegistry;
+use crate::multi_approval::ApprovalQueue;
+use crate::pause::PauseSwitches;
+use crate::price_feed::{OracleClient, OracleClientState};
+use crate::redemption::RedemptionController;
+use crate::snapshot::SolvencySnapshots;
+use crate::stability_pool::StabilityPool;
+use crate::timelock::Timelock;
+use crate::vault_system::VaultController;
+
+pub(crate) type ProtocolMemory = VirtualMemory<DefaultMemoryImpl>;
+
+const STATE_MEMORY_ID: MemoryId = MemoryId::new(0);
+pub(crate) const AUDIT_LOG_INDEX_MEMORY_ID: MemoryId = MemoryId::new(1);
+pub(crate) const AUDIT_LOG_DATA_MEMORY_ID: MemoryId = MemoryId::new(2);
+#[cfg(feature = "allowlist")]
+pub(crate) const ALLOWLIST_MEMORY_ID: MemoryId = MemoryId::new(3);
+/// Start of stable memory holding a memory manager
+const MEMORY_MANAGER_MAGIC: &[u8; 3] = b"MGR";
+
+/// Layout of `StableState` this release writes. Changing the layout (e.g. adding a field to
+/// `Vault` or `LiquidationConfig`) bumps it and appends a step to `MIGRATIONS`
+pub(crate) const SCHEMA_VERSION: u32 = 2;
+
+/// Steps upgrading the candid-encoded state one version at a time; entry `i` takes version
+/// `i + 1` to `i + 2`. A step decodes the old layout (kept as a private struct next to it),
+/// fills in what changed and encodes the next layout
+const MIGRATIONS: &[fn(Vec<u8>) -> Result<Vec<u8>, String>] = &[migrate_v1];
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+    
+    /// Candid-encoded `VersionedState`, written on upgrade
+    static STATE: RefCell<StableCell<Vec<u8>, ProtocolMemory>> = RefCell::new(
+        StableCell::init(memory(STATE_MEMORY_ID), Vec::new())
+            .expect("Failed to open protocol state memory")
+    );
+}
+
+/// Virtual stable memory shared out by the canister's memory manager
+pub(crate) fn memory(id: MemoryId) -> ProtocolMemory {
+    MEMORY_MANAGER.with(|manager| manager.borrow().get(id))
+}
+
+/// State written to stable memory before an upgrade and read back after it: every module's heap
+/// state except the certified tree, which is rebuilt from it, and backups in progress
+#[derive(CandidType, Deserialize, Default)]
+struct StableState {
+    vault_controller: VaultController,
+    liquidation_controller: LiquidationController,
+    access_control: AccessControl,
+    config_registry: ConfigRegistry,
+    iusd_ledger: IusdLedger,
+    oracle_client: OracleClientState,
+    stability_pool: StabilityPool,
+    bond_registry: BondRegistry,
+    insurance_fund: InsuranceFund,
+    auction_house: AuctionHouse,
+    timelock: Timelock,
+    council: Council,
+    approval_queue: ApprovalQueue,
+    pause_switches: PauseSwitches,
+    cycles_monitor: CyclesMonitorState,
+    solvency_snapshots: SolvencySnapshots,
+    keeper: KeeperState,
+    redemption_controller: RedemptionController,
+}
+
+/// Schema 1, which saved only the vaults and liquidations
+#[derive(CandidType, Deserialize)]
+struct StableStateV1 {
+    vault_controller: VaultController,
+    liquidation_controller: LiquidationController,
+}
+
+impl From<StableStateV1> for StableState {
+    /// Everything else was lost on each upgrade, so it starts from its defaults
+    fn from(state: StableStateV1) -> Self {
+        Self {
+            vault_controller: state.vault_controller,
+            liquidation_controller: state.liquidation_controller,
+            ..Default::default()
+        }
+    }
+}
+
+fn migrate_v1(bytes: Vec<u8>) -> Result<Vec<u8>, String> {
+    let state: StableStateV1 = candid::decode_one(&bytes).map_err(|e| e.to_string())?;
+    candid::encode_one(StableState::from(state)).map_err(|e| e.to_string())
+}
+
+/// What is actually saved: the encoded state stamped with its layout
+#[derive(CandidType, Deserialize)]
+struct VersionedState {
+    schema_version: u32,
+    state: Vec<u8>,
+}
+
+/// Brings saved state up to `SCHEMA_VERSION` step by step
+fn migrate(mut saved: VersionedState) -> Result<StableState, String> {
+    if saved.schema_version == 0 || saved.schema_version > SCHEMA_VERSION {
+        return Err(format!(
+            "Cannot migrate state schema {} to {}",
+            saved.schema_version, SCHEMA_VERSION
+        ));
+    }
+    
+    while saved.schema_version < SCHEMA_VERSION {
+        let step = MIGRATIONS[(saved.schema_version - 1) as usize];
+        saved.state = step(saved.state)
+            .map_err(|e| format!("Migration from schema {} failed: {}", saved.schema_version, e))?;
+        saved.schema_version += 1;
+    }
+    candid::decode_one(&saved.state).map_err(|e| format!("Failed to decode state: {}", e))
+}
+
+/// Moves every module's state out, leaving defaults; modules running timers are copied instead
+fn take() -> StableState {
+    fn moved<T: Default + 'static>() -> T {
+        std::mem::take(ic_cdk::storage::get_mut::<T>())
+    }
+    
+    StableState {
+        vault_controller: moved(),
+        liquidation_controller: moved(),
+        access_control: moved(),
+        config_registry: moved(),
+        iusd_ledger: moved(),
+        oracle_client: ic_cdk::storage::get::<OracleClient>().to_state(),
+        stability_pool: moved(),
+        bond_registry: moved(),
+        insurance_fund: moved(),
+        auction_house: moved(),
+        timelock: moved(),
+        council: moved(),
+        approval_queue: moved(),
+        pause_switches: moved(),
+        cycles_monitor: ic_cdk::storage::get::<CyclesMonitor>().to_state(),
+        solvency_snapshots: moved(),
+        keeper: ic_cdk::storage::get::<Keeper>().to_state(),
+        redemption_controller: moved(),
+    }
+}
+
+/// Replaces every module's state; running timers are left as they are
+fn replace(state: StableState) {
+    fn put<T: Default + 'static>(value: T) {
+        *ic_cdk::storage::get_mut::<T>() = value;
+    }
+    
+    put(state.vault_controller);
+    put(state.liquidation_controller);
+    put(state.access_control);
+    put(state.config_registry);
+    put(state.iusd_ledger);
+    ic_cdk::storage::get_mut::<OracleClient>().restore(state.oracle_client);
+    put(state.stability_pool);
+    put(state.bond_registry);
+    put(state.insurance_fund);
+    put(state.auction_house);
+    put(state.timelock);
+    put(state.council);
+    put(state.approval_queue);
+    put(state.pause_switches);
+    ic_cdk::storage::get_mut::<CyclesMonitor>().restore(state.cycles_monitor);
+    put(state.solvency_snapshots);
+    ic_cdk::storage::get_mut::<Keeper>().restore(state.keeper);
+    put(state.redemption_controller);
+}
+
+/// Candid-encoded `VersionedState` of the current state, as saved across upgrades
+pub(crate) fn encode() -> Vec<u8> {
+    // `StableState` owns its fields, so the state is moved out for encoding and back afterwards
+    let state = take();
+    let encoded = candid::encode_one(&state).expect("Failed to encode protocol state");
+    replace(state);
+    
+    let saved = VersionedState {
+        schema_version: SCHEMA_VERSION,
+        state: encoded,
+    };
+    candid::encode_one(&saved).expect("Failed to encode protocol state")
+}
+
+/// Replaces the current state with one produced by `encode`, migrating older layouts
+pub(crate) fn decode(bytes: &[u8]) -> Result<(), String> {
+    install(parse(bytes)?);
+    Ok(())
+}
+
+fn parse(bytes: &[u8]) -> Result<StableState, String> {
+    let saved: VersionedState = candid::decode_one(bytes).map_err(|e| format!("Failed to decode state: {}", e))?;
+    migrate(saved)
+}
+
+fn install(state: StableState) {
+    replace(state);
+    let liquidation_controller = ic_cdk::storage::get_mut::<LiquidationController>();
+    liquidation_controller.release_claims();
+    liquidation_controller.recertify_events();
+    ic_cdk::storage::get::<SolvencySnapshots>().recertify();
+    
+    // Timers don't survive an upgrade, and a restore may change their settings
+    ic_cdk::storage::get_mut::<OracleClient>().schedule();
+    ic_cdk::storage::get_mut::<Keeper>().schedule();
+    ic_cdk::storage::get_mut::<CyclesMonitor>().schedule();
+}
+
+/// Writes the state to stable memory before an upgrade
+pub(crate) fn save() {
+    let bytes = encode();
+    STATE.with(|cell| cell.borrow_mut().set(bytes).expect("Failed to save protocol state"));
+}
+
+/// Reads state saved to raw stable memory by releases before the memory manager
+fn restore_unmanaged() -> StableState {
+    match ic_cdk::storage::stable_restore::<(VersionedState,)>() {
+        Ok((saved,)) => migrate(saved).unwrap_or_else(|e| ic_cdk::trap(&e)),
+        // The first release that saved its state wrote it bare, in the layout of schema 1
+        Err(_) => {
+            let (state,): (StableStateV1,) = ic_cdk::storage::stable_restore().expect("Failed to restore protocol state");
+            state.into()
+        }
+    }
+}
+
+/// Restores state saved by `save`, migrating older layouts; returns false when there is none,
+/// i.e. when upgrading from a release that did not save its state
+pub(crate) fn restore() -> bool {
+    if ic_cdk::api::stable::stable64_size() == 0 {
+        return false;
+    }
+    
+    // Checked before the memory manager is first used, as it lays itself out over anything else
+    let mut magic = [0u8; 3];
+    ic_cdk::api::stable::stable64_read(0, &mut magic);
+    if &magic != MEMORY_MANAGER_MAGIC {
+        install(restore_unmanaged());
+        return true;
+    }
+    
+    let bytes = STATE.with(|cell| cell.borrow().get().clone());
+    if bytes.is_empty() {
+        return false;
+    }
+    decode(&bytes).unwrap_or_else(|e| ic_cdk::trap(&e));
+    true
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use candid::parser::value::{IDLArgs, IDLValue};
+    use candid::{CandidType, Principal};
+    use crate::access_control::Role;
+    use crate::ledger_backend::LedgerBackend;
+    use crate::vault_system::CollateralType;
+    
+    /// Decoded candid value with every vector sorted, so maps compare equal whatever order their
+    /// entries were encoded in
+    fn canonical(value: &impl CandidType) -> IDLValue {
+        fn sort(value: IDLValue) -> IDLValue {
+            match value {
+                IDLValue::Vec(values) => {
+                    let mut values: Vec<IDLValue> = values.into_iter().map(sort).collect();
+                    values.sort_by_key(|value| value.to_string());
+                    IDLValue::Vec(values)
+                }
+                IDLValue::Record(mut fields) => {
+                    for field in &mut fields {
+                        field.val = sort(std::mem::replace(&mut field.val, IDLValue::Null));
+                    }
+                    IDLValue::Record(fields)
+                }
+                IDLValue::Variant(mut variant) => {
+                    variant.0.val = sort(std::mem::replace(&mut variant.0.val, IDLValue::Null));
+                    IDLValue::Variant(variant)
+                }
+                IDLValue::Opt(value) => IDLValue::Opt(Box::new(sort(*value))),
+                value => value,
+            }
+        }
+        
+        let bytes = candid::encode_one(value).unwrap();
+        let mut args = IDLArgs::from_bytes(&bytes).unwrap().args;
+        sort(args.remove(0))
+    }
+    
+    /// Every module's state by name, so one left out of `StableState` shows up in a failed assertion
+    pub(crate) fn modules() -> Vec<(&'static str, IDLValue)> {
+        vec![
+            ("VaultController", canonical(ic_cdk::storage::get::<VaultController>())),
+            ("LiquidationController", canonical(ic_cdk::storage::get::<LiquidationController>())),
+            ("AccessControl", canonical(ic_cdk::storage::get::<AccessControl>())),
+            ("ConfigRegistry", canonical(ic_cdk::storage::get::<ConfigRegistry>())),
+            ("IusdLedger", canonical(ic_cdk::storage::get::<IusdLedger>())),
+            ("OracleClient", canonical(&ic_cdk::storage::get::<OracleClient>().to_state())),
+            ("StabilityPool", canonical(ic_cdk::storage::get::<StabilityPool>())),
+            ("BondRegistry", canonical(ic_cdk::storage::get::<BondRegistry>())),
+            ("InsuranceFund", canonical(ic_cdk::storage::get::<InsuranceFund>())),
+            ("AuctionHouse", canonical(ic_cdk::storage::get::<AuctionHouse>())),
+            ("Timelock", canonical(ic_cdk::storage::get::<Timelock>())),
+            ("Council", canonical(ic_cdk::storage::get::<Council>())),
+            ("ApprovalQueue", canonical(ic_cdk::storage::get::<ApprovalQueue>())),
+            ("PauseSwitches", canonical(ic_cdk::storage::get::<PauseSwitches>())),
+            ("CyclesMonitor", canonical(&ic_cdk::storage::get::<CyclesMonitor>().to_state())),
+            ("SolvencySnapshots", canonical(ic_cdk::storage::get::<SolvencySnapshots>())),
+            ("Keeper", canonical(&ic_cdk::storage::get::<Keeper>().to_state())),
+            ("RedemptionController", canonical(ic_cdk::storage::get::<RedemptionController>())),
+        ]
+    }
+    
+    /// Puts something other than the default in every module
+    pub(crate) fn populate() {
+        let controller = ic_cdk::storage::get_mut::<VaultController>();
+        controller.collateral_ratios.insert(CollateralType::ICP, 7000);
+        controller.next_vault_id = 5;
+        *ic_cdk::storage::get_mut::<LiquidationController>() = LiquidationController::sample();
+        ic_cdk::storage::get_mut::<AccessControl>().grant(Principal::from_slice(&[1]), Role::Admin);
+        *ic_cdk::storage::get_mut::<ConfigRegistry>() = ConfigRegistry::sample();
+        ic_cdk::storage::get_mut::<IusdLedger>().set_backend(LedgerBackend::Icrc1 { canister_id: Principal::from_slice(&[4]) });
+        *ic_cdk::storage::get_mut::<OracleClient>() = OracleClient::sample();
+        *ic_cdk::storage::get_mut::<StabilityPool>() = StabilityPool::sample();
+        *ic_cdk::storage::get_mut::<BondRegistry>() = BondRegistry::sample();
+        ic_cdk::storage::get_mut::<InsuranceFund>().credit_collateral(&CollateralType::CkETH, 1_000_000);
+        *ic_cdk::storage::get_mut::<AuctionHouse>() = AuctionHouse::sample();
+        *ic_cdk::storage::get_mut::<Timelock>() = Timelock::sample();
+        *ic_cdk::storage::get_mut::<Council>() = Council::sample();
+        *ic_cdk::storage::get_mut::<ApprovalQueue>() = ApprovalQueue::sample();
+        *ic_cdk::storage::get_mut::<PauseSwitches>() = PauseSwitches::sample();
+        *ic_cdk::storage::get_mut::<CyclesMonitor>() = CyclesMonitor::sample();
+        *ic_cdk::storage::get_mut::<SolvencySnapshots>() = SolvencySnapshots::sample();
+        *ic_cdk::storage::get_mut::<Keeper>() = Keeper::sample();
+        *ic_cdk::storage::get_mut::<RedemptionController>() = RedemptionController::sample();
+    }
+    
+    /// Puts every module back to its default
+    pub(crate) fn reset() {
+        replace(StableState::default());
+    }
+    
+    /// Replaces the state with one from `encode`, as `decode` does but without its timers and
+    /// certification
+    pub(crate) fn load(bytes: &[u8]) {
+        replace(parse(bytes).unwrap());
+    }
+    
+    #[test]
+    fn upgrade_round_trip_keeps_every_module() {
+        let defaults = modules();
+        populate();
+        let populated = modules();
+        for ((name, value), (_, default)) in populated.iter().zip(&defaults) {
+            assert_ne!(value, default, "{} was not populated", name);
+        }
+        
+        let saved = encode();
+        assert_eq!(modules(), populated, "encoding changed the state");
+        reset();
+        assert_eq!(modules(), defaults);
+        
+        let saved: VersionedState = candid::decode_one(&saved).unwrap();
+        assert_eq!(saved.schema_version, SCHEMA_VERSION);
+        replace(migrate(saved).unwrap());
+        for ((name, value), (_, expected)) in modules().iter().zip(&populated) {
+            assert_eq!(value, expected, "{} did not survive the upgrade", name);
+        }
+    }
+    
+    #[test]
+    fn schema_1_keeps_vaults_and_liquidations() {
+        let mut vault_controller = VaultController::default();
+        vault_controller.next_vault_id = 9;
+        let saved = VersionedState {
+            schema_version: 1,
+            state: candid::encode_one(StableStateV1 {
+                vault_controller,
+                liquidation_controller: LiquidationController::sample(),
+            })
+            .unwrap(),
+        };
+        
+        let state = migrate(saved).unwrap();
+        assert_eq!(state.vault_controller.next_vault_id, 9);
+        assert_eq!(state.liquidation_controller.liquidation_bonus(), 700);
+        assert!(state.access_control.members_of(Role::Admin).is_empty());
+    }
+}
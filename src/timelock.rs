@@ -0,0 +1,331 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::*;
+use std::collections::BTreeMap;
+use crate::access_control::{require_role, Role};
+use crate::adaptive_bonus::AdaptiveBonusConfig;
+use crate::audit_log;
+use crate::liquidation::LiquidationController;
+use crate::validation;
+use crate::vault_system::{CollateralType, VaultController};
+
+/// Default delay between proposing and executing a risk-parameter change
+const DEFAULT_RISK_CHANGE_DELAY_SECONDS: u64 = 2 * 24 * 3600; // 2 days
+
+/// A parameter affecting vault solvency, changed only through the timelock
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum RiskParamChange {
+    /// Maximum LTV in basis points
+    CollateralRatio { collateral_type: CollateralType, ratio: u32 },
+    /// Liquidation threshold in basis points; None restores 95% of the LTV
+    LiquidationThreshold { collateral_type: CollateralType, threshold: Option<u32> },
+    /// Global liquidation bonus in basis points
+    LiquidationBonus { bonus: u32 },
+    /// Bonus override for a collateral type; None falls back to the global bonus
+    CollateralBonus { collateral_type: CollateralType, bonus: Option<u32> },
+    /// Bounds and tuning of the adaptive bonus; None turns it off
+    AdaptiveBonus { config: Option<AdaptiveBonusConfig> },
+    /// Maximum total debt against a collateral type; None removes the ceiling
+    DebtCeiling { collateral_type: CollateralType, ceiling: Option<u128> },
+    /// Delay applied to changes proposed afterwards
+    ChangeDelay { seconds: u64 },
+}
+
+/// A change waiting out its delay
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct PendingRiskChange {
+    id: u64,
+    change: RiskParamChange,
+    proposed_by: Principal,
+    proposed_at: u64,
+    /// Earliest time the change can be executed (seconds)
+    executable_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum RiskChangeAction {
+    Proposed,
+    Executed,
+    Cancelled,
+}
+
+/// Audit record of a timelock action
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct RiskChangeEvent {
+    change_id: u64,
+    change: RiskParamChange,
+    action: RiskChangeAction,
+    caller: Principal,
+    timestamp: u64,
+    /// Setting the change replaced, for executed changes
+    previous: Option<RiskParamChange>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct Timelock {
+    delay_seconds: u64,
+    pending: BTreeMap<u64, PendingRiskChange>,
+    next_change_id: u64,
+    /// Every proposal, execution and cancellation, oldest first
+    events: Vec<RiskChangeEvent>,
+}
+
+impl Default for Timelock {
+    fn default() -> Self {
+        Self {
+            delay_seconds: DEFAULT_RISK_CHANGE_DELAY_SECONDS,
+            pending: BTreeMap::new(),
+            next_change_id: 0,
+            events: Vec::new(),
+        }
+    }
+}
+
+/// Rejects values outside their bounds. An LTV and a liquidation threshold are only checked
+/// against each other's current value on execution, so changes to both can be queued together
+fn validate_change(change: &RiskParamChange, executing: bool) -> Result<(), String> {
+    match change {
+        RiskParamChange::CollateralRatio { collateral_type, ratio } => {
+            if executing {
+                validation::check_ltv(collateral_type, *ratio)
+            } else {
+                validation::check_risk_params(collateral_type, Some(*ratio), None)
+            }
+        }
+        RiskParamChange::LiquidationThreshold { collateral_type, threshold: Some(threshold) } => {
+            if executing {
+                validation::check_liquidation_threshold(collateral_type, *threshold)
+            } else {
+                validation::check_risk_params(collateral_type, None, Some(*threshold))
+            }
+        }
+        RiskParamChange::LiquidationBonus { bonus } => validation::check_liquidation_bonus("Liquidation bonus", *bonus),
+        RiskParamChange::CollateralBonus { bonus: Some(bonus), .. } => {
+            validation::check_liquidation_bonus("Collateral bonus", *bonus)
+        }
+        RiskParamChange::AdaptiveBonus { config: Some(config) } => {
+            config.validate()?;
+            validation::check_liquidation_bonus("Adaptive bonus minimum", config.min_bonus)?;
+            validation::check_liquidation_bonus("Adaptive bonus maximum", config.max_bonus)
+        }
+        RiskParamChange::ChangeDelay { seconds } => validation::check_positive("Risk change delay", *seconds),
+        // Removing an override, a ceiling or the adaptive bonus is always possible
+        RiskParamChange::LiquidationThreshold { threshold: None, .. }
+        | RiskParamChange::CollateralBonus { bonus: None, .. }
+        | RiskParamChange::AdaptiveBonus { config: None }
+        | RiskParamChange::DebtCeiling { .. } => Ok(()),
+    }
+}
+
+impl Timelock {
+    /// Applies a change, returning the setting it replaced in the same shape
+    fn apply(&mut self, change: &RiskParamChange) -> Result<RiskParamChange, String> {
+        let controller = ic_cdk::storage::get_mut::<VaultController>();
+        let liquidation_controller = ic_cdk::storage::get_mut::<LiquidationController>();
+        match change {
+            RiskParamChange::CollateralRatio { collateral_type, ratio } => {
+                let previous = controller.collateral_ratios.insert(collateral_type.clone(), *ratio);
+                Ok(RiskParamChange::CollateralRatio {
+                    collateral_type: collateral_type.clone(),
+                    ratio: previous.unwrap_or(0),
+                })
+            }
+            RiskParamChange::LiquidationThreshold { collateral_type, threshold } => {
+                let previous = match threshold {
+                    Some(threshold) => controller.liquidation_thresholds.insert(collateral_type.clone(), *threshold),
+                    None => controller.liquidation_thresholds.remove(collateral_type),
+                };
+                Ok(RiskParamChange::LiquidationThreshold {
+                    collateral_type: collateral_type.clone(),
+                    threshold: previous,
+                })
+            }
+            RiskParamChange::LiquidationBonus { bonus } => {
+                let previous = liquidation_controller.liquidation_bonus();
+                liquidation_controller.set_liquidation_bonus(*bonus)?;
+                Ok(RiskParamChange::LiquidationBonus { bonus: previous })
+            }
+            RiskParamChange::CollateralBonus { collateral_type, bonus } => {
+                let previous = liquidation_controller.collateral_bonus(collateral_type);
+                liquidation_controller.set_collateral_bonus(collateral_type, *bonus)?;
+                Ok(RiskParamChange::CollateralBonus {
+                    collateral_type: collateral_type.clone(),
+                    bonus: previous,
+                })
+            }
+            RiskParamChange::AdaptiveBonus { config } => {
+                let previous = liquidation_controller.adaptive_bonus();
+                liquidation_controller.set_adaptive_bonus(config.clone())?;
+                Ok(RiskParamChange::AdaptiveBonus { config: previous })
+            }
+            RiskParamChange::DebtCeiling { collateral_type, ceiling } => {
+                let previous = match ceiling {
+                    Some(ceiling) => controller.debt_ceilings.insert(collateral_type.clone(), *ceiling),
+                    None => controller.debt_ceilings.remove(collateral_type),
+                };
+                Ok(RiskParamChange::DebtCeiling {
+                    collateral_type: collateral_type.clone(),
+                    ceiling: previous,
+                })
+            }
+            RiskParamChange::ChangeDelay { seconds } => {
+                let previous = self.delay_seconds;
+                self.delay_seconds = *seconds;
+                Ok(RiskParamChange::ChangeDelay { seconds: previous })
+            }
+        }
+    }
+    
+    /// Queues a change to take effect after the delay
+    pub fn propose(&mut self, change: RiskParamChange, caller: Principal, now: u64) -> Result<u64, String> {
+        validate_change(&change, false)?;
+        
+        let id = self.next_change_id;
+        self.next_change_id += 1;
+        self.pending.insert(id, PendingRiskChange {
+            id,
+            change: change.clone(),
+            proposed_by: caller,
+            proposed_at: now,
+            executable_at: now + self.delay_seconds,
+        });
+        self.log(id, change, RiskChangeAction::Proposed, caller, now, None);
+        Ok(id)
+    }
+    
+    /// Applies a queued change whose delay has passed
+    pub fn execute(&mut self, id: u64, caller: Principal, now: u64) -> Result<(), String> {
+        let pending = self.pending
+            .get(&id)
+            .ok_or("Risk change not found")?
+            .clone();
+        if now < pending.executable_at {
+            return Err("Risk change is still in its delay".to_string());
+        }
+        validate_change(&pending.change, true)?;
+        
+        let previous = self.apply(&pending.change)?;
+        self.pending.remove(&id);
+        self.log(id, pending.change, RiskChangeAction::Executed, caller, now, Some(previous));
+        
+        // Thresholds may have moved, so vaults are reassessed at the latest prices
+        let controller = ic_cdk::storage::get_mut::<VaultController>();
+        controller.refresh_unsafe_vaults();
+        Ok(())
+    }
+    
+    /// Drops a queued change
+    pub fn cancel(&mut self, id: u64, caller: Principal, now: u64) -> Result<(), String> {
+        let pending = self.pending
+            .remove(&id)
+            .ok_or("Risk change not found")?;
+        self.log(id, pending.change, RiskChangeAction::Cancelled, caller, now, None);
+        Ok(())
+    }
+    
+    fn log(
+        &mut self,
+        change_id: u64,
+        change: RiskParamChange,
+        action: RiskChangeAction,
+        caller: Principal,
+        timestamp: u64,
+        previous: Option<RiskParamChange>,
+    ) {
+        let audit_action = match action {
+            RiskChangeAction::Proposed => "propose_risk_change",
+            RiskChangeAction::Executed => "execute_risk_change",
+            RiskChangeAction::Cancelled => "cancel_risk_change",
+        };
+        audit_log::record(
+            &format!("{} {}", audit_action, change_id),
+            previous.as_ref().map(|previous| format!("{:?}", previous)),
+            Some(format!("{:?}", change)),
+        );
+        self.events.push(RiskChangeEvent {
+            change_id,
+            change,
+            action,
+            caller,
+            timestamp,
+            previous,
+        });
+    }
+}
+
+#[cfg(test)]
+impl Timelock {
+    /// Timelock with a queued change, for tests of saving and restoring state
+    pub(crate) fn sample() -> Self {
+        let change = RiskParamChange::LiquidationBonus { bonus: 800 };
+        let proposed_by = Principal::from_slice(&[1]);
+        Self {
+            delay_seconds: 3600,
+            pending: BTreeMap::from([(0, PendingRiskChange {
+                id: 0,
+                change: change.clone(),
+                proposed_by,
+                proposed_at: 1_000,
+                executable_at: 4_600,
+            })]),
+            next_change_id: 1,
+            events: vec![RiskChangeEvent {
+                change_id: 0,
+                change,
+                action: RiskChangeAction::Proposed,
+                caller: proposed_by,
+                timestamp: 1_000,
+                previous: None,
+            }],
+        }
+    }
+}
+
+// Canister endpoints
+/// Queues a risk-parameter change; it can be executed once the delay has passed, giving
+/// vault owners notice
+#[update]
+pub(crate) fn propose_risk_change(change: RiskParamChange) -> Result<u64, String> {
+    require_role(Role::Admin)?;
+    
+    let timelock = ic_cdk::storage::get_mut::<Timelock>();
+    timelock.propose(change, ic_cdk::caller(), ic_cdk::api::time() / 1_000_000_000)
+}
+
+/// Executes a queued change once its delay has passed; callable by anyone
+#[update]
+pub(crate) fn execute_risk_change(change_id: u64) -> Result<(), String> {
+    let timelock = ic_cdk::storage::get_mut::<Timelock>();
+    timelock.execute(change_id, ic_cdk::caller(), ic_cdk::api::time() / 1_000_000_000)
+}
+
+/// Vetoes a queued change; guardians can cancel at once, while changes themselves wait out the
+/// delay
+#[update]
+pub(crate) fn cancel_risk_change(change_id: u64) -> Result<(), String> {
+    require_role(Role::Guardian)?;
+    
+    let timelock = ic_cdk::storage::get_mut::<Timelock>();
+    timelock.cancel(change_id, ic_cdk::caller(), ic_cdk::api::time() / 1_000_000_000)
+}
+
+#[query]
+fn get_pending_risk_changes() -> Vec<PendingRiskChange> {
+    let timelock = ic_cdk::storage::get::<Timelock>();
+    timelock.pending.values().cloned().collect()
+}
+
+#[query]
+fn get_risk_change_log(offset: u64, limit: u64) -> Vec<RiskChangeEvent> {
+    let timelock = ic_cdk::storage::get::<Timelock>();
+    timelock.events
+        .iter()
+        .skip(offset as usize)
+        .take(limit as usize)
+        .cloned()
+        .collect()
+}
+
+#[query]
+fn get_risk_change_delay() -> u64 {
+    ic_cdk::storage::get::<Timelock>().delay_seconds
+}
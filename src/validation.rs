@@ -0,0 +1,104 @@
+use std::fmt::Display;
+use crate::vault_system::{CollateralType, VaultController};
+
+/// 100% in basis points
+pub(crate) const MAX_BPS: u32 = 10_000;
+/// Bounds for liquidation bonuses (in basis points)
+pub(crate) const MIN_LIQUIDATION_BONUS: u32 = 100;   // 1%
+pub(crate) const MAX_LIQUIDATION_BONUS: u32 = 5000;  // 50%
+/// Longest grace period a new vault can get (nanoseconds)
+pub(crate) const MAX_GRACE_PERIOD: u64 = 30 * 24 * 60 * 60 * 1_000_000_000; // 30 days
+
+/// Rejects a share above 100%, e.g. a fee or slippage tolerance
+pub(crate) fn check_bps(name: &str, value: u32) -> Result<(), String> {
+    if value > MAX_BPS {
+        return Err(format!("{} must be at most {} basis points", name, MAX_BPS));
+    }
+    Ok(())
+}
+
+/// Rejects a deviation or share that is zero or above 100%
+pub(crate) fn check_positive_bps(name: &str, value: u32) -> Result<(), String> {
+    if value == 0 || value > MAX_BPS {
+        return Err(format!("{} must be between 1 and {} basis points", name, MAX_BPS));
+    }
+    Ok(())
+}
+
+pub(crate) fn check_positive<T: PartialOrd + Default>(name: &str, value: T) -> Result<(), String> {
+    if value <= T::default() {
+        return Err(format!("{} must be positive", name));
+    }
+    Ok(())
+}
+
+/// Rejects a range whose minimum is not below its maximum
+pub(crate) fn check_min_below_max<T: PartialOrd + Display>(name: &str, min: T, max: T) -> Result<(), String> {
+    if min >= max {
+        return Err(format!("Minimum {} ({}) must be below maximum ({})", name, min, max));
+    }
+    Ok(())
+}
+
+/// Rejects a vault grace period above 30 days, so a typo can't exempt new vaults indefinitely
+pub(crate) fn check_grace_period(grace_period: u64) -> Result<(), String> {
+    if grace_period > MAX_GRACE_PERIOD {
+        return Err(format!("Grace period must be at most {} nanoseconds (30 days)", MAX_GRACE_PERIOD));
+    }
+    Ok(())
+}
+
+pub(crate) fn check_liquidation_bonus(name: &str, bonus: u32) -> Result<(), String> {
+    if bonus < MIN_LIQUIDATION_BONUS || bonus > MAX_LIQUIDATION_BONUS {
+        return Err(format!(
+            "{} must be between {} and {} basis points",
+            name, MIN_LIQUIDATION_BONUS, MAX_LIQUIDATION_BONUS
+        ));
+    }
+    Ok(())
+}
+
+/// Checks a collateral type's LTV and liquidation threshold: 0 < LTV <= threshold < 100%. Either
+/// may be left out to check only the other's bounds
+pub(crate) fn check_risk_params(
+    collateral_type: &CollateralType,
+    ltv: Option<u32>,
+    liquidation_threshold: Option<u32>,
+) -> Result<(), String> {
+    if let Some(ltv) = ltv {
+        if ltv == 0 || ltv >= MAX_BPS {
+            return Err(format!("LTV for {:?} must be between 1 and {} basis points", collateral_type, MAX_BPS - 1));
+        }
+    }
+    if let Some(threshold) = liquidation_threshold {
+        if threshold == 0 || threshold >= MAX_BPS {
+            return Err(format!(
+                "Liquidation threshold for {:?} must be between 1 and {} basis points",
+                collateral_type, MAX_BPS - 1
+            ));
+        }
+    }
+    if let (Some(ltv), Some(threshold)) = (ltv, liquidation_threshold) {
+        if ltv > threshold {
+            return Err(format!(
+                "LTV for {:?} ({}) must not exceed its liquidation threshold ({})",
+                collateral_type, ltv, threshold
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks a new LTV against the collateral type's current liquidation threshold
+pub(crate) fn check_ltv(collateral_type: &CollateralType, ltv: u32) -> Result<(), String> {
+    let controller = ic_cdk::storage::get::<VaultController>();
+    let threshold = controller.liquidation_thresholds.get(collateral_type).copied();
+    check_risk_params(collateral_type, Some(ltv), threshold)
+}
+
+/// Checks a new liquidation threshold against the collateral type's current LTV
+pub(crate) fn check_liquidation_threshold(collateral_type: &CollateralType, threshold: u32) -> Result<(), String> {
+    let controller = ic_cdk::storage::get::<VaultController>();
+    let ltv = controller.collateral_ratios.get(collateral_type).copied();
+    check_risk_params(collateral_type, ltv, Some(threshold))
+}
@@ -1,8 +1,16 @@
 use candid::{CandidType, Deserialize, Principal};
-use ic_cdk::api::call::CallResult;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use ic_cdk_macros::*;
+use crate::access_control::{require_role, Role};
+#[cfg(feature = "allowlist")]
+use crate::allowlist;
+use crate::audit_log;
+use crate::config::ConfigRegistry;
+use crate::ledger_backend::IusdLedger;
+use crate::pause::{self, Operation};
 use crate::price_feed::{self, AggregatedPrice};
+use crate::timelock::{self, RiskParamChange};
+use crate::validation;
 
 /// Supported collateral types
 #[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
@@ -16,49 +24,247 @@ pub enum CollateralType {
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct Vault {
     /// Owner of the vault
-    owner: String,
+    pub(crate) owner: String,
     /// Amount of collateral deposited
-    collateral_amount: u128,
+    pub(crate) collateral_amount: u128,
     /// Type of collateral
-    collateral_type: CollateralType,
+    pub(crate) collateral_type: CollateralType,
     /// Amount of iUSD debt
-    debt_amount: u128,
+    pub(crate) debt_amount: u128,
     /// Last updated timestamp
-    last_updated: u64,
+    pub(crate) last_updated: u64,
+    /// Timestamp of the vault's first mint
+    pub(crate) first_minted_at: Option<u64>,
 }
 
-#[derive(CandidType)]
-struct Account {
-    owner: Principal,
-    subaccount: Option<[u8; 32]>,
+impl Vault {
+    /// Collateral held by the vault, one entry per collateral type. Vaults currently hold a
+    /// single collateral type, so this returns one leg
+    pub(crate) fn collateral_legs(&self) -> Vec<(CollateralType, u128)> {
+        vec![(self.collateral_type.clone(), self.collateral_amount)]
+    }
 }
 
-#[derive(CandidType)]
-struct MintArgs {
-    to: Account,
-    amount: u128,
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Account {
+    pub(crate) owner: Principal,
+    pub(crate) subaccount: Option<[u8; 32]>,
 }
 
+/// Arguments of a collateral ledger's `transfer`
 #[derive(CandidType)]
-struct BurnArgs {
-    from: Account,
-    amount: u128,
+pub(crate) struct TransferArgs {
+    pub(crate) to: Account,
+    pub(crate) amount: u128,
+}
+
+/// Pulls iUSD that `from` approved the protocol to spend (ICRC-2) into the protocol's account
+pub(crate) async fn pull_iusd(from: Principal, amount: u128) -> Result<(), String> {
+    let iusd_ledger = ic_cdk::storage::get::<IusdLedger>();
+    iusd_ledger.pull(from, amount).await
+}
+
+/// Price feed asset symbol for a collateral type
+pub(crate) fn price_feed_asset(collateral_type: &CollateralType) -> &'static str {
+    match collateral_type {
+        CollateralType::ICP => "ICP",
+        CollateralType::CkBTC => "BTC",
+        CollateralType::CkETH => "ETH",
+    }
+}
+
+/// Number of decimals used by a collateral type's base units
+pub(crate) fn collateral_decimals(collateral_type: &CollateralType) -> u32 {
+    match collateral_type {
+        CollateralType::ICP => 8,
+        CollateralType::CkBTC => 8,
+        CollateralType::CkETH => 18,
+    }
+}
+
+/// Decimals of iUSD base units
+const IUSD_DECIMALS: u32 = 8;
+
+/// Converts a USD value (iUSD base units, 8 decimals) into collateral base units
+pub(crate) fn collateral_units_for_value(value: u128, price_data: &AggregatedPrice, decimals: u32) -> Result<u128, String> {
+    if price_data.price == 0 {
+        return Err("Invalid collateral price".to_string());
+    }
+    
+    // units = value / 10^8 / (price / 10^price_decimals) * 10^decimals
+    let scale = decimals + price_data.decimals;
+    let scaled_value = if scale >= IUSD_DECIMALS {
+        value.checked_mul(10u128.pow(scale - IUSD_DECIMALS))
+    } else {
+        Some(value / 10u128.pow(IUSD_DECIMALS - scale))
+    };
+    
+    scaled_value
+        .map(|scaled| scaled / price_data.price)
+        .ok_or_else(|| "Collateral amount overflow".to_string())
+}
+
+/// Precision of nominal collateral ratios used by the sorted vault index
+const NOMINAL_RATIO_PRECISION: u128 = 100_000_000;
+
+/// Collateral per unit of debt, independent of price so vaults sharing a collateral type
+/// keep their relative order as prices move
+pub(crate) fn nominal_ratio(collateral_amount: u128, debt_amount: u128) -> u128 {
+    collateral_amount.saturating_mul(NOMINAL_RATIO_PRECISION) / debt_amount
+}
+
+/// Ledger canister holding a collateral type
+pub(crate) fn collateral_canister_id(collateral_type: &CollateralType) -> Principal {
+    let registry = ic_cdk::storage::get::<ConfigRegistry>();
+    registry
+        .collateral_ledger(collateral_type)
+        .unwrap_or_else(|| ic_cdk::trap("Collateral ledgers not configured"))
+}
+
+/// USD value (8 decimals) of a collateral amount at the given price
+fn collateral_value_at(collateral_type: &CollateralType, amount: u128, price_data: &AggregatedPrice) -> Result<u128, String> {
+    // Note: amount is in base units (e.g., e8s for ICP), so we need to adjust decimals
+    let raw_value = amount
+        .checked_mul(price_data.price)
+        .ok_or("Collateral value overflow")?;
+        
+    // Convert to base units (iUSD uses 8 decimals)
+    let scale = collateral_decimals(collateral_type) + price_data.decimals;
+    if scale >= IUSD_DECIMALS {
+        Ok(raw_value / 10u128.pow(scale - IUSD_DECIMALS))
+    } else {
+        raw_value
+            .checked_mul(10u128.pow(IUSD_DECIMALS - scale))
+            .ok_or_else(|| "Collateral value overflow".to_string())
+    }
 }
 
-/// Main vault controller
-#[derive(Default)]
+/// End of a price's confidence band to use when confidence is poor
+enum PriceBound {
+    /// Valuing collateral
+    Low,
+    /// Covering debt with collateral
+    High,
+}
+
+/// Main vault controller, saved to stable memory across upgrades
+#[derive(CandidType, Deserialize, Default)]
 pub struct VaultController {
     /// Maps vault_id to Vault
-    vaults: HashMap<u64, Vault>,
+    pub(crate) vaults: HashMap<u64, Vault>,
     /// Next available vault ID
-    next_vault_id: u64,
+    pub(crate) next_vault_id: u64,
     /// Collateralization ratios for each asset (in basis points, e.g. 7500 = 75%)
-    collateral_ratios: HashMap<CollateralType, u32>,
+    pub(crate) collateral_ratios: HashMap<CollateralType, u32>,
     /// Minimum collateral amounts
-    min_collateral: HashMap<CollateralType, u128>,
+    pub(crate) min_collateral: HashMap<CollateralType, u128>,
+    /// Debt-to-collateral ratio beyond which a vault is liquidatable (in basis points); types
+    /// without an entry use 95% of their LTV
+    pub(crate) liquidation_thresholds: HashMap<CollateralType, u32>,
+    /// Maximum total debt per collateral type (no entry = uncapped)
+    pub(crate) debt_ceilings: HashMap<CollateralType, u128>,
+    /// Indebted vaults per collateral type, ordered by nominal collateral ratio (riskiest first)
+    pub(crate) sorted_vaults: HashMap<CollateralType, BTreeSet<(u128, u64)>>,
+    /// Nominal ratio each vault is currently indexed under
+    index_keys: HashMap<u64, u128>,
+    /// Time after a vault's first mint during which it gets a wider liquidation threshold (nanoseconds)
+    pub(crate) grace_period: u64,
+    /// How much the liquidation threshold widens during the grace period (in basis points)
+    pub(crate) grace_margin: u32,
+    /// Latest LTV price pushed by the oracle per collateral type
+    pub(crate) pushed_prices: HashMap<CollateralType, AggregatedPrice>,
+    /// Collateral value of each indebted vault at the latest pushed price
+    pub(crate) collateral_values: HashMap<u64, u128>,
+    /// Vaults past their liquidation threshold as of the latest price push or position change
+    pub(crate) unsafe_vaults: BTreeSet<u64>,
+    /// When set, prices whose source standard deviation exceeds this many basis points are
+    /// replaced by the conservative end of their confidence band
+    pub(crate) confidence_threshold: Option<u32>,
+}
+
+/// Hands a refreshed LTV price to the vault controller for every collateral type priced by the asset
+pub(crate) fn push_price(asset: &str, price: AggregatedPrice) {
+    let vault_controller = ic_cdk::storage::get_mut::<VaultController>();
+    for collateral_type in [CollateralType::ICP, CollateralType::CkBTC, CollateralType::CkETH] {
+        if price_feed_asset(&collateral_type) == asset {
+            vault_controller.on_price_update(&collateral_type, price.clone());
+        }
+    }
 }
 
 impl VaultController {
+    /// Re-positions a vault in the sorted index after its collateral or debt changed
+    pub(crate) fn reindex_vault(&mut self, vault_id: u64) {
+        let vault = match self.vaults.get(&vault_id) {
+            Some(vault) => vault,
+            None => return,
+        };
+        let index = self.sorted_vaults.entry(vault.collateral_type.clone()).or_default();
+        
+        if let Some(old_key) = self.index_keys.remove(&vault_id) {
+            index.remove(&(old_key, vault_id));
+        }
+        
+        // Only vaults with debt can be redeemed against
+        if vault.debt_amount > 0 {
+            let key = nominal_ratio(vault.collateral_amount, vault.debt_amount);
+            index.insert((key, vault_id));
+            self.index_keys.insert(vault_id, key);
+        }
+        
+        self.refresh_vault_health(vault_id);
+    }
+    
+    /// Applies a freshly pushed oracle price, revaluing every indebted vault of that collateral type
+    pub(crate) fn on_price_update(&mut self, collateral_type: &CollateralType, price: AggregatedPrice) {
+        self.pushed_prices.insert(collateral_type.clone(), price);
+        
+        let vault_ids: Vec<u64> = self.vaults_by_risk(collateral_type).collect();
+        for vault_id in vault_ids {
+            self.refresh_vault_health(vault_id);
+        }
+    }
+    
+    /// Recomputes a vault's cached collateral value and unsafe flag from the last pushed price
+    fn refresh_vault_health(&mut self, vault_id: u64) {
+        self.collateral_values.remove(&vault_id);
+        self.unsafe_vaults.remove(&vault_id);
+        
+        let vault = match self.vaults.get(&vault_id) {
+            Some(vault) if vault.debt_amount > 0 => vault,
+            _ => return,
+        };
+        let collateral_value = match self.pushed_prices
+            .get(&vault.collateral_type)
+            .map(|price| self.conservative_price(price.clone(), PriceBound::Low))
+            .map(|price| collateral_value_at(&vault.collateral_type, vault.collateral_amount, &price))
+        {
+            Some(Ok(value)) => value,
+            _ => return,
+        };
+        
+        if self.exceeds_liquidation_threshold(vault, collateral_value) {
+            self.unsafe_vaults.insert(vault_id);
+        }
+        self.collateral_values.insert(vault_id, collateral_value);
+    }
+    
+    /// Reassesses every vault, e.g. after a risk parameter changed
+    pub(crate) fn refresh_unsafe_vaults(&mut self) {
+        let vault_ids: Vec<u64> = self.vaults.keys().copied().collect();
+        for vault_id in vault_ids {
+            self.refresh_vault_health(vault_id);
+        }
+    }
+    
+    /// Iterates indebted vaults of a collateral type from lowest to highest collateral ratio
+    pub(crate) fn vaults_by_risk(&self, collateral_type: &CollateralType) -> impl Iterator<Item = u64> + '_ {
+        self.sorted_vaults
+            .get(collateral_type)
+            .into_iter()
+            .flat_map(|index| index.iter().map(|(_, vault_id)| *vault_id))
+    }
+    
     /// Creates a new vault
     pub fn create_vault(
         &mut self,
@@ -71,6 +277,7 @@ impl VaultController {
             collateral_type,
             debt_amount: 0,
             last_updated: ic_cdk::api::time(),
+            first_minted_at: None,
         };
         
         let vault_id = self.next_vault_id;
@@ -86,6 +293,9 @@ impl VaultController {
         vault_id: u64,
         amount: u128,
     ) -> Result<(), &'static str> {
+        if pause::is_paused(Operation::Deposit) {
+            return Err("Deposit is paused");
+        }
         let vault = self.vaults.get_mut(&vault_id)
             .ok_or("Vault not found")?;
             
@@ -99,37 +309,60 @@ impl VaultController {
         
         vault.collateral_amount += amount;
         vault.last_updated = ic_cdk::api::time();
+        self.reindex_vault(vault_id);
         
         Ok(())
     }
 
     // Helper function for getting collateral value
-    async fn get_collateral_value(
+    pub(crate) async fn get_collateral_value(
         &self,
         collateral_type: &CollateralType,
         amount: u128,
     ) -> Result<u128, String> {
-        let asset = match collateral_type {
-            CollateralType::ICP => "ICP",
-            CollateralType::CkBTC => "BTC",
-            CollateralType::CkETH => "ETH",
-        };
-        
-        let price_data = price_feed::fetch_prices(asset).await?;
-        
-        // Convert amount to USD value
-        // Note: amount is in base units (e.g., e8s for ICP), so we need to adjust decimals
-        let decimals = match collateral_type {
-            CollateralType::ICP => 8,
-            CollateralType::CkBTC => 8,
-            CollateralType::CkETH => 18,
-        };
-        
-        let amount_float = amount as f64 / (10u128.pow(decimals) as f64);
-        let value_usd = amount_float * price_data.price;
-        
-        // Convert to base units (iUSD uses 8 decimals)
-        Ok((value_usd * 100_000_000.0) as u128)
+        let price_data = price_feed::current_price(price_feed_asset(collateral_type))?;
+        collateral_value_at(collateral_type, amount, &self.conservative_price(price_data, PriceBound::Low))
+    }
+    
+    // Collateral value for borrowing and withdrawal LTV checks, at the lower of the spot and
+    // smoothed (TWAP or EMA) prices: a spike can't be borrowed against before the average
+    // catches up, nor a crash while the average lags. Liquidation checks use the smoothed price
+    // alone, so flash drops don't trigger them
+    pub(crate) async fn get_collateral_ltv_value(
+        &self,
+        collateral_type: &CollateralType,
+        amount: u128,
+    ) -> Result<u128, String> {
+        let asset = price_feed_asset(collateral_type);
+        let spot = price_feed::current_price(asset)?;
+        let smoothed = price_feed::current_smoothed_price(asset)?;
+        let price_data = if spot.price < smoothed.price { spot } else { smoothed };
+        collateral_value_at(collateral_type, amount, &self.conservative_price(price_data, PriceBound::Low))
+    }
+    
+    // Helper function for converting a USD value (8 decimals) into collateral base units
+    pub(crate) async fn get_collateral_amount_for_value(
+        &self,
+        collateral_type: &CollateralType,
+        value: u128,
+    ) -> Result<u128, String> {
+        let price_data = price_feed::current_price(price_feed_asset(collateral_type))?;
+        let price_data = self.conservative_price(price_data, PriceBound::High);
+        collateral_units_for_value(value, &price_data, collateral_decimals(collateral_type))
+    }
+    
+    /// Falls back to the low end of the confidence band when valuing collateral, and the high end
+    /// when sizing collateral paid out against debt, if the sources disagree too much
+    fn conservative_price(&self, mut price_data: AggregatedPrice, bound: PriceBound) -> AggregatedPrice {
+        let low_confidence = self.confidence_threshold
+            .is_some_and(|threshold| price_data.is_low_confidence(threshold));
+        if low_confidence {
+            price_data.price = match bound {
+                PriceBound::Low => price_data.price.min(price_data.min_price),
+                PriceBound::High => price_data.price.max(price_data.max_price),
+            };
+        }
+        price_data
     }
     
     /// Withdraws collateral from a vault
@@ -138,6 +371,7 @@ impl VaultController {
         vault_id: u64,
         amount: u128,
     ) -> Result<(), String> {
+        pause::check_not_paused(Operation::Withdraw)?;
         let vault = self.vaults.get_mut(&vault_id)
             .ok_or("Vault not found")?;
             
@@ -147,7 +381,7 @@ impl VaultController {
         
         // Get current collateral value in USD
         let remaining_collateral = vault.collateral_amount - amount;
-        let collateral_value = self.get_collateral_value(&vault.collateral_type, remaining_collateral).await?;
+        let collateral_value = self.get_collateral_ltv_value(&vault.collateral_type, remaining_collateral).await?;
         
         // Check if withdrawal would break LTV ratio
         let ratio = self.collateral_ratios.get(&vault.collateral_type)
@@ -160,29 +394,37 @@ impl VaultController {
         
         vault.collateral_amount = remaining_collateral;
         vault.last_updated = ic_cdk::api::time();
+        self.reindex_vault(vault_id);
         
         Ok(())
     }
 
     async fn mint_iusd_tokens(&self, to: Account, amount: u128) -> Result<(), String> {
-        // Call iUSD canister's mint function
-        let iusd_canister = Principal::from_text("CANISTER-ID-HERE").unwrap();
-        let args = MintArgs { to, amount };
-        
-        match ic_cdk::call(iusd_canister, "mint", (args,)).await {
-            Ok(()) => Ok(()),
-            Err((code, msg)) => Err(format!("Failed to mint iUSD: {:?} - {}", code, msg))
-        }
+        let iusd_ledger = ic_cdk::storage::get::<IusdLedger>();
+        iusd_ledger.mint(to, amount).await
     }
 
-    async fn burn_iusd_tokens(&self, from: Account, amount: u128) -> Result<(), String> {
-        // Call iUSD canister's burn function
-        let iusd_canister = Principal::from_text("CANISTER-ID-HERE").unwrap();
-        let args = BurnArgs { from, amount };
-        
-        match ic_cdk::call(iusd_canister, "burn", (args,)).await {
-            Ok(()) => Ok(()),
-            Err((code, msg)) => Err(format!("Failed to burn iUSD: {:?} - {}", code, msg))
+    pub(crate) async fn burn_iusd_tokens(&self, from: Account, amount: u128) -> Result<(), String> {
+        let iusd_ledger = ic_cdk::storage::get::<IusdLedger>();
+        iusd_ledger.burn(from, amount).await
+    }
+    
+    /// Total debt of the vaults backed by a collateral type
+    pub(crate) fn total_debt(&self, collateral_type: &CollateralType) -> u128 {
+        self.vaults
+            .values()
+            .filter(|vault| vault.collateral_type == *collateral_type)
+            .map(|vault| vault.debt_amount)
+            .sum()
+    }
+    
+    /// Rejects minting `amount` more against a collateral type beyond its debt ceiling
+    fn check_debt_ceiling(&self, collateral_type: &CollateralType, amount: u128) -> Result<(), String> {
+        match self.debt_ceilings.get(collateral_type) {
+            Some(ceiling) if self.total_debt(collateral_type) + amount > *ceiling => {
+                Err("Mint would exceed the collateral type's debt ceiling".to_string())
+            }
+            _ => Ok(()),
         }
     }
     
@@ -192,11 +434,12 @@ impl VaultController {
         vault_id: u64,
         amount: u128,
     ) -> Result<(), String> {
+        pause::check_not_paused(Operation::Mint)?;
         let vault = self.vaults.get_mut(&vault_id)
             .ok_or("Vault not found")?;
             
         // Get current collateral value in USD
-        let collateral_value = self.get_collateral_value(&vault.collateral_type, vault.collateral_amount).await?;
+        let collateral_value = self.get_collateral_ltv_value(&vault.collateral_type, vault.collateral_amount).await?;
         
         // Calculate maximum allowed debt
         let ratio = self.collateral_ratios.get(&vault.collateral_type)
@@ -207,6 +450,8 @@ impl VaultController {
             return Err("Mint would exceed maximum LTV".to_string());
         }
         
+        self.check_debt_ceiling(&vault.collateral_type, amount)?;
+        
         // Mint tokens
         let to = Account {
             owner: Principal::from_text(&vault.owner).map_err(|e| e.to_string())?,
@@ -217,54 +462,143 @@ impl VaultController {
         // Update vault state
         vault.debt_amount += amount;
         vault.last_updated = ic_cdk::api::time();
+        if vault.first_minted_at.is_none() {
+            vault.first_minted_at = Some(vault.last_updated);
+        }
+        self.reindex_vault(vault_id);
         
         Ok(())
     }
     
-    /// Repays iUSD debt
+    /// Repays iUSD debt with iUSD the payer approved the protocol to spend (ICRC-2)
     pub async fn repay_debt(
         &mut self,
         vault_id: u64,
+        payer: Principal,
         amount: u128,
     ) -> Result<(), String> {
-        let vault = self.vaults.get_mut(&vault_id)
+        let vault = self.vaults.get(&vault_id)
             .ok_or("Vault not found")?;
             
         if vault.debt_amount < amount {
             return Err("Repayment amount exceeds debt".to_string());
         }
         
-        // Burn tokens first
-        let from = Account {
-            owner: Principal::from_text(&vault.owner).map_err(|e| e.to_string())?,
+        // Pull the payer's iUSD, then burn it from the protocol's own account
+        pull_iusd(payer, amount).await?;
+        let iusd_ledger = ic_cdk::storage::get::<IusdLedger>();
+        let payer_account = Account {
+            owner: payer,
             subaccount: None,
         };
-        self.burn_iusd_tokens(from, amount).await?;
+        
+        // A liquidation or redemption may have cut the debt while the pull was in flight
+        let still_owed = self.vaults.get(&vault_id).is_some_and(|vault| vault.debt_amount >= amount);
+        let burned = if still_owed {
+            let protocol_account = iusd_ledger.protocol_account()?;
+            self.burn_iusd_tokens(protocol_account, amount).await
+        } else {
+            Err("Repayment amount exceeds debt".to_string())
+        };
+        if let Err(e) = burned {
+            // Hand the pulled iUSD back rather than keep it against unchanged debt
+            iusd_ledger.transfer(payer_account, amount).await
+                .map_err(|refund_error| format!("{}; refund failed: {}", e, refund_error))?;
+            return Err(e);
+        }
         
         // Update vault state
-        vault.debt_amount -= amount;
+        let vault = self.vaults.get_mut(&vault_id)
+            .ok_or("Vault closed during repayment")?;
+        vault.debt_amount = vault.debt_amount.checked_sub(amount)
+            .ok_or("Vault debt changed during repayment")?;
+        vault.last_updated = ic_cdk::api::time();
+        self.reindex_vault(vault_id);
+        
+        Ok(())
+    }
+    
+    /// Removes debt and collateral from a vault (used by redemptions and liquidations)
+    pub(crate) fn reduce_position(
+        &mut self,
+        vault_id: u64,
+        debt: u128,
+        collateral: u128,
+    ) -> Result<(), String> {
+        let vault = self.vaults.get_mut(&vault_id)
+            .ok_or("Vault not found")?;
+            
+        if vault.debt_amount < debt || vault.collateral_amount < collateral {
+            return Err("Reduction exceeds vault position".to_string());
+        }
+        
+        vault.debt_amount -= debt;
+        vault.collateral_amount -= collateral;
+        vault.last_updated = ic_cdk::api::time();
+        self.reindex_vault(vault_id);
+        
+        Ok(())
+    }
+    
+    /// Returns collateral to a vault (used when an auction settles with collateral left over)
+    pub(crate) fn credit_collateral(&mut self, vault_id: u64, amount: u128) -> Result<(), String> {
+        let vault = self.vaults.get_mut(&vault_id)
+            .ok_or("Vault not found")?;
+            
+        vault.collateral_amount += amount;
         vault.last_updated = ic_cdk::api::time();
+        self.reindex_vault(vault_id);
         
         Ok(())
     }
     
+    /// Checks if a vault is still within the grace period after its first mint
+    pub(crate) fn in_grace_period(&self, vault: &Vault) -> bool {
+        match vault.first_minted_at {
+            Some(first_minted_at) => ic_cdk::api::time() < first_minted_at + self.grace_period,
+            None => false,
+        }
+    }
+    
     /// Checks if a vault is eligible for liquidation
     pub async fn is_liquidatable(&self, vault_id: u64) -> Result<bool, String> {
         let vault = self.vaults.get(&vault_id)
             .ok_or("Vault not found")?;
-            
-        // Get current collateral value in USD
-        let collateral_value = self.get_collateral_value(&vault.collateral_type, vault.collateral_amount).await?;
+        if vault.debt_amount == 0 {
+            return Ok(false);
+        }
         
-        // Get liquidation threshold (slightly higher than LTV ratio)
-        let ratio = self.collateral_ratios.get(&vault.collateral_type)
-            .ok_or("Collateral type not supported")?;
+        // Valuations come from pushed prices, so refuse to act once the oracle has gone stale
+        price_feed::current_price(price_feed_asset(&vault.collateral_type))?;
+        let collateral_value = *self.collateral_values.get(&vault_id)
+            .ok_or("No price pushed for collateral type")?;
+            
+        Ok(self.exceeds_liquidation_threshold(vault, collateral_value))
+    }
+    
+    /// Liquidation threshold of a collateral type in basis points
+    pub(crate) fn liquidation_threshold(&self, collateral_type: &CollateralType) -> Option<u32> {
+        if let Some(threshold) = self.liquidation_thresholds.get(collateral_type) {
+            return Some(*threshold);
+        }
+        // Defaults to 95% of the LTV ratio
+        self.collateral_ratios.get(collateral_type).map(|ratio| ratio * 95 / 100)
+    }
+    
+    /// Whether a vault's debt exceeds its liquidation threshold at the given collateral value
+    fn exceeds_liquidation_threshold(&self, vault: &Vault, collateral_value: u128) -> bool {
+        let mut liquidation_threshold = match self.liquidation_threshold(&vault.collateral_type) {
+            Some(threshold) => threshold as u128,
+            None => return false,
+        };
         
-        // Liquidation threshold is 5% above the maximum LTV
-        let liquidation_threshold = (*ratio as u128) * 95 / 100; // 95% of LTV ratio
+        // Fresh positions only liquidate on moves beyond a wider threshold, not oracle jitter
+        if self.in_grace_period(vault) {
+            liquidation_threshold = liquidation_threshold * (10000 + self.grace_margin as u128) / 10000;
+        }
         let max_debt = (collateral_value * liquidation_threshold) / 10000;
         
-        Ok(vault.debt_amount > max_debt)
+        vault.debt_amount > max_debt
     }
     
     /// Get vault health factor
@@ -286,6 +620,8 @@ impl VaultController {
 // Canister endpoints
 #[update]
 async fn create_vault(owner: String, collateral_type: CollateralType) -> Result<u64, String> {
+    #[cfg(feature = "allowlist")]
+    allowlist::require_allowed()?;
     let controller = ic_cdk::storage::get_mut::<VaultController>();
     controller.create_vault(owner, collateral_type)
         .map_err(|e| e.to_string())
@@ -299,6 +635,67 @@ fn get_vault(vault_id: u64) -> Result<Vault, String> {
         .ok_or_else(|| "Vault not found".to_string())
 }
 
+/// Queues a new maximum LTV (basis points) for a collateral type through the risk-parameter
+/// timelock; returns the change ID
+#[update]
+fn set_collateral_ratio(collateral_type: CollateralType, ratio: u32) -> Result<u64, String> {
+    timelock::propose_risk_change(RiskParamChange::CollateralRatio { collateral_type, ratio })
+}
+
+/// Queues a new liquidation threshold (basis points) through the timelock; None restores the
+/// default of 95% of the LTV. Returns the change ID
+#[update]
+fn set_liquidation_threshold(collateral_type: CollateralType, threshold: Option<u32>) -> Result<u64, String> {
+    timelock::propose_risk_change(RiskParamChange::LiquidationThreshold { collateral_type, threshold })
+}
+
+/// Sets the smallest collateral balance a vault may hold; takes effect at once, as it only
+/// affects new deposits
+#[update]
+pub(crate) fn set_min_collateral(collateral_type: CollateralType, min_amount: u128) -> Result<(), String> {
+    require_role(Role::Admin)?;
+    validation::check_positive("Minimum collateral", min_amount)?;
+    
+    let controller = ic_cdk::storage::get_mut::<VaultController>();
+    audit_log::record_change(
+        &format!("set_min_collateral {:?}", collateral_type),
+        &controller.min_collateral.get(&collateral_type).copied(),
+        &Some(min_amount),
+    );
+    controller.min_collateral.insert(collateral_type, min_amount);
+    Ok(())
+}
+
+#[update]
+pub(crate) fn set_vault_grace_period(grace_period: u64, grace_margin: u32) -> Result<(), String> {
+    require_role(Role::Admin)?;
+    validation::check_grace_period(grace_period)?;
+    validation::check_bps("Grace margin", grace_margin)?;
+    
+    let controller = ic_cdk::storage::get_mut::<VaultController>();
+    audit_log::record_change(
+        "set_vault_grace_period",
+        &(controller.grace_period, controller.grace_margin),
+        &(grace_period, grace_margin),
+    );
+    controller.grace_period = grace_period;
+    controller.grace_margin = grace_margin;
+    Ok(())
+}
+
+#[update]
+pub(crate) fn set_confidence_threshold(confidence_threshold: Option<u32>) -> Result<(), String> {
+    require_role(Role::OracleManager)?;
+    if let Some(threshold) = confidence_threshold {
+        validation::check_positive_bps("Confidence threshold", threshold)?;
+    }
+    
+    let controller = ic_cdk::storage::get_mut::<VaultController>();
+    audit_log::record_change("set_confidence_threshold", &controller.confidence_threshold, &confidence_threshold);
+    controller.confidence_threshold = confidence_threshold;
+    Ok(())
+}
+
 #[update]
 async fn withdraw_collateral(vault_id: u64, amount: u128) -> Result<(), String> {
     let controller = ic_cdk::storage::get_mut::<VaultController>();
@@ -307,14 +704,18 @@ async fn withdraw_collateral(vault_id: u64, amount: u128) -> Result<(), String>
 
 #[update]
 async fn mint_iusd(vault_id: u64, amount: u128) -> Result<(), String> {
+    #[cfg(feature = "allowlist")]
+    allowlist::require_allowed()?;
     let controller = ic_cdk::storage::get_mut::<VaultController>();
     controller.mint_iusd(vault_id, amount).await
 }
 
+/// Repays a vault's debt from the caller's iUSD, which the caller must first approve the
+/// protocol to spend (`icrc2_approve`)
 #[update]
 async fn repay_debt(vault_id: u64, amount: u128) -> Result<(), String> {
     let controller = ic_cdk::storage::get_mut::<VaultController>();
-    controller.repay_debt(vault_id, amount).await
+    controller.repay_debt(vault_id, ic_cdk::caller(), amount).await
 }
 
 #[update]
@@ -327,4 +728,71 @@ async fn check_liquidatable(vault_id: u64) -> Result<bool, String> {
 async fn get_health_factor(vault_id: u64) -> Result<f64, String> {
     let controller = ic_cdk::storage::get::<VaultController>();
     controller.get_health_factor(vault_id).await
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn price(price: u128, decimals: u32) -> AggregatedPrice {
+        AggregatedPrice {
+            price,
+            decimals,
+            timestamp: 0,
+            min_price: price,
+            max_price: price,
+            std_deviation: 0,
+        }
+    }
+    
+    #[test]
+    fn converts_8_decimal_collateral() {
+        // 1 ICP at $12.34 is 12.34 iUSD
+        let icp = price(1234, 2);
+        assert_eq!(collateral_value_at(&CollateralType::ICP, 100_000_000, &icp), Ok(1_234_000_000));
+        assert_eq!(collateral_units_for_value(1_234_000_000, &icp, 8), Ok(100_000_000));
+        
+        // 1 satoshi at $60,000 is worth 0.0006 iUSD, with prices at 8 decimals
+        let btc = price(6_000_000_000_000, 8);
+        assert_eq!(collateral_value_at(&CollateralType::CkBTC, 1, &btc), Ok(60_000));
+        assert_eq!(collateral_units_for_value(60_000, &btc, 8), Ok(1));
+    }
+    
+    #[test]
+    fn converts_18_decimal_collateral() {
+        // 1 ETH at $3000.12345678
+        let eth = price(300_012_345_678, 8);
+        assert_eq!(collateral_value_at(&CollateralType::CkETH, 10u128.pow(18), &eth), Ok(300_012_345_678));
+        assert_eq!(collateral_units_for_value(300_012_345_678, &eth, 18), Ok(10u128.pow(18)));
+    }
+    
+    #[test]
+    fn rounds_down_at_the_boundaries() {
+        // At $3000 one iUSD base unit (1e-8 USD) buys 3,333,333.33 wei
+        let eth = price(300_000_000_000, 8);
+        assert_eq!(collateral_value_at(&CollateralType::CkETH, 1, &eth), Ok(0));
+        assert_eq!(collateral_value_at(&CollateralType::CkETH, 3_333_333, &eth), Ok(0));
+        assert_eq!(collateral_value_at(&CollateralType::CkETH, 3_333_334, &eth), Ok(1));
+        assert_eq!(collateral_units_for_value(1, &eth, 18), Ok(3_333_333));
+        
+        // Rounding never values collateral above what it sells for, in either direction
+        for value in [1, 7, 99_999_999, 100_000_000, 123_456_789_012] {
+            let units = collateral_units_for_value(value, &eth, 18).unwrap();
+            assert!(collateral_value_at(&CollateralType::CkETH, units, &eth).unwrap() <= value);
+            assert!(collateral_value_at(&CollateralType::CkETH, units + 1, &eth).unwrap() >= value);
+        }
+        
+        // Just under a satoshi's worth rounds to no units, a full satoshi's worth to one
+        let btc = price(6_000_000_000_000, 8);
+        assert_eq!(collateral_units_for_value(59_999, &btc, 8), Ok(0));
+        assert_eq!(collateral_units_for_value(60_000, &btc, 8), Ok(1));
+    }
+    
+    #[test]
+    fn rejects_overflow_and_zero_prices() {
+        let eth = price(300_000_000_000, 8);
+        assert!(collateral_value_at(&CollateralType::CkETH, u128::MAX, &eth).is_err());
+        assert!(collateral_units_for_value(u128::MAX, &eth, 18).is_err());
+        assert!(collateral_units_for_value(100_000_000, &price(0, 8), 8).is_err());
+    }
+}
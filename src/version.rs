@@ -0,0 +1,32 @@
+use candid::{CandidType, Deserialize};
+use ic_cdk_macros::*;
+use crate::stable_state::SCHEMA_VERSION;
+
+/// Cargo features the canister was built with
+const FEATURES: &[&str] = &[
+    #[cfg(feature = "allowlist")]
+    "allowlist",
+];
+
+/// What is deployed, for operators and indexers checking an upgrade went through
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct VersionInfo {
+    /// Crate version, e.g. `0.1.0`
+    version: String,
+    /// Commit the wasm was built from, when the build sets `GIT_COMMIT`
+    git_commit: Option<String>,
+    /// Layout of the state saved across upgrades
+    schema_version: u32,
+    features: Vec<String>,
+}
+
+// Canister endpoints
+#[query]
+fn get_version() -> VersionInfo {
+    VersionInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_commit: option_env!("GIT_COMMIT").map(|commit| commit.to_string()),
+        schema_version: SCHEMA_VERSION,
+        features: FEATURES.iter().map(|feature| feature.to_string()).collect(),
+    }
+}
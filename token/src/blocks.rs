@@ -0,0 +1,717 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::call::CallResult;
+use ic_cdk::api::management_canister::main::{
+    create_canister_with_extra_cycles, deposit_cycles, install_code, CanisterIdRecord,
+    CanisterInstallMode, CanisterSettings, CreateCanisterArgument, InstallCodeArgument,
+};
+use ic_cdk_macros::*;
+use ic_certified_map::{fork, fork_hash, labeled, labeled_hash, AsHashTree, HashTree, RbTree};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::cmp::Reverse;
+use std::time::Duration;
+
+use crate::roles::Role;
+use crate::{
+    audit_change, balance, record_audit, storage, Account, TokenState, Transaction, TransactionType,
+    ACCOUNT_INDEX, BALANCES,
+};
+
+/// Default local blocks that trigger moving the oldest ones to an archive
+const ARCHIVE_TRIGGER_BLOCKS: u64 = 2000;
+/// Default blocks moved to an archive per run
+const ARCHIVE_BATCH_BLOCKS: u64 = 1000;
+/// Default blocks an archive holds before a new one is spawned
+const ARCHIVE_CAPACITY_BLOCKS: u64 = 1_000_000;
+/// Default cycles a new archive canister is created with
+const ARCHIVE_CREATION_CYCLES: u128 = 2_000_000_000_000;
+/// Default archive balance below which the archive is topped up
+const ARCHIVE_MIN_CYCLES: u128 = 500_000_000_000;
+/// Default cycles sent per archive top-up
+const ARCHIVE_TOP_UP_CYCLES: u128 = 1_000_000_000_000;
+/// Encoded block bytes sent to an archive per call, under the 2 MiB message limit
+const MAX_APPEND_BYTES: u64 = 1_500_000;
+/// Blocks returned per requested range
+const MAX_BLOCKS_PER_RESPONSE: u128 = 100;
+const BALANCES_LABEL: &[u8] = b"balances";
+const ICRC3_SCHEMA_URL: &str = "https://github.com/dfinity/ICRC-1/blob/main/standards/ICRC-3/README.md";
+
+/// ICRC-3 generic value, the representation blocks are hashed and served in
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum Value {
+    Blob(Vec<u8>),
+    Text(String),
+    Nat(u128),
+    Int(i128),
+    Array(Vec<Value>),
+    Map(Vec<(String, Value)>),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct GetBlocksArgs {
+    start: u128,
+    length: u128,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct BlockWithId {
+    id: u128,
+    block: Value,
+}
+
+/// Archive query callback, `func (vec GetBlocksArgs) -> (GetBlocksResult) query`
+#[derive(Deserialize, Clone, Debug)]
+#[serde(transparent)]
+pub struct QueryBlockArchiveFn(candid::Func);
+
+impl CandidType for QueryBlockArchiveFn {
+    fn _ty() -> candid::types::Type {
+        candid::types::Type::Func(candid::types::Function {
+            modes: vec![candid::parser::types::FuncMode::Query],
+            args: vec![Vec::<GetBlocksArgs>::ty()],
+            rets: vec![GetBlocksResult::ty()],
+        })
+    }
+
+    fn idl_serialize<S: candid::types::Serializer>(&self, serializer: S) -> Result<(), S::Error> {
+        self.0.idl_serialize(serializer)
+    }
+}
+
+/// Archived part of a request, to be fetched from the archive with `callback`
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ArchivedBlocks {
+    args: Vec<GetBlocksArgs>,
+    callback: QueryBlockArchiveFn,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct GetBlocksResult {
+    log_length: u128,
+    blocks: Vec<BlockWithId>,
+    archived_blocks: Vec<ArchivedBlocks>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct GetArchivesArgs {
+    /// List archives after this one; None lists from the first
+    from: Option<Principal>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ICRC3ArchiveInfo {
+    canister_id: Principal,
+    start: u128,
+    /// Index of the archive's last block
+    end: u128,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ICRC3DataCertificate {
+    certificate: Vec<u8>,
+    /// CBOR hash tree with `last_block_index` and `last_block_hash`
+    hash_tree: Vec<u8>,
+}
+
+/// Balance with a certificate and a CBOR witness of it under `balances` in the certified tree
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CertifiedBalance {
+    balance: u128,
+    certificate: Vec<u8>,
+    hash_tree: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SupportedBlockType {
+    block_type: String,
+    url: String,
+}
+
+/// Archive canister the token spawned, with the range of blocks moved to it
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ArchiveRecord {
+    canister_id: Principal,
+    start: u64,
+    length: u64,
+}
+
+/// When local blocks are moved to archives, and how archives are created and kept funded
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ArchivePolicy {
+    /// Local blocks that trigger archiving
+    trigger_blocks: u64,
+    /// Encoded size of the local blocks that also triggers archiving
+    trigger_bytes: Option<u64>,
+    /// Blocks moved per run, fewer if they exceed one call's size
+    batch_blocks: u64,
+    /// Blocks a new archive is created to hold
+    blocks_per_archive: u64,
+    /// Cycles a new archive is created with
+    creation_cycles: u128,
+    /// Archive balance below which it is topped up before blocks are appended
+    min_archive_cycles: u128,
+    top_up_cycles: u128,
+}
+
+impl Default for ArchivePolicy {
+    fn default() -> Self {
+        Self {
+            trigger_blocks: ARCHIVE_TRIGGER_BLOCKS,
+            trigger_bytes: None,
+            batch_blocks: ARCHIVE_BATCH_BLOCKS,
+            blocks_per_archive: ARCHIVE_CAPACITY_BLOCKS,
+            creation_cycles: ARCHIVE_CREATION_CYCLES,
+            min_archive_cycles: ARCHIVE_MIN_CYCLES,
+            top_up_cycles: ARCHIVE_TOP_UP_CYCLES,
+        }
+    }
+}
+
+/// Installation arguments of the archive canister (`archive/`)
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct ArchiveInit {
+    ledger_id: Principal,
+    block_offset: u64,
+    max_blocks: u64,
+}
+
+/// Entry of the block log: a transaction chained to the previous block's hash
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Block {
+    pub(crate) transaction: Transaction,
+    /// None for the first block
+    parent_hash: Option<[u8; 32]>,
+}
+
+fn leb128(mut n: u128) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn sleb128(mut n: i128) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        let done = (n == 0 && byte & 0x40 == 0) || (n == -1 && byte & 0x40 != 0);
+        if done {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+impl Value {
+    /// ICRC-3 representation-independent hash
+    pub fn hash(&self) -> [u8; 32] {
+        match self {
+            Value::Blob(bytes) => Sha256::digest(bytes).into(),
+            Value::Text(text) => Sha256::digest(text.as_bytes()).into(),
+            Value::Nat(n) => Sha256::digest(leb128(*n)).into(),
+            Value::Int(i) => Sha256::digest(sleb128(*i)).into(),
+            Value::Array(items) => {
+                let mut hasher = Sha256::new();
+                for item in items {
+                    hasher.update(item.hash());
+                }
+                hasher.finalize().into()
+            }
+            Value::Map(entries) => {
+                // Entries are hashed in the order of their key and value hashes, not as listed
+                let mut pairs: Vec<Vec<u8>> = entries
+                    .iter()
+                    .map(|(key, value)| [Sha256::digest(key.as_bytes()).as_slice(), &value.hash()].concat())
+                    .collect();
+                pairs.sort();
+                let mut hasher = Sha256::new();
+                for pair in pairs {
+                    hasher.update(pair);
+                }
+                hasher.finalize().into()
+            }
+        }
+    }
+}
+
+impl Block {
+    /// ICRC-3 block type, per the ICRC-1 and ICRC-2 block schemas
+    fn block_type(&self) -> &'static str {
+        match (&self.transaction.transaction_type, &self.transaction.spender) {
+            (TransactionType::Mint, _) => "1mint",
+            (TransactionType::Burn, _) => "1burn",
+            (TransactionType::Transfer, None) => "1xfer",
+            (TransactionType::Transfer, Some(_)) => "2xfer",
+            (TransactionType::Approve, _) => "2approve",
+        }
+    }
+
+    /// ICRC-3 representation; its hash is what the next block chains to
+    pub fn to_value(&self) -> Value {
+        let transaction = &self.transaction;
+        let mut tx = vec![("amt".to_string(), Value::Nat(transaction.amount))];
+        if let Some(from) = &transaction.from {
+            tx.push(("from".to_string(), from.to_value()));
+        }
+        if matches!(transaction.transaction_type, TransactionType::Mint | TransactionType::Transfer) {
+            tx.push(("to".to_string(), transaction.to.to_value()));
+        }
+        if let Some(spender) = &transaction.spender {
+            tx.push(("spender".to_string(), spender.to_value()));
+        }
+        if let Some(expires_at) = transaction.expires_at {
+            tx.push(("expires_at".to_string(), Value::Nat(expires_at as u128)));
+        }
+        if let Some(memo) = &transaction.memo {
+            tx.push(("memo".to_string(), Value::Blob(memo.clone())));
+        }
+
+        let mut block = vec![
+            ("btype".to_string(), Value::Text(self.block_type().to_string())),
+            ("ts".to_string(), Value::Nat(transaction.timestamp as u128)),
+            ("tx".to_string(), Value::Map(tx)),
+        ];
+        if let Some(fee) = transaction.fee {
+            block.push(("fee".to_string(), Value::Nat(fee)));
+        }
+        if let Some(parent_hash) = self.parent_hash {
+            block.push(("phash".to_string(), Value::Blob(parent_hash.to_vec())));
+        }
+        Value::Map(block)
+    }
+}
+
+impl TokenState {
+    /// Appends a transaction to the block log and certifies the new tip and balances, returning
+    /// its block index
+    pub(crate) fn record(&mut self, transaction: Transaction) -> u128 {
+        self.update_stats(&transaction);
+        self.publish_supply_event(self.log_length(), &transaction);
+        let block_index = self.log_length() as u64;
+        ACCOUNT_INDEX.with(|index| {
+            let mut index = index.borrow_mut();
+            for account in transaction.accounts() {
+                index.insert((account.balance_key(), Reverse(block_index)), ());
+            }
+        });
+
+        let block = Block {
+            transaction,
+            parent_hash: self.last_block_hash,
+        };
+        let value = block.to_value();
+        self.last_block_hash = Some(value.hash());
+        self.local_block_bytes += encoded_size(&value);
+        self.blocks.push(block);
+        self.update_certified_data();
+
+        // Archive from a later message rather than while this call holds the state
+        if self.archive_due() && self.archive_wasm.is_some() && !self.archiving {
+            self.archiving = true;
+            ic_cdk_timers::set_timer(Duration::ZERO, || ic_cdk::spawn(archive_blocks()));
+        }
+
+        self.log_length() - 1
+    }
+
+    /// Whether the local blocks exceed the policy's count or size trigger
+    fn archive_due(&self) -> bool {
+        let policy = &self.archive_policy;
+        self.blocks.len() as u64 >= policy.trigger_blocks
+            || policy.trigger_bytes.is_some_and(|bytes| self.local_block_bytes >= bytes)
+    }
+
+    /// Hash tree of the ICRC-3 tip: the newest block's index and hash
+    fn tip_tree(&self) -> RbTree<&'static str, Vec<u8>> {
+        let mut tree = RbTree::new();
+        if let Some(hash) = self.last_block_hash {
+            tree.insert("last_block_index", leb128(self.log_length() - 1));
+            tree.insert("last_block_hash", hash.to_vec());
+        }
+        tree
+    }
+
+    /// Hash tree of every balance, LEB128-encoded under the account's certified key
+    fn balance_tree(&self) -> RbTree<Vec<u8>, Vec<u8>> {
+        let mut tree = RbTree::new();
+        BALANCES.with(|balances| {
+            for (key, balance) in balances.borrow().iter() {
+                tree.insert(key.as_slice().to_vec(), leb128(balance));
+            }
+        });
+        tree
+    }
+
+    /// Certifies the root over `balances` and the tip labels, which sort after it
+    pub(crate) fn update_certified_data(&self) {
+        let balances = labeled_hash(BALANCES_LABEL, &self.balance_tree().root_hash());
+        let root = fork_hash(&balances, &self.tip_tree().root_hash());
+        // Certified data only exists inside a canister
+        if cfg!(not(test)) {
+            ic_cdk::api::set_certified_data(&root);
+        }
+    }
+
+    /// Local blocks in the requested ranges, and where to fetch the archived ones
+    pub fn get_blocks(&self, args: Vec<GetBlocksArgs>) -> GetBlocksResult {
+        let log_length = self.log_length();
+        let first_local = self.archived_length as u128;
+
+        let mut blocks = Vec::new();
+        let mut archived_blocks = Vec::new();
+        for range in args {
+            let end = range.start
+                .saturating_add(range.length.min(MAX_BLOCKS_PER_RESPONSE))
+                .min(log_length);
+
+            for archive in &self.archives {
+                let start = range.start.max(archive.start as u128);
+                let archive_end = end.min(archive.start as u128 + archive.length as u128);
+                if start < archive_end {
+                    archived_blocks.push(ArchivedBlocks {
+                        args: vec![GetBlocksArgs {
+                            start,
+                            length: archive_end - start,
+                        }],
+                        callback: QueryBlockArchiveFn(candid::Func {
+                            principal: archive.canister_id,
+                            method: "icrc3_get_blocks".to_string(),
+                        }),
+                    });
+                }
+            }
+
+            for id in range.start.max(first_local)..end {
+                blocks.push(BlockWithId {
+                    id,
+                    block: self.blocks[(id - first_local) as usize].to_value(),
+                });
+            }
+        }
+
+        GetBlocksResult {
+            log_length,
+            blocks,
+            archived_blocks,
+        }
+    }
+}
+
+/// Creates an archive canister controlled by the token and installs the archive wasm into it
+async fn spawn_archive(wasm: Vec<u8>, block_offset: u64, policy: &ArchivePolicy) -> Result<Principal, String> {
+    let settings = CanisterSettings {
+        controllers: Some(vec![ic_cdk::id()]),
+        compute_allocation: None,
+        memory_allocation: None,
+        freezing_threshold: None,
+    };
+    let (record,) = create_canister_with_extra_cycles(
+        CreateCanisterArgument { settings: Some(settings) },
+        policy.creation_cycles,
+    )
+    .await
+    .map_err(|(code, msg)| format!("Failed to create archive: {:?} - {}", code, msg))?;
+
+    let init = ArchiveInit {
+        ledger_id: ic_cdk::id(),
+        block_offset,
+        max_blocks: policy.blocks_per_archive,
+    };
+    install_code(InstallCodeArgument {
+        mode: CanisterInstallMode::Install,
+        canister_id: record.canister_id,
+        wasm_module: wasm,
+        arg: candid::encode_one(init).map_err(|e| e.to_string())?,
+    })
+    .await
+    .map_err(|(code, msg)| format!("Failed to install archive: {:?} - {}", code, msg))?;
+
+    Ok(record.canister_id)
+}
+
+/// Moves the oldest local blocks to the newest archive, spawning one when it is full and
+/// topping it up when it runs low on cycles
+async fn move_blocks_to_archive() -> Result<(), String> {
+    let state = storage::get::<TokenState>();
+    let policy = state.archive_policy.clone();
+
+    // The archive reports its own capacity, so archives spawned under an earlier policy fill up
+    // to the size they were created with
+    let mut current = None;
+    if let Some(record) = state.archives.last() {
+        let result: CallResult<(u64,)> = ic_cdk::call(record.canister_id, "remaining_capacity", ()).await;
+        let (remaining,) = result
+            .map_err(|(code, msg)| format!("Failed to read archive capacity: {:?} - {}", code, msg))?;
+        if remaining > 0 {
+            current = Some((record.canister_id, remaining));
+        }
+    }
+    let (archive, remaining) = match current {
+        Some(current) => current,
+        None => {
+            let state = storage::get::<TokenState>();
+            let wasm = state.archive_wasm.clone().ok_or("Archive wasm not set")?;
+            let canister_id = spawn_archive(wasm, state.archived_length, &policy).await?;
+
+            let state = storage::get_mut::<TokenState>();
+            state.archives.push(ArchiveRecord {
+                canister_id,
+                start: state.archived_length,
+                length: 0,
+            });
+            (canister_id, policy.blocks_per_archive)
+        }
+    };
+
+    let result: CallResult<(u128,)> = ic_cdk::call(archive, "cycles_balance", ()).await;
+    let (cycles,) = result
+        .map_err(|(code, msg)| format!("Failed to read archive cycles: {:?} - {}", code, msg))?;
+    if cycles < policy.min_archive_cycles {
+        deposit_cycles(CanisterIdRecord { canister_id: archive }, policy.top_up_cycles)
+            .await
+            .map_err(|(code, msg)| format!("Failed to top up archive: {:?} - {}", code, msg))?;
+    }
+
+    // Blocks recorded while the calls are in flight are appended behind the batch, so
+    // the oldest ones are still the batch once they return
+    let state = storage::get::<TokenState>();
+    let mut batch = Vec::new();
+    let mut batch_bytes = 0;
+    for block in state.blocks.iter().take(policy.batch_blocks.min(remaining) as usize) {
+        let value = block.to_value();
+        let size = encoded_size(&value);
+        if !batch.is_empty() && batch_bytes + size > MAX_APPEND_BYTES {
+            break;
+        }
+        batch_bytes += size;
+        batch.push(value);
+    }
+    let result: CallResult<()> = ic_cdk::call(archive, "append_blocks", (batch.clone(),)).await;
+    result.map_err(|(code, msg)| format!("Failed to append blocks to archive: {:?} - {}", code, msg))?;
+
+    let state = storage::get_mut::<TokenState>();
+    state.blocks.drain(..batch.len());
+    state.archived_length += batch.len() as u64;
+    state.local_block_bytes = state.local_block_bytes.saturating_sub(batch_bytes);
+    if let Some(record) = state.archives.last_mut() {
+        record.length += batch.len() as u64;
+    }
+    Ok(())
+}
+
+/// Blocks in the requested ranges, with the archived ones fetched from their archives
+async fn fetch_blocks(args: Vec<GetBlocksArgs>) -> Result<GetBlocksResult, String> {
+    let result = storage::get::<TokenState>().get_blocks(args);
+    let mut blocks = result.blocks;
+    for archived in result.archived_blocks {
+        let callback = archived.callback.0;
+        let response: CallResult<(GetBlocksResult,)> =
+            ic_cdk::call(callback.principal, &callback.method, (archived.args,)).await;
+        let (archive_result,) = response
+            .map_err(|(code, msg)| format!("Failed to read blocks from archive: {:?} - {}", code, msg))?;
+        blocks.extend(archive_result.blocks);
+    }
+    blocks.sort_by_key(|block| block.id);
+
+    Ok(GetBlocksResult {
+        log_length: result.log_length,
+        blocks,
+        archived_blocks: Vec::new(),
+    })
+}
+
+/// Archiving run scheduled by `record`; a failed run is retried with the next block
+async fn archive_blocks() {
+    if let Err(e) = move_blocks_to_archive().await {
+        ic_cdk::println!("Archiving failed: {}", e);
+    }
+
+    let state = storage::get_mut::<TokenState>();
+    state.archiving = false;
+}
+
+/// Size of a block's candid encoding, as counted against the archiving size trigger
+fn encoded_size(value: &Value) -> u64 {
+    candid::encode_one(value).map_or(0, |bytes| bytes.len() as u64)
+}
+
+fn encode_hash_tree(tree: &HashTree) -> Vec<u8> {
+    let mut serializer = serde_cbor::ser::Serializer::new(vec![]);
+    serializer.self_describe().unwrap();
+    tree.serialize(&mut serializer).unwrap();
+    serializer.into_inner()
+}
+
+// Canister endpoints
+/// ICRC-3 blocks by index; archived ranges are returned as callbacks to their archive
+#[query]
+fn icrc3_get_blocks(args: Vec<GetBlocksArgs>) -> GetBlocksResult {
+    let state = storage::get::<TokenState>();
+    state.get_blocks(args)
+}
+
+#[query]
+fn icrc3_get_archives(args: GetArchivesArgs) -> Vec<ICRC3ArchiveInfo> {
+    let state = storage::get::<TokenState>();
+    let skip = args.from
+        .and_then(|from| state.archives.iter().position(|archive| archive.canister_id == from))
+        .map_or(0, |position| position + 1);
+    state.archives
+        .iter()
+        .skip(skip)
+        .filter(|archive| archive.length > 0)
+        .map(|archive| ICRC3ArchiveInfo {
+            canister_id: archive.canister_id,
+            start: archive.start as u128,
+            end: (archive.start + archive.length - 1) as u128,
+        })
+        .collect()
+}
+
+/// Certificate over the newest block's index and hash; None before the first block
+#[query]
+fn icrc3_get_tip_certificate() -> Option<ICRC3DataCertificate> {
+    let state = storage::get::<TokenState>();
+    state.last_block_hash?;
+    let certificate = ic_cdk::api::data_certificate()?;
+
+    let balances = labeled_hash(BALANCES_LABEL, &state.balance_tree().root_hash());
+    let tip = state.tip_tree();
+    let tree = fork(HashTree::Pruned(balances), tip.as_hash_tree());
+    Some(ICRC3DataCertificate {
+        certificate,
+        hash_tree: encode_hash_tree(&tree),
+    })
+}
+
+/// Balance with a witness light clients check against the certificate, rather than
+/// trusting the replica that answered
+#[query]
+fn certified_balance_of(account: Account) -> Result<CertifiedBalance, String> {
+    let state = storage::get::<TokenState>();
+    let certificate = ic_cdk::api::data_certificate()
+        .ok_or("Certificates are only available in query calls")?;
+
+    let balances = state.balance_tree();
+    let tip = state.tip_tree();
+    let tree = fork(
+        labeled(BALANCES_LABEL, balances.witness(account.balance_key().as_slice())),
+        HashTree::Pruned(tip.root_hash()),
+    );
+    Ok(CertifiedBalance {
+        balance: balance(&account),
+        certificate,
+        hash_tree: encode_hash_tree(&tree),
+    })
+}
+
+#[query]
+fn icrc3_supported_block_types() -> Vec<SupportedBlockType> {
+    ["1mint", "1burn", "1xfer", "2approve", "2xfer"]
+        .iter()
+        .map(|block_type| SupportedBlockType {
+            block_type: block_type.to_string(),
+            url: ICRC3_SCHEMA_URL.to_string(),
+        })
+        .collect()
+}
+
+/// `icrc3_get_blocks` with archived ranges read from the archives, so callers don't follow the
+/// callbacks themselves
+#[query(composite = true)]
+async fn get_blocks(args: Vec<GetBlocksArgs>) -> Result<GetBlocksResult, String> {
+    fetch_blocks(args).await
+}
+
+/// Block at `index`, wherever it is held
+#[query(composite = true)]
+async fn get_block(index: u128) -> Result<Option<Value>, String> {
+    let result = fetch_blocks(vec![GetBlocksArgs { start: index, length: 1 }]).await?;
+    Ok(result.blocks.into_iter().next().map(|block| block.block))
+}
+
+/// Sets when blocks are archived and how archives are created and funded
+#[update]
+fn set_archive_policy(policy: ArchivePolicy) -> Result<(), String> {
+    let state = storage::get_mut::<TokenState>();
+    state.check_role(Role::Admin)?;
+    if policy.trigger_blocks == 0 || policy.trigger_bytes == Some(0) {
+        return Err("Archive triggers must be positive".to_string());
+    }
+    if policy.batch_blocks == 0 || policy.blocks_per_archive == 0 {
+        return Err("Archive batch and capacity must be positive".to_string());
+    }
+    audit_change("set_archive_policy", &state.archive_policy, &policy);
+    state.archive_policy = policy;
+    Ok(())
+}
+
+#[query]
+fn get_archive_policy() -> ArchivePolicy {
+    storage::get::<TokenState>().archive_policy.clone()
+}
+
+/// Sets the wasm (built from `archive/`) that archive canisters are spawned with
+#[update]
+fn set_archive_wasm(wasm: Vec<u8>) -> Result<(), String> {
+    let state = storage::get_mut::<TokenState>();
+    state.check_role(Role::Admin)?;
+    let hash: [u8; 32] = Sha256::digest(&wasm).into();
+    record_audit("set_archive_wasm", None, Some(format!("sha256 {}", hex::encode(hash))));
+    state.archive_wasm = Some(wasm);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex_hash(value: &Value) -> String {
+        hex::encode(value.hash())
+    }
+
+    #[test]
+    fn value_hashes_match_the_icrc3_examples() {
+        assert_eq!(hex_hash(&Value::Nat(42)), "684888c0ebb17f374298b65ee2807526c066094c701bcc7ebbe1c1095f494fc1");
+        assert_eq!(hex_hash(&Value::Int(-42)), "de5a6f78116eca62d7fc5ce159d23ae6b889b365a1739ad2cf36f925a140d0cc");
+        assert_eq!(
+            hex_hash(&Value::Text("Hello, World!".to_string())),
+            "dffd6021bb2bd5b0af676290809ec3a53191dd81c7f70a4b28688a362182986f"
+        );
+        assert_eq!(
+            hex_hash(&Value::Blob(vec![1, 2, 3, 4])),
+            "9f64a747e1b97f131fabb6b447296c9b6f0201e79fb3c5356e6c77e89b6a806a"
+        );
+        let array = Value::Array(vec![Value::Nat(3), Value::Text("foo".to_string()), Value::Blob(vec![5, 6])]);
+        assert_eq!(hex_hash(&array), "514a04011caa503990d446b7dec5d79e19c221ae607fb08b2848c67734d468d6");
+    }
+
+    #[test]
+    fn map_hash_ignores_entry_order() {
+        let entries = vec![
+            ("amt".to_string(), Value::Nat(100)),
+            ("op".to_string(), Value::Text("mint".to_string())),
+            ("ts".to_string(), Value::Nat(1_700_000_000)),
+        ];
+        let mut reversed = entries.clone();
+        reversed.reverse();
+        assert_eq!(Value::Map(entries).hash(), Value::Map(reversed).hash());
+    }
+
+    #[test]
+    fn leb128_encodes_like_the_spec() {
+        assert_eq!(leb128(0), vec![0x00]);
+        assert_eq!(leb128(624_485), vec![0xe5, 0x8e, 0x26]);
+        assert_eq!(sleb128(-123_456), vec![0xc0, 0xbb, 0x78]);
+        assert_eq!(sleb128(63), vec![0x3f]);
+        assert_eq!(sleb128(64), vec![0xc0, 0x00]);
+    }
+}
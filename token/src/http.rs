@@ -0,0 +1,166 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::*;
+use std::collections::HashMap;
+
+use crate::{storage, Account, TokenState, TransactionWithId, MAX_TRANSACTIONS_PER_PAGE};
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HttpRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HttpResponse {
+    status_code: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+/// Header row of the CSV export, matching `Transaction::to_csv_row`
+const CSV_HEADER: &str = "id,timestamp,type,from_owner,from_subaccount,to_owner,to_subaccount,spender_owner,spender_subaccount,amount,fee,expires_at,memo";
+
+fn optional_text<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+fn optional_json<T: ToString>(value: Option<T>) -> String {
+    value.map_or("null".to_string(), |value| format!("\"{}\"", value.to_string()))
+}
+
+impl TransactionWithId {
+    /// JSON object with amounts and timestamps as strings, since they can exceed what JSON
+    /// numbers hold exactly; subaccounts and memos are hex
+    fn to_json(&self) -> String {
+        let tx = &self.transaction;
+        let account_json = |account: &Account| {
+            format!(
+                "{{\"owner\":\"{}\",\"subaccount\":{}}}",
+                account.owner,
+                optional_json(account.subaccount.map(hex::encode))
+            )
+        };
+        format!(
+            "{{\"id\":\"{}\",\"timestamp\":\"{}\",\"type\":\"{:?}\",\"from\":{},\"to\":{},\"spender\":{},\"amount\":\"{}\",\"fee\":{},\"expires_at\":{},\"memo\":{}}}",
+            self.id,
+            tx.timestamp,
+            tx.transaction_type,
+            tx.from.as_ref().map_or("null".to_string(), account_json),
+            account_json(&tx.to),
+            tx.spender.as_ref().map_or("null".to_string(), account_json),
+            tx.amount,
+            optional_json(tx.fee),
+            optional_json(tx.expires_at),
+            optional_json(tx.memo.as_ref().map(hex::encode))
+        )
+    }
+
+    fn to_csv_row(&self) -> String {
+        let tx = &self.transaction;
+        let account_columns = |account: Option<&Account>| {
+            format!(
+                "{},{}",
+                optional_text(account.map(|account| account.owner)),
+                optional_text(account.and_then(|account| account.subaccount).map(hex::encode))
+            )
+        };
+        format!(
+            "{},{},{:?},{},{},{},{},{},{},{}",
+            self.id,
+            tx.timestamp,
+            tx.transaction_type,
+            account_columns(tx.from.as_ref()),
+            account_columns(Some(&tx.to)),
+            account_columns(tx.spender.as_ref()),
+            tx.amount,
+            optional_text(tx.fee),
+            optional_text(tx.expires_at),
+            optional_text(tx.memo.as_ref().map(hex::encode))
+        )
+    }
+}
+
+/// Query string parameters of a URL, without percent-decoding: none of the export's values
+/// need it
+fn query_params(url: &str) -> HashMap<&str, &str> {
+    url.split_once('?')
+        .map(|(_, query)| query)
+        .unwrap_or("")
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .collect()
+}
+
+fn http_error(status_code: u16, message: &str) -> HttpResponse {
+    HttpResponse {
+        status_code,
+        headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+        body: message.as_bytes().to_vec(),
+    }
+}
+
+// Canister endpoints
+/// Transaction history over the HTTP gateway, e.g.
+/// `curl "https://<canister-id>.raw.icp0.io/transactions?format=csv&start=0&length=100"`.
+/// `format` is `json` (default) or `csv`, `account` (a principal) limits the history to its
+/// default account with `start` then counting its transactions, and pages hold at most 100
+/// transactions still held by the token
+#[query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    let path = request.url.split('?').next().unwrap_or("");
+    if request.method != "GET" {
+        return http_error(405, "Only GET is supported");
+    }
+    if path != "/transactions" {
+        return http_error(404, "Not found; use /transactions");
+    }
+
+    let params = query_params(&request.url);
+    let parse_number = |name: &str, default: u128| match params.get(name) {
+        Some(value) => value.parse::<u128>().map_err(|_| format!("Invalid {}", name)),
+        None => Ok(default),
+    };
+    let (start, length) = match (parse_number("start", 0), parse_number("length", MAX_TRANSACTIONS_PER_PAGE)) {
+        (Ok(start), Ok(length)) => (start, length),
+        (Err(e), _) | (_, Err(e)) => return http_error(400, &e),
+    };
+
+    let state = storage::get::<TokenState>();
+    let page = match params.get("account") {
+        Some(owner) => match Principal::from_text(owner) {
+            Ok(owner) => state.get_transactions_by_account(&Account { owner, subaccount: None }, start, length),
+            Err(_) => return http_error(400, "Invalid account"),
+        },
+        None => state.get_transactions(start, length),
+    };
+
+    let (content_type, body) = match params.get("format").copied().unwrap_or("json") {
+        "json" => {
+            let transactions: Vec<String> = page.transactions.iter().map(TransactionWithId::to_json).collect();
+            let body = format!(
+                "{{\"total\":\"{}\",\"first_local_index\":\"{}\",\"transactions\":[{}]}}",
+                page.total,
+                page.first_local_index,
+                transactions.join(",")
+            );
+            ("application/json", body)
+        }
+        "csv" => {
+            let mut rows = vec![CSV_HEADER.to_string()];
+            rows.extend(page.transactions.iter().map(TransactionWithId::to_csv_row));
+            ("text/csv", rows.join("\n") + "\n")
+        }
+        _ => return http_error(400, "Unsupported format; use json or csv"),
+    };
+
+    HttpResponse {
+        status_code: 200,
+        headers: vec![
+            ("Content-Type".to_string(), content_type.to_string()),
+            ("X-Total-Count".to_string(), page.total.to_string()),
+        ],
+        body: body.into_bytes(),
+    }
+}
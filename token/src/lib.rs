@@ -0,0 +1,2083 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::*;
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell, StableLog, Storable};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::ops::Bound;
+use std::time::Duration;
+
+mod blocks;
+mod http;
+mod roles;
+mod storage;
+
+use blocks::{ArchivePolicy, ArchiveRecord, Block, Value};
+use roles::{propose_action, ApprovalAction, CompletedAction, PendingAction, PendingAdmin, Role};
+
+type TokenMemory = VirtualMemory<DefaultMemoryImpl>;
+/// Balance map key, see `Account::balance_key`
+type AccountKey = Blob<62>;
+
+const STATE_MEMORY_ID: MemoryId = MemoryId::new(0);
+const BALANCES_MEMORY_ID: MemoryId = MemoryId::new(1);
+const ACCOUNT_INDEX_MEMORY_ID: MemoryId = MemoryId::new(2);
+const AUDIT_LOG_INDEX_MEMORY_ID: MemoryId = MemoryId::new(3);
+const AUDIT_LOG_DATA_MEMORY_ID: MemoryId = MemoryId::new(4);
+
+/// Default transfer and approval fee (0.0001 iUSD)
+const DEFAULT_TRANSFER_FEE: u128 = 10_000;
+/// Canisters notified of mints and burns; every notification costs the token cycles
+const MAX_SUPPLY_SUBSCRIBERS: usize = 20;
+/// Distinct approvals destructive actions need until changed through `SetApprovalThreshold`
+const DEFAULT_ACTION_THRESHOLD: u32 = 2;
+
+/// Longest memo accepted on transfers, approvals, mints and burns
+const MAX_MEMO_BYTES: usize = 32;
+/// Interval of the ledger self-audit
+const AUDIT_INTERVAL_SECS: u64 = 3600;
+/// ICRC-1 deduplication window for calls that set `created_at_time`
+const TRANSACTION_WINDOW_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+/// Clock difference tolerated between callers and the ledger
+const PERMITTED_DRIFT_NANOS: u64 = 2 * 60 * 1_000_000_000;
+const SECONDS_PER_HOUR: u64 = 3600;
+/// Hourly buckets summed for the transfer volume
+const VOLUME_WINDOW_HOURS: u64 = 24;
+/// Transactions returned per history page
+const MAX_TRANSACTIONS_PER_PAGE: u128 = 100;
+/// Holders returned per `list_holders` page
+const MAX_HOLDERS_PER_PAGE: u64 = 1000;
+/// Entries returned per `get_audit_log` page
+const MAX_AUDIT_ENTRIES_PER_PAGE: u64 = 100;
+/// Language consent messages are written in
+const CONSENT_LANGUAGE: &str = "en";
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    /// Candid-encoded `TokenState`, written on upgrade
+    static STATE: RefCell<StableCell<Vec<u8>, TokenMemory>> = RefCell::new(
+        StableCell::init(MEMORY_MANAGER.with(|manager| manager.borrow().get(STATE_MEMORY_ID)), Vec::new())
+            .expect("Failed to open token state memory")
+    );
+
+    /// Balances by account key, kept out of `TokenState` so a transfer touches only the
+    /// entries it changes; zero balances are removed
+    static BALANCES: RefCell<StableBTreeMap<AccountKey, u128, TokenMemory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|manager| manager.borrow().get(BALANCES_MEMORY_ID)))
+    );
+
+    /// Block indices of the transactions touching each account, written as blocks are recorded;
+    /// indices are reversed so an account's range iterates newest first
+    static ACCOUNT_INDEX: RefCell<StableBTreeMap<(AccountKey, Reverse<u64>), (), TokenMemory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|manager| manager.borrow().get(ACCOUNT_INDEX_MEMORY_ID)))
+    );
+
+    /// Privileged actions, oldest first; append-only and outside `TokenState`, so upgrades never
+    /// drop entries
+    static AUDIT_LOG: RefCell<StableLog<AuditEntry, TokenMemory, TokenMemory>> = RefCell::new(
+        StableLog::init(
+            MEMORY_MANAGER.with(|manager| manager.borrow().get(AUDIT_LOG_INDEX_MEMORY_ID)),
+            MEMORY_MANAGER.with(|manager| manager.borrow().get(AUDIT_LOG_DATA_MEMORY_ID)),
+        )
+        .expect("Failed to open audit log memory")
+    );
+}
+
+/// Deduplication key of a call: the method, the caller and the candid-encoded arguments
+fn dedup_key<T: CandidType>(method: &str, caller: Principal, args: &T) -> [u8; 32] {
+    let encoded = candid::encode_one(args).expect("Failed to encode call arguments");
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_bytes());
+    hasher.update(caller.as_slice());
+    hasher.update(&encoded);
+    hasher.finalize().into()
+}
+
+/// Up to `limit` block indices of the account's transactions below `before`, newest first
+fn account_block_ids(account: &Account, before: Option<u64>, limit: usize) -> Vec<u64> {
+    let key = account.balance_key();
+    let from = match before {
+        Some(before) => Bound::Excluded((key, Reverse(before))),
+        None => Bound::Included((key, Reverse(u64::MAX))),
+    };
+    ACCOUNT_INDEX.with(|index| {
+        index.borrow()
+            .range((from, Bound::Included((key, Reverse(0)))))
+            .take(limit)
+            .map(|((_, Reverse(id)), _)| id)
+            .collect()
+    })
+}
+
+/// Up to `limit` accounts with a balance after `start_after`, in balance key order
+fn holders_after(start_after: Option<&Account>, limit: usize) -> Vec<HolderBalance> {
+    let from = match start_after {
+        Some(account) => Bound::Excluded(account.balance_key()),
+        None => Bound::Unbounded,
+    };
+    BALANCES.with(|balances| {
+        balances.borrow()
+            .range((from, Bound::Unbounded))
+            .take(limit)
+            .map(|(key, balance)| HolderBalance {
+                account: Account::from_balance_key(&key),
+                balance,
+            })
+            .collect()
+    })
+}
+
+fn oldest_account_block_id(account: &Account) -> Option<u64> {
+    let key = account.balance_key();
+    ACCOUNT_INDEX.with(|index| {
+        let index = index.borrow();
+        if index.contains_key(&(key, Reverse(0))) {
+            return Some(0);
+        }
+        // Greatest entry below the account's block 0, which is its oldest if it is the account's
+        index.iter_upper_bound(&(key, Reverse(0)))
+            .next()
+            .filter(|((entry_key, _), _)| *entry_key == key)
+            .map(|((_, Reverse(id)), _)| id)
+    })
+}
+
+fn balance(account: &Account) -> u128 {
+    BALANCES.with(|balances| balances.borrow().get(&account.balance_key()).unwrap_or(0))
+}
+
+fn set_balance(account: &Account, balance: u128) {
+    BALANCES.with(|balances| {
+        let mut balances = balances.borrow_mut();
+        if balance == 0 {
+            balances.remove(&account.balance_key());
+        } else {
+            balances.insert(account.balance_key(), balance);
+        }
+    });
+}
+
+/// Appends a privileged action taken by the caller; values are recorded in their debug form
+fn record_audit(action: &str, old_value: Option<String>, new_value: Option<String>) {
+    AUDIT_LOG.with(|log| {
+        let log = log.borrow();
+        let entry = AuditEntry {
+            id: log.len(),
+            caller: ic_cdk::caller(),
+            timestamp: ic_cdk::api::time(),
+            action: action.to_string(),
+            old_value,
+            new_value,
+        };
+        log.append(&entry).expect("Failed to append to audit log");
+    });
+}
+
+/// Records a setting changing from `old_value` to `new_value`
+fn audit_change<T: Debug>(action: &str, old_value: &T, new_value: &T) {
+    record_audit(action, Some(format!("{:?}", old_value)), Some(format!("{:?}", new_value)));
+}
+
+/// Records an action without a previous value, e.g. adding a minter
+fn audit_action<T: Debug>(action: &str, value: &T) {
+    record_audit(action, None, Some(format!("{:?}", value)));
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Metadata {
+    name: String,
+    symbol: String,
+    decimals: u8,
+    total_supply: u128,
+}
+
+/// Value of an `icrc1_metadata` entry
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum MetadataValue {
+    Nat(u128),
+    Int(i128),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+pub type Subaccount = [u8; 32];
+
+/// Subaccount an account without one refers to
+const DEFAULT_SUBACCOUNT: Subaccount = [0; 32];
+
+/// ICRC-1 account; `subaccount: None` and the all-zero subaccount are the same account
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Account {
+    owner: Principal,
+    subaccount: Option<Subaccount>,
+}
+
+impl PartialEq for Account {
+    fn eq(&self, other: &Self) -> bool {
+        self.owner == other.owner && self.effective_subaccount() == other.effective_subaccount()
+    }
+}
+
+impl Eq for Account {}
+
+impl Hash for Account {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.owner.hash(state);
+        self.effective_subaccount().hash(state);
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TransferArg {
+    from_subaccount: Option<Subaccount>,
+    to: Account,
+    amount: u128,
+    fee: Option<u128>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum TransferError {
+    BadFee { expected_fee: u128 },
+    BadBurn { min_burn_amount: u128 },
+    InsufficientFunds { balance: u128 },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: u128 },
+    TemporarilyUnavailable,
+    GenericError { error_code: u128, message: String },
+}
+
+/// ICRC-2 allowance of a spender over an account
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Allowance {
+    allowance: u128,
+    /// Expiry in nanoseconds since the epoch; None never expires
+    expires_at: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ApproveArgs {
+    from_subaccount: Option<Subaccount>,
+    spender: Account,
+    amount: u128,
+    /// Approval only succeeds if the current allowance equals this, guarding against races
+    expected_allowance: Option<u128>,
+    expires_at: Option<u64>,
+    fee: Option<u128>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum ApproveError {
+    BadFee { expected_fee: u128 },
+    InsufficientFunds { balance: u128 },
+    AllowanceChanged { current_allowance: u128 },
+    Expired { ledger_time: u64 },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: u128 },
+    TemporarilyUnavailable,
+    GenericError { error_code: u128, message: String },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AllowanceArgs {
+    account: Account,
+    spender: Account,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TransferFromArgs {
+    spender_subaccount: Option<Subaccount>,
+    from: Account,
+    to: Account,
+    amount: u128,
+    fee: Option<u128>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum TransferFromError {
+    BadFee { expected_fee: u128 },
+    BadBurn { min_burn_amount: u128 },
+    InsufficientFunds { balance: u128 },
+    InsufficientAllowance { allowance: u128 },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: u128 },
+    TemporarilyUnavailable,
+    GenericError { error_code: u128, message: String },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TokenStats {
+    /// Accounts with a non-zero balance
+    holder_count: u64,
+    total_supply: u128,
+    /// Amount transferred over the last 24 hours, excluding mints and burns
+    transfer_volume_24h: u128,
+    total_minted: u128,
+    /// Burns, including fees burned for lack of a fee collector
+    total_burned: u128,
+    /// Report of the latest timer audit
+    last_audit: Option<InvariantReport>,
+    /// Timer audits that found drift
+    failed_audits: u64,
+}
+
+/// Result of checking the ledger's supply against its balances and issuance
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct InvariantReport {
+    checked_at: u64,
+    total_supply: u128,
+    /// Sum of all balances
+    balance_sum: u128,
+    /// Mints minus burns, as tallied when blocks are recorded
+    net_issuance: i128,
+    holder_count: u64,
+    /// `balance_sum` minus `total_supply`
+    balance_drift: i128,
+    /// `net_issuance` minus `total_supply`
+    issuance_drift: i128,
+    /// Whether both drifts are zero
+    ok: bool,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct StandardRecord {
+    name: String,
+    url: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ConsentMessageMetadata {
+    language: String,
+    utc_offset_minutes: Option<i16>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum DisplayMessageType {
+    GenericDisplay,
+    LineDisplay {
+        characters_per_line: u16,
+        lines_per_page: u16,
+    },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ConsentMessageSpec {
+    metadata: ConsentMessageMetadata,
+    device_spec: Option<DisplayMessageType>,
+}
+
+/// ICRC-21 request for a description of the call `method(arg)`
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ConsentMessageRequest {
+    method: String,
+    /// Candid-encoded call argument
+    arg: Vec<u8>,
+    user_preferences: ConsentMessageSpec,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct LineDisplayPage {
+    lines: Vec<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum ConsentMessage {
+    GenericDisplayMessage(String),
+    LineDisplayMessage { pages: Vec<LineDisplayPage> },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ConsentInfo {
+    consent_message: ConsentMessage,
+    metadata: ConsentMessageMetadata,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Icrc21ErrorInfo {
+    description: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum Icrc21Error {
+    UnsupportedCanisterCall(Icrc21ErrorInfo),
+    ConsentMessageUnavailable(Icrc21ErrorInfo),
+    InsufficientPayment(Icrc21ErrorInfo),
+    GenericError { error_code: u128, description: String },
+}
+
+/// Call remembered for deduplication
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RecentTransaction {
+    block_index: u128,
+    created_at_time: u64,
+}
+
+/// Why a call was rejected by the deduplication check
+#[derive(Debug)]
+enum DedupError {
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: u128 },
+}
+
+impl From<DedupError> for TransferError {
+    fn from(error: DedupError) -> Self {
+        match error {
+            DedupError::TooOld => TransferError::TooOld,
+            DedupError::CreatedInFuture { ledger_time } => TransferError::CreatedInFuture { ledger_time },
+            DedupError::Duplicate { duplicate_of } => TransferError::Duplicate { duplicate_of },
+        }
+    }
+}
+
+impl From<DedupError> for ApproveError {
+    fn from(error: DedupError) -> Self {
+        match error {
+            DedupError::TooOld => ApproveError::TooOld,
+            DedupError::CreatedInFuture { ledger_time } => ApproveError::CreatedInFuture { ledger_time },
+            DedupError::Duplicate { duplicate_of } => ApproveError::Duplicate { duplicate_of },
+        }
+    }
+}
+
+impl From<DedupError> for TransferFromError {
+    fn from(error: DedupError) -> Self {
+        match error {
+            DedupError::TooOld => TransferFromError::TooOld,
+            DedupError::CreatedInFuture { ledger_time } => TransferFromError::CreatedInFuture { ledger_time },
+            DedupError::Duplicate { duplicate_of } => TransferFromError::Duplicate { duplicate_of },
+        }
+    }
+}
+
+/// Account on the blocklist
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct FrozenAccount {
+    account: Account,
+    reason: String,
+    frozen_at: u64,
+}
+
+/// Freeze or unfreeze of an account
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct FreezeEvent {
+    account: Account,
+    frozen: bool,
+    /// Reason given when freezing
+    reason: Option<String>,
+    timestamp: u64,
+}
+
+/// A privileged action, e.g. a fee change, freeze or minter change
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AuditEntry {
+    /// Position in the log
+    id: u64,
+    caller: Principal,
+    timestamp: u64,
+    /// Endpoint or operation, e.g. `set_fee`
+    action: String,
+    /// Setting before the action, where it replaced one
+    old_value: Option<String>,
+    new_value: Option<String>,
+}
+
+impl Storable for AuditEntry {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).expect("Failed to encode audit entry"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to decode audit entry")
+    }
+}
+
+/// Mint or burn, sent to supply subscribers as a one-way call
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SupplyEvent {
+    block_index: u128,
+    /// `Mint` or `Burn`
+    transaction_type: TransactionType,
+    /// Account credited by a mint or debited by a burn
+    account: Account,
+    amount: u128,
+    /// Total supply after the event
+    total_supply: u128,
+    timestamp: u64,
+}
+
+/// Installation arguments
+#[derive(CandidType, Deserialize)]
+pub struct TokenInitArgs {
+    /// ICRC-1 minting account; transfers to it burn
+    minting_account: Option<Account>,
+    /// Canisters allowed to mint and burn, e.g. the protocol canister
+    authorized_minters: Vec<Principal>,
+    /// Principal that rotates the minters and minting account, e.g. the governance canister
+    minting_governance: Principal,
+    /// Principals granted the Admin role, which can then grant the other roles
+    admins: Vec<Principal>,
+}
+
+/// Token state other than balances and the account index, kept on the heap and saved to
+/// stable memory across upgrades
+#[derive(CandidType, Deserialize)]
+pub struct TokenState {
+    /// Token metadata
+    metadata: Metadata,
+    /// Logo as a data URL, published under `icrc1:logo`
+    logo: Option<String>,
+    /// Authorized minters (vault canister)
+    authorized_minters: Vec<Principal>,
+    /// ICRC-1 minting account; transfers to it burn
+    minting_account: Option<Account>,
+    /// Only principal allowed to change the minters and the minting account
+    minting_governance: Option<Principal>,
+    /// Minting governance waiting to accept control
+    pending_minting_governance: Option<Principal>,
+    /// Roles granted per principal
+    roles: BTreeMap<Principal, BTreeSet<Role>>,
+    /// Admin nominations by candidate
+    pending_admins: BTreeMap<Principal, PendingAdmin>,
+    /// Distinct approvals destructive actions need
+    action_threshold: u32,
+    /// Destructive actions waiting for approvals, by ID
+    pending_actions: BTreeMap<u64, PendingAction>,
+    next_action_id: u64,
+    /// Executed and cancelled actions, oldest first
+    action_history: Vec<CompletedAction>,
+    /// Whether transfers are paused; minters (the protocol) can still move tokens, so
+    /// repayments and deposits keep working
+    transfers_paused: bool,
+    /// Accounts no tokens may be moved from or to
+    frozen_accounts: Vec<FrozenAccount>,
+    freeze_events: Vec<FreezeEvent>,
+    total_minted: u128,
+    total_burned: u128,
+    /// Transferred amount per hour (hours since the epoch), for the last 24 hours
+    hourly_volume: Vec<(u64, u128)>,
+    /// Total supply mints may not exceed, as a circuit breaker against a compromised minter
+    max_supply: Option<u128>,
+    /// ICRC-2 allowances by (account, spender)
+    allowances: HashMap<(Account, Account), Allowance>,
+    /// Calls with a `created_at_time` inside the deduplication window, by `dedup_key`
+    recent_transactions: HashMap<[u8; 32], RecentTransaction>,
+    /// Fee charged to the sender of every transfer and approval
+    fee: u128,
+    /// Account credited with fees; fees are burned when unset
+    fee_collector: Option<Account>,
+    /// Blocks not yet archived; the first one has index `archived_length`
+    blocks: Vec<Block>,
+    /// Blocks moved to archive canisters
+    archived_length: u64,
+    /// Hash of the newest block, which the next block chains to
+    last_block_hash: Option<[u8; 32]>,
+    /// Archive canisters, in block order
+    archives: Vec<ArchiveRecord>,
+    /// Wasm installed into spawned archives; blocks stay local until it is set
+    archive_wasm: Option<Vec<u8>>,
+    /// Whether an archiving run is scheduled or in progress
+    archiving: bool,
+    archive_policy: ArchivePolicy,
+    /// Encoded size of the blocks not yet archived
+    local_block_bytes: u64,
+    /// Method to call with a `SupplyEvent`, per subscribed canister
+    supply_subscribers: BTreeMap<Principal, String>,
+    last_audit: Option<InvariantReport>,
+    failed_audits: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Transaction {
+    from: Option<Account>,
+    to: Account,
+    /// Account that moved the tokens or was approved, for approvals and `transfer_from`
+    spender: Option<Account>,
+    amount: u128,
+    /// Fee charged to `from`; None for mints and burns
+    fee: Option<u128>,
+    /// Approval expiry in nanoseconds since the epoch
+    expires_at: Option<u64>,
+    /// Caller-supplied reference, e.g. for deposit attribution by exchanges
+    memo: Option<Vec<u8>>,
+    timestamp: u64,
+    transaction_type: TransactionType,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TransactionWithId {
+    /// Block index of the transaction
+    id: u128,
+    transaction: Transaction,
+}
+
+/// Page of transaction history; only blocks still held by the token are paged, older ones are
+/// read from the archives through `icrc3_get_blocks`
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TransactionPage {
+    /// Transactions in the whole log, or the account's local history
+    total: u128,
+    /// Block index of the oldest transaction held by the token
+    first_local_index: u128,
+    transactions: Vec<TransactionWithId>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HolderBalance {
+    account: Account,
+    balance: u128,
+}
+
+/// Page of an account's transactions, newest first
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AccountTransactions {
+    balance: u128,
+    transactions: Vec<TransactionWithId>,
+    /// Indices of the page's transactions that are archived, to be read with `icrc3_get_blocks`
+    archived_ids: Vec<u128>,
+    /// Block index of the account's first transaction
+    oldest_tx_id: Option<u128>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum TransactionType {
+    Mint,
+    Burn,
+    Transfer,
+    Approve,
+}
+
+impl Transaction {
+    /// Accounts that sent, received, or spent or were approved for this transaction
+    fn accounts(&self) -> Vec<&Account> {
+        let mut accounts: Vec<&Account> = self.from.iter().chain(self.spender.iter()).collect();
+        if !matches!(self.transaction_type, TransactionType::Burn) {
+            accounts.push(&self.to);
+        }
+        accounts
+    }
+}
+
+impl std::fmt::Display for Account {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.subaccount.filter(|subaccount| *subaccount != DEFAULT_SUBACCOUNT) {
+            Some(subaccount) => write!(f, "{} (subaccount {})", self.owner, hex::encode(subaccount)),
+            None => write!(f, "{}", self.owner),
+        }
+    }
+}
+
+impl Account {
+    fn effective_subaccount(&self) -> &Subaccount {
+        self.subaccount.as_ref().unwrap_or(&DEFAULT_SUBACCOUNT)
+    }
+
+    /// Key of the account's balance in the balance map and certified tree: owner length, owner
+    /// and subaccount
+    fn balance_key(&self) -> AccountKey {
+        let owner = self.owner.as_slice();
+        let mut key = vec![owner.len() as u8];
+        key.extend_from_slice(owner);
+        key.extend_from_slice(self.effective_subaccount());
+        AccountKey::try_from(key.as_slice()).unwrap()
+    }
+
+    /// Account a balance key was built from; the default subaccount comes back as None
+    fn from_balance_key(key: &AccountKey) -> Self {
+        let bytes = key.as_slice();
+        let owner_len = bytes[0] as usize;
+        let subaccount: Subaccount = bytes[1 + owner_len..].try_into().unwrap();
+        Account {
+            owner: Principal::from_slice(&bytes[1..1 + owner_len]),
+            subaccount: Some(subaccount).filter(|subaccount| *subaccount != DEFAULT_SUBACCOUNT),
+        }
+    }
+
+    /// ICRC-3 encoding: the owner, followed by the subaccount unless it is the default one, so
+    /// both spellings of an account hash the same
+    fn to_value(&self) -> Value {
+        let mut parts = vec![Value::Blob(self.owner.as_slice().to_vec())];
+        if *self.effective_subaccount() != DEFAULT_SUBACCOUNT {
+            parts.push(Value::Blob(self.effective_subaccount().to_vec()));
+        }
+        Value::Array(parts)
+    }
+}
+
+impl Default for TokenState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TokenState {
+    pub fn new() -> Self {
+        Self {
+            metadata: Metadata {
+                name: "Internet Computer USD".to_string(),
+                symbol: "iUSD".to_string(),
+                decimals: 8,
+                total_supply: 0,
+            },
+            logo: None,
+            authorized_minters: Vec::new(),
+            minting_account: None,
+            minting_governance: None,
+            pending_minting_governance: None,
+            roles: BTreeMap::new(),
+            pending_admins: BTreeMap::new(),
+            action_threshold: DEFAULT_ACTION_THRESHOLD,
+            pending_actions: BTreeMap::new(),
+            next_action_id: 0,
+            action_history: Vec::new(),
+            transfers_paused: false,
+            frozen_accounts: Vec::new(),
+            freeze_events: Vec::new(),
+            total_minted: 0,
+            total_burned: 0,
+            hourly_volume: Vec::new(),
+            max_supply: None,
+            allowances: HashMap::new(),
+            recent_transactions: HashMap::new(),
+            fee: DEFAULT_TRANSFER_FEE,
+            fee_collector: None,
+            blocks: Vec::new(),
+            archived_length: 0,
+            last_block_hash: None,
+            archives: Vec::new(),
+            archive_wasm: None,
+            archiving: false,
+            archive_policy: ArchivePolicy::default(),
+            local_block_bytes: 0,
+            supply_subscribers: BTreeMap::new(),
+            last_audit: None,
+            failed_audits: 0,
+        }
+    }
+
+    /// Number of blocks ever recorded, archived or not
+    pub fn log_length(&self) -> u128 {
+        self.archived_length as u128 + self.blocks.len() as u128
+    }
+
+    pub fn is_frozen(&self, account: &Account) -> bool {
+        self.frozen_accounts.iter().any(|frozen| frozen.account == *account)
+    }
+
+    /// Rejects moving tokens from or to a frozen account
+    fn check_not_frozen(&self, from: Option<&Account>, to: &Account) -> Result<(), String> {
+        if from.is_some_and(|from| self.is_frozen(from)) {
+            return Err("Source account is frozen".to_string());
+        }
+        if self.is_frozen(to) {
+            return Err("Destination account is frozen".to_string());
+        }
+        Ok(())
+    }
+
+    /// Adds an account to the blocklist
+    pub fn freeze(&mut self, account: Account, reason: String) -> Result<(), String> {
+        if self.is_frozen(&account) {
+            return Err("Account is already frozen".to_string());
+        }
+
+        let now = ic_cdk::api::time();
+        self.frozen_accounts.push(FrozenAccount {
+            account: account.clone(),
+            reason: reason.clone(),
+            frozen_at: now,
+        });
+        self.freeze_events.push(FreezeEvent {
+            account,
+            frozen: true,
+            reason: Some(reason),
+            timestamp: now,
+        });
+        Ok(())
+    }
+
+    /// Removes an account from the blocklist
+    pub fn unfreeze(&mut self, account: Account) -> Result<(), String> {
+        if !self.is_frozen(&account) {
+            return Err("Account is not frozen".to_string());
+        }
+
+        self.frozen_accounts.retain(|frozen| frozen.account != account);
+        self.freeze_events.push(FreezeEvent {
+            account,
+            frozen: false,
+            reason: None,
+            timestamp: ic_cdk::api::time(),
+        });
+        Ok(())
+    }
+
+    /// Transfers are paused for everyone but the authorized minters
+    fn transfers_paused_for(&self, caller: &Principal) -> bool {
+        self.transfers_paused && !self.authorized_minters.contains(caller)
+    }
+
+    fn is_minting_account(&self, account: &Account) -> bool {
+        self.minting_account.as_ref() == Some(account)
+    }
+
+    /// Credits a fee already deducted from its payer to the fee collector, or burns it
+    fn collect_fee(&mut self, fee: u128) {
+        match &self.fee_collector {
+            Some(collector) => set_balance(collector, balance(collector) + fee),
+            None => {
+                self.metadata.total_supply -= fee;
+                self.total_burned += fee;
+            }
+        }
+    }
+
+    /// Registers a canister to be notified of every mint and burn
+    pub fn subscribe_supply_events(&mut self, subscriber: Principal, method: String) -> Result<(), String> {
+        if method.is_empty() {
+            return Err("Callback method must not be empty".to_string());
+        }
+        if !self.supply_subscribers.contains_key(&subscriber)
+            && self.supply_subscribers.len() >= MAX_SUPPLY_SUBSCRIBERS
+        {
+            return Err(format!("At most {} supply subscribers", MAX_SUPPLY_SUBSCRIBERS));
+        }
+
+        self.supply_subscribers.insert(subscriber, method);
+        Ok(())
+    }
+
+    /// Sends a mint or burn to every subscriber as a one-way call, so a failing subscriber cannot
+    /// fail the transaction; subscribers that miss one can catch up from the block log
+    fn publish_supply_event(&self, block_index: u128, transaction: &Transaction) {
+        let account = match transaction.transaction_type {
+            TransactionType::Mint => &transaction.to,
+            TransactionType::Burn => match &transaction.from {
+                Some(from) => from,
+                None => return,
+            },
+            _ => return,
+        };
+        let event = SupplyEvent {
+            block_index,
+            transaction_type: transaction.transaction_type.clone(),
+            account: account.clone(),
+            amount: transaction.amount,
+            total_supply: self.metadata.total_supply,
+            timestamp: transaction.timestamp,
+        };
+        for (subscriber, method) in &self.supply_subscribers {
+            let _ = ic_cdk::api::call::notify(*subscriber, method, (event.clone(),));
+        }
+    }
+
+    /// Amount with the token's decimals and symbol, e.g. `12.5 iUSD`
+    fn format_amount(&self, amount: u128) -> String {
+        let scale = 10u128.pow(self.metadata.decimals as u32);
+        let fraction = format!("{:0width$}", amount % scale, width = self.metadata.decimals as usize);
+        let fraction = fraction.trim_end_matches('0');
+        if fraction.is_empty() {
+            format!("{} {}", amount / scale, self.metadata.symbol)
+        } else {
+            format!("{}.{} {}", amount / scale, fraction, self.metadata.symbol)
+        }
+    }
+
+    /// Markdown ICRC-21 description of a call to `method` with the candid-encoded `arg`
+    pub fn consent_message(&self, method: &str, arg: &[u8]) -> Result<String, Icrc21Error> {
+        let invalid = |e: candid::Error| {
+            Icrc21Error::UnsupportedCanisterCall(Icrc21ErrorInfo {
+                description: format!("Failed to decode {} argument: {}", method, e),
+            })
+        };
+        let subaccount = |subaccount: Option<Subaccount>| Account {
+            owner: ic_cdk::caller(),
+            subaccount,
+        };
+
+        match method {
+            "icrc1_transfer" => {
+                let args: TransferArg = candid::decode_one(arg).map_err(invalid)?;
+                if self.is_minting_account(&args.to) {
+                    return Ok(format!(
+                        "# Burn {}\n\n**Amount:** {}\n\n**From:** {}",
+                        self.metadata.symbol,
+                        self.format_amount(args.amount),
+                        subaccount(args.from_subaccount),
+                    ));
+                }
+                Ok(format!(
+                    "# Send {}\n\n**Amount:** {}\n\n**From:** {}\n\n**To:** {}\n\n**Fee:** {}",
+                    self.metadata.symbol,
+                    self.format_amount(args.amount),
+                    subaccount(args.from_subaccount),
+                    args.to,
+                    self.format_amount(self.fee),
+                ))
+            }
+            "icrc2_approve" => {
+                let args: ApproveArgs = candid::decode_one(arg).map_err(invalid)?;
+                let expiry = match args.expires_at {
+                    Some(expires_at) => format!("{} (nanoseconds since the epoch)", expires_at),
+                    None => "Never".to_string(),
+                };
+                Ok(format!(
+                    "# Approve spending\n\n**Spender:** {}\n\n**Allowance:** {}\n\n**From:** {}\n\n**Expires:** {}\n\n**Fee:** {}",
+                    args.spender,
+                    self.format_amount(args.amount),
+                    subaccount(args.from_subaccount),
+                    expiry,
+                    self.format_amount(self.fee),
+                ))
+            }
+            "icrc2_transfer_from" => {
+                let args: TransferFromArgs = candid::decode_one(arg).map_err(invalid)?;
+                Ok(format!(
+                    "# Spend approved {}\n\n**Amount:** {}\n\n**From:** {}\n\n**To:** {}\n\n**Spender:** {}\n\n**Fee:** {}",
+                    self.metadata.symbol,
+                    self.format_amount(args.amount),
+                    args.from,
+                    args.to,
+                    subaccount(args.spender_subaccount),
+                    self.format_amount(self.fee),
+                ))
+            }
+            _ => Err(Icrc21Error::UnsupportedCanisterCall(Icrc21ErrorInfo {
+                description: format!("No consent message for {}", method),
+            })),
+        }
+    }
+
+    /// ICRC-1 deduplication of calls that set `created_at_time`: rejects calls outside the
+    /// window and repeats of an identical call, returning the key to remember the call under
+    fn check_duplicate<T: CandidType>(
+        &mut self,
+        method: &str,
+        args: &T,
+        created_at_time: Option<u64>,
+        caller: Principal,
+        now: u64,
+    ) -> Result<Option<([u8; 32], u64)>, DedupError> {
+        let created_at_time = match created_at_time {
+            Some(created_at_time) => created_at_time,
+            None => return Ok(None),
+        };
+
+        if created_at_time.saturating_add(TRANSACTION_WINDOW_NANOS + PERMITTED_DRIFT_NANOS) < now {
+            return Err(DedupError::TooOld);
+        }
+        if created_at_time > now + PERMITTED_DRIFT_NANOS {
+            return Err(DedupError::CreatedInFuture { ledger_time: now });
+        }
+
+        self.recent_transactions.retain(|_, recent| {
+            recent.created_at_time + TRANSACTION_WINDOW_NANOS + PERMITTED_DRIFT_NANOS >= now
+        });
+        let key = dedup_key(method, caller, args);
+        if let Some(recent) = self.recent_transactions.get(&key) {
+            return Err(DedupError::Duplicate {
+                duplicate_of: recent.block_index,
+            });
+        }
+        Ok(Some((key, created_at_time)))
+    }
+
+    fn remember(&mut self, dedup: Option<([u8; 32], u64)>, block_index: u128) {
+        if let Some((key, created_at_time)) = dedup {
+            self.recent_transactions.insert(key, RecentTransaction {
+                block_index,
+                created_at_time,
+            });
+        }
+    }
+
+    fn update_stats(&mut self, transaction: &Transaction) {
+        match transaction.transaction_type {
+            TransactionType::Mint => self.total_minted += transaction.amount,
+            TransactionType::Burn => self.total_burned += transaction.amount,
+            TransactionType::Transfer => {
+                let hour = transaction.timestamp / 1_000_000_000 / SECONDS_PER_HOUR;
+                match self.hourly_volume.last_mut() {
+                    Some((last_hour, volume)) if *last_hour == hour => *volume += transaction.amount,
+                    _ => self.hourly_volume.push((hour, transaction.amount)),
+                }
+                self.hourly_volume.retain(|(bucket, _)| bucket + VOLUME_WINDOW_HOURS > hour);
+            }
+            TransactionType::Approve => {}
+        }
+    }
+
+    pub fn stats(&self) -> TokenStats {
+        let hour = ic_cdk::api::time() / 1_000_000_000 / SECONDS_PER_HOUR;
+        TokenStats {
+            holder_count: BALANCES.with(|balances| balances.borrow().len()),
+            total_supply: self.metadata.total_supply,
+            transfer_volume_24h: self.hourly_volume
+                .iter()
+                .filter(|(bucket, _)| bucket + VOLUME_WINDOW_HOURS > hour)
+                .map(|(_, volume)| volume)
+                .sum(),
+            total_minted: self.total_minted,
+            total_burned: self.total_burned,
+            last_audit: self.last_audit.clone(),
+            failed_audits: self.failed_audits,
+        }
+    }
+
+    /// Checks that the total supply equals the sum of balances and the net issuance; reads every
+    /// balance
+    pub fn verify_invariants(&self) -> InvariantReport {
+        let (balance_sum, holder_count) = BALANCES.with(|balances| {
+            let balances = balances.borrow();
+            let sum = balances.iter().fold(0u128, |sum, (_, balance)| sum.saturating_add(balance));
+            (sum, balances.len())
+        });
+        let total_supply = self.metadata.total_supply;
+        let net_issuance = self.total_minted as i128 - self.total_burned as i128;
+        let balance_drift = balance_sum as i128 - total_supply as i128;
+        let issuance_drift = net_issuance - total_supply as i128;
+
+        InvariantReport {
+            checked_at: ic_cdk::api::time(),
+            total_supply,
+            balance_sum,
+            net_issuance,
+            holder_count,
+            balance_drift,
+            issuance_drift,
+            ok: balance_drift == 0 && issuance_drift == 0,
+        }
+    }
+
+    /// Runs the self-audit and keeps its report for `get_token_stats`
+    fn audit(&mut self) {
+        let report = self.verify_invariants();
+        if !report.ok {
+            self.failed_audits += 1;
+            ic_cdk::println!(
+                "Ledger invariant violated: balance drift {}, issuance drift {}",
+                report.balance_drift,
+                report.issuance_drift
+            );
+        }
+        self.last_audit = Some(report);
+    }
+
+    /// Page of transactions by block index, starting at `start`
+    pub fn get_transactions(&self, start: u128, length: u128) -> TransactionPage {
+        let first_local = self.archived_length as u128;
+        let end = start
+            .saturating_add(length.min(MAX_TRANSACTIONS_PER_PAGE))
+            .min(self.log_length());
+        let transactions = (start.max(first_local)..end)
+            .map(|id| TransactionWithId {
+                id,
+                transaction: self.blocks[(id - first_local) as usize].transaction.clone(),
+            })
+            .collect();
+
+        TransactionPage {
+            total: self.log_length(),
+            first_local_index: first_local,
+            transactions,
+        }
+    }
+
+    /// Page of an account's transactions, oldest first; `start` counts the account's
+    /// transactions, not block indices
+    pub fn get_transactions_by_account(&self, account: &Account, start: u128, length: u128) -> TransactionPage {
+        let first_local = self.archived_length;
+        let mut ids = account_block_ids(account, None, usize::MAX);
+        ids.retain(|id| *id >= first_local);
+        ids.reverse();
+
+        let total = ids.len() as u128;
+        let transactions = ids
+            .into_iter()
+            .skip(start.min(total) as usize)
+            .take(length.min(MAX_TRANSACTIONS_PER_PAGE) as usize)
+            .map(|id| TransactionWithId {
+                id: id as u128,
+                transaction: self.blocks[(id - first_local) as usize].transaction.clone(),
+            })
+            .collect();
+        TransactionPage {
+            total,
+            first_local_index: first_local as u128,
+            transactions,
+        }
+    }
+
+    /// Index-canister style page of an account's transactions, newest first, from just below
+    /// block index `start` (or the newest when None)
+    pub fn get_account_transactions(&self, account: &Account, start: Option<u128>, max_results: u128) -> AccountTransactions {
+        let first_local = self.archived_length;
+        let start = start.map(|start| start.min(u64::MAX as u128) as u64);
+        let limit = max_results.min(MAX_TRANSACTIONS_PER_PAGE) as usize;
+
+        let mut transactions = Vec::new();
+        let mut archived_ids = Vec::new();
+        for id in account_block_ids(account, start, limit) {
+            if id < first_local {
+                archived_ids.push(id as u128);
+            } else {
+                transactions.push(TransactionWithId {
+                    id: id as u128,
+                    transaction: self.blocks[(id - first_local) as usize].transaction.clone(),
+                });
+            }
+        }
+
+        AccountTransactions {
+            balance: balance(account),
+            transactions,
+            archived_ids,
+            oldest_tx_id: oldest_account_block_id(account).map(|id| id as u128),
+        }
+    }
+
+    /// Mint new tokens (only callable by authorized minters)
+    pub fn mint(
+        &mut self,
+        to: Account,
+        amount: u128,
+        memo: Option<Vec<u8>>,
+        created_at_time: Option<u64>,
+    ) -> Result<(), String> {
+        let caller = ic_cdk::caller();
+        let now = ic_cdk::api::time();
+        if !self.authorized_minters.contains(&caller) {
+            return Err("Unauthorized minter".to_string());
+        }
+        check_memo(&memo)?;
+        let args = (to.clone(), amount, memo.clone(), created_at_time);
+        let dedup = self
+            .check_duplicate("mint", &args, created_at_time, caller, now)
+            .map_err(|e| format!("Mint rejected: {:?}", e))?;
+        self.check_not_frozen(None, &to)?;
+        let total_supply = self
+            .metadata
+            .total_supply
+            .checked_add(amount)
+            .ok_or_else(|| "Mint would overflow the total supply".to_string())?;
+        if let Some(max_supply) = self.max_supply {
+            if total_supply > max_supply {
+                return Err(format!("Mint would exceed the supply cap of {}", max_supply));
+            }
+        }
+
+        // No balance exceeds the total supply, so neither can overflow
+        set_balance(&to, balance(&to) + amount);
+        self.metadata.total_supply = total_supply;
+
+        // Record transaction
+        let block_index = self.record(Transaction {
+            from: None,
+            to,
+            spender: None,
+            amount,
+            fee: None,
+            expires_at: None,
+            memo,
+            timestamp: now,
+            transaction_type: TransactionType::Mint,
+        });
+        self.remember(dedup, block_index);
+
+        Ok(())
+    }
+
+    /// Burn tokens (only callable by authorized minters)
+    pub fn burn(&mut self, from: Account, amount: u128, memo: Option<Vec<u8>>) -> Result<(), String> {
+        let caller = ic_cdk::caller();
+        if !self.authorized_minters.contains(&caller) {
+            return Err("Unauthorized minter".to_string());
+        }
+        check_memo(&memo)?;
+
+        let current_balance = balance(&from);
+        if current_balance < amount {
+            return Err("Insufficient balance".to_string());
+        }
+
+        set_balance(&from, current_balance - amount);
+        self.metadata.total_supply -= amount;
+
+        // Record transaction
+        self.record(Transaction {
+            from: Some(from),
+            to: Account {
+                owner: Principal::anonymous(),
+                subaccount: None,
+            },
+            spender: None,
+            amount,
+            fee: None,
+            expires_at: None,
+            memo,
+            timestamp: ic_cdk::api::time(),
+            transaction_type: TransactionType::Burn,
+        });
+
+        Ok(())
+    }
+
+    /// ICRC-1 transfer from the caller's account, charging the transfer fee; transfers to the
+    /// minting account are fee-free burns
+    pub fn transfer(&mut self, args: TransferArg, caller: Principal, now: u64) -> Result<u128, TransferError> {
+        if self.transfers_paused_for(&caller) {
+            return Err(TransferError::TemporarilyUnavailable);
+        }
+        let from = Account {
+            owner: caller,
+            subaccount: args.from_subaccount,
+        };
+        if let Err(message) = check_memo(&args.memo) {
+            return Err(TransferError::GenericError {
+                error_code: 0,
+                message,
+            });
+        }
+        let dedup = self.check_duplicate("icrc1_transfer", &args, args.created_at_time, caller, now)?;
+        let to = args.to;
+        let amount = args.amount;
+        if let Err(message) = self.check_not_frozen(Some(&from), &to) {
+            return Err(TransferError::GenericError {
+                error_code: 1,
+                message,
+            });
+        }
+
+        let is_burn = self.is_minting_account(&to);
+        let fee = if is_burn { 0 } else { self.fee };
+        if args.fee.is_some_and(|arg_fee| arg_fee != fee) {
+            return Err(TransferError::BadFee { expected_fee: fee });
+        }
+        if is_burn && amount < self.fee {
+            return Err(TransferError::BadBurn {
+                min_burn_amount: self.fee,
+            });
+        }
+
+        // A debit past u128::MAX is more than any balance
+        let from_balance = balance(&from);
+        let debit = match amount.checked_add(fee) {
+            Some(debit) if debit <= from_balance => debit,
+            _ => return Err(TransferError::InsufficientFunds { balance: from_balance }),
+        };
+
+        // Update balances
+        set_balance(&from, from_balance - debit);
+        if is_burn {
+            self.metadata.total_supply -= amount;
+        } else {
+            set_balance(&to, balance(&to) + amount);
+            self.collect_fee(fee);
+        }
+
+        // Record transaction
+        let block_index = self.record(Transaction {
+            from: Some(from),
+            to,
+            spender: None,
+            amount,
+            fee: Some(fee).filter(|_| !is_burn),
+            expires_at: None,
+            memo: args.memo,
+            timestamp: now,
+            transaction_type: if is_burn { TransactionType::Burn } else { TransactionType::Transfer },
+        });
+        self.remember(dedup, block_index);
+
+        Ok(block_index)
+    }
+
+    /// Current allowance of `spender` over `account`; expired approvals count as zero
+    pub fn allowance(&self, account: &Account, spender: &Account, now: u64) -> Allowance {
+        self.allowances
+            .get(&(account.clone(), spender.clone()))
+            .filter(|allowance| allowance.expires_at.is_none_or(|expires_at| expires_at > now))
+            .cloned()
+            .unwrap_or(Allowance {
+                allowance: 0,
+                expires_at: None,
+            })
+    }
+
+    /// Sets `spender`'s allowance over the caller's account, replacing any previous approval
+    pub fn approve(&mut self, args: ApproveArgs, caller: Principal, now: u64) -> Result<u128, ApproveError> {
+        let from = Account {
+            owner: caller,
+            subaccount: args.from_subaccount,
+        };
+        if let Err(message) = check_memo(&args.memo) {
+            return Err(ApproveError::GenericError {
+                error_code: 0,
+                message,
+            });
+        }
+        let dedup = self.check_duplicate("icrc2_approve", &args, args.created_at_time, caller, now)?;
+
+        let fee = self.fee;
+        if args.fee.is_some_and(|arg_fee| arg_fee != fee) {
+            return Err(ApproveError::BadFee { expected_fee: fee });
+        }
+        if args.spender.owner == from.owner {
+            return Err(ApproveError::GenericError {
+                error_code: 0,
+                message: "Cannot approve an account's own owner".to_string(),
+            });
+        }
+        if args.expires_at.is_some_and(|expires_at| expires_at <= now) {
+            return Err(ApproveError::Expired { ledger_time: now });
+        }
+
+        let current = self.allowance(&from, &args.spender, now);
+        if let Some(expected) = args.expected_allowance {
+            if expected != current.allowance {
+                return Err(ApproveError::AllowanceChanged {
+                    current_allowance: current.allowance,
+                });
+            }
+        }
+
+        let from_balance = balance(&from);
+        if from_balance < fee {
+            return Err(ApproveError::InsufficientFunds { balance: from_balance });
+        }
+
+        set_balance(&from, from_balance - fee);
+        self.collect_fee(fee);
+        let key = (from.clone(), args.spender.clone());
+        if args.amount == 0 {
+            self.allowances.remove(&key);
+        } else {
+            self.allowances.insert(key, Allowance {
+                allowance: args.amount,
+                expires_at: args.expires_at,
+            });
+        }
+
+        // Record transaction
+        let block_index = self.record(Transaction {
+            from: Some(from),
+            to: args.spender.clone(),
+            spender: Some(args.spender),
+            amount: args.amount,
+            fee: Some(fee),
+            expires_at: args.expires_at,
+            memo: args.memo,
+            timestamp: now,
+            transaction_type: TransactionType::Approve,
+        });
+        self.remember(dedup, block_index);
+
+        Ok(block_index)
+    }
+
+    /// Moves tokens out of an account that approved the caller, spending the allowance on the
+    /// amount plus the fee; moving them to the minting account burns them without a fee
+    pub fn transfer_from(
+        &mut self,
+        args: TransferFromArgs,
+        caller: Principal,
+        now: u64,
+    ) -> Result<u128, TransferFromError> {
+        if self.transfers_paused_for(&caller) {
+            return Err(TransferFromError::TemporarilyUnavailable);
+        }
+        let spender = Account {
+            owner: caller,
+            subaccount: args.spender_subaccount,
+        };
+        if let Err(message) = check_memo(&args.memo) {
+            return Err(TransferFromError::GenericError {
+                error_code: 0,
+                message,
+            });
+        }
+        let dedup = self.check_duplicate("icrc2_transfer_from", &args, args.created_at_time, caller, now)?;
+
+        if let Err(message) = self.check_not_frozen(Some(&args.from), &args.to) {
+            return Err(TransferFromError::GenericError {
+                error_code: 1,
+                message,
+            });
+        }
+
+        let is_burn = self.is_minting_account(&args.to);
+        let fee = if is_burn { 0 } else { self.fee };
+        if args.fee.is_some_and(|arg_fee| arg_fee != fee) {
+            return Err(TransferFromError::BadFee { expected_fee: fee });
+        }
+        if is_burn && args.amount < self.fee {
+            return Err(TransferFromError::BadBurn {
+                min_burn_amount: self.fee,
+            });
+        }
+
+        // An owner moving its own tokens needs no allowance
+        let key = (args.from.clone(), spender.clone());
+        let from_balance = balance(&args.from);
+        // A debit past u128::MAX is more than any balance
+        let debit = match args.amount.checked_add(fee) {
+            Some(debit) => debit,
+            None => return Err(TransferFromError::InsufficientFunds { balance: from_balance }),
+        };
+        if spender != args.from {
+            let allowance = self.allowance(&args.from, &spender, now);
+            if allowance.allowance < debit {
+                return Err(TransferFromError::InsufficientAllowance {
+                    allowance: allowance.allowance,
+                });
+            }
+        }
+
+        if from_balance < debit {
+            return Err(TransferFromError::InsufficientFunds { balance: from_balance });
+        }
+
+        // Update balances and the remaining allowance
+        set_balance(&args.from, from_balance - debit);
+        if is_burn {
+            self.metadata.total_supply -= args.amount;
+        } else {
+            set_balance(&args.to, balance(&args.to) + args.amount);
+            self.collect_fee(fee);
+        }
+        if spender != args.from {
+            let allowance = self.allowances.get_mut(&key).unwrap();
+            allowance.allowance -= debit;
+            if allowance.allowance == 0 {
+                self.allowances.remove(&key);
+            }
+        }
+
+        // Record transaction
+        let block_index = self.record(Transaction {
+            from: Some(args.from),
+            to: args.to,
+            spender: Some(spender),
+            amount: args.amount,
+            fee: Some(fee).filter(|_| !is_burn),
+            expires_at: None,
+            memo: args.memo,
+            timestamp: now,
+            transaction_type: if is_burn { TransactionType::Burn } else { TransactionType::Transfer },
+        });
+        self.remember(dedup, block_index);
+
+        Ok(block_index)
+    }
+}
+
+/// Rejects memos longer than `MAX_MEMO_BYTES`
+fn check_memo(memo: &Option<Vec<u8>>) -> Result<(), String> {
+    match memo {
+        Some(memo) if memo.len() > MAX_MEMO_BYTES => {
+            Err(format!("Memo is {} bytes, at most {} are allowed", memo.len(), MAX_MEMO_BYTES))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Lays a markdown consent message out for a line display, dropping the markup
+fn line_display_pages(message: &str, characters_per_line: u16, lines_per_page: u16) -> Vec<LineDisplayPage> {
+    let width = characters_per_line.max(1) as usize;
+    let lines: Vec<String> = message
+        .lines()
+        .filter(|line| !line.is_empty())
+        .flat_map(|line| {
+            let chars: Vec<char> = line.trim_start_matches("# ").replace("**", "").chars().collect();
+            chars.chunks(width).map(|chunk| chunk.iter().collect::<String>()).collect::<Vec<_>>()
+        })
+        .collect();
+    lines
+        .chunks(lines_per_page.max(1) as usize)
+        .map(|page| LineDisplayPage { lines: page.to_vec() })
+        .collect()
+}
+
+/// Audits the ledger every `AUDIT_INTERVAL_SECS`; timers don't survive upgrades, so this runs on
+/// init and post-upgrade
+fn schedule_audit() {
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(AUDIT_INTERVAL_SECS), || {
+        storage::get_mut::<TokenState>().audit();
+    });
+}
+
+// Canister endpoints
+#[init]
+fn init(args: TokenInitArgs) {
+    let mut state = TokenState::new();
+    state.minting_account = args.minting_account;
+    state.authorized_minters = args.authorized_minters;
+    state.minting_governance = Some(args.minting_governance);
+    for admin in args.admins {
+        state.roles.entry(admin).or_default().insert(Role::Admin);
+    }
+    *storage::get_mut::<TokenState>() = state;
+    schedule_audit();
+}
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    let state = storage::get::<TokenState>();
+    let bytes = candid::encode_one(state).expect("Failed to encode token state");
+    STATE.with(|cell| cell.borrow_mut().set(bytes).expect("Failed to save token state"));
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    let mut state: TokenState = STATE
+        .with(|cell| candid::decode_one(cell.borrow().get()))
+        .expect("Failed to decode token state");
+
+    // Timers don't survive the upgrade, so a scheduled archiving run is gone, and the
+    // certified data has to be set again
+    state.archiving = false;
+    state.update_certified_data();
+    *storage::get_mut::<TokenState>() = state;
+    schedule_audit();
+}
+
+#[query]
+fn metadata() -> Metadata {
+    let state = storage::get::<TokenState>();
+    state.metadata.clone()
+}
+
+#[query]
+fn balance_of(account: Account) -> u128 {
+    balance(&account)
+}
+
+#[query]
+fn icrc1_name() -> String {
+    storage::get::<TokenState>().metadata.name.clone()
+}
+
+#[query]
+fn icrc1_symbol() -> String {
+    storage::get::<TokenState>().metadata.symbol.clone()
+}
+
+#[query]
+fn icrc1_decimals() -> u8 {
+    storage::get::<TokenState>().metadata.decimals
+}
+
+#[query]
+fn icrc1_total_supply() -> u128 {
+    storage::get::<TokenState>().metadata.total_supply
+}
+
+#[query]
+fn icrc1_balance_of(account: Account) -> u128 {
+    balance(&account)
+}
+
+#[query]
+fn icrc1_supported_standards() -> Vec<StandardRecord> {
+    [
+        ("ICRC-1", "https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-1"),
+        ("ICRC-2", "https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-2"),
+        ("ICRC-3", "https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-3"),
+        ("ICRC-21", "https://github.com/dfinity/wg-identity-authentication/blob/main/topics/ICRC-21/icrc_21_consent_msg.md"),
+    ]
+    .iter()
+    .map(|(name, url)| StandardRecord {
+        name: name.to_string(),
+        url: url.to_string(),
+    })
+    .collect()
+}
+
+/// ICRC-21 consent message wallets show before signing a transfer or approval
+#[update]
+fn icrc21_canister_call_consent_message(request: ConsentMessageRequest) -> Result<ConsentInfo, Icrc21Error> {
+    let state = storage::get::<TokenState>();
+    let message = state.consent_message(&request.method, &request.arg)?;
+    let consent_message = match request.user_preferences.device_spec {
+        Some(DisplayMessageType::LineDisplay {
+            characters_per_line,
+            lines_per_page,
+        }) => ConsentMessage::LineDisplayMessage {
+            pages: line_display_pages(&message, characters_per_line, lines_per_page),
+        },
+        _ => ConsentMessage::GenericDisplayMessage(message),
+    };
+    Ok(ConsentInfo {
+        consent_message,
+        metadata: ConsentMessageMetadata {
+            language: CONSENT_LANGUAGE.to_string(),
+            utc_offset_minutes: request.user_preferences.metadata.utc_offset_minutes,
+        },
+    })
+}
+
+/// Metadata in the ICRC-1 key/value format read by wallets
+#[query]
+fn icrc1_metadata() -> Vec<(String, MetadataValue)> {
+    let state = storage::get::<TokenState>();
+    let mut metadata = vec![
+        ("icrc1:name".to_string(), MetadataValue::Text(state.metadata.name.clone())),
+        ("icrc1:symbol".to_string(), MetadataValue::Text(state.metadata.symbol.clone())),
+        ("icrc1:decimals".to_string(), MetadataValue::Nat(state.metadata.decimals as u128)),
+        ("icrc1:fee".to_string(), MetadataValue::Nat(state.fee)),
+    ];
+    if let Some(logo) = state.logo.clone() {
+        metadata.push(("icrc1:logo".to_string(), MetadataValue::Text(logo)));
+    }
+    metadata
+}
+
+/// Holder count, supply, 24h transfer volume and mint/burn totals, kept up to date by every
+/// transaction, and the latest self-audit
+#[query]
+fn get_token_stats() -> TokenStats {
+    storage::get::<TokenState>().stats()
+}
+
+/// Checks total supply against the sum of balances and the net issuance
+#[query]
+fn verify_invariants() -> InvariantReport {
+    storage::get::<TokenState>().verify_invariants()
+}
+
+/// Fee charged on every transfer and approval
+#[query]
+fn icrc1_fee() -> u128 {
+    let state = storage::get::<TokenState>();
+    state.fee
+}
+
+#[query]
+fn get_fee_collector() -> Option<Account> {
+    let state = storage::get::<TokenState>();
+    state.fee_collector.clone()
+}
+
+#[update]
+fn transfer(to: Account, amount: u128) -> Result<(), String> {
+    let state = storage::get_mut::<TokenState>();
+    let args = TransferArg {
+        from_subaccount: None,
+        to,
+        amount,
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    };
+    state
+        .transfer(args, ic_cdk::caller(), ic_cdk::api::time())
+        .map(|_| ())
+        .map_err(|e| format!("Transfer failed: {:?}", e))
+}
+
+#[update]
+fn icrc1_transfer(args: TransferArg) -> Result<u128, TransferError> {
+    let state = storage::get_mut::<TokenState>();
+    state.transfer(args, ic_cdk::caller(), ic_cdk::api::time())
+}
+
+#[update]
+fn icrc2_approve(args: ApproveArgs) -> Result<u128, ApproveError> {
+    let state = storage::get_mut::<TokenState>();
+    state.approve(args, ic_cdk::caller(), ic_cdk::api::time())
+}
+
+#[query]
+fn icrc2_allowance(args: AllowanceArgs) -> Allowance {
+    let state = storage::get::<TokenState>();
+    state.allowance(&args.account, &args.spender, ic_cdk::api::time())
+}
+
+#[update]
+fn icrc2_transfer_from(args: TransferFromArgs) -> Result<u128, TransferFromError> {
+    let state = storage::get_mut::<TokenState>();
+    state.transfer_from(args, ic_cdk::caller(), ic_cdk::api::time())
+}
+
+/// Transactions from block index `start`, at most 100 per page
+#[query]
+fn get_transactions(start: u128, length: u128) -> TransactionPage {
+    let state = storage::get::<TokenState>();
+    state.get_transactions(start, length)
+}
+
+/// Accounts with a balance and their balances, in a stable order: pass the last account of a
+/// page as `start_after` to get the next one
+#[query]
+fn list_holders(start_after: Option<Account>, limit: u64) -> Vec<HolderBalance> {
+    holders_after(start_after.as_ref(), limit.min(MAX_HOLDERS_PER_PAGE) as usize)
+}
+
+/// Transactions touching an account, newest first, read from the per-account index
+#[query]
+fn get_account_transactions(account: Account, start: Option<u128>, max_results: u128) -> AccountTransactions {
+    let state = storage::get::<TokenState>();
+    state.get_account_transactions(&account, start, max_results)
+}
+
+/// Transactions the account took part in, paged by their position in its history
+#[query]
+fn get_transactions_by_account(account: Account, start: u128, length: u128) -> TransactionPage {
+    let state = storage::get::<TokenState>();
+    state.get_transactions_by_account(&account, start, length)
+}
+
+// Admin functions
+
+/// Sets the transfer fee and the account it is credited to; fees are burned without one
+#[update]
+fn set_fee(fee: u128, fee_collector: Option<Account>) -> Result<(), String> {
+    let state = storage::get_mut::<TokenState>();
+    state.check_role(Role::Treasurer)?;
+    audit_change("set_fee", &(state.fee, &state.fee_collector), &(fee, &fee_collector));
+    state.fee = fee;
+    state.fee_collector = fee_collector;
+    Ok(())
+}
+
+/// Sets the logo, a data URL such as `data:image/svg+xml;base64,...`
+#[update]
+fn set_logo(logo: Option<String>) -> Result<(), String> {
+    let state = storage::get_mut::<TokenState>();
+    state.check_role(Role::Admin)?;
+    audit_change("set_logo", &state.logo, &logo);
+    state.logo = logo;
+    Ok(())
+}
+
+/// Proposes freezing an account, after which tokens can no longer be moved from or to it; takes
+/// effect once enough guardians approve. Returns the action ID
+#[update]
+fn freeze_account(account: Account, reason: String) -> Result<u64, String> {
+    propose_action(ApprovalAction::FreezeAccount { account, reason })
+}
+
+#[update]
+fn unfreeze_account(account: Account) -> Result<(), String> {
+    let state = storage::get_mut::<TokenState>();
+    state.check_role(Role::Guardian)?;
+    audit_action("unfreeze_account", &account);
+    state.unfreeze(account)
+}
+
+/// Pauses or resumes transfers; the protocol's own transfers are never paused
+#[update]
+fn set_transfers_paused(paused: bool) -> Result<(), String> {
+    let state = storage::get_mut::<TokenState>();
+    state.check_role(Role::Guardian)?;
+    audit_change("set_transfers_paused", &state.transfers_paused, &paused);
+    state.transfers_paused = paused;
+    Ok(())
+}
+
+#[query]
+fn get_transfers_paused() -> bool {
+    storage::get::<TokenState>().transfers_paused
+}
+
+#[query]
+fn get_blocklist() -> Vec<FrozenAccount> {
+    storage::get::<TokenState>().frozen_accounts.clone()
+}
+
+#[query]
+fn get_freeze_events() -> Vec<FreezeEvent> {
+    storage::get::<TokenState>().freeze_events.clone()
+}
+
+/// Audit entries oldest first, at most 100 per page
+#[query]
+fn get_audit_log(offset: u64, limit: u64) -> Vec<AuditEntry> {
+    AUDIT_LOG.with(|log| {
+        let log = log.borrow();
+        let end = log.len().min(offset.saturating_add(limit.min(MAX_AUDIT_ENTRIES_PER_PAGE)));
+        (offset..end).filter_map(|id| log.get(id)).collect()
+    })
+}
+
+#[query]
+fn get_audit_log_length() -> u64 {
+    AUDIT_LOG.with(|log| log.borrow().len())
+}
+
+/// Sets the ICRC-1 minting account, transfers to which burn
+#[update]
+fn set_minting_account(minting_account: Option<Account>) -> Result<(), String> {
+    let state = storage::get_mut::<TokenState>();
+    state.check_minting_governance()?;
+    audit_change("set_minting_account", &state.minting_account, &minting_account);
+    state.minting_account = minting_account;
+    Ok(())
+}
+
+#[query]
+fn icrc1_minting_account() -> Option<Account> {
+    storage::get::<TokenState>().minting_account.clone()
+}
+
+/// Caps the total supply mints can reach; None removes the cap
+#[update]
+fn set_max_supply(max_supply: Option<u128>) -> Result<(), String> {
+    let state = storage::get_mut::<TokenState>();
+    state.check_role(Role::Admin)?;
+    audit_change("set_max_supply", &state.max_supply, &max_supply);
+    state.max_supply = max_supply;
+    Ok(())
+}
+
+#[query]
+fn get_max_supply() -> Option<u128> {
+    storage::get::<TokenState>().max_supply
+}
+
+/// Registers a canister (e.g. savings or analytics) whose `method` is called with a
+/// `SupplyEvent` on every mint and burn
+#[update]
+fn subscribe_supply_events(subscriber: Principal, method: String) -> Result<(), String> {
+    let state = storage::get_mut::<TokenState>();
+    state.check_role(Role::Admin)?;
+    audit_action("subscribe_supply_events", &(subscriber, &method));
+    state.subscribe_supply_events(subscriber, method)
+}
+
+#[update]
+fn unsubscribe_supply_events(subscriber: Principal) -> Result<(), String> {
+    let state = storage::get_mut::<TokenState>();
+    state.check_role(Role::Admin)?;
+    if let Some(method) = state.supply_subscribers.remove(&subscriber) {
+        audit_action("unsubscribe_supply_events", &(subscriber, method));
+    }
+    Ok(())
+}
+
+#[query]
+fn get_supply_subscribers() -> Vec<(Principal, String)> {
+    let state = storage::get::<TokenState>();
+    state.supply_subscribers
+        .iter()
+        .map(|(subscriber, method)| (*subscriber, method.clone()))
+        .collect()
+}
+
+// Minter functions
+#[update]
+fn mint(
+    to: Account,
+    amount: u128,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+) -> Result<(), String> {
+    let state = storage::get_mut::<TokenState>();
+    state.mint(to, amount, memo, created_at_time)
+}
+
+#[update]
+fn burn(from: Account, amount: u128, memo: Option<Vec<u8>>) -> Result<(), String> {
+    let state = storage::get_mut::<TokenState>();
+    state.burn(from, amount, memo)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn balance_keys_round_trip() {
+        let owner = Principal::from_slice(&[7; 29]);
+        let default = Account { owner, subaccount: None };
+        let spelled_out = Account { owner, subaccount: Some(DEFAULT_SUBACCOUNT) };
+        let other = Account { owner, subaccount: Some([1; 32]) };
+
+        assert_eq!(default.balance_key(), spelled_out.balance_key());
+        assert_ne!(default.balance_key(), other.balance_key());
+        assert_eq!(Account::from_balance_key(&spelled_out.balance_key()).subaccount, None);
+        assert_eq!(Account::from_balance_key(&other.balance_key()).subaccount, Some([1; 32]));
+        let anonymous = Account { owner: Principal::anonymous(), subaccount: None };
+        assert_eq!(Account::from_balance_key(&anonymous.balance_key()).owner, Principal::anonymous());
+    }
+
+    #[test]
+    fn memos_over_the_limit_are_rejected() {
+        assert!(check_memo(&None).is_ok());
+        assert!(check_memo(&Some(vec![0; MAX_MEMO_BYTES])).is_ok());
+        assert!(check_memo(&Some(vec![0; MAX_MEMO_BYTES + 1])).is_err());
+    }
+
+    #[test]
+    fn consent_messages_wrap_into_pages() {
+        let pages = line_display_pages("# Transfer\n\n**Amount:** 1.5 iUSD", 10, 2);
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].lines, vec!["Transfer", "Amount: 1."]);
+        assert_eq!(pages[1].lines, vec!["5 iUSD"]);
+    }
+
+    const NOW: u64 = 1_700_000_000_000_000_000;
+
+    fn account(id: u8) -> Account {
+        Account {
+            owner: Principal::from_slice(&[id; 29]),
+            subaccount: None,
+        }
+    }
+
+    /// Token whose whole supply sits in `holder`'s account
+    fn funded_token(holder: &Account, amount: u128) -> TokenState {
+        let mut state = TokenState::new();
+        set_balance(holder, amount);
+        state.metadata.total_supply = amount;
+        state.total_minted = amount;
+        state
+    }
+
+    fn approve_args(spender: &Account, amount: u128) -> ApproveArgs {
+        ApproveArgs {
+            from_subaccount: None,
+            spender: spender.clone(),
+            amount,
+            expected_allowance: None,
+            expires_at: None,
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        }
+    }
+
+    fn transfer_from_args(from: &Account, to: &Account, amount: u128) -> TransferFromArgs {
+        TransferFromArgs {
+            spender_subaccount: None,
+            from: from.clone(),
+            to: to.clone(),
+            amount,
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        }
+    }
+
+    #[test]
+    fn approvals_check_the_expected_allowance() {
+        let (owner, spender) = (account(1), account(2));
+        let mut state = funded_token(&owner, 1_000_000);
+
+        let first = ApproveArgs { expected_allowance: Some(0), ..approve_args(&spender, 500) };
+        assert!(state.approve(first.clone(), owner.owner, NOW).is_ok());
+        assert!(matches!(
+            state.approve(first, owner.owner, NOW),
+            Err(ApproveError::AllowanceChanged { current_allowance: 500 })
+        ));
+
+        let raise = ApproveArgs { expected_allowance: Some(500), ..approve_args(&spender, 700) };
+        assert!(state.approve(raise, owner.owner, NOW).is_ok());
+        assert_eq!(state.allowance(&owner, &spender, NOW).allowance, 700);
+    }
+
+    #[test]
+    fn expired_allowances_cannot_be_spent() {
+        let (owner, spender, recipient) = (account(1), account(2), account(3));
+        let mut state = funded_token(&owner, 1_000_000);
+
+        let past = ApproveArgs { expires_at: Some(NOW), ..approve_args(&spender, 500_000) };
+        assert!(matches!(
+            state.approve(past, owner.owner, NOW),
+            Err(ApproveError::Expired { ledger_time: NOW })
+        ));
+
+        let expiring = ApproveArgs { expires_at: Some(NOW + 10), ..approve_args(&spender, 500_000) };
+        state.approve(expiring, owner.owner, NOW).unwrap();
+        assert_eq!(state.allowance(&owner, &spender, NOW + 9).allowance, 500_000);
+        assert_eq!(state.allowance(&owner, &spender, NOW + 10).allowance, 0);
+        assert!(matches!(
+            state.transfer_from(transfer_from_args(&owner, &recipient, 1_000), spender.owner, NOW + 10),
+            Err(TransferFromError::InsufficientAllowance { allowance: 0 })
+        ));
+    }
+
+    #[test]
+    fn allowances_must_cover_the_fee() {
+        let (owner, spender, recipient) = (account(1), account(2), account(3));
+        let mut state = funded_token(&owner, 1_000_000);
+        let fee = state.fee;
+
+        state.approve(approve_args(&spender, 100_000), owner.owner, NOW).unwrap();
+        assert!(matches!(
+            state.transfer_from(transfer_from_args(&owner, &recipient, 100_000), spender.owner, NOW),
+            Err(TransferFromError::InsufficientAllowance { allowance: 100_000 })
+        ));
+
+        state.transfer_from(transfer_from_args(&owner, &recipient, 100_000 - fee), spender.owner, NOW).unwrap();
+        assert_eq!(balance(&recipient), 100_000 - fee);
+        assert_eq!(balance(&owner), 1_000_000 - fee - 100_000);
+    }
+
+    #[test]
+    fn spent_and_zeroed_allowances_are_removed() {
+        let (owner, spender, recipient) = (account(1), account(2), account(3));
+        let mut state = funded_token(&owner, 1_000_000);
+        let fee = state.fee;
+        let key = (owner.clone(), spender.clone());
+
+        state.approve(approve_args(&spender, 50_000 + fee), owner.owner, NOW).unwrap();
+        state.transfer_from(transfer_from_args(&owner, &recipient, 50_000), spender.owner, NOW).unwrap();
+        assert!(!state.allowances.contains_key(&key));
+
+        state.approve(approve_args(&spender, 50_000), owner.owner, NOW).unwrap();
+        state.approve(approve_args(&spender, 0), owner.owner, NOW).unwrap();
+        assert!(!state.allowances.contains_key(&key));
+        assert_eq!(state.allowance(&owner, &spender, NOW).allowance, 0);
+    }
+
+    #[test]
+    fn owners_spend_their_own_tokens_without_an_allowance() {
+        let (owner, recipient) = (account(1), account(3));
+        let mut state = funded_token(&owner, 1_000_000);
+        let fee = state.fee;
+
+        state.transfer_from(transfer_from_args(&owner, &recipient, 200_000), owner.owner, NOW).unwrap();
+        assert_eq!(balance(&recipient), 200_000);
+        assert_eq!(balance(&owner), 1_000_000 - 200_000 - fee);
+        assert!(state.allowances.is_empty());
+    }
+
+    #[test]
+    fn transfer_from_to_the_minting_account_burns() {
+        let (owner, spender, minting_account) = (account(1), account(2), account(9));
+        let mut state = funded_token(&owner, 1_000_000);
+        state.minting_account = Some(minting_account.clone());
+        let fee = state.fee;
+
+        state.approve(approve_args(&spender, 300_000), owner.owner, NOW).unwrap();
+        let supply = state.metadata.total_supply;
+        assert!(matches!(
+            state.transfer_from(transfer_from_args(&owner, &minting_account, fee - 1), spender.owner, NOW),
+            Err(TransferFromError::BadBurn { .. })
+        ));
+
+        state.transfer_from(transfer_from_args(&owner, &minting_account, 100_000), spender.owner, NOW).unwrap();
+        assert_eq!(state.metadata.total_supply, supply - 100_000);
+        assert_eq!(balance(&minting_account), 0);
+        assert_eq!(state.allowance(&owner, &spender, NOW).allowance, 200_000);
+        let block = state.blocks.last().unwrap();
+        assert!(matches!(block.transaction.transaction_type, TransactionType::Burn));
+        assert_eq!(block.transaction.fee, None);
+    }
+}
@@ -0,0 +1,433 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::*;
+use std::collections::BTreeSet;
+
+use crate::{audit_action, audit_change, record_audit, storage, Account, TokenState};
+
+/// Roles admin endpoints require; Admin also passes every other role's checks. The minters
+/// and minting account stay under the separate minting governance
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Token settings, archiving and role assignment
+    Admin,
+    /// Freezing accounts and pausing transfers
+    Guardian,
+    /// Shared with the protocol's roles; no token endpoint requires it
+    OracleManager,
+    /// Transfer fee and fee collector
+    Treasurer,
+}
+
+/// An admin nomination waiting for the candidate to accept, so a mistyped principal can't take
+/// the role
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PendingAdmin {
+    candidate: Principal,
+    proposed_by: Principal,
+    proposed_at: u64,
+    /// Admin whose role moves to the candidate on acceptance; None adds an admin
+    replaces: Option<Principal>,
+}
+
+/// Destructive actions no single principal can take; each needs approvals from `action_threshold`
+/// distinct holders of its role
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum ApprovalAction {
+    /// Adds an account to the blocklist
+    FreezeAccount { account: Account, reason: String },
+    AddMinter(Principal),
+    RemoveMinter(Principal),
+    RotateMinters(Vec<Principal>),
+    SetApprovalThreshold(u32),
+}
+
+impl ApprovalAction {
+    /// Role each approver must hold
+    fn required_role(&self) -> Role {
+        match self {
+            ApprovalAction::FreezeAccount { .. } => Role::Guardian,
+            _ => Role::Admin,
+        }
+    }
+
+    fn is_minter_change(&self) -> bool {
+        matches!(
+            self,
+            ApprovalAction::AddMinter(_) | ApprovalAction::RemoveMinter(_) | ApprovalAction::RotateMinters(_)
+        )
+    }
+}
+
+/// An action collecting approvals
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PendingAction {
+    id: u64,
+    action: ApprovalAction,
+    proposed_by: Principal,
+    proposed_at: u64,
+    /// Distinct principals that approved, the proposer included
+    approvals: BTreeSet<Principal>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum ActionOutcome {
+    Executed,
+    /// Reached the threshold but the action returned an error
+    Failed(String),
+    Cancelled,
+}
+
+/// A pending action that was executed or cancelled
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CompletedAction {
+    action: PendingAction,
+    outcome: ActionOutcome,
+    completed_at: u64,
+}
+
+impl TokenState {
+    /// Whether `principal` holds `role` (or Admin); the canister itself holds every role
+    fn has_role(&self, principal: &Principal, role: Role) -> bool {
+        let granted = self.roles
+            .get(principal)
+            .is_some_and(|roles| roles.contains(&role) || roles.contains(&Role::Admin));
+        *principal == ic_cdk::id() || granted
+    }
+
+    /// Rejects callers without `role` (or Admin)
+    pub(crate) fn check_role(&self, role: Role) -> Result<(), String> {
+        if !self.has_role(&ic_cdk::caller(), role) {
+            return Err(format!("Unauthorized: requires the {:?} role", role));
+        }
+        Ok(())
+    }
+
+    /// Whether an action has enough approvals from principals still holding its role; for minter
+    /// changes the minting governance's approval counts as one of them
+    fn is_action_approved(&self, pending: &PendingAction) -> bool {
+        let role = pending.action.required_role();
+        let approvals = pending.approvals
+            .iter()
+            .filter(|approver| {
+                self.has_role(approver, role)
+                    || (pending.action.is_minter_change() && self.minting_governance == Some(**approver))
+            })
+            .count();
+        approvals >= self.action_threshold as usize
+    }
+
+    /// Records an approval and executes the action once it has enough; returns whether it ran
+    fn approve_action(&mut self, id: u64, approver: Principal) -> Result<bool, String> {
+        let pending = self.pending_actions.get_mut(&id).ok_or("Action not found")?;
+        if !pending.approvals.insert(approver) {
+            return Err("Action already approved by caller".to_string());
+        }
+        if !self.is_action_approved(&self.pending_actions[&id]) {
+            return Ok(false);
+        }
+
+        let pending = self.pending_actions.remove(&id).unwrap();
+        let result = self.execute_action(&pending.action);
+        let outcome = match &result {
+            Ok(()) => ActionOutcome::Executed,
+            Err(e) => ActionOutcome::Failed(e.clone()),
+        };
+        record_audit(
+            &format!("execute_approved_action {}", id),
+            None,
+            Some(format!("{:?} ({:?})", pending.action, outcome)),
+        );
+        self.action_history.push(CompletedAction {
+            action: pending,
+            outcome,
+            completed_at: ic_cdk::api::time(),
+        });
+        result.map(|_| true)
+    }
+
+    fn execute_action(&mut self, action: &ApprovalAction) -> Result<(), String> {
+        match action {
+            ApprovalAction::FreezeAccount { account, reason } => self.freeze(account.clone(), reason.clone()),
+            ApprovalAction::AddMinter(minter) => {
+                if !self.authorized_minters.contains(minter) {
+                    self.authorized_minters.push(*minter);
+                }
+                Ok(())
+            }
+            ApprovalAction::RemoveMinter(minter) => {
+                self.authorized_minters.retain(|authorized| authorized != minter);
+                Ok(())
+            }
+            ApprovalAction::RotateMinters(minters) => {
+                self.authorized_minters = minters.clone();
+                Ok(())
+            }
+            ApprovalAction::SetApprovalThreshold(threshold) => {
+                self.action_threshold = *threshold;
+                Ok(())
+            }
+        }
+    }
+
+    fn role_members(&self, role: Role) -> Vec<Principal> {
+        self.roles
+            .iter()
+            .filter(|(_, roles)| roles.contains(&role))
+            .map(|(principal, _)| *principal)
+            .collect()
+    }
+
+    /// Revokes a role; the last admin cannot be removed, so roles can always be reassigned
+    fn revoke_role(&mut self, principal: Principal, role: Role) -> Result<(), String> {
+        if role == Role::Admin && self.role_members(Role::Admin) == vec![principal] {
+            return Err("Cannot revoke the last admin".to_string());
+        }
+
+        if let Some(roles) = self.roles.get_mut(&principal) {
+            roles.remove(&role);
+            if roles.is_empty() {
+                self.roles.remove(&principal);
+            }
+        }
+        Ok(())
+    }
+
+    /// Rejects callers other than the minting governance
+    pub(crate) fn check_minting_governance(&self) -> Result<(), String> {
+        if self.minting_governance != Some(ic_cdk::caller()) {
+            return Err("Unauthorized".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Canister endpoints
+#[update]
+fn grant_role(principal: Principal, role: Role) -> Result<(), String> {
+    let state = storage::get_mut::<TokenState>();
+    state.check_role(Role::Admin)?;
+    if role == Role::Admin {
+        return Err("Admins must be nominated with propose_admin and accept the role".to_string());
+    }
+    state.roles.entry(principal).or_default().insert(role);
+    audit_action("grant_role", &(principal, role));
+    Ok(())
+}
+
+#[update]
+fn revoke_role(principal: Principal, role: Role) -> Result<(), String> {
+    let state = storage::get_mut::<TokenState>();
+    state.check_role(Role::Admin)?;
+    state.revoke_role(principal, role)?;
+    audit_action("revoke_role", &(principal, role));
+    Ok(())
+}
+
+/// Nominates a new admin, who takes the role once they call `accept_admin`. With `replace_self`
+/// the caller's own Admin role moves to them, handing the role over
+#[update]
+fn propose_admin(candidate: Principal, replace_self: bool) -> Result<(), String> {
+    let state = storage::get_mut::<TokenState>();
+    state.check_role(Role::Admin)?;
+    let caller = ic_cdk::caller();
+    state.pending_admins.insert(candidate, PendingAdmin {
+        candidate,
+        proposed_by: caller,
+        proposed_at: ic_cdk::api::time(),
+        replaces: Some(caller).filter(|_| replace_self),
+    });
+    audit_action("propose_admin", &state.pending_admins[&candidate]);
+    Ok(())
+}
+
+/// Accepts the caller's admin nomination
+#[update]
+fn accept_admin() -> Result<(), String> {
+    let state = storage::get_mut::<TokenState>();
+    let caller = ic_cdk::caller();
+    let pending = state.pending_admins
+        .remove(&caller)
+        .ok_or("No admin nomination for caller")?;
+    state.roles.entry(caller).or_default().insert(Role::Admin);
+    if let Some(replaced) = pending.replaces.filter(|replaced| *replaced != caller) {
+        state.revoke_role(replaced, Role::Admin)?;
+    }
+    audit_action("accept_admin", &pending);
+    Ok(())
+}
+
+#[update]
+fn cancel_admin_proposal(candidate: Principal) -> Result<(), String> {
+    let state = storage::get_mut::<TokenState>();
+    state.check_role(Role::Admin)?;
+    let pending = state.pending_admins
+        .remove(&candidate)
+        .ok_or("No admin nomination for candidate")?;
+    audit_action("cancel_admin_proposal", &pending);
+    Ok(())
+}
+
+#[query]
+fn get_pending_admins() -> Vec<PendingAdmin> {
+    storage::get::<TokenState>().pending_admins.values().cloned().collect()
+}
+
+/// Queues a destructive action with the caller's approval, executing it at once if that is
+/// enough. Minter changes can be proposed by admins or the minting governance
+#[update]
+pub(crate) fn propose_action(action: ApprovalAction) -> Result<u64, String> {
+    let state = storage::get_mut::<TokenState>();
+    let caller = ic_cdk::caller();
+    if !(action.is_minter_change() && state.minting_governance == Some(caller)) {
+        state.check_role(action.required_role())?;
+    }
+    if let ApprovalAction::SetApprovalThreshold(0) = action {
+        return Err("Approval threshold must be at least 1".to_string());
+    }
+
+    let id = state.next_action_id;
+    state.next_action_id += 1;
+    state.pending_actions.insert(id, PendingAction {
+        id,
+        action,
+        proposed_by: caller,
+        proposed_at: ic_cdk::api::time(),
+        approvals: BTreeSet::new(),
+    });
+    state.approve_action(id, caller)?;
+    Ok(id)
+}
+
+/// Approves a queued action, executing it if this approval reaches the threshold; returns
+/// whether it was executed
+#[update]
+fn approve_action(action_id: u64) -> Result<bool, String> {
+    let state = storage::get_mut::<TokenState>();
+    let caller = ic_cdk::caller();
+    let action = &state.pending_actions
+        .get(&action_id)
+        .ok_or("Action not found")?
+        .action;
+    if !(action.is_minter_change() && state.minting_governance == Some(caller)) {
+        state.check_role(action.required_role())?;
+    }
+    state.approve_action(action_id, caller)
+}
+
+/// Drops a queued action; callable by its proposer or an admin
+#[update]
+fn cancel_action(action_id: u64) -> Result<(), String> {
+    let state = storage::get_mut::<TokenState>();
+    let pending = state.pending_actions.get(&action_id).ok_or("Action not found")?;
+    if pending.proposed_by != ic_cdk::caller() {
+        state.check_role(Role::Admin)?;
+    }
+
+    let pending = state.pending_actions.remove(&action_id).unwrap();
+    audit_action(&format!("cancel_action {}", action_id), &pending.action);
+    state.action_history.push(CompletedAction {
+        action: pending,
+        outcome: ActionOutcome::Cancelled,
+        completed_at: ic_cdk::api::time(),
+    });
+    Ok(())
+}
+
+#[query]
+fn get_pending_actions() -> Vec<PendingAction> {
+    storage::get::<TokenState>().pending_actions.values().cloned().collect()
+}
+
+#[query]
+fn get_action_history(offset: u64, limit: u64) -> Vec<CompletedAction> {
+    let state = storage::get::<TokenState>();
+    state.action_history
+        .iter()
+        .skip(offset as usize)
+        .take(limit as usize)
+        .cloned()
+        .collect()
+}
+
+#[query]
+fn get_approval_threshold() -> u32 {
+    storage::get::<TokenState>().action_threshold
+}
+
+#[query]
+fn get_roles(principal: Principal) -> Vec<Role> {
+    let state = storage::get::<TokenState>();
+    state.roles.get(&principal).map(|roles| roles.iter().copied().collect()).unwrap_or_default()
+}
+
+#[query]
+fn get_role_members(role: Role) -> Vec<Principal> {
+    storage::get::<TokenState>().role_members(role)
+}
+
+/// Proposes adding a minter through the approval queue; returns the action ID
+#[update]
+fn add_minter(minter: Principal) -> Result<u64, String> {
+    propose_action(ApprovalAction::AddMinter(minter))
+}
+
+/// Proposes removing a minter through the approval queue; returns the action ID
+#[update]
+fn remove_minter(minter: Principal) -> Result<u64, String> {
+    propose_action(ApprovalAction::RemoveMinter(minter))
+}
+
+/// Proposes replacing every minter at once, e.g. when the protocol canister is redeployed;
+/// returns the action ID
+#[update]
+fn rotate_minters(minters: Vec<Principal>) -> Result<u64, String> {
+    propose_action(ApprovalAction::RotateMinters(minters))
+}
+
+/// Proposes handing control of the minters and minting account to another principal, which
+/// takes over once it calls `accept_minting_governance`
+#[update]
+fn set_minting_governance(governance: Principal) -> Result<(), String> {
+    let state = storage::get_mut::<TokenState>();
+    state.check_minting_governance()?;
+    audit_action("set_minting_governance", &governance);
+    state.pending_minting_governance = Some(governance);
+    Ok(())
+}
+
+#[update]
+fn accept_minting_governance() -> Result<(), String> {
+    let state = storage::get_mut::<TokenState>();
+    if state.pending_minting_governance != Some(ic_cdk::caller()) {
+        return Err("Caller is not the proposed minting governance".to_string());
+    }
+    audit_change("accept_minting_governance", &state.minting_governance, &state.pending_minting_governance);
+    state.minting_governance = state.pending_minting_governance.take();
+    Ok(())
+}
+
+/// Drops a proposed minting governance that has not accepted yet
+#[update]
+fn cancel_minting_governance_transfer() -> Result<(), String> {
+    let state = storage::get_mut::<TokenState>();
+    state.check_minting_governance()?;
+    if let Some(governance) = state.pending_minting_governance.take() {
+        audit_action("cancel_minting_governance_transfer", &governance);
+    }
+    Ok(())
+}
+
+#[query]
+fn get_minters() -> Vec<Principal> {
+    storage::get::<TokenState>().authorized_minters.clone()
+}
+
+#[query]
+fn get_minting_governance() -> Option<Principal> {
+    storage::get::<TokenState>().minting_governance
+}
+
+#[query]
+fn get_pending_minting_governance() -> Option<Principal> {
+    storage::get::<TokenState>().pending_minting_governance
+}